@@ -1,72 +1,305 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
 
 use comfy_table::{ContentArrangement, Table};
 use rustyline::error::ReadlineError;
+use rustyline::history::{FileHistory, History};
 use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::net::TcpStream;
 
-use lunaris_common::protocol::{self, Request, Response};
-use lunaris_common::value::Value;
+use lunaris_common::protocol::{
+    self, ErrorCode, Format, Handshake, Request, Response, SourceLocation, StatementKind,
+};
+use lunaris_common::value::{self, Value};
 
 const DEFAULT_SERVER_ADDR: &str = "127.0.0.1:7435";
 
 const SERVER_ADDR_ENV_VAR: &str = "SERVER_ADDR";
 
+/// Set to `json` to talk to the server in JSON instead of the binary format.
+const WIRE_FORMAT_ENV_VAR: &str = "LUNARIS_WIRE_FORMAT";
+
+/// Set to `json` to print results as JSON instead of tables.
+const OUTPUT_FORMAT_ENV_VAR: &str = "LUNARIS_FORMAT";
+
+/// Number of digits shown after the decimal point of floats in tables. By
+/// default floats get as many digits as it takes to read them back exactly.
+const FLOAT_PRECISION_ENV_VAR: &str = "LUNARIS_FLOAT_PRECISION";
+
+static FLOAT_PRECISION: OnceLock<Option<usize>> = OnceLock::new();
+
+/// Set to `1` to print how long the server took after every statement.
+const TIMING_ENV_VAR: &str = "LUNARIS_TIMING";
+
+/// File the shell keeps its history in, `~/.lunaris_history` by default.
+/// Set it to an empty string to keep no history.
+const HISTORY_ENV_VAR: &str = "LUNARIS_HISTORY";
+
+/// Attempts at reaching the server again after the connection dropped.
+const RECONNECT_ATTEMPTS: u32 = 5;
+
+/// Wait before the second attempt, doubled before every further one.
+const RECONNECT_BACKOFF_MS: u64 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Table,
+    /// A header line and one line per row, read back by `COPY ... FROM`.
+    Csv,
+    Json,
+    /// One `column: value` line per column, for rows too wide for a table.
+    Vertical,
+}
+
+/// How the shell prints results, changed with `.mode` and `.timer`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Settings {
+    output: OutputFormat,
+    /// Follow every statement's message with the time the server took.
+    timer: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            output: OutputFormat::Table,
+            timer: false,
+        }
+    }
+}
+
+impl Settings {
+    fn apply(&mut self, command: SettingCommand) {
+        match command {
+            SettingCommand::Mode(output) => self.output = output,
+            SettingCommand::Timer(timer) => self.timer = timer,
+        }
+    }
+}
+
+/// Values of `:name` placeholders, each kept as the SQL literal that
+/// replaces it.
+#[derive(Debug, Default)]
+struct Params(BTreeMap<String, String>);
+
+/// A `.param` dot-command.
+#[derive(Debug, PartialEq)]
+enum ParamCommand {
+    Set { name: String, value: String },
+    Unset(String),
+    List,
+}
+
+impl Params {
+    fn apply(&mut self, command: ParamCommand) {
+        match command {
+            ParamCommand::Set { name, value } => {
+                self.0.insert(name, param_literal(&value));
+            }
+            ParamCommand::Unset(name) => {
+                self.0.remove(&name);
+            }
+            ParamCommand::List => {
+                for (name, literal) in &self.0 {
+                    println!(":{name} = {literal}");
+                }
+            }
+        }
+    }
+}
+
+/// A dot-command changing one of the `Settings`.
+#[derive(Debug, PartialEq)]
+enum SettingCommand {
+    Mode(OutputFormat),
+    Timer(bool),
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let server_addr = std::env::var(SERVER_ADDR_ENV_VAR).unwrap_or(DEFAULT_SERVER_ADDR.to_string());
 
-    let stream = match TcpStream::connect(&server_addr).await {
-        Ok(s) => s,
+    let requested = match std::env::var(WIRE_FORMAT_ENV_VAR) {
+        Ok(v) if v.eq_ignore_ascii_case("json") => Format::Json,
+        _ => Format::Binary,
+    };
+    let mut conn = match Connection::open(&server_addr, requested).await {
+        Ok(conn) => conn,
         Err(err) => {
             eprintln!("Failed to connect to {server_addr}: {err}");
             eprintln!("Is the server running? Start it with: cargo run -p server");
             process::exit(1);
         }
     };
-
-    let (mut reader, mut writer) = stream.into_split();
+    let mut settings = Settings::default();
+    if std::env::var(OUTPUT_FORMAT_ENV_VAR).is_ok_and(|v| v.eq_ignore_ascii_case("json")) {
+        settings.output = OutputFormat::Json;
+    }
+    settings.timer = std::env::var(TIMING_ENV_VAR).is_ok_and(|v| v == "1");
+    let precision = match std::env::var(FLOAT_PRECISION_ENV_VAR) {
+        Ok(v) => match v.parse() {
+            Ok(digits) => Some(digits),
+            Err(_) => {
+                eprintln!("Ignoring {FLOAT_PRECISION_ENV_VAR}={v}: not a digit count");
+                None
+            }
+        },
+        Err(_) => None,
+    };
+    FLOAT_PRECISION.get_or_init(|| precision);
 
     if let Some(path) = std::env::args().nth(1) {
-        run_script(&path, &mut reader, &mut writer).await
+        run_script(&path, settings, &mut conn).await
     } else {
-        run_repl(&server_addr, &mut reader, &mut writer).await
+        run_repl(settings, &mut conn).await
     }
 }
 
-async fn run_script(
-    path: &str,
-    reader: &mut OwnedReadHalf,
-    writer: &mut OwnedWriteHalf,
-) -> anyhow::Result<()> {
-    let contents = std::fs::read_to_string(path)?;
+/// The connection to the server, along with what it takes to open it again.
+struct Connection {
+    addr: String,
+    /// Format asked for in the handshake.
+    requested: Format,
+    /// Format the server agreed to.
+    format: Format,
+    reader: OwnedReadHalf,
+    writer: OwnedWriteHalf,
+}
 
-    for stmt in contents.split(';') {
-        let trimmed = stmt.trim();
-        if trimmed.is_empty() {
-            continue;
+/// The server went away in the middle of a statement.
+#[derive(Debug)]
+struct ConnectionLost;
+
+impl fmt::Display for ConnectionLost {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Server closed connection.")
+    }
+}
+
+impl std::error::Error for ConnectionLost {}
+
+impl Connection {
+    async fn open(addr: &str, requested: Format) -> anyhow::Result<Self> {
+        let (mut reader, mut writer) = TcpStream::connect(addr).await?.into_split();
+        let format = handshake(requested, &mut reader, &mut writer).await?;
+        Ok(Self {
+            addr: addr.to_string(),
+            requested,
+            format,
+            reader,
+            writer,
+        })
+    }
+
+    /// Connect again, backing off between attempts. Fails with the error of
+    /// the last attempt once all of them failed.
+    async fn reconnect(&mut self) -> anyhow::Result<()> {
+        let mut backoff = Duration::from_millis(RECONNECT_BACKOFF_MS);
+        let mut attempt = 1;
+        loop {
+            match Connection::open(&self.addr, self.requested).await {
+                Ok(conn) => {
+                    *self = conn;
+                    return Ok(());
+                }
+                Err(e) if attempt == RECONNECT_ATTEMPTS => return Err(e),
+                Err(_) => {
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+            }
         }
-        if let Err(e) = send_and_display(trimmed, reader, writer).await {
-            eprintln!("Error: {e}");
-            process::exit(1);
+    }
+
+    /// Reconnect if `err` means the connection dropped, so the caller can try
+    /// again. Any other error is handed back.
+    async fn recover(&mut self, err: anyhow::Error) -> anyhow::Result<()> {
+        if !is_connection_lost(&err) {
+            return Err(err);
         }
+        eprintln!("Connection lost, reconnecting to {}...", self.addr);
+        self.reconnect().await?;
+        eprintln!("Reconnected, any open transaction was rolled back.");
+        Ok(())
     }
+}
 
-    Ok(())
+fn is_connection_lost(err: &anyhow::Error) -> bool {
+    // undecodable frames are the server's fault, not the connection's
+    err.is::<ConnectionLost>()
+        || err
+            .downcast_ref::<io::Error>()
+            .is_some_and(|e| e.kind() != io::ErrorKind::InvalidData)
 }
 
-async fn run_repl(
-    server_addr: &str,
+/// Agree on the payload format with the server, returning the one it picked.
+async fn handshake(
+    requested: Format,
     reader: &mut OwnedReadHalf,
     writer: &mut OwnedWriteHalf,
-) -> anyhow::Result<()> {
-    println!("Connected to Lunaris at {server_addr}");
-    println!("Type SQL statements, or 'exit' to quit.\n");
+) -> anyhow::Result<Format> {
+    let hello = Handshake { format: requested };
+    protocol::send_message(writer, &hello, Format::Json).await?;
+    match protocol::recv_message::<Handshake, _>(reader).await? {
+        Some(ack) => Ok(ack.format),
+        None => anyhow::bail!("Server closed connection during handshake."),
+    }
+}
+
+async fn run_script(path: &str, settings: Settings, conn: &mut Connection) -> anyhow::Result<()> {
+    let contents = std::fs::read_to_string(path)?;
+
+    // the server splits the script, so semicolons in strings are safe
+    let request = Request::Script { sql: contents };
+    let result = async {
+        protocol::send_message(&mut conn.writer, &request, conn.format).await?;
+        while display_response(settings, &mut conn.reader, None).await? {}
+        anyhow::Ok(())
+    }
+    .await;
+    if let Err(e) = result {
+        eprintln!("Error: {e}");
+        process::exit(1);
+    }
+
+    Ok(())
+}
+
+async fn run_repl(mut settings: Settings, conn: &mut Connection) -> anyhow::Result<()> {
+    let mut params = Params::default();
+    println!("Connected to Lunaris at {}", conn.addr);
+    println!("Type SQL statements ending with ';', or 'exit' to quit.");
+    println!("End a statement with '\\G' instead to print its rows vertically.");
+    println!("'.tables' lists the tables and '.schema <table>' describes one.");
+    println!("'.mode table|csv|json|line' and '.timer on|off' change how results are shown.");
+    println!("'.param set <name> <value>' fills in ':name' in the statements that follow.");
+    println!("'.ping' checks that the server is still there.\n");
 
     let mut rl = rustyline::DefaultEditor::new()?;
-    loop {
-        let line = match rl.readline("lunaris> ") {
+    let history = history_path(|name| std::env::var(name).ok());
+    if let Some(path) = &history {
+        load_history(rl.history_mut(), path);
+    }
+    // text of a statement that hasn't seen its terminating semicolon yet
+    let mut pending = String::new();
+    'repl: loop {
+        let prompt = if pending.is_empty() {
+            "lunaris> "
+        } else {
+            "   ...> "
+        };
+        let line = match rl.readline(prompt) {
             Ok(line) => line,
+            Err(ReadlineError::Interrupted) if !pending.is_empty() => {
+                pending.clear();
+                continue;
+            }
             Err(ReadlineError::Interrupted | ReadlineError::Eof) => {
                 println!("Goodbye.");
                 break;
@@ -78,51 +311,512 @@ async fn run_repl(
         };
 
         let trimmed = line.trim();
-        if trimmed.is_empty() {
-            continue;
+        if pending.is_empty() {
+            if trimmed.is_empty() {
+                continue;
+            }
+            if trimmed.eq_ignore_ascii_case("exit") || trimmed.eq_ignore_ascii_case("quit") {
+                println!("Goodbye.");
+                break;
+            }
+
+            if trimmed.starts_with('.') {
+                rl.add_history_entry(trimmed)?;
+                if trimmed == ".ping" {
+                    let result = match ping(conn).await {
+                        Err(e) => match conn.recover(e).await {
+                            Ok(()) => ping(conn).await,
+                            Err(e) => Err(e),
+                        },
+                        ok => ok,
+                    };
+                    match result {
+                        Ok(elapsed) => println!("Pong in {}", format_elapsed(elapsed)),
+                        Err(e) => {
+                            eprintln!("Error: {e}");
+                            break;
+                        }
+                    }
+                    continue;
+                }
+                match parse_setting(trimmed) {
+                    Some(Ok(command)) => {
+                        settings.apply(command);
+                        continue;
+                    }
+                    Some(Err(usage)) => {
+                        eprintln!("{usage}");
+                        continue;
+                    }
+                    None => {}
+                }
+                match parse_param_command(trimmed) {
+                    Some(Ok(command)) => {
+                        params.apply(command);
+                        continue;
+                    }
+                    Some(Err(usage)) => {
+                        eprintln!("{usage}");
+                        continue;
+                    }
+                    None => {}
+                }
+                let Some(sql) = meta_command_sql(trimmed) else {
+                    eprintln!("Unknown command: {trimmed}");
+                    continue;
+                };
+                if let Err(e) = run_statement(&sql, settings, &params, conn).await {
+                    eprintln!("Error: {e}");
+                    break;
+                }
+                continue;
+            }
         }
-        if trimmed.eq_ignore_ascii_case("exit") || trimmed.eq_ignore_ascii_case("quit") {
-            println!("Goodbye.");
-            break;
+
+        if !pending.is_empty() {
+            pending.push('\n');
         }
+        pending.push_str(&line);
 
-        rl.add_history_entry(&line)?;
+        let (statements, rest) = split_statements(&pending);
+        if statements.is_empty() {
+            // still waiting for the semicolon, unless the input was nothing
+            // but semicolons
+            if rest.is_empty() {
+                pending.clear();
+            }
+            continue;
+        }
+        rl.add_history_entry(pending.trim())?;
+        pending = rest;
 
-        if let Err(e) = send_and_display(trimmed, reader, writer).await {
-            eprintln!("Error: {e}");
-            break;
+        for sql in statements {
+            let (sql, settings) = match sql.strip_suffix("\\G") {
+                Some(sql) => (
+                    sql,
+                    Settings {
+                        output: OutputFormat::Vertical,
+                        ..settings
+                    },
+                ),
+                None => (sql.as_str(), settings),
+            };
+            if let Err(e) = run_statement(sql, settings, &params, conn).await {
+                eprintln!("Error: {e}");
+                break 'repl;
+            }
         }
     }
 
+    if let Some(path) = &history {
+        save_history(rl.history_mut(), path);
+    }
     Ok(())
 }
 
+/// Where the shell history lives, if it is kept at all.
+fn history_path(env: impl Fn(&str) -> Option<String>) -> Option<PathBuf> {
+    match env(HISTORY_ENV_VAR) {
+        Some(path) if path.is_empty() => None,
+        Some(path) => Some(PathBuf::from(path)),
+        None => {
+            let home = env("HOME").map(PathBuf::from);
+            Some(
+                home.unwrap_or_else(|| PathBuf::from("."))
+                    .join(".lunaris_history"),
+            )
+        }
+    }
+}
+
+/// Read the entries of earlier sessions. A missing file just means there
+/// is no history yet; other problems are reported but don't stop the shell.
+fn load_history(history: &mut FileHistory, path: &Path) {
+    match history.load(path) {
+        Ok(()) => {}
+        Err(ReadlineError::Io(e)) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => eprintln!("Could not read history from {}: {e}", path.display()),
+    }
+}
+
+fn save_history(history: &mut FileHistory, path: &Path) {
+    if let Err(e) = history.save(path) {
+        eprintln!("Could not save history to {}: {e}", path.display());
+    }
+}
+
+/// Where `split_statements` is in its input.
+#[derive(Clone, Copy, PartialEq)]
+enum SplitState {
+    Code,
+    Quoted(char),
+    /// `--` up to the end of the line.
+    LineComment,
+    /// `/* ... */`
+    BlockComment,
+}
+
+/// Split `input` into the statements terminated by a semicolon or `\G` and
+/// the unterminated remainder. Statements ending in `\G` keep it, so the
+/// caller can print them vertically. Terminators inside quoted strings,
+/// identifiers or comments don't end a statement. Statements holding
+/// nothing but comments are dropped, and so is such a remainder unless a
+/// block comment is still open.
+fn split_statements(input: &str) -> (Vec<String>, String) {
+    let mut statements = Vec::new();
+    let mut state = SplitState::Code;
+    // whether the current statement has more than whitespace and comments
+    let mut code = false;
+    let mut start = 0;
+    let mut chars = input.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let next = chars.peek().map(|&(_, c)| c);
+        match (state, c) {
+            // a doubled quote closes and reopens the string, which is fine
+            (SplitState::Quoted(q), _) if c == q => state = SplitState::Code,
+            (SplitState::Quoted(_), _) => {}
+            (SplitState::LineComment, '\n') => state = SplitState::Code,
+            (SplitState::LineComment, _) => {}
+            (SplitState::BlockComment, '*') if next == Some('/') => {
+                chars.next();
+                state = SplitState::Code;
+            }
+            (SplitState::BlockComment, _) => {}
+            (SplitState::Code, '\'' | '"') => {
+                state = SplitState::Quoted(c);
+                code = true;
+            }
+            (SplitState::Code, '-') if next == Some('-') => {
+                chars.next();
+                state = SplitState::LineComment;
+            }
+            (SplitState::Code, '/') if next == Some('*') => {
+                chars.next();
+                state = SplitState::BlockComment;
+            }
+            (SplitState::Code, ';') => {
+                if code {
+                    statements.push(input[start..i].trim().to_string());
+                }
+                code = false;
+                start = i + 1;
+            }
+            (SplitState::Code, '\\') if next == Some('G') => {
+                chars.next();
+                if code {
+                    statements.push(format!("{}\\G", input[start..i].trim()));
+                }
+                code = false;
+                start = i + 2;
+            }
+            (SplitState::Code, _) => code |= !c.is_whitespace(),
+        }
+    }
+    let rest = match code || state == SplitState::BlockComment {
+        true => input[start..].trim().to_string(),
+        false => String::new(),
+    };
+    (statements, rest)
+}
+
+/// SQL that a REPL meta-command such as `.tables` stands for.
+fn meta_command_sql(command: &str) -> Option<String> {
+    let mut parts = command.split_whitespace();
+    let sql = match (parts.next()?, parts.next(), parts.next()) {
+        (".tables", None, _) => "SHOW TABLES".to_string(),
+        (".schema", Some(table), None) => format!("DESCRIBE {table}"),
+        _ => return None,
+    };
+    Some(sql)
+}
+
+/// Parse a `.mode` or `.timer` command. None if `command` is neither, and
+/// the command's usage if its argument is missing or not one it knows.
+fn parse_setting(command: &str) -> Option<Result<SettingCommand, String>> {
+    let mut parts = command.split_whitespace();
+    let name = parts.next()?;
+    let arg = parts.next().map(str::to_ascii_lowercase);
+    let extra = parts.next().is_some();
+    let setting = match name {
+        ".mode" => match arg.as_deref() {
+            Some("table") => Some(SettingCommand::Mode(OutputFormat::Table)),
+            Some("csv") => Some(SettingCommand::Mode(OutputFormat::Csv)),
+            Some("json") => Some(SettingCommand::Mode(OutputFormat::Json)),
+            Some("line") => Some(SettingCommand::Mode(OutputFormat::Vertical)),
+            _ => None,
+        },
+        ".timer" => match arg.as_deref() {
+            Some("on") => Some(SettingCommand::Timer(true)),
+            Some("off") => Some(SettingCommand::Timer(false)),
+            _ => None,
+        },
+        _ => return None,
+    };
+    let usage = match name {
+        ".mode" => "Usage: .mode table|csv|json|line",
+        _ => "Usage: .timer on|off",
+    };
+    Some(setting.filter(|_| !extra).ok_or_else(|| usage.to_string()))
+}
+
+/// Parse `.param set <name> <value>`, `.param unset <name>` or `.param
+/// list`. The value is the rest of the line. None if `command` isn't a
+/// `.param` command, its usage if it is a malformed one.
+fn parse_param_command(command: &str) -> Option<Result<ParamCommand, String>> {
+    let rest = command.strip_prefix(".param")?;
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let (action, rest) = split_word(rest);
+    let (name, value) = split_word(rest);
+    let valid_name = is_param_name(name);
+    let command = match action {
+        "set" if valid_name && !value.is_empty() => Some(ParamCommand::Set {
+            name: name.to_string(),
+            value: value.to_string(),
+        }),
+        "unset" if valid_name && value.is_empty() => Some(ParamCommand::Unset(name.to_string())),
+        "list" if name.is_empty() => Some(ParamCommand::List),
+        _ => None,
+    };
+    Some(command.ok_or_else(|| {
+        "Usage: .param set <name> <value> | .param unset <name> | .param list".to_string()
+    }))
+}
+
+/// The first word of `text` and what follows it, both trimmed.
+fn split_word(text: &str) -> (&str, &str) {
+    let text = text.trim();
+    match text.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim()),
+        None => (text, ""),
+    }
+}
+
+fn is_param_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// SQL literal for a parameter value. Numbers, NULL, TRUE and FALSE are
+/// used as they are; anything else is text, quoted with its quotes doubled.
+/// A value in single quotes is always text, so `'42'` is a string.
+fn param_literal(value: &str) -> String {
+    if let Some(text) = value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')) {
+        return format!("'{}'", text.replace('\'', "''"));
+    }
+    let is_number = value.parse::<i64>().is_ok() || value.parse::<f64>().is_ok_and(f64::is_finite);
+    let is_keyword = ["NULL", "TRUE", "FALSE"]
+        .iter()
+        .any(|k| value.eq_ignore_ascii_case(k));
+    match is_number || is_keyword {
+        true => value.to_string(),
+        false => format!("'{}'", value.replace('\'', "''")),
+    }
+}
+
+/// `sql` with every `:name` placeholder outside quoted strings and
+/// identifiers replaced by its value. Fails on a placeholder that isn't
+/// set, rather than sending it to the server.
+fn substitute_params(sql: &str, params: &Params) -> Result<String, String> {
+    let mut out = String::with_capacity(sql.len());
+    let mut quote = None;
+    let mut rest = sql;
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+        match (quote, c) {
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(q), _) if c == q => quote = None,
+            (None, ':') if rest.starts_with(':') => {
+                // a `::` cast, not a placeholder
+                out.push(':');
+                rest = &rest[1..];
+            }
+            (None, ':') => {
+                let len = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                let name = &rest[..len];
+                if is_param_name(name) {
+                    let value = params.0.get(name).ok_or_else(|| {
+                        format!("Parameter :{name} is not set, use .param set {name} <value>")
+                    })?;
+                    out.push_str(value);
+                    rest = &rest[len..];
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        out.push(c);
+    }
+    Ok(out)
+}
+
+/// Round-trip time of a ping to the server, in microseconds.
+async fn ping(conn: &mut Connection) -> anyhow::Result<u64> {
+    let start = Instant::now();
+    protocol::send_message(&mut conn.writer, &Request::Ping, conn.format).await?;
+    match protocol::recv_message::<Response, _>(&mut conn.reader).await? {
+        Some(Response::Pong) => Ok(start.elapsed().as_micros() as u64),
+        Some(other) => anyhow::bail!("Expected a pong, got {other:?}"),
+        None => Err(ConnectionLost.into()),
+    }
+}
+
+/// Send `sql` and print the answer. When the connection dropped on the way,
+/// connect again and send it once more.
+async fn run_statement(
+    sql: &str,
+    settings: Settings,
+    params: &Params,
+    conn: &mut Connection,
+) -> anyhow::Result<()> {
+    match send_and_display(sql, settings, params, conn).await {
+        Err(e) => {
+            conn.recover(e).await?;
+            send_and_display(sql, settings, params, conn).await
+        }
+        ok => ok,
+    }
+}
+
+/// Fill in the placeholders of `sql`, send it and print the answer. A
+/// placeholder without a value is reported like a failed statement.
 async fn send_and_display(
     sql: &str,
-    reader: &mut OwnedReadHalf,
-    writer: &mut OwnedWriteHalf,
+    settings: Settings,
+    params: &Params,
+    conn: &mut Connection,
 ) -> anyhow::Result<()> {
-    let request = Request {
-        sql: sql.to_string(),
+    let sql = match substitute_params(sql, params) {
+        Ok(sql) => sql,
+        Err(message) => {
+            eprintln!("Error: {message}");
+            return Ok(());
+        }
     };
-    protocol::send_message(writer, &request).await?;
+    let request = Request::Query { sql: sql.clone() };
+    protocol::send_message(&mut conn.writer, &request, conn.format).await?;
+    display_response(settings, &mut conn.reader, Some(&sql)).await?;
+    Ok(())
+}
 
-    match protocol::recv_message::<Response, _>(reader).await? {
-        Some(Response::Ok(result)) => {
-            if let Some(rs) = result.result_set {
-                print_result_set(&rs.columns, &rs.rows);
+/// Read and print the answer to one statement, which is `sql` if known.
+/// Returns false instead when the server signals the end of a script.
+async fn display_response(
+    settings: Settings,
+    reader: &mut OwnedReadHalf,
+    sql: Option<&str>,
+) -> anyhow::Result<bool> {
+    let output = settings.output;
+    // rows arrive in chunks; collect them so the table can be laid out once
+    let mut columns = Vec::new();
+    let mut rows = Vec::new();
+    loop {
+        match protocol::recv_message::<Response, _>(reader).await? {
+            Some(Response::Rows(chunk)) => {
+                columns = chunk.columns;
+                rows.extend(chunk.rows);
             }
-            println!("{}", result.message);
-        }
-        Some(Response::Error { message }) => {
-            eprintln!("Error: {message}");
+            Some(Response::Done {
+                message,
+                kind,
+                rows_affected,
+                elapsed_micros,
+            }) => {
+                let message = match settings.timer {
+                    true => format!("{message} ({})", format_elapsed(elapsed_micros)),
+                    false => message,
+                };
+                // a query that returned nothing still gets its (empty) table,
+                // a mutation only shows rows if its RETURNING sent some
+                let has_rows = kind == StatementKind::Query || !columns.is_empty();
+                match (output, has_rows) {
+                    (OutputFormat::Table, true) => {
+                        print_result_set(&columns, &rows);
+                        println!("{message}");
+                    }
+                    (OutputFormat::Vertical, true) => {
+                        print!("{}", format_vertical(&columns, &rows));
+                        println!("{message}");
+                    }
+                    (OutputFormat::Csv, true) => {
+                        print!("{}", format_csv(&columns, &rows));
+                    }
+                    (OutputFormat::Table | OutputFormat::Csv | OutputFormat::Vertical, false) => {
+                        println!("{message}")
+                    }
+                    (OutputFormat::Json, true) if kind == StatementKind::Query => {
+                        println!("{}", result_set_to_json(&columns, &rows));
+                    }
+                    (OutputFormat::Json, true) => println!(
+                        "{}",
+                        serde_json::json!({
+                            "message": message,
+                            "rows_affected": rows_affected,
+                            "rows": result_set_to_json(&columns, &rows),
+                        })
+                    ),
+                    (OutputFormat::Json, false) => println!(
+                        "{}",
+                        serde_json::json!({ "message": message, "rows_affected": rows_affected })
+                    ),
+                }
+                return Ok(true);
+            }
+            Some(Response::Error {
+                code,
+                message,
+                location,
+            }) => {
+                eprintln!("{}", format_error(output, code, &message, location));
+                if output != OutputFormat::Json
+                    && let (Some(sql), Some(location)) = (sql, location)
+                    && let Some(caret) = error_caret(sql, location)
+                {
+                    eprintln!("{caret}");
+                }
+                return Ok(true);
+            }
+            Some(Response::ScriptDone) => return Ok(false),
+            Some(Response::Pong) => anyhow::bail!("Unexpected pong from the server."),
+            None => return Err(ConnectionLost.into()),
         }
-        None => {
-            anyhow::bail!("Server closed connection.");
+    }
+}
+
+/// Server-side duration of a statement, e.g. `40µs`, `1.2ms` or `3.40s`.
+fn format_elapsed(micros: u64) -> String {
+    match micros {
+        0..1000 => format!("{micros}µs"),
+        1000..1_000_000 => format!("{:.1}ms", micros as f64 / 1000.0),
+        _ => format!("{:.2}s", micros as f64 / 1_000_000.0),
+    }
+}
+
+fn format_error(
+    output: OutputFormat,
+    code: ErrorCode,
+    message: &str,
+    location: Option<SourceLocation>,
+) -> String {
+    match output {
+        OutputFormat::Table | OutputFormat::Csv | OutputFormat::Vertical => {
+            format!("Error [{code}]: {message}")
         }
+        OutputFormat::Json => serde_json::json!({
+            "error": { "code": code, "message": message, "location": location }
+        })
+        .to_string(),
     }
+}
 
-    Ok(())
+/// The line of `sql` holding `location`, with a caret under the column.
+fn error_caret(sql: &str, location: SourceLocation) -> Option<String> {
+    let line = sql.lines().nth(location.line.checked_sub(1)? as usize)?;
+    let indent = location.column.saturating_sub(1) as usize;
+    Some(format!("  {line}\n  {}^", " ".repeat(indent)))
 }
 
 fn print_result_set(columns: &[String], rows: &[Vec<Value>]) {
@@ -138,12 +832,420 @@ fn print_result_set(columns: &[String], rows: &[Vec<Value>]) {
     println!("{table}");
 }
 
+/// Rows as blocks of `column: value` lines, each headed by a row separator.
+/// Names are right-aligned, and the lines of multi-line values line up
+/// behind them.
+fn format_vertical(columns: &[String], rows: &[Vec<Value>]) -> String {
+    let width = columns.iter().map(|c| c.chars().count()).max().unwrap_or(0);
+    let indent = " ".repeat(width + 2);
+    let mut out = String::new();
+    for (n, row) in rows.iter().enumerate() {
+        out.push_str(&format!("{0} {1}. row {0}\n", "*".repeat(27), n + 1));
+        for (column, value) in columns.iter().zip(row) {
+            let value = format_value(value).replace('\n', &format!("\n{indent}"));
+            out.push_str(&format!("{column:>width$}: {value}\n"));
+        }
+    }
+    out
+}
+
+/// Rows as CSV under a header line. NULL is an empty field and an empty
+/// string is `""`, as `COPY ... FROM` reads them.
+fn format_csv(columns: &[String], rows: &[Vec<Value>]) -> String {
+    let field = |text: &str| match text.contains([',', '"', '\n', '\r']) || text.is_empty() {
+        true => format!("\"{}\"", text.replace('"', "\"\"")),
+        false => text.to_string(),
+    };
+    let mut out = String::new();
+    let header: Vec<String> = columns.iter().map(|c| field(c)).collect();
+    out.push_str(&header.join(","));
+    out.push('\n');
+    for row in rows {
+        let fields: Vec<String> = row
+            .iter()
+            .map(|v| match v {
+                Value::Null => String::new(),
+                Value::Blob(b) => format!("\\x{}", value::to_hex(b)),
+                v => field(&format_value(v)),
+            })
+            .collect();
+        out.push_str(&fields.join(","));
+        out.push('\n');
+    }
+    out
+}
+
 fn format_value(v: &Value) -> String {
     match v {
         Value::Null => "NULL".to_string(),
         Value::Integer(i) => i.to_string(),
-        Value::Float(f) => f.to_string(),
+        Value::Float(f) => value::format_float(*f, FLOAT_PRECISION.get().copied().flatten()),
         Value::Boolean(b) => b.to_string(),
         Value::Text(s) => s.clone(),
+        Value::Blob(b) => format!("X'{}'", value::to_hex(b)),
+    }
+}
+
+/// A result set as an array of objects keyed by column name.
+fn result_set_to_json(columns: &[String], rows: &[Vec<Value>]) -> serde_json::Value {
+    let objects = rows
+        .iter()
+        .map(|row| {
+            let fields = columns
+                .iter()
+                .cloned()
+                .zip(row.iter().map(value_to_json))
+                .collect();
+            serde_json::Value::Object(fields)
+        })
+        .collect();
+    serde_json::Value::Array(objects)
+}
+
+fn value_to_json(v: &Value) -> serde_json::Value {
+    match v {
+        Value::Null => serde_json::Value::Null,
+        Value::Integer(i) => (*i).into(),
+        // NaN and infinities have no JSON representation
+        Value::Float(f) => serde_json::Number::from_f64(*f)
+            .map(serde_json::Value::Number)
+            .unwrap_or(serde_json::Value::Null),
+        Value::Boolean(b) => (*b).into(),
+        Value::Text(s) => s.as_str().into(),
+        Value::Blob(b) => value::to_hex(b).into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_meta_commands() {
+        assert_eq!(meta_command_sql(".tables").as_deref(), Some("SHOW TABLES"));
+        assert_eq!(
+            meta_command_sql(".schema  users").as_deref(),
+            Some("DESCRIBE users")
+        );
+        assert_eq!(meta_command_sql(".schema"), None);
+        assert_eq!(meta_command_sql(".nope"), None);
+    }
+
+    #[test]
+    fn test_setting_commands() {
+        let parsed = |command: &str| parse_setting(command).map(Result::unwrap);
+        for (name, output) in [
+            ("table", OutputFormat::Table),
+            ("csv", OutputFormat::Csv),
+            ("JSON", OutputFormat::Json),
+            ("line", OutputFormat::Vertical),
+        ] {
+            let command = format!(".mode {name}");
+            assert_eq!(parsed(&command), Some(SettingCommand::Mode(output)));
+        }
+        assert_eq!(parsed(".timer on"), Some(SettingCommand::Timer(true)));
+        assert_eq!(parsed(".timer  off"), Some(SettingCommand::Timer(false)));
+
+        for bad in [
+            ".mode",
+            ".mode xml",
+            ".timer",
+            ".timer yes",
+            ".timer on off",
+        ] {
+            assert!(matches!(parse_setting(bad), Some(Err(_))), "{bad}");
+        }
+        assert_eq!(parse_setting(".tables"), None);
+
+        let mut settings = Settings::default();
+        assert_eq!(settings.output, OutputFormat::Table);
+        assert!(!settings.timer);
+        settings.apply(SettingCommand::Mode(OutputFormat::Csv));
+        settings.apply(SettingCommand::Timer(true));
+        assert_eq!(
+            settings,
+            Settings {
+                output: OutputFormat::Csv,
+                timer: true
+            }
+        );
+    }
+
+    #[test]
+    fn test_param_substitution() {
+        let mut params = Params::default();
+        for command in [
+            ".param set id 42",
+            ".param set name O'Brien; DROP TABLE t",
+            ".param set code '42'",
+        ] {
+            params.apply(parse_param_command(command).unwrap().unwrap());
+        }
+
+        assert_eq!(
+            substitute_params("SELECT * FROM t WHERE id = :id", &params).unwrap(),
+            "SELECT * FROM t WHERE id = 42"
+        );
+        assert_eq!(
+            substitute_params("INSERT INTO t VALUES (:id, :name, :code)", &params).unwrap(),
+            "INSERT INTO t VALUES (42, 'O''Brien; DROP TABLE t', '42')"
+        );
+        // quoted text and casts are left alone
+        assert_eq!(
+            substitute_params("SELECT ':id', \":id\", 1::id", &params).unwrap(),
+            "SELECT ':id', \":id\", 1::id"
+        );
+
+        assert_eq!(
+            substitute_params("SELECT :missing", &params),
+            Err("Parameter :missing is not set, use .param set missing <value>".into())
+        );
+        params.apply(ParamCommand::Unset("id".into()));
+        assert!(substitute_params("SELECT :id", &params).is_err());
+
+        assert_eq!(
+            parse_param_command(".param list"),
+            Some(Ok(ParamCommand::List))
+        );
+        for bad in [".param", ".param set x", ".param set 1x 2", ".param unset"] {
+            assert!(matches!(parse_param_command(bad), Some(Err(_))), "{bad}");
+        }
+        assert_eq!(parse_param_command(".params"), None);
+    }
+
+    #[test]
+    fn test_csv_output() {
+        let columns = vec!["id".to_string(), "note".to_string()];
+        let rows = vec![
+            vec![Value::Integer(1), Value::Text("a, \"b\"\nc".into())],
+            vec![Value::Integer(2), Value::Text(String::new())],
+            vec![Value::Null, Value::Blob(vec![0xab])],
+        ];
+        assert_eq!(
+            format_csv(&columns, &rows),
+            "id,note\n1,\"a, \"\"b\"\"\nc\"\n2,\"\"\n,\\xab\n"
+        );
+    }
+
+    #[test]
+    fn test_split_statements() {
+        let (stmts, rest) = split_statements("SELECT 1; SELECT 2;\nSELECT");
+        assert_eq!(stmts, vec!["SELECT 1", "SELECT 2"]);
+        assert_eq!(rest, "SELECT");
+
+        let (stmts, rest) = split_statements("INSERT INTO t VALUES ('a;b', 'it''s;');");
+        assert_eq!(stmts, vec!["INSERT INTO t VALUES ('a;b', 'it''s;')"]);
+        assert_eq!(rest, "");
+
+        // the semicolon is still inside the unterminated string
+        let (stmts, rest) = split_statements("INSERT INTO t VALUES ('a;");
+        assert!(stmts.is_empty());
+        assert_eq!(rest, "INSERT INTO t VALUES ('a;");
+
+        let (stmts, rest) = split_statements(" ; ;");
+        assert!(stmts.is_empty());
+        assert_eq!(rest, "");
+
+        // comments don't end statements and aren't statements of their own
+        let (stmts, rest) = split_statements("SELECT 1 -- one; two\n;");
+        assert_eq!(stmts, vec!["SELECT 1 -- one; two"]);
+        assert_eq!(rest, "");
+        let (stmts, rest) = split_statements("SELECT /* a; b */ 2; -- done;\n/* c; */;");
+        assert_eq!(stmts, vec!["SELECT /* a; b */ 2"]);
+        assert_eq!(rest, "");
+        // an open block comment still waits for the rest
+        let (stmts, rest) = split_statements("SELECT 3; /* more;");
+        assert_eq!(stmts, vec!["SELECT 3"]);
+        assert_eq!(rest, "/* more;");
+    }
+
+    #[test]
+    fn test_vertical_output() {
+        let columns = vec!["id".to_string(), "comment".to_string()];
+        let rows = vec![
+            vec![Value::Integer(1), Value::Text("first\nsecond".into())],
+            vec![Value::Integer(2), Value::Null],
+        ];
+        let stars = "*".repeat(27);
+        assert_eq!(
+            format_vertical(&columns, &rows),
+            format!(
+                "{stars} 1. row {stars}\n     id: 1\ncomment: first\n         second\n\
+                 {stars} 2. row {stars}\n     id: 2\ncomment: NULL\n"
+            )
+        );
+        assert_eq!(format_vertical(&columns, &[]), "");
+
+        let (stmts, rest) = split_statements("SELECT 1\\G SELECT '\\G';");
+        assert_eq!(stmts, vec!["SELECT 1\\G", "SELECT '\\G'"]);
+        assert_eq!(rest, "");
+    }
+
+    #[test]
+    fn test_history_roundtrip() {
+        let dir = std::env::temp_dir().join("lunaris_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("client_history");
+        let _ = std::fs::remove_file(&path);
+
+        // nothing to load yet
+        let mut history = FileHistory::new();
+        load_history(&mut history, &path);
+        assert_eq!(history.len(), 0);
+
+        history.add("SELECT 1;").unwrap();
+        history.add(".tables").unwrap();
+        save_history(&mut history, &path);
+
+        let mut reloaded = FileHistory::new();
+        load_history(&mut reloaded, &path);
+        let entries: Vec<&String> = reloaded.iter().collect();
+        assert_eq!(entries, ["SELECT 1;", ".tables"]);
+        let _ = std::fs::remove_file(&path);
+
+        let env = |vars: &'static [(&str, &str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+        assert_eq!(
+            history_path(env(&[("HOME", "/home/u")])),
+            Some(PathBuf::from("/home/u/.lunaris_history"))
+        );
+        assert_eq!(
+            history_path(env(&[("HOME", "/home/u"), (HISTORY_ENV_VAR, "/tmp/h")])),
+            Some(PathBuf::from("/tmp/h"))
+        );
+        assert_eq!(history_path(env(&[(HISTORY_ENV_VAR, "")])), None);
+    }
+
+    #[test]
+    fn test_blobs_render_as_hex() {
+        let blob = Value::Blob(vec![0xde, 0xad, 0, 0x0f]);
+        assert_eq!(format_value(&blob), "X'dead000f'");
+        assert_eq!(value_to_json(&blob), serde_json::json!("dead000f"));
+    }
+
+    #[test]
+    fn test_float_rendering() {
+        assert_eq!(
+            format_value(&Value::Float(0.1 + 0.2)),
+            "0.30000000000000004"
+        );
+        assert_eq!(format_value(&Value::Float(4.0)), "4.0");
+        assert_eq!(format_value(&Value::Float(f64::NAN)), "NaN");
+    }
+
+    #[test]
+    fn test_elapsed_rendering() {
+        assert_eq!(format_elapsed(1234), "1.2ms");
+        assert_eq!(format_elapsed(40), "40µs");
+        assert_eq!(format_elapsed(3_400_000), "3.40s");
+    }
+
+    #[test]
+    fn test_error_output() {
+        let message = "Table not found: t";
+        assert_eq!(
+            format_error(OutputFormat::Table, ErrorCode::TableNotFound, message, None),
+            "Error [TABLE_NOT_FOUND]: Table not found: t"
+        );
+        let printed = format_error(OutputFormat::Json, ErrorCode::TableNotFound, message, None);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&printed).unwrap(),
+            serde_json::json!({
+                "error": { "code": "TABLE_NOT_FOUND", "message": message, "location": null }
+            })
+        );
+    }
+
+    #[test]
+    fn test_error_caret() {
+        let sql = "SELECT id\nFORM t";
+        let location = SourceLocation { line: 2, column: 1 };
+        assert_eq!(error_caret(sql, location).unwrap(), "  FORM t\n  ^");
+        let location = SourceLocation { line: 1, column: 8 };
+        assert_eq!(
+            error_caret(sql, location).unwrap(),
+            "  SELECT id\n         ^"
+        );
+        assert!(error_caret(sql, SourceLocation { line: 3, column: 1 }).is_none());
+    }
+
+    /// Accept a connection and answer its handshake.
+    async fn accept(listener: &tokio::net::TcpListener) -> TcpStream {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let hello: Handshake = protocol::recv_message(&mut stream).await.unwrap().unwrap();
+        protocol::send_message(&mut stream, &hello, Format::Json)
+            .await
+            .unwrap();
+        stream
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_after_drop() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = tokio::spawn(async move {
+            // the first connection goes away without answering, like a
+            // server being restarted
+            let mut stream = accept(&listener).await;
+            let first: Request = protocol::recv_message(&mut stream).await.unwrap().unwrap();
+            drop(stream);
+
+            let mut stream = accept(&listener).await;
+            let replayed: Request = protocol::recv_message(&mut stream).await.unwrap().unwrap();
+            let done = Response::Done {
+                message: "OK".into(),
+                kind: StatementKind::Mutation,
+                rows_affected: 0,
+                elapsed_micros: 0,
+            };
+            protocol::send_message(&mut stream, &done, Format::Binary)
+                .await
+                .unwrap();
+            (first, replayed)
+        });
+
+        let mut conn = Connection::open(&addr, Format::Binary).await.unwrap();
+        run_statement(
+            "SELECT 1",
+            Settings::default(),
+            &Params::default(),
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        let query = Request::Query {
+            sql: "SELECT 1".into(),
+        };
+        let (first, replayed) = server.await.unwrap();
+        assert_eq!(first, query);
+        assert_eq!(replayed, query);
+    }
+
+    #[test]
+    fn test_json_output_roundtrip() {
+        let columns = vec!["id".to_string(), "name".to_string(), "score".to_string()];
+        let rows = vec![
+            vec![
+                Value::Integer(1),
+                Value::Text("alice".into()),
+                Value::Float(9.5),
+            ],
+            vec![Value::Integer(2), Value::Null, Value::Boolean(false)],
+        ];
+
+        let printed = result_set_to_json(&columns, &rows).to_string();
+        let parsed: serde_json::Value = serde_json::from_str(&printed).unwrap();
+        assert_eq!(
+            parsed,
+            serde_json::json!([
+                { "id": 1, "name": "alice", "score": 9.5 },
+                { "id": 2, "name": null, "score": false },
+            ])
+        );
     }
 }
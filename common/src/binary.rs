@@ -0,0 +1,193 @@
+//! Compact binary encoding for protocol messages.
+//!
+//! Integers and floats are little-endian, strings and sequences carry a u32
+//! length prefix, and enums start with a one-byte variant tag.
+
+use std::io;
+
+use crate::value::Value;
+
+pub trait BinaryCodec: Sized {
+    fn encode(&self, out: &mut Vec<u8>);
+
+    /// Decode a value from the front of `input`, advancing it past the
+    /// consumed bytes.
+    fn decode(input: &mut &[u8]) -> io::Result<Self>;
+}
+
+pub fn to_bytes<T: BinaryCodec>(value: &T) -> Vec<u8> {
+    let mut out = Vec::new();
+    value.encode(&mut out);
+    out
+}
+
+/// Decode a value that must span all of `bytes`.
+pub fn from_bytes<T: BinaryCodec>(mut bytes: &[u8]) -> io::Result<T> {
+    let value = T::decode(&mut bytes)?;
+    if !bytes.is_empty() {
+        return Err(invalid(format!("{} trailing bytes", bytes.len())));
+    }
+    Ok(value)
+}
+
+pub(crate) fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+pub(crate) fn take<'a>(input: &mut &'a [u8], n: usize) -> io::Result<&'a [u8]> {
+    if input.len() < n {
+        return Err(invalid(format!(
+            "unexpected end of message: needed {n} bytes, {} left",
+            input.len()
+        )));
+    }
+    let (head, rest) = input.split_at(n);
+    *input = rest;
+    Ok(head)
+}
+
+pub(crate) fn read_u8(input: &mut &[u8]) -> io::Result<u8> {
+    Ok(take(input, 1)?[0])
+}
+
+pub(crate) fn read_len(input: &mut &[u8]) -> io::Result<usize> {
+    Ok(u32::from_le_bytes(take(input, 4)?.try_into().unwrap()) as usize)
+}
+
+pub(crate) fn write_len(out: &mut Vec<u8>, len: usize) {
+    out.extend_from_slice(&(len as u32).to_le_bytes());
+}
+
+impl BinaryCodec for String {
+    fn encode(&self, out: &mut Vec<u8>) {
+        write_len(out, self.len());
+        out.extend_from_slice(self.as_bytes());
+    }
+
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        let len = read_len(input)?;
+        let bytes = take(input, len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|e| invalid(e.to_string()))
+    }
+}
+
+impl<T: BinaryCodec> BinaryCodec for Vec<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        write_len(out, self.len());
+        for item in self {
+            item.encode(out);
+        }
+    }
+
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        let len = read_len(input)?;
+        // don't trust the length for the allocation, every item takes at
+        // least one byte
+        let mut items = Vec::with_capacity(len.min(input.len()));
+        for _ in 0..len {
+            items.push(T::decode(input)?);
+        }
+        Ok(items)
+    }
+}
+
+impl<T: BinaryCodec> BinaryCodec for Option<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            None => out.push(0),
+            Some(value) => {
+                out.push(1);
+                value.encode(out);
+            }
+        }
+    }
+
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        match read_u8(input)? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode(input)?)),
+            tag => Err(invalid(format!("bad option tag {tag}"))),
+        }
+    }
+}
+
+impl BinaryCodec for Value {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Value::Null => out.push(0),
+            Value::Integer(v) => {
+                out.push(1);
+                out.extend_from_slice(&v.to_le_bytes());
+            }
+            Value::Float(v) => {
+                out.push(2);
+                out.extend_from_slice(&v.to_le_bytes());
+            }
+            Value::Boolean(v) => {
+                out.push(3);
+                out.push(*v as u8);
+            }
+            Value::Text(v) => {
+                out.push(4);
+                v.encode(out);
+            }
+            Value::Blob(v) => {
+                out.push(5);
+                write_len(out, v.len());
+                out.extend_from_slice(v);
+            }
+        }
+    }
+
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        match read_u8(input)? {
+            0 => Ok(Value::Null),
+            1 => Ok(Value::Integer(i64::from_le_bytes(
+                take(input, 8)?.try_into().unwrap(),
+            ))),
+            2 => Ok(Value::Float(f64::from_le_bytes(
+                take(input, 8)?.try_into().unwrap(),
+            ))),
+            3 => Ok(Value::Boolean(read_u8(input)? != 0)),
+            4 => Ok(Value::Text(String::decode(input)?)),
+            5 => {
+                let len = read_len(input)?;
+                Ok(Value::Blob(take(input, len)?.to_vec()))
+            }
+            tag => Err(invalid(format!("bad value tag {tag}"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_roundtrip() {
+        let values = vec![
+            Value::Null,
+            Value::Integer(-42),
+            Value::Float(2.5),
+            Value::Boolean(true),
+            Value::Text("héllo".into()),
+            Value::Blob(vec![0, 0xde, 0, 0xad]),
+        ];
+        let bytes = to_bytes(&values);
+        assert_eq!(from_bytes::<Vec<Value>>(&bytes).unwrap(), values);
+    }
+
+    #[test]
+    fn test_malformed_input() {
+        let bytes = to_bytes(&Value::Text("abc".into()));
+        assert!(from_bytes::<Value>(&bytes[..bytes.len() - 1]).is_err());
+        assert!(from_bytes::<Value>(&[9]).is_err());
+
+        let mut bytes = to_bytes(&Value::Null);
+        bytes.push(0);
+        assert!(from_bytes::<Value>(&bytes).is_err());
+
+        // a huge sequence length fails on the missing items, not on allocation
+        assert!(from_bytes::<Vec<Value>>(&u32::MAX.to_le_bytes()).is_err());
+    }
+}
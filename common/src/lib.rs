@@ -1,2 +1,4 @@
+pub mod binary;
 pub mod protocol;
+pub mod timestamp;
 pub mod value;
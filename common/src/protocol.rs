@@ -1,61 +1,594 @@
+use crate::binary::{self, BinaryCodec};
 use crate::value::Value;
 use std::io;
 
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Request {
-    pub sql: String,
+/// Encoding of a frame's payload. Every frame is sent as
+/// [payload length: u32 big-endian][format tag: u8][payload].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Format {
+    #[default]
+    Json,
+    Binary,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl Format {
+    fn tag(self) -> u8 {
+        match self {
+            Format::Json => 0,
+            Format::Binary => 1,
+        }
+    }
+
+    fn from_tag(tag: u8) -> io::Result<Self> {
+        match tag {
+            0 => Ok(Format::Json),
+            1 => Ok(Format::Binary),
+            _ => Err(binary::invalid(format!("unknown format tag {tag}"))),
+        }
+    }
+}
+
+/// Optional first message of a connection, always sent as JSON. The client
+/// names the format it wants and the server answers with the one it will use
+/// for responses. Connections without a handshake get JSON responses.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Handshake {
+    pub format: Format,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub enum Request {
+    /// A single statement.
+    Query { sql: String },
+    /// Any number of semicolon separated statements, split by the server and
+    /// run one after another. A failing statement doesn't stop the rest.
+    Script { sql: String },
+    /// Check that the server is alive. Answered with `Pong` right away,
+    /// without touching the database.
+    Ping,
+}
+
+/// The server answers every statement with any number of `Rows` frames
+/// followed by either `Done` or `Error`. An `Error` may arrive after some rows
+/// have already been sent. The answers to the statements of a script are
+/// sent in order and followed by `ScriptDone`. A `Ping` gets a lone `Pong`.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub enum Response {
-    Ok(QueryResult),
-    Error { message: String },
+    /// A chunk of the result set. A query sends at least one, even when it
+    /// returns no rows, so the client learns the column names.
+    Rows(ResultSet),
+    Done {
+        message: String,
+        #[serde(default)]
+        kind: StatementKind,
+        /// Rows inserted or deleted by a mutation, 0 for queries.
+        #[serde(default)]
+        rows_affected: u64,
+        /// How long the server took to run the statement.
+        #[serde(default)]
+        elapsed_micros: u64,
+    },
+    Error {
+        code: ErrorCode,
+        message: String,
+        /// Where in the statement a syntax error was found.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        location: Option<SourceLocation>,
+    },
+    ScriptDone,
+    Pong,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct QueryResult {
-    pub message: String,
-    pub result_set: Option<ResultSet>,
+/// Whether a statement returns a result set or changes the database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StatementKind {
+    Query,
+    #[default]
+    Mutation,
+}
+
+/// A position in the SQL text, both counted from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceLocation {
+    pub line: u32,
+    pub column: u32,
+}
+
+impl std::fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+/// What kind of error a statement failed with, for clients that need to
+/// react to specific failures. Serialized as e.g. `"TABLE_NOT_FOUND"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    SyntaxError,
+    CompileError,
+    TableNotFound,
+    TableAlreadyExists,
+    IndexAlreadyExists,
+    ColumnNotFound,
+    TypeMismatch,
+    ValueCountMismatch,
+    DuplicateKey,
+    NotNullViolation,
+    UniqueViolation,
+    TransactionError,
+    ExecutionError,
+    RowTooLarge,
+    StorageError,
+    IoError,
+    ReadOnly,
+    ValueTooLong,
+    ImportError,
+    Timeout,
+    AmbiguousColumn,
+    InvalidName,
+}
+
+impl ErrorCode {
+    /// Every code, in the order of their binary tags.
+    const ALL: [ErrorCode; 22] = [
+        ErrorCode::SyntaxError,
+        ErrorCode::CompileError,
+        ErrorCode::TableNotFound,
+        ErrorCode::TableAlreadyExists,
+        ErrorCode::IndexAlreadyExists,
+        ErrorCode::ColumnNotFound,
+        ErrorCode::TypeMismatch,
+        ErrorCode::ValueCountMismatch,
+        ErrorCode::DuplicateKey,
+        ErrorCode::NotNullViolation,
+        ErrorCode::UniqueViolation,
+        ErrorCode::TransactionError,
+        ErrorCode::ExecutionError,
+        ErrorCode::RowTooLarge,
+        ErrorCode::StorageError,
+        ErrorCode::IoError,
+        ErrorCode::ReadOnly,
+        ErrorCode::ValueTooLong,
+        ErrorCode::ImportError,
+        ErrorCode::Timeout,
+        ErrorCode::AmbiguousColumn,
+        ErrorCode::InvalidName,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::SyntaxError => "SYNTAX_ERROR",
+            ErrorCode::CompileError => "COMPILE_ERROR",
+            ErrorCode::TableNotFound => "TABLE_NOT_FOUND",
+            ErrorCode::TableAlreadyExists => "TABLE_ALREADY_EXISTS",
+            ErrorCode::IndexAlreadyExists => "INDEX_ALREADY_EXISTS",
+            ErrorCode::ColumnNotFound => "COLUMN_NOT_FOUND",
+            ErrorCode::TypeMismatch => "TYPE_MISMATCH",
+            ErrorCode::ValueCountMismatch => "VALUE_COUNT_MISMATCH",
+            ErrorCode::DuplicateKey => "DUPLICATE_KEY",
+            ErrorCode::NotNullViolation => "NOT_NULL_VIOLATION",
+            ErrorCode::UniqueViolation => "UNIQUE_VIOLATION",
+            ErrorCode::TransactionError => "TRANSACTION_ERROR",
+            ErrorCode::ExecutionError => "EXECUTION_ERROR",
+            ErrorCode::RowTooLarge => "ROW_TOO_LARGE",
+            ErrorCode::StorageError => "STORAGE_ERROR",
+            ErrorCode::IoError => "IO_ERROR",
+            ErrorCode::ReadOnly => "READ_ONLY",
+            ErrorCode::ValueTooLong => "VALUE_TOO_LONG",
+            ErrorCode::ImportError => "IMPORT_ERROR",
+            ErrorCode::Timeout => "TIMEOUT",
+            ErrorCode::AmbiguousColumn => "AMBIGUOUS_COLUMN",
+            ErrorCode::InvalidName => "INVALID_NAME",
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct ResultSet {
     pub columns: Vec<String>,
+    /// Type of every column, `Unknown` where the server can't tell, e.g.
+    /// for `SELECT NULL`.
+    #[serde(default)]
+    pub column_types: Vec<ValueType>,
     pub rows: Vec<Vec<Value>>,
 }
 
-pub async fn send_message<T: Serialize, W: AsyncWriteExt + Unpin>(
+/// Type of a result column, as far as the schema and the expressions
+/// computing it tell. Serialized as e.g. `"integer"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ValueType {
+    Integer,
+    Float,
+    Boolean,
+    Text,
+    Blob,
+    /// Sent as ISO-8601 text.
+    Timestamp,
+    Unknown,
+}
+
+impl ValueType {
+    /// Every type, in the order of their binary tags.
+    const ALL: [ValueType; 7] = [
+        ValueType::Integer,
+        ValueType::Float,
+        ValueType::Boolean,
+        ValueType::Text,
+        ValueType::Blob,
+        ValueType::Timestamp,
+        ValueType::Unknown,
+    ];
+}
+
+impl BinaryCodec for Request {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Request::Query { sql } => {
+                out.push(0);
+                sql.encode(out);
+            }
+            Request::Script { sql } => {
+                out.push(1);
+                sql.encode(out);
+            }
+            Request::Ping => out.push(2),
+        }
+    }
+
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        match binary::read_u8(input)? {
+            0 => Ok(Request::Query {
+                sql: String::decode(input)?,
+            }),
+            1 => Ok(Request::Script {
+                sql: String::decode(input)?,
+            }),
+            2 => Ok(Request::Ping),
+            tag => Err(binary::invalid(format!("bad request tag {tag}"))),
+        }
+    }
+}
+
+impl BinaryCodec for Response {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Response::Rows(rows) => {
+                out.push(0);
+                rows.encode(out);
+            }
+            Response::Done {
+                message,
+                kind,
+                rows_affected,
+                elapsed_micros,
+            } => {
+                out.push(1);
+                message.encode(out);
+                out.push(*kind as u8);
+                out.extend_from_slice(&rows_affected.to_le_bytes());
+                out.extend_from_slice(&elapsed_micros.to_le_bytes());
+            }
+            Response::Error {
+                code,
+                message,
+                location,
+            } => {
+                out.push(2);
+                code.encode(out);
+                message.encode(out);
+                location.encode(out);
+            }
+            Response::ScriptDone => out.push(3),
+            Response::Pong => out.push(4),
+        }
+    }
+
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        match binary::read_u8(input)? {
+            0 => Ok(Response::Rows(ResultSet::decode(input)?)),
+            1 => Ok(Response::Done {
+                message: String::decode(input)?,
+                kind: match binary::read_u8(input)? {
+                    0 => StatementKind::Query,
+                    1 => StatementKind::Mutation,
+                    tag => return Err(binary::invalid(format!("bad statement kind {tag}"))),
+                },
+                rows_affected: u64::from_le_bytes(binary::take(input, 8)?.try_into().unwrap()),
+                elapsed_micros: u64::from_le_bytes(binary::take(input, 8)?.try_into().unwrap()),
+            }),
+            2 => Ok(Response::Error {
+                code: ErrorCode::decode(input)?,
+                message: String::decode(input)?,
+                location: Option::decode(input)?,
+            }),
+            3 => Ok(Response::ScriptDone),
+            4 => Ok(Response::Pong),
+            tag => Err(binary::invalid(format!("bad response tag {tag}"))),
+        }
+    }
+}
+
+impl BinaryCodec for ErrorCode {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(Self::ALL.iter().position(|c| c == self).unwrap() as u8);
+    }
+
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        let tag = binary::read_u8(input)?;
+        Self::ALL
+            .get(tag as usize)
+            .copied()
+            .ok_or_else(|| binary::invalid(format!("bad error code tag {tag}")))
+    }
+}
+
+impl BinaryCodec for SourceLocation {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.line.to_le_bytes());
+        out.extend_from_slice(&self.column.to_le_bytes());
+    }
+
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        let mut read_u32 = || -> io::Result<u32> {
+            Ok(u32::from_le_bytes(
+                binary::take(input, 4)?.try_into().unwrap(),
+            ))
+        };
+        Ok(Self {
+            line: read_u32()?,
+            column: read_u32()?,
+        })
+    }
+}
+
+impl BinaryCodec for ResultSet {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.columns.encode(out);
+        self.column_types.encode(out);
+        self.rows.encode(out);
+    }
+
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        Ok(Self {
+            columns: Vec::decode(input)?,
+            column_types: Vec::decode(input)?,
+            rows: Vec::decode(input)?,
+        })
+    }
+}
+
+impl BinaryCodec for ValueType {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(Self::ALL.iter().position(|t| t == self).unwrap() as u8);
+    }
+
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        let tag = binary::read_u8(input)?;
+        Self::ALL
+            .get(tag as usize)
+            .copied()
+            .ok_or_else(|| binary::invalid(format!("bad value type tag {tag}")))
+    }
+}
+
+impl BinaryCodec for Handshake {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(self.format.tag());
+    }
+
+    fn decode(input: &mut &[u8]) -> io::Result<Self> {
+        Ok(Self {
+            format: Format::from_tag(binary::read_u8(input)?)?,
+        })
+    }
+}
+
+/// A received frame whose payload hasn't been decoded yet.
+pub struct Frame {
+    pub format: Format,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub fn decode<T: DeserializeOwned + BinaryCodec>(&self) -> io::Result<T> {
+        match self.format {
+            Format::Json => serde_json::from_slice(&self.payload)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Format::Binary => binary::from_bytes(&self.payload),
+        }
+    }
+}
+
+pub fn encode<T: Serialize + BinaryCodec>(msg: &T, format: Format) -> io::Result<Vec<u8>> {
+    match format {
+        Format::Json => {
+            serde_json::to_vec(msg).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        }
+        Format::Binary => Ok(binary::to_bytes(msg)),
+    }
+}
+
+pub async fn send_message<T: Serialize + BinaryCodec, W: AsyncWriteExt + Unpin>(
     writer: &mut W,
     msg: &T,
+    format: Format,
 ) -> io::Result<()> {
-    let payload =
-        serde_json::to_vec(msg).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let payload = encode(msg, format)?;
     let len = payload.len() as u32;
     writer.write_all(&len.to_be_bytes()).await?;
+    writer.write_all(&[format.tag()]).await?;
     writer.write_all(&payload).await?;
     writer.flush().await?;
     Ok(())
 }
 
 /// Returns `None` on clean EOF (connection closed).
-pub async fn recv_message<T: for<'de> Deserialize<'de>, R: AsyncReadExt + Unpin>(
-    reader: &mut R,
-) -> io::Result<Option<T>> {
-    let mut len_buf = [0u8; 4];
-    match reader.read_exact(&mut len_buf).await {
+pub async fn recv_frame<R: AsyncReadExt + Unpin>(reader: &mut R) -> io::Result<Option<Frame>> {
+    let mut header = [0u8; 5];
+    match reader.read_exact(&mut header).await {
         Ok(_) => {}
         Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
         Err(e) => return Err(e),
     }
-    let len = u32::from_be_bytes(len_buf) as usize;
+    let len = u32::from_be_bytes(header[0..4].try_into().unwrap()) as usize;
+    let format = Format::from_tag(header[4])?;
 
     let mut payload = vec![0u8; len];
     reader.read_exact(&mut payload).await?;
+    Ok(Some(Frame { format, payload }))
+}
+
+/// Returns `None` on clean EOF (connection closed).
+pub async fn recv_message<T: DeserializeOwned + BinaryCodec, R: AsyncReadExt + Unpin>(
+    reader: &mut R,
+) -> io::Result<Option<T>> {
+    match recv_frame(reader).await? {
+        Some(frame) => frame.decode().map(Some),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let msg = serde_json::from_slice(&payload)
-        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-    Ok(Some(msg))
+    fn sample_result(rows: usize) -> Response {
+        Response::Rows(ResultSet {
+            columns: vec!["id".into(), "score".into(), "name".into(), "note".into()],
+            column_types: vec![
+                ValueType::Integer,
+                ValueType::Float,
+                ValueType::Text,
+                ValueType::Unknown,
+            ],
+            rows: (0..rows)
+                .map(|i| {
+                    vec![
+                        Value::Integer(i as i64),
+                        Value::Float(i as f64 * 0.5),
+                        Value::Text(format!("user{i}")),
+                        if i % 2 == 0 {
+                            Value::Null
+                        } else {
+                            Value::Boolean(true)
+                        },
+                    ]
+                })
+                .collect(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_roundtrip_both_formats() {
+        for format in [Format::Json, Format::Binary] {
+            let (mut client, mut server) = tokio::io::duplex(1 << 16);
+            let requests = [
+                Request::Query {
+                    sql: "SELECT * FROM t".into(),
+                },
+                Request::Script {
+                    sql: "DELETE FROM t; SELECT * FROM t;".into(),
+                },
+                Request::Ping,
+            ];
+            for request in requests {
+                send_message(&mut client, &request, format).await.unwrap();
+                let frame = recv_frame(&mut server).await.unwrap().unwrap();
+                assert_eq!(frame.format, format);
+                assert_eq!(frame.decode::<Request>().unwrap(), request);
+            }
+
+            let responses = [
+                sample_result(3),
+                Response::Done {
+                    message: "3 row(s) returned".into(),
+                    kind: StatementKind::Query,
+                    rows_affected: 0,
+                    elapsed_micros: 1200,
+                },
+                Response::Done {
+                    message: "2 row(s) inserted".into(),
+                    kind: StatementKind::Mutation,
+                    rows_affected: 2,
+                    elapsed_micros: 0,
+                },
+                Response::Error {
+                    code: ErrorCode::TableNotFound,
+                    message: "Table not found: t".into(),
+                    location: None,
+                },
+                Response::Error {
+                    code: ErrorCode::SyntaxError,
+                    message: "Parse error: found: FORM".into(),
+                    location: Some(SourceLocation { line: 2, column: 9 }),
+                },
+                Response::ScriptDone,
+                Response::Pong,
+            ];
+            for response in responses {
+                send_message(&mut server, &response, format).await.unwrap();
+                let received: Response = recv_message(&mut client).await.unwrap().unwrap();
+                assert_eq!(received, response);
+            }
+
+            drop(server);
+            assert!(recv_frame(&mut client).await.unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn test_error_codes() {
+        for code in ErrorCode::ALL {
+            assert_eq!(serde_json::to_value(code).unwrap(), code.as_str());
+            let bytes = binary::to_bytes(&code);
+            assert_eq!(binary::from_bytes::<ErrorCode>(&bytes).unwrap(), code);
+        }
+        assert!(binary::from_bytes::<ErrorCode>(&[ErrorCode::ALL.len() as u8]).is_err());
+    }
+
+    #[test]
+    fn test_binary_is_smaller_for_large_result_sets() {
+        let response = sample_result(10_000);
+        let json = encode(&response, Format::Json).unwrap();
+        let bin = encode(&response, Format::Binary).unwrap();
+        assert!(bin.len() < json.len());
+        assert_eq!(binary::from_bytes::<Response>(&bin).unwrap(), response);
+    }
+
+    /// Run with `cargo test -p lunaris-common --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_encode_decode_10k_rows() {
+        let response = sample_result(10_000);
+        for format in [Format::Json, Format::Binary] {
+            let start = std::time::Instant::now();
+            let bytes = encode(&response, format).unwrap();
+            let encoded = start.elapsed();
+            let frame = Frame {
+                format,
+                payload: bytes,
+            };
+            let start = std::time::Instant::now();
+            let decoded: Response = frame.decode().unwrap();
+            let parsed = start.elapsed();
+            assert_eq!(decoded, response);
+            println!(
+                "{format:?}: {} bytes, encode {encoded:?}, decode {parsed:?}",
+                frame.payload.len()
+            );
+        }
+    }
 }
@@ -0,0 +1,163 @@
+//! Conversion between ISO-8601 timestamps and milliseconds since the Unix
+//! epoch, the representation stored in TIMESTAMP columns. Only UTC is
+//! supported.
+
+const MILLIS_PER_DAY: i64 = 86_400_000;
+
+/// Parse `YYYY-MM-DD`, optionally followed by `THH:MM:SS`, fractional
+/// seconds and a trailing `Z`. A space may separate the date and time.
+pub fn parse_timestamp(s: &str) -> Option<i64> {
+    let s = s.strip_suffix('Z').unwrap_or(s);
+    let (date, time) = match s.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (s, None),
+    };
+
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = parse_digits(parts.next()?, 4)?;
+    let month: u32 = parse_digits(parts.next()?, 2)?;
+    let day: u32 = parse_digits(parts.next()?, 2)?;
+    if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+        return None;
+    }
+
+    let mut millis = days_from_civil(year, month, day) * MILLIS_PER_DAY;
+    if let Some(time) = time {
+        let (hms, fraction) = match time.split_once('.') {
+            Some((hms, fraction)) => (hms, Some(fraction)),
+            None => (time, None),
+        };
+        let mut parts = hms.splitn(3, ':');
+        let hour: i64 = parse_digits(parts.next()?, 2)?;
+        let minute: i64 = parse_digits(parts.next()?, 2)?;
+        let second: i64 = parse_digits(parts.next()?, 2)?;
+        if hour > 23 || minute > 59 || second > 59 {
+            return None;
+        }
+        millis += ((hour * 60 + minute) * 60 + second) * 1000;
+
+        if let Some(fraction) = fraction {
+            if fraction.is_empty() || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            // anything below a millisecond is dropped
+            let digits = &fraction[..fraction.len().min(3)];
+            let scale = 10i64.pow(3 - digits.len() as u32);
+            millis += digits.parse::<i64>().ok()? * scale;
+        }
+    }
+    Some(millis)
+}
+
+/// Render epoch milliseconds as `YYYY-MM-DDTHH:MM:SSZ`, with milliseconds
+/// only when they aren't zero.
+pub fn format_timestamp(millis: i64) -> String {
+    let days = millis.div_euclid(MILLIS_PER_DAY);
+    let in_day = millis.rem_euclid(MILLIS_PER_DAY);
+    let (year, month, day) = civil_from_days(days);
+    let (seconds, ms) = (in_day / 1000, in_day % 1000);
+    let time = format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    );
+    if ms == 0 {
+        format!("{year:04}-{month:02}-{day:02}T{time}Z")
+    } else {
+        format!("{year:04}-{month:02}-{day:02}T{time}.{ms:03}Z")
+    }
+}
+
+fn parse_digits<T: std::str::FromStr>(s: &str, len: usize) -> Option<T> {
+    if s.len() != len || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Days since 1970-01-01 in the proleptic Gregorian calendar, counted in
+// 400 year eras starting on March 1st so leap days come last.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_from_march = (month as i64 + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_from_march + 2) / 5 + 1) as u32;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timestamp_roundtrip() {
+        let cases = [
+            ("1970-01-01T00:00:00Z", 0),
+            ("2024-01-01T00:00:00Z", 1_704_067_200_000),
+            ("2024-02-29T12:30:15.250Z", 1_709_209_815_250),
+            ("1969-12-31T23:59:59.999Z", -1),
+            ("1900-03-01T00:00:00Z", -2_203_891_200_000),
+        ];
+        for (text, millis) in cases {
+            assert_eq!(parse_timestamp(text), Some(millis), "{text}");
+            assert_eq!(format_timestamp(millis), text);
+        }
+
+        assert_eq!(parse_timestamp("2024-01-01"), Some(1_704_067_200_000));
+        assert_eq!(
+            parse_timestamp("2024-01-01 00:00:01"),
+            Some(1_704_067_201_000)
+        );
+        assert_eq!(
+            parse_timestamp("2024-01-01T00:00:00.5"),
+            Some(1_704_067_200_500)
+        );
+    }
+
+    #[test]
+    fn test_invalid_timestamps() {
+        for text in [
+            "",
+            "2024-1-01",
+            "2023-02-29",
+            "2024-13-01",
+            "2024-01-01T24:00:00",
+            "2024-01-01T00:00",
+            "2024-01-01T00:00:00.",
+            "2024-01-01T00:00:00+02:00",
+            "yesterday",
+        ] {
+            assert_eq!(parse_timestamp(text), None, "{text}");
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
 use std::fmt;
+use std::hash::{Hash, Hasher};
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
 pub enum Value {
@@ -9,6 +11,7 @@ pub enum Value {
     Float(f64),
     Boolean(bool),
     Text(String),
+    Blob(Vec<u8>),
 }
 
 impl fmt::Display for Value {
@@ -16,21 +19,356 @@ impl fmt::Display for Value {
         match self {
             Value::Null => write!(f, "NULL"),
             Value::Integer(v) => write!(f, "{v}"),
-            Value::Float(v) => write!(f, "{v}"),
+            Value::Float(v) => write!(f, "{}", format_float(*v, None)),
             Value::Boolean(v) => write!(f, "{v}"),
             Value::Text(v) => write!(f, "{v}"),
+            Value::Blob(v) => write!(f, "X'{}'", to_hex(v)),
         }
     }
 }
 
-pub fn compare(a: &Value, b: &Value) -> Option<std::cmp::Ordering> {
+/// How text values are compared. Other values compare the same under every
+/// collation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Collation {
+    /// Byte by byte.
+    #[default]
+    Binary,
+    /// Ignoring the case of ASCII letters, like SQLite's NOCASE.
+    NoCase,
+}
+
+impl fmt::Display for Collation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Collation::Binary => write!(f, "BINARY"),
+            Collation::NoCase => write!(f, "NOCASE"),
+        }
+    }
+}
+
+pub fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    compare_collated(a, b, Collation::Binary)
+}
+
+/// `compare`, with text compared under `collation`.
+pub fn compare_collated(a: &Value, b: &Value, collation: Collation) -> Option<Ordering> {
     match (a, b) {
         (Value::Integer(a), Value::Integer(b)) => Some(a.cmp(b)),
         (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
-        (Value::Integer(a), Value::Float(b)) => (*a as f64).partial_cmp(b),
-        (Value::Float(a), Value::Integer(b)) => a.partial_cmp(&(*b as f64)),
-        (Value::Text(a), Value::Text(b)) => Some(a.cmp(b)),
+        (Value::Integer(a), Value::Float(b)) => {
+            (!b.is_nan()).then(|| compare_integer_float(*a, *b))
+        }
+        (Value::Float(a), Value::Integer(b)) => {
+            (!a.is_nan()).then(|| compare_integer_float(*b, *a).reverse())
+        }
+        (Value::Text(a), Value::Text(b)) => Some(match collation {
+            Collation::Binary => a.cmp(b),
+            Collation::NoCase => fold_case(a).cmp(fold_case(b)),
+        }),
         (Value::Boolean(a), Value::Boolean(b)) => Some(a.cmp(b)),
+        (Value::Blob(a), Value::Blob(b)) => Some(a.cmp(b)),
         _ => None,
     }
 }
+
+/// Order of any two values, for sorting. Unlike `compare` it never gives up:
+/// NULL sorts first, then booleans, numbers, text and blobs, and NaN sorts
+/// after every other number.
+pub fn total_compare(a: &Value, b: &Value) -> Ordering {
+    total_compare_collated(a, b, Collation::Binary)
+}
+
+/// `total_compare`, with text compared under `collation`.
+pub fn total_compare_collated(a: &Value, b: &Value, collation: Collation) -> Ordering {
+    fn rank(value: &Value) -> u8 {
+        match value {
+            Value::Null => 0,
+            Value::Boolean(_) => 1,
+            Value::Integer(_) | Value::Float(_) => 2,
+            Value::Text(_) => 3,
+            Value::Blob(_) => 4,
+        }
+    }
+
+    match (a, b) {
+        (Value::Float(a), Value::Float(b)) => a
+            .is_nan()
+            .cmp(&b.is_nan())
+            .then_with(|| a.partial_cmp(b).unwrap_or(Ordering::Equal)),
+        (Value::Integer(a), Value::Float(b)) => compare_integer_float(*a, *b),
+        (Value::Float(a), Value::Integer(b)) => compare_integer_float(*b, *a).reverse(),
+        _ => compare_collated(a, b, collation).unwrap_or_else(|| rank(a).cmp(&rank(b))),
+    }
+}
+
+/// A value ordered by `total_compare`, for use as a map or set key. Values
+/// that compare as equal are equal and hash the same: every NaN is one
+/// value, -0.0 equals 0.0, and a whole float equals the integer it holds.
+#[derive(Debug, Clone)]
+pub struct OrderedValue(pub Value);
+
+impl PartialEq for OrderedValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for OrderedValue {}
+
+impl PartialOrd for OrderedValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for OrderedValue {
+    fn cmp(&self, other: &Self) -> Ordering {
+        total_compare(&self.0, &other.0)
+    }
+}
+
+impl Hash for OrderedValue {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match &self.0 {
+            Value::Null => 0u8.hash(state),
+            Value::Boolean(v) => (1u8, v).hash(state),
+            Value::Integer(v) => (2u8, v).hash(state),
+            Value::Float(v) if v.is_nan() => 3u8.hash(state),
+            // -2^63 ..= 2^63 - 1, where `as` is exact for a whole float
+            Value::Float(v) if v.fract() == 0.0 && *v >= -(2f64.powi(63)) && *v < 2f64.powi(63) => {
+                (2u8, *v as i64).hash(state)
+            }
+            Value::Float(v) => (4u8, v.to_bits()).hash(state),
+            Value::Text(v) => (5u8, v).hash(state),
+            Value::Blob(v) => (6u8, v).hash(state),
+        }
+    }
+}
+
+fn fold_case(s: &str) -> impl Iterator<Item = u8> + '_ {
+    s.bytes().map(|b| b.to_ascii_lowercase())
+}
+
+// Exact even where the integer doesn't fit into a float, where `i as f64`
+// alone would round it onto a neighbouring float. Rounding keeps the order,
+// so only a tie needs a second look, and then the float is whole.
+fn compare_integer_float(i: i64, f: f64) -> Ordering {
+    if f.is_nan() {
+        return Ordering::Less;
+    }
+    match (i as f64).partial_cmp(&f) {
+        Some(Ordering::Equal) => (i as i128).cmp(&(f as i128)),
+        Some(order) => order,
+        None => unreachable!("neither side is NaN"),
+    }
+}
+
+/// Render a float with a decimal point even when it is whole (`1.0`), with
+/// `precision` digits after it if given. NaN and the infinities are written
+/// `NaN`, `Infinity` and `-Infinity`.
+pub fn format_float(v: f64, precision: Option<usize>) -> String {
+    if v.is_nan() {
+        return "NaN".into();
+    }
+    if v.is_infinite() {
+        return if v > 0.0 { "Infinity" } else { "-Infinity" }.into();
+    }
+    match precision {
+        Some(precision) => format!("{v:.precision$}"),
+        None if v.fract() == 0.0 => format!("{v:.1}"),
+        None => v.to_string(),
+    }
+}
+
+/// Lowercase hex digits of `bytes`, two per byte.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Bytes spelled by `hex`, two digits per byte in either case.
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_float() {
+        assert_eq!(format_float(0.1 + 0.2, None), "0.30000000000000004");
+        assert_eq!(format_float(0.1 + 0.2, Some(2)), "0.30");
+        assert_eq!(format_float(1.0, None), "1.0");
+        assert_eq!(format_float(-3.0, None), "-3.0");
+        assert_eq!(format_float(1.0, Some(3)), "1.000");
+        assert_eq!(format_float(1e20, None), "100000000000000000000.0");
+        assert_eq!(format_float(f64::NAN, Some(2)), "NaN");
+        assert_eq!(format_float(f64::INFINITY, None), "Infinity");
+        assert_eq!(format_float(f64::NEG_INFINITY, None), "-Infinity");
+        assert_eq!(Value::Float(2.0).to_string(), "2.0");
+    }
+
+    #[test]
+    fn test_mixed_number_compare() {
+        let int = Value::Integer;
+        let float = Value::Float;
+        // 2^53 + 1 rounds to 2^53 as a float
+        let big = (1i64 << 53) + 1;
+        assert_eq!(
+            compare(&int(big), &float(big as f64)),
+            Some(Ordering::Greater)
+        );
+        assert_eq!(compare(&float(big as f64), &int(big)), Some(Ordering::Less));
+        assert_eq!(
+            compare(&int(big - 1), &float(big as f64)),
+            Some(Ordering::Equal)
+        );
+        // the literal 9007199254740993.0 is the float 2^53
+        let literal: f64 = "9007199254740993.0".parse().unwrap();
+        assert_ne!(compare(&int(big), &float(literal)), Some(Ordering::Equal));
+        // i64::MAX as f64 is 2^63, one past it
+        assert_eq!(
+            compare(&int(i64::MAX), &float(i64::MAX as f64)),
+            Some(Ordering::Less)
+        );
+        assert_eq!(
+            compare(&int(i64::MIN), &float(i64::MIN as f64)),
+            Some(Ordering::Equal)
+        );
+        assert_eq!(compare(&int(3), &float(2.5)), Some(Ordering::Greater));
+        assert_eq!(compare(&int(-3), &float(-2.5)), Some(Ordering::Less));
+        assert_eq!(
+            compare(&int(0), &float(f64::INFINITY)),
+            Some(Ordering::Less)
+        );
+        assert_eq!(compare(&int(1), &float(f64::NAN)), None);
+        assert_eq!(compare(&float(f64::NAN), &int(1)), None);
+    }
+
+    #[test]
+    fn test_total_compare() {
+        let mut values = vec![
+            Value::Float(f64::NAN),
+            Value::Text("a".into()),
+            Value::Integer(2),
+            Value::Float(f64::INFINITY),
+            Value::Null,
+            Value::Float(1.5),
+            Value::Boolean(true),
+            Value::Integer(-7),
+            Value::Blob(vec![0]),
+            Value::Float(f64::NEG_INFINITY),
+        ];
+        values.sort_by(total_compare);
+        let rendered: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+        assert_eq!(
+            rendered,
+            [
+                "NULL",
+                "true",
+                "-Infinity",
+                "-7",
+                "1.5",
+                "2",
+                "Infinity",
+                "NaN",
+                "a",
+                "X'00'"
+            ]
+        );
+
+        let nan = Value::Float(f64::NAN);
+        assert_eq!(total_compare(&nan, &nan), Ordering::Equal);
+        assert_eq!(compare(&nan, &nan), None);
+        // 2^53 + 1 has no exact float, yet is still ordered after 2^53
+        let big = 1i64 << 53;
+        assert_eq!(
+            total_compare(&Value::Integer(big + 1), &Value::Float(big as f64)),
+            Ordering::Greater
+        );
+        assert_eq!(
+            total_compare(&Value::Integer(big), &Value::Float(big as f64)),
+            Ordering::Equal
+        );
+    }
+
+    #[test]
+    fn test_ordered_value() {
+        use std::collections::{BTreeSet, HashSet};
+
+        let values = [
+            Value::Integer(1),
+            Value::Float(1.0),
+            Value::Float(f64::NAN),
+            Value::Float(-f64::NAN),
+            Value::Float(0.0),
+            Value::Float(-0.0),
+            Value::Integer(0),
+            Value::Float(1.5),
+            Value::Null,
+            Value::Null,
+            Value::Text("1".into()),
+            Value::Float(i64::MAX as f64),
+            Value::Integer(i64::MAX),
+        ];
+        let hashed: HashSet<OrderedValue> = values.iter().cloned().map(OrderedValue).collect();
+        // `insert` keeps the first of equal values
+        let mut sorted = BTreeSet::new();
+        for value in &values {
+            sorted.insert(OrderedValue(value.clone()));
+        }
+        assert_eq!(hashed.len(), sorted.len());
+        let rendered: Vec<String> = sorted.iter().map(|v| v.0.to_string()).collect();
+        // 2^63 as a float is past i64::MAX, so the two stay apart
+        assert_eq!(
+            rendered,
+            [
+                "NULL",
+                "0.0",
+                "1",
+                "1.5",
+                "9223372036854775807",
+                "9223372036854775808.0",
+                "NaN",
+                "1"
+            ]
+        );
+        for value in &sorted {
+            assert!(hashed.contains(value), "{:?}", value.0);
+        }
+        assert_eq!(
+            OrderedValue(Value::Integer(1)),
+            OrderedValue(Value::Float(1.0))
+        );
+        assert_ne!(
+            OrderedValue(Value::Integer(1)),
+            OrderedValue(Value::Text("1".into()))
+        );
+    }
+
+    #[test]
+    fn test_nocase_compare() {
+        let text = |s: &str| Value::Text(s.into());
+        let nocase = |a: &str, b: &str| compare_collated(&text(a), &text(b), Collation::NoCase);
+        assert_eq!(nocase("Alice", "aLICE"), Some(Ordering::Equal));
+        assert_eq!(nocase("alice", "Bob"), Some(Ordering::Less));
+        assert_eq!(
+            compare(&text("alice"), &text("Bob")),
+            Some(Ordering::Greater)
+        );
+        // only ASCII letters are folded
+        assert_eq!(nocase("É", "é"), compare(&text("É"), &text("é")));
+        assert_eq!(nocase("a_", "A["), Some(Ordering::Greater));
+        assert_eq!(
+            compare_collated(&Value::Integer(1), &text("1"), Collation::NoCase),
+            None
+        );
+    }
+}
@@ -1,38 +1,88 @@
 use std::collections::HashMap;
 use std::path::Path;
 
+use crate::constants::{CATALOG_TABLE, STATUS_TABLE};
 use crate::error::{LunarisError, LunarisResult};
-use crate::storage::btree::BTreeTable;
+use crate::storage::btree::{BTreeTable, row_key};
 use crate::storage::cursor::Cursor;
+use crate::storage::index::IndexSchema;
 use crate::storage::row::TableSchema;
 
+/// Names no table or index may take: the catalog's own file and the
+/// virtual tables.
+const RESERVED_NAMES: [&str; 3] = ["catalog", CATALOG_TABLE, STATUS_TABLE];
+
+/// Check that `name` can name a table or index (`kind`), whose file is
+/// `<name>.db` in the data directory. Reserved names are refused in any
+/// case, since file systems may not tell them apart.
+pub fn validate_name(kind: &str, name: &str) -> LunarisResult<()> {
+    if let Some(reserved) = RESERVED_NAMES
+        .iter()
+        .find(|reserved| name.eq_ignore_ascii_case(reserved))
+    {
+        return Err(LunarisError::InvalidName(format!(
+            "{kind} name '{reserved}' is reserved"
+        )));
+    }
+    if name.is_empty()
+        || name
+            .chars()
+            .any(|c| matches!(c, '/' | '\\' | '.') || c.is_control())
+    {
+        return Err(LunarisError::InvalidName(format!(
+            "{kind} name '{name}' must not be empty or contain '/', '\\', '.' or control characters"
+        )));
+    }
+    Ok(())
+}
+
 pub struct Catalog {
     schemas: HashMap<String, TableSchema>,
+    indexes: HashMap<String, IndexSchema>,
+    /// Key of each table's and index's entry in `btree`, for rewriting it.
+    keys: HashMap<String, u64>,
     btree: BTreeTable,
 }
 
 impl Catalog {
     pub fn open(db_dir: &Path) -> LunarisResult<Self> {
-        let path = db_dir.join("catalog.db");
-        let mut btree = BTreeTable::open_or_create(&path)?;
+        Self::load(BTreeTable::open_or_create(&db_dir.join("catalog.db"))?)
+    }
+
+    pub fn open_read_only(db_dir: &Path) -> LunarisResult<Self> {
+        Self::load(BTreeTable::open_read_only(&db_dir.join("catalog.db"))?)
+    }
+
+    fn load(btree: BTreeTable) -> LunarisResult<Self> {
         let mut schemas = HashMap::new();
+        let mut indexes = HashMap::new();
+        let mut keys = HashMap::new();
 
         let dummy_schema = TableSchema::new("_catalog".into(), vec![]);
         let mut cursor = Cursor::new(dummy_schema);
-        if cursor.rewind(&mut btree)? {
+        if cursor.rewind(&btree)? {
             loop {
                 let data = btree.get_cell_data_at(&cursor)?;
-                if let Ok(schema) = serde_json::from_slice::<TableSchema>(data) {
+                if let Ok(schema) = serde_json::from_slice::<TableSchema>(&data) {
+                    keys.insert(schema.table_name.clone(), cursor.row_id(&btree)?);
                     schemas.insert(schema.table_name.clone(), schema);
+                } else if let Ok(index) = serde_json::from_slice::<IndexSchema>(&data) {
+                    keys.insert(index.index_name.clone(), cursor.row_id(&btree)?);
+                    indexes.insert(index.index_name.clone(), index);
                 }
 
-                if !cursor.next(&mut btree)? {
+                if !cursor.next(&btree)? {
                     break;
                 }
             }
         }
 
-        Ok(Self { schemas, btree })
+        Ok(Self {
+            schemas,
+            indexes,
+            keys,
+            btree,
+        })
     }
 
     pub fn get_schema(&self, table_name: &str) -> LunarisResult<TableSchema> {
@@ -52,18 +102,134 @@ impl Catalog {
         self.schemas.contains_key(table_name)
     }
 
+    /// Indexes of `table_name`, by name.
+    pub fn indexes_on(&self, table_name: &str) -> Vec<IndexSchema> {
+        let mut indexes: Vec<IndexSchema> = self
+            .indexes
+            .values()
+            .filter(|index| index.table_name == table_name)
+            .cloned()
+            .collect();
+        indexes.sort_by(|a, b| a.index_name.cmp(&b.index_name));
+        indexes
+    }
+
+    pub fn index_exists(&self, index_name: &str) -> bool {
+        self.indexes.contains_key(index_name)
+    }
+
+    /// Tables and indexes share one namespace, as each gets a `<name>.db`
+    /// file in the data directory.
+    pub fn check_name_free(&self, name: &str) -> LunarisResult<()> {
+        if self.schemas.contains_key(name) {
+            return Err(LunarisError::TableAlreadyExists(name.to_string()));
+        }
+        if self.indexes.contains_key(name) {
+            return Err(LunarisError::IndexAlreadyExists(name.to_string()));
+        }
+        Ok(())
+    }
+
     pub fn register_table(&mut self, schema: &TableSchema) -> LunarisResult<()> {
-        if self.schemas.contains_key(&schema.table_name) {
-            return Err(LunarisError::TableAlreadyExists(schema.table_name.clone()));
+        validate_name("table", &schema.table_name)?;
+        self.check_name_free(&schema.table_name)?;
+        let key = row_key(self.btree.next_row_id()?);
+        self.persist(key, schema)?;
+        self.keys.insert(schema.table_name.clone(), key);
+        self.schemas
+            .insert(schema.table_name.clone(), schema.clone());
+        Ok(())
+    }
+
+    /// Record the number of rows in a table. In a read-only catalog the
+    /// count is only kept in memory.
+    pub fn set_row_count(&mut self, table_name: &str, count: u64) -> LunarisResult<()> {
+        let schema = self
+            .schemas
+            .get_mut(table_name)
+            .ok_or_else(|| LunarisError::TableNotFound(table_name.to_string()))?;
+        schema.row_count = Some(count);
+        if self.btree.pager.is_read_only() {
+            return Ok(());
         }
+        let schema = schema.clone();
+        let key = self.keys[table_name];
+        self.btree.delete(key)?;
+        self.persist(key, &schema)
+    }
 
-        let key = self.btree.next_row_id();
-        let data = serde_json::to_vec(schema).map_err(|e| LunarisError::Storage(e.to_string()))?;
-        self.btree.insert(key, &data)?;
-        self.btree.flush()?;
+    /// Store the table's entry without a row count, keeping the count in
+    /// memory. Until `set_row_count` stores one again, `Database::open`
+    /// takes the table for uncounted and counts its rows.
+    pub fn clear_stored_row_count(&mut self, table_name: &str) -> LunarisResult<()> {
+        let mut schema = self.get_schema(table_name)?;
+        if self.btree.pager.is_read_only() {
+            return Ok(());
+        }
+        schema.row_count = None;
+        let key = self.keys[table_name];
+        self.btree.delete(key)?;
+        self.persist(key, &schema)
+    }
 
+    /// Replace the schema of an existing table, as `ALTER TABLE` does.
+    pub fn update_table(&mut self, schema: &TableSchema) -> LunarisResult<()> {
+        let key = *self
+            .keys
+            .get(&schema.table_name)
+            .ok_or_else(|| LunarisError::TableNotFound(schema.table_name.clone()))?;
+        self.btree.delete(key)?;
+        self.persist(key, schema)?;
         self.schemas
             .insert(schema.table_name.clone(), schema.clone());
         Ok(())
     }
+
+    /// Replace the definition of an existing index.
+    pub fn update_index(&mut self, index: &IndexSchema) -> LunarisResult<()> {
+        let key = self.keys[&index.index_name];
+        self.btree.delete(key)?;
+        self.persist(key, index)?;
+        self.indexes.insert(index.index_name.clone(), index.clone());
+        Ok(())
+    }
+
+    pub fn register_index(&mut self, index: &IndexSchema) -> LunarisResult<()> {
+        validate_name("index", &index.index_name)?;
+        self.check_name_free(&index.index_name)?;
+        let key = row_key(self.btree.next_row_id()?);
+        self.persist(key, index)?;
+        self.keys.insert(index.index_name.clone(), key);
+        self.indexes.insert(index.index_name.clone(), index.clone());
+        Ok(())
+    }
+
+    /// Remove a table and every index on it in one write of the catalog.
+    /// Returns the removed indexes.
+    pub fn drop_table(&mut self, table_name: &str) -> LunarisResult<Vec<IndexSchema>> {
+        if !self.schemas.contains_key(table_name) {
+            return Err(LunarisError::TableNotFound(table_name.to_string()));
+        }
+        let indexes = self.indexes_on(table_name);
+        let names = std::iter::once(table_name).chain(indexes.iter().map(|i| &i.index_name[..]));
+        for name in names {
+            if let Some(key) = self.keys.remove(name) {
+                self.btree.delete(key)?;
+            }
+            self.indexes.remove(name);
+        }
+        self.schemas.remove(table_name);
+        self.btree.flush()?;
+        Ok(indexes)
+    }
+
+    pub fn flush(&mut self) -> LunarisResult<()> {
+        self.btree.flush()
+    }
+
+    fn persist<T: serde::Serialize>(&mut self, key: u64, entry: &T) -> LunarisResult<()> {
+        let data = serde_json::to_vec(entry).map_err(|e| LunarisError::Storage(e.to_string()))?;
+        self.btree.insert(key, &data)?;
+        self.btree.flush()
+    }
 }
@@ -0,0 +1,225 @@
+//! Server settings, taken from command-line arguments and environment
+//! variables. Arguments win over the environment.
+
+use std::path::PathBuf;
+
+use crate::constants::{DEFAULT_MAX_RESULT_ROWS, DEFAULT_STATEMENT_TIMEOUT_MS};
+
+const DEFAULT_HOST: &str = "127.0.0.1";
+const DEFAULT_PORT: u16 = 7435;
+
+pub const USAGE: &str = "\
+Usage: lunaris-server [OPTIONS]
+
+Options:
+  --host <HOST>   Address to listen on [env: LUNARIS_HOST] [default: 127.0.0.1]
+  --port <PORT>   Port to listen on [env: LUNARIS_PORT] [default: 7435]
+  --read-only     Serve the database without modifying it [env: LUNARIS_READ_ONLY=1]
+  --max-result-rows <N>
+                  Rows a statement may return before it fails [env: LUNARIS_MAX_RESULT_ROWS]
+                  [default: 1000000]
+  --statement-timeout <MS>
+                  Milliseconds a statement may run before it fails
+                  [env: LUNARIS_STATEMENT_TIMEOUT_MS] [default: 30000]
+  -h, --help      Print this help
+
+Environment:
+  LUNARIS_DATA_DIR  Directory holding the database files [default: ~/.lunaris]";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub host: String,
+    pub port: u16,
+    pub data_dir: PathBuf,
+    pub read_only: bool,
+    pub max_result_rows: u64,
+    pub statement_timeout_ms: u64,
+}
+
+/// What the server was asked to do.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Run(Config),
+    Help,
+}
+
+impl Config {
+    /// Address to bind, as `host:port`. IPv6 hosts get their brackets.
+    pub fn addr(&self) -> String {
+        match self.host.contains(':') {
+            true => format!("[{}]:{}", self.host, self.port),
+            false => format!("{}:{}", self.host, self.port),
+        }
+    }
+}
+
+/// Read the settings from `args` (without the program name) and the
+/// environment lookup `env`. Errors are meant to be shown to the user as is.
+pub fn parse<I, E>(args: I, env: E) -> Result<Command, String>
+where
+    I: IntoIterator<Item = String>,
+    E: Fn(&str) -> Option<String>,
+{
+    let mut host = env("LUNARIS_HOST");
+    let mut port = env("LUNARIS_PORT").map(|v| (v, "LUNARIS_PORT"));
+    let mut read_only = env("LUNARIS_READ_ONLY").is_some_and(|v| v == "1" || v == "true");
+    let mut max_rows = env("LUNARIS_MAX_RESULT_ROWS").map(|v| (v, "LUNARIS_MAX_RESULT_ROWS"));
+    let mut timeout =
+        env("LUNARIS_STATEMENT_TIMEOUT_MS").map(|v| (v, "LUNARIS_STATEMENT_TIMEOUT_MS"));
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let (name, inline) = match arg.split_once('=') {
+            Some((name, value)) => (name.to_string(), Some(value.to_string())),
+            None => (arg, None),
+        };
+        let mut value = || {
+            inline
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("{name} needs a value"))
+        };
+        match name.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--host" => host = Some(value()?),
+            "--port" => port = Some((value()?, "--port")),
+            "--read-only" => read_only = true,
+            "--max-result-rows" => max_rows = Some((value()?, "--max-result-rows")),
+            "--statement-timeout" => timeout = Some((value()?, "--statement-timeout")),
+            _ => return Err(format!("unknown option '{name}', see --help")),
+        }
+    }
+
+    let host = host.unwrap_or_else(|| DEFAULT_HOST.to_string());
+    if host.is_empty() {
+        return Err("the host must not be empty".into());
+    }
+    let port = match port {
+        Some((value, source)) => value.parse().map_err(|_| {
+            format!("invalid port '{value}' in {source}: expected a number from 0 to 65535")
+        })?,
+        None => DEFAULT_PORT,
+    };
+    let max_result_rows = match max_rows {
+        Some((value, source)) => positive(&value, source, "row limit")?,
+        None => DEFAULT_MAX_RESULT_ROWS,
+    };
+    let statement_timeout_ms = match timeout {
+        Some((value, source)) => positive(&value, source, "statement timeout")?,
+        None => DEFAULT_STATEMENT_TIMEOUT_MS,
+    };
+    let data_dir = env("LUNARIS_DATA_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| home_dir(&env).join(".lunaris"));
+
+    Ok(Command::Run(Config {
+        host,
+        port,
+        data_dir,
+        read_only,
+        max_result_rows,
+        statement_timeout_ms,
+    }))
+}
+
+fn positive(value: &str, source: &str, what: &str) -> Result<u64, String> {
+    match value.parse() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!(
+            "invalid {what} '{value}' in {source}: expected a positive number"
+        )),
+    }
+}
+
+fn home_dir(env: &impl Fn(&str) -> Option<String>) -> PathBuf {
+    env("HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_with(args: &[&str], env: &[(&str, &str)]) -> Result<Command, String> {
+        let args = args.iter().map(|a| a.to_string());
+        parse(args, |name| {
+            env.iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.to_string())
+        })
+    }
+
+    fn config(command: Result<Command, String>) -> Config {
+        match command {
+            Ok(Command::Run(config)) => config,
+            other => panic!("expected a config, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_listen_address() {
+        let defaults = config(parse_with(&[], &[("HOME", "/home/u")]));
+        assert_eq!(defaults.addr(), "127.0.0.1:7435");
+        assert_eq!(defaults.data_dir, PathBuf::from("/home/u/.lunaris"));
+        assert!(!defaults.read_only);
+
+        let env = [("LUNARIS_HOST", "0.0.0.0"), ("LUNARIS_PORT", "9000")];
+        assert_eq!(config(parse_with(&[], &env)).addr(), "0.0.0.0:9000");
+        // arguments win over the environment
+        let args = ["--port", "9001", "--host=::1"];
+        assert_eq!(config(parse_with(&args, &env)).addr(), "[::1]:9001");
+
+        assert_eq!(parse_with(&["--help"], &[]), Ok(Command::Help));
+        assert!(config(parse_with(&["--read-only"], &[])).read_only);
+    }
+
+    #[test]
+    fn test_max_result_rows() {
+        let defaults = config(parse_with(&[], &[]));
+        assert_eq!(defaults.max_result_rows, DEFAULT_MAX_RESULT_ROWS);
+        let env = [("LUNARIS_MAX_RESULT_ROWS", "500")];
+        assert_eq!(config(parse_with(&[], &env)).max_result_rows, 500);
+        let args = ["--max-result-rows=20"];
+        assert_eq!(config(parse_with(&args, &env)).max_result_rows, 20);
+        assert_eq!(
+            parse_with(&["--max-result-rows", "0"], &[]),
+            Err("invalid row limit '0' in --max-result-rows: expected a positive number".into())
+        );
+    }
+
+    #[test]
+    fn test_statement_timeout() {
+        let defaults = config(parse_with(&[], &[]));
+        assert_eq!(defaults.statement_timeout_ms, DEFAULT_STATEMENT_TIMEOUT_MS);
+        let env = [("LUNARIS_STATEMENT_TIMEOUT_MS", "500")];
+        assert_eq!(config(parse_with(&[], &env)).statement_timeout_ms, 500);
+        let args = ["--statement-timeout", "20"];
+        assert_eq!(config(parse_with(&args, &env)).statement_timeout_ms, 20);
+        assert_eq!(
+            parse_with(&[], &[("LUNARIS_STATEMENT_TIMEOUT_MS", "1s")]),
+            Err(
+                "invalid statement timeout '1s' in LUNARIS_STATEMENT_TIMEOUT_MS: \
+                 expected a positive number"
+                    .into()
+            )
+        );
+    }
+
+    #[test]
+    fn test_invalid_port() {
+        assert_eq!(
+            parse_with(&[], &[("LUNARIS_PORT", "http")]),
+            Err("invalid port 'http' in LUNARIS_PORT: expected a number from 0 to 65535".into())
+        );
+        assert_eq!(
+            parse_with(&["--port", "70000"], &[]),
+            Err("invalid port '70000' in --port: expected a number from 0 to 65535".into())
+        );
+        assert_eq!(
+            parse_with(&["--port"], &[]),
+            Err("--port needs a value".into())
+        );
+        assert!(parse_with(&["--verbose"], &[]).is_err());
+    }
+}
@@ -2,9 +2,43 @@ pub const PAGE_SIZE: usize = 4096;
 pub const PAGE_HEADER_SIZE: usize = 16;
 pub const CELL_AREA_SIZE: usize = PAGE_SIZE - PAGE_HEADER_SIZE;
 pub const CELL_POINTER_SIZE: usize = 2;
-pub const META_PAGE_SIZE: usize = 16;
+pub const META_PAGE_SIZE: usize = 32;
+/// Leaf cells larger than this spill their row data into overflow pages.
+pub const MAX_LOCAL_CELL_SIZE: usize = CELL_AREA_SIZE / 4;
+/// Largest row a table accepts, in bytes. Rows past `MAX_LOCAL_CELL_SIZE`
+/// live in overflow pages, but the whole row is still read into memory.
+pub const MAX_ROW_SIZE: usize = 1 << 30;
+/// Number of pages each pager keeps in memory before evicting (4 MB).
+pub const DEFAULT_PAGE_CACHE_CAPACITY: usize = 1024;
+/// Pages a transaction may change in one file before it fails (256 MB).
+/// They stay in memory until commit, past the page cache capacity.
+pub const MAX_TRANSACTION_PAGES: usize = 1 << 16;
+/// Once the write-ahead log grows past this many bytes it is emptied after
+/// the next commit has been applied.
+pub const WAL_CHECKPOINT_SIZE: u64 = 1 << 20;
 
 pub const VM_STARTING_REGISTERS: usize = 64;
+/// Rows a single statement may return before it is aborted.
+pub const DEFAULT_MAX_RESULT_ROWS: u64 = 1_000_000;
+
+/// How long a statement may run before it is aborted.
+pub const DEFAULT_STATEMENT_TIMEOUT_MS: u64 = 30_000;
+/// Instructions the VM runs between checks of the statement timeout.
+pub const TIMEOUT_CHECK_INTERVAL: u64 = 1024;
+
+/// Read-only virtual table listing the tables of the database.
+pub const CATALOG_TABLE: &str = "lunaris_tables";
+/// Read-only virtual table with statistics of the running server.
+pub const STATUS_TABLE: &str = "lunaris_status";
+
+/// How long a statement waits for another session's transaction to finish
+/// before giving up on writing.
+pub const BUSY_TIMEOUT_MS: u64 = 5000;
+
+/// Result rows sent per `Response::Rows` frame.
+pub const STREAM_CHUNK_ROWS: usize = 1000;
+/// Frames a statement may run ahead of the connection writing them out.
+pub const STREAM_CHANNEL_CAPACITY: usize = 4;
 
 // "LUNA"
 pub const MAGIC: u32 = 0x4C554E41;
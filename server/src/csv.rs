@@ -0,0 +1,150 @@
+//! Reading CSV files for `COPY ... FROM`. Fields follow RFC 4180: quotes
+//! around a field allow delimiters, line breaks and doubled `""` quotes
+//! inside it. An empty field without quotes is NULL, `""` is an empty string.
+
+use lunaris_common::timestamp;
+use lunaris_common::value::{self, Value};
+
+use crate::error::{LunarisError, LunarisResult};
+use crate::storage::row::{ColumnDef, ColumnType};
+
+/// One line of the file, or more if a quoted field spans line breaks.
+#[derive(Debug, PartialEq)]
+pub struct Record {
+    /// Line the record starts on, counting from 1.
+    pub line: usize,
+    pub fields: Vec<Option<String>>,
+}
+
+/// Split `text` into records. Empty lines are skipped.
+pub fn parse(text: &str, delimiter: char) -> LunarisResult<Vec<Record>> {
+    let mut records = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+
+    while chars.peek().is_some() {
+        let start = line;
+        let mut fields = Vec::new();
+        loop {
+            let mut field = String::new();
+            let mut quoted = false;
+            if chars.peek() == Some(&'"') {
+                chars.next();
+                quoted = true;
+                loop {
+                    match chars.next() {
+                        Some('"') if chars.peek() == Some(&'"') => {
+                            chars.next();
+                            field.push('"');
+                        }
+                        Some('"') => break,
+                        Some(c) => {
+                            line += (c == '\n') as usize;
+                            field.push(c);
+                        }
+                        None => return Err(import_error(start, "unterminated quoted field")),
+                    }
+                }
+            }
+            while let Some(&c) = chars.peek() {
+                if c == delimiter || c == '\n' || c == '\r' {
+                    break;
+                }
+                if quoted {
+                    return Err(import_error(line, "unexpected text after a quoted field"));
+                }
+                field.push(c);
+                chars.next();
+            }
+            fields.push((quoted || !field.is_empty()).then_some(field));
+
+            match chars.next() {
+                Some(c) if c == delimiter => continue,
+                Some('\r') if chars.peek() == Some(&'\n') => {
+                    chars.next();
+                }
+                _ => {}
+            }
+            line += 1;
+            break;
+        }
+        if fields != [None] {
+            records.push(Record {
+                line: start,
+                fields,
+            });
+        }
+    }
+    Ok(records)
+}
+
+/// Convert a field to the value stored in `col`. The error says why the
+/// field doesn't fit, without the line, which the caller knows.
+pub fn field_value(field: Option<&str>, col: &ColumnDef) -> Result<Value, String> {
+    let Some(field) = field else {
+        return Ok(Value::Null);
+    };
+    let value = match &col.col_type {
+        ColumnType::Integer => field.parse().ok().map(Value::Integer),
+        ColumnType::Float => field.parse().ok().map(Value::Float),
+        ColumnType::Boolean => match field.to_ascii_lowercase().as_str() {
+            "true" | "t" | "1" => Some(Value::Boolean(true)),
+            "false" | "f" | "0" => Some(Value::Boolean(false)),
+            _ => None,
+        },
+        ColumnType::Varchar(_) | ColumnType::Text => Some(Value::Text(field.to_string())),
+        ColumnType::Blob(_) => field
+            .strip_prefix("\\x")
+            .and_then(value::from_hex)
+            .map(Value::Blob),
+        ColumnType::Timestamp => timestamp::parse_timestamp(field).map(Value::Integer),
+    };
+    value.ok_or_else(|| {
+        format!(
+            "expected {} for column '{}', got '{field}'",
+            col.col_type, col.name
+        )
+    })
+}
+
+pub fn import_error(line: usize, message: impl Into<String>) -> LunarisError {
+    LunarisError::Import {
+        line,
+        message: message.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_records() {
+        let text = "1,plain,\r\n2,\"with, comma\",\"\"\n\n3,\"two\nlines and \"\"quotes\"\"\",x\n";
+        let field = |s: &str| Some(s.to_string());
+        assert_eq!(
+            parse(text, ',').unwrap(),
+            vec![
+                Record {
+                    line: 1,
+                    fields: vec![field("1"), field("plain"), None],
+                },
+                Record {
+                    line: 2,
+                    fields: vec![field("2"), field("with, comma"), field("")],
+                },
+                Record {
+                    line: 4,
+                    fields: vec![field("3"), field("two\nlines and \"quotes\""), field("x")],
+                },
+            ]
+        );
+
+        for (text, line) in [("a\n\"open", 2), ("a\n\"b\"c", 2)] {
+            assert!(
+                matches!(parse(text, ','), Err(LunarisError::Import { line: l, .. }) if l == line),
+                "{text:?}"
+            );
+        }
+    }
+}
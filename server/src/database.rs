@@ -1,49 +1,500 @@
 use crate::catalog::Catalog;
+use crate::constants::{BUSY_TIMEOUT_MS, DEFAULT_MAX_RESULT_ROWS, DEFAULT_STATEMENT_TIMEOUT_MS};
 use crate::error::{LunarisError, LunarisResult};
-use crate::storage::btree::BTreeTable;
-use crate::storage::row::{serialize_row, TableSchema};
+use crate::storage;
+use crate::storage::btree::{self, BTreeTable};
+use crate::storage::cursor::Cursor;
+use crate::storage::index::{self, IndexSchema};
+use crate::storage::row::{ColumnType, TableSchema, serialize_row};
+use crate::vm::bytecode::{Program, StatementKind};
 use crate::vm::compiler;
-use crate::vm::parser;
+use crate::vm::parser::{self, AdminStatement};
 use crate::vm::vm::{ExecutionResult, Lvm};
-use lunaris_common::value::Value;
+use lunaris_common::protocol::ValueType;
+use lunaris_common::timestamp;
+use lunaris_common::value::{self, Collation, Value};
 use sqlparser::ast::Statement;
-use std::collections::HashMap;
-use std::path::PathBuf;
-use std::sync::{Mutex, RwLock};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use std::sync::{Condvar, Mutex, RwLock};
+use std::time::{Duration, Instant};
 
 pub struct Database {
     db_dir: PathBuf,
     catalog: RwLock<Catalog>,
-    tables: RwLock<HashMap<String, Mutex<BTreeTable>>>,
+    /// Open table and index files. Queries share a table's lock; writes,
+    /// commits and rollbacks take it exclusively.
+    tables: RwLock<HashMap<String, RwLock<BTreeTable>>>,
+    /// Session currently allowed to write. Only one session writes at a time;
+    /// the slot is released when its transaction commits or rolls back.
+    writer: Mutex<Option<u64>>,
+    writer_released: Condvar,
+    next_session_id: AtomicU64,
+    /// Set by `open_read_only`; every write fails with `ReadOnly`.
+    read_only: bool,
+    max_result_rows: u64,
+    statement_timeout: Duration,
+    opened: Instant,
+}
+
+/// Per-connection state. Outside of `BEGIN ... COMMIT` every statement runs
+/// in its own transaction, which is committed once the statement succeeds
+/// and rolled back if it fails.
+pub struct Session {
+    id: u64,
+    explicit: bool,
+    /// Tables written by the current transaction.
+    written: HashSet<String>,
+    /// Rows the current transaction added to (or, if negative, removed from)
+    /// each table, applied to the catalog's counts on commit.
+    row_deltas: HashMap<String, i64>,
+    /// Views of the tables the running statement reads, taken on first use
+    /// and kept until it ends, so its cursors all see the same commit.
+    /// `None` between statements.
+    snapshots: Option<HashMap<String, BTreeTable>>,
+}
+
+impl Session {
+    pub fn in_transaction(&self) -> bool {
+        self.explicit
+    }
 }
 
 impl Database {
-    pub fn open(db_dir: PathBuf) -> LunarisResult<Self> {
-        std::fs::create_dir_all(&db_dir)?;
+    /// Open the database in `db_dir`, creating the directory if needed.
+    /// New directories and files are private to the owner on Unix.
+    pub fn open(db_dir: impl Into<PathBuf>) -> LunarisResult<Self> {
+        let db_dir = db_dir.into();
+        storage::create_private_dir(&db_dir)?;
 
         let catalog = Catalog::open(&db_dir)?;
-        let tables = HashMap::new();
-        let db = Self {
+        let db = Self::new(db_dir, catalog, false);
+        db.count_uncounted_rows()?;
+        Ok(db)
+    }
+
+    /// Open an existing database for queries only. Nothing in `db_dir` is
+    /// created or modified, and statements that would write fail with
+    /// `LunarisError::ReadOnly`.
+    pub fn open_read_only(db_dir: impl Into<PathBuf>) -> LunarisResult<Self> {
+        let db_dir = db_dir.into();
+        let catalog = Catalog::open_read_only(&db_dir)?;
+        let db = Self::new(db_dir, catalog, true);
+        db.count_uncounted_rows()?;
+        Ok(db)
+    }
+
+    /// Count the rows of tables created before row counts were kept, once.
+    fn count_uncounted_rows(&self) -> LunarisResult<()> {
+        let mut catalog = self.catalog.write().unwrap();
+        for name in catalog.table_names() {
+            let schema = catalog.get_schema(&name)?;
+            if schema.row_count.is_some() {
+                continue;
+            }
+            let count = self.with_committed_table(&name, |tree| {
+                let mut cursor = Cursor::new(schema);
+                let mut count = 0;
+                let mut has_row = cursor.rewind(tree)?;
+                while has_row {
+                    count += 1;
+                    has_row = cursor.next(tree)?;
+                }
+                Ok(count)
+            })?;
+            catalog.set_row_count(&name, count)?;
+        }
+        Ok(())
+    }
+
+    fn new(db_dir: PathBuf, catalog: Catalog, read_only: bool) -> Self {
+        Self {
             db_dir,
             catalog: RwLock::new(catalog),
-            tables: RwLock::new(tables),
-        };
+            tables: RwLock::new(HashMap::new()),
+            writer: Mutex::new(None),
+            writer_released: Condvar::new(),
+            next_session_id: AtomicU64::new(1),
+            read_only,
+            max_result_rows: DEFAULT_MAX_RESULT_ROWS,
+            statement_timeout: Duration::from_millis(DEFAULT_STATEMENT_TIMEOUT_MS),
+            opened: Instant::now(),
+        }
+    }
 
-        Ok(db)
+    /// Abort statements that return more than `max` rows, so a runaway query
+    /// can't exhaust the memory of the server or its client.
+    pub fn set_max_result_rows(&mut self, max: u64) {
+        self.max_result_rows = max;
+    }
+
+    pub fn max_result_rows(&self) -> u64 {
+        self.max_result_rows
+    }
+
+    /// Abort statements whose program runs for longer than `timeout`.
+    pub fn set_statement_timeout(&mut self, timeout: Duration) {
+        self.statement_timeout = timeout;
+    }
+
+    pub fn statement_timeout(&self) -> Duration {
+        self.statement_timeout
+    }
+
+    pub fn new_session(&self) -> Session {
+        Session {
+            id: self.next_session_id.fetch_add(1, AtomicOrdering::Relaxed),
+            explicit: false,
+            written: HashSet::new(),
+            row_deltas: HashMap::new(),
+            snapshots: None,
+        }
     }
 
-    pub fn execute_sql(&self, sql: &str) -> LunarisResult<ExecutionResult> {
-        let stmt = parser::parse_sql(sql)?;
+    /// Rows in a table, including the changes of the session's own
+    /// transaction but not those of other sessions.
+    pub fn row_count(&self, session: &Session, table_name: &str) -> LunarisResult<u64> {
+        let committed = self.get_schema(table_name)?.row_count.unwrap_or(0);
+        let delta = session.row_deltas.get(table_name).copied().unwrap_or(0);
+        Ok(committed.saturating_add_signed(delta))
+    }
 
-        if matches!(stmt, Statement::ShowTables { .. }) {
-            return self.execute_show_tables();
+    /// Statistics for monitoring, as (stat, object, value) rows: the seconds
+    /// since the database was opened, how many table and index files are
+    /// open, the pages their pagers cache, and the pages of every open file.
+    /// Files are opened on first use, so tables nobody has touched yet
+    /// aren't counted.
+    pub fn status(&self) -> Vec<[Value; 3]> {
+        let mut files = Vec::new();
+        let mut cached_pages = 0;
+        {
+            let tables = self.tables.read().unwrap();
+            for (name, lock) in tables.iter() {
+                let tree = lock.read().unwrap();
+                cached_pages += tree.pager.cached_pages();
+                files.push((name.clone(), tree.pager.page_count));
+            }
         }
+        files.sort();
 
         let catalog = self.catalog.read().unwrap();
-        let program = compiler::compile(&stmt, &catalog)?;
-        drop(catalog);
+        let open_tables = files
+            .iter()
+            .filter(|(name, _)| catalog.table_exists(name))
+            .count();
+        let stat = |stat: &str, value: u64| {
+            [
+                Value::Text(stat.into()),
+                Value::Null,
+                Value::Integer(value as i64),
+            ]
+        };
+        let mut rows = vec![
+            stat("uptime_seconds", self.opened.elapsed().as_secs()),
+            stat("open_tables", open_tables as u64),
+            stat("open_indexes", (files.len() - open_tables) as u64),
+            stat("cached_pages", cached_pages as u64),
+        ];
+        for (name, pages) in files {
+            rows.push([
+                Value::Text("pages".into()),
+                Value::Text(name),
+                Value::Integer(pages as i64),
+            ]);
+        }
+        rows
+    }
+
+    /// Roll back whatever the session left uncommitted, e.g. on disconnect.
+    pub fn close_session(&self, session: &mut Session) {
+        let _ = self.rollback(session);
+    }
+
+    /// Clean up after a statement of `session` panicked. The panic poisons
+    /// the locks it held; rolling back the session's tables undoes whatever
+    /// it left half done, so the locks are cleared and the writer slot is
+    /// released for the other sessions.
+    pub fn recover_session(&self, session: &mut Session) {
+        self.catalog.clear_poison();
+        self.tables.clear_poison();
+        for table in self.tables.read().unwrap().values() {
+            table.clear_poison();
+        }
+        self.writer.clear_poison();
+        session.snapshots = None;
+        self.close_session(session);
+    }
+
+    /// Run a statement, or several separated by semicolons, returning the
+    /// result of the last one. Outside of `BEGIN ... COMMIT` the statements
+    /// share one transaction, committed once they all succeed and rolled back
+    /// if one fails. Schema changes aren't transactional: one commits what
+    /// the statements before it wrote, and takes effect on its own.
+    pub fn execute_sql(&self, session: &mut Session, sql: &str) -> LunarisResult<ExecutionResult> {
+        self.run_sql(session, sql, |_| Lvm::new())
+    }
+
+    /// Run every statement of a script in order. A failing statement doesn't
+    /// stop the ones after it; its error takes its place in the results.
+    pub fn execute_script(
+        &self,
+        session: &mut Session,
+        sql: &str,
+    ) -> LunarisResult<Vec<LunarisResult<ExecutionResult>>> {
+        let statements = parser::split_statements(sql)?;
+        Ok(statements
+            .iter()
+            .map(|stmt| self.execute_sql(session, stmt))
+            .collect())
+    }
+
+    /// Like `execute_sql`, but passes every result row of the last statement
+    /// to `sink` together with the result column names and types as soon as
+    /// it is produced. The returned result has no rows.
+    pub fn execute_sql_streaming<F>(
+        &self,
+        session: &mut Session,
+        sql: &str,
+        mut sink: F,
+    ) -> LunarisResult<ExecutionResult>
+    where
+        F: FnMut(&[String], &[ValueType], Vec<Value>) -> LunarisResult<()>,
+    {
+        let mut columns = Vec::new();
+        let mut types = Vec::new();
+        self.run_sql(session, sql, |program| {
+            columns = program.result_columns.clone();
+            types = program.result_types.clone();
+            Lvm::streaming(Box::new(|row| sink(&columns, &types, row)))
+        })
+    }
+
+    fn run_sql<'a, F>(
+        &self,
+        session: &mut Session,
+        sql: &str,
+        make_vm: F,
+    ) -> LunarisResult<ExecutionResult>
+    where
+        F: FnOnce(&Program) -> Lvm<'a>,
+    {
+        let statements = parser::split_statements(sql)?;
+        let [earlier @ .., last] = statements.as_slice() else {
+            return self.run_statement(session, sql, false, make_vm);
+        };
+        if earlier.is_empty() {
+            return self.run_statement(session, sql, false, make_vm);
+        }
+
+        let start = Instant::now();
+        let result = earlier
+            .iter()
+            .try_for_each(|stmt| {
+                self.run_statement(session, stmt, true, |_| Lvm::new())
+                    .map(drop)
+            })
+            .and_then(|()| self.run_statement(session, last, true, make_vm));
+        if !session.explicit {
+            match &result {
+                Ok(_) => self.commit(session)?,
+                Err(_) => self.rollback(session)?,
+            }
+        }
+        let mut result = result?;
+        result.elapsed_micros = start.elapsed().as_micros() as u64;
+        Ok(result)
+    }
+
+    /// Run one statement. Unless it is part of a `batch`, which commits as a
+    /// whole, it commits on success and rolls back on failure outside of
+    /// `BEGIN ... COMMIT`.
+    fn run_statement<'a, F>(
+        &self,
+        session: &mut Session,
+        sql: &str,
+        batch: bool,
+        make_vm: F,
+    ) -> LunarisResult<ExecutionResult>
+    where
+        F: FnOnce(&Program) -> Lvm<'a>,
+    {
+        let start = Instant::now();
+        if parser::is_empty_statement(sql) {
+            return Ok(ExecutionResult {
+                columns: Vec::new(),
+                column_types: Vec::new(),
+                rows: Vec::new(),
+                rows_affected: 0,
+                rows_examined: 0,
+                elapsed_micros: start.elapsed().as_micros() as u64,
+                message: "empty statement".into(),
+                kind: StatementKind::Other,
+            });
+        }
+        let stmt = match parser::parse_admin(sql)? {
+            Some(admin) => {
+                let mut result = self.execute_admin(session, admin)?;
+                result.elapsed_micros = start.elapsed().as_micros() as u64;
+                return Ok(result);
+            }
+            None => parser::parse_sql(sql)?,
+        };
+
+        let mut result = match &stmt {
+            Statement::ShowTables { .. } => self.execute_show_tables()?,
+            Statement::ExplainTable { table_name, .. } => {
+                self.execute_describe(&table_name.to_string())?
+            }
+            _ => {
+                let catalog = self.catalog.read().unwrap();
+                let program = compiler::compile(&stmt, &catalog)?;
+                drop(catalog);
+
+                let schema_change = matches!(
+                    stmt,
+                    Statement::CreateTable(_)
+                        | Statement::CreateIndex(_)
+                        | Statement::AlterTable { .. }
+                        | Statement::Drop { .. }
+                );
+                if batch && schema_change && !session.explicit {
+                    self.commit(session)?;
+                }
+                session.snapshots = Some(HashMap::new());
+                let result = make_vm(&program).execute(self, session, &program);
+                session.snapshots = None;
+                if !session.explicit && (!batch || schema_change) {
+                    match &result {
+                        Ok(_) => self.commit(session)?,
+                        Err(_) => self.rollback(session)?,
+                    }
+                }
+                result?
+            }
+        };
+        result.elapsed_micros = start.elapsed().as_micros() as u64;
+        Ok(result)
+    }
+
+    pub fn begin(&self, session: &mut Session) -> LunarisResult<()> {
+        if session.explicit {
+            return Err(LunarisError::Transaction(
+                "a transaction is already active".into(),
+            ));
+        }
+        session.explicit = true;
+        Ok(())
+    }
+
+    /// Write every table touched by the session's transaction to disk, then
+    /// the new row counts to the catalog.
+    pub fn commit(&self, session: &mut Session) -> LunarisResult<()> {
+        session.explicit = false;
+        let written = std::mem::take(&mut session.written);
+        let deltas = std::mem::take(&mut session.row_deltas);
+        let result = self
+            .clear_stored_row_counts(&deltas)
+            .and_then(|()| {
+                written
+                    .iter()
+                    .try_for_each(|table| self.with_table_mut(table, |tree| tree.commit()))
+            })
+            .and_then(|()| self.apply_row_deltas(&deltas));
+        self.release_writer(session);
+        result
+    }
+
+    /// The catalog and the tables are separate files, so their commits can't
+    /// be one write. Until the new counts are stored, the catalog holds none
+    /// for the tables whose rows change, and a crash in between leaves them
+    /// to be counted again on open rather than miscounted.
+    fn clear_stored_row_counts(&self, deltas: &HashMap<String, i64>) -> LunarisResult<()> {
+        let mut catalog = self.catalog.write().unwrap();
+        for (table, delta) in deltas {
+            if *delta != 0 {
+                catalog.clear_stored_row_count(table)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_row_deltas(&self, deltas: &HashMap<String, i64>) -> LunarisResult<()> {
+        let mut catalog = self.catalog.write().unwrap();
+        for (table, delta) in deltas {
+            if *delta == 0 {
+                continue;
+            }
+            let count = catalog.get_schema(table)?.row_count.unwrap_or(0);
+            catalog.set_row_count(table, count.saturating_add_signed(*delta))?;
+        }
+        Ok(())
+    }
+
+    /// Discard the in-memory changes of the session's transaction.
+    pub fn rollback(&self, session: &mut Session) -> LunarisResult<()> {
+        session.explicit = false;
+        session.row_deltas.clear();
+        let written = std::mem::take(&mut session.written);
+        let result = written.iter().try_for_each(|table| {
+            self.with_table_mut(table, |tree| {
+                tree.rollback();
+                Ok(())
+            })
+        });
+        self.release_writer(session);
+        result
+    }
+
+    /// Run `f` on a table the session is about to modify, taking the writer
+    /// slot and starting the table's transaction on first use.
+    pub fn with_table_for_write<F, R>(
+        &self,
+        session: &mut Session,
+        table_name: &str,
+        f: F,
+    ) -> LunarisResult<R>
+    where
+        F: FnOnce(&mut BTreeTable) -> LunarisResult<R>,
+    {
+        self.acquire_writer(session)?;
+        let first_write = session.written.insert(table_name.to_string());
+        self.with_table_mut(table_name, |tree| {
+            if first_write {
+                tree.begin();
+            }
+            f(tree)
+        })
+    }
+
+    fn acquire_writer(&self, session: &Session) -> LunarisResult<()> {
+        if self.read_only {
+            return Err(LunarisError::ReadOnly);
+        }
+        let writer = self.writer.lock().unwrap();
+        let (mut writer, _) = self
+            .writer_released
+            .wait_timeout_while(writer, Duration::from_millis(BUSY_TIMEOUT_MS), |w| {
+                w.is_some_and(|id| id != session.id)
+            })
+            .unwrap();
+        if writer.is_some_and(|id| id != session.id) {
+            return Err(LunarisError::Transaction(
+                "database is locked by another transaction".into(),
+            ));
+        }
+        *writer = Some(session.id);
+        Ok(())
+    }
 
-        Lvm::new().execute(self, &program)
+    fn release_writer(&self, session: &Session) {
+        let mut writer = self.writer.lock().unwrap();
+        if *writer == Some(session.id) {
+            *writer = None;
+            self.writer_released.notify_all();
+        }
     }
 
     fn execute_show_tables(&self) -> LunarisResult<ExecutionResult> {
@@ -57,9 +508,134 @@ impl Database {
 
         Ok(ExecutionResult {
             columns: vec!["table_name".to_string()],
+            column_types: vec![ValueType::Text],
             rows,
             rows_affected: 0,
+            rows_examined: 0,
+            elapsed_micros: 0,
             message: format!("{row_count} table(s)"),
+            kind: StatementKind::Query,
+        })
+    }
+
+    fn execute_admin(
+        &self,
+        session: &mut Session,
+        admin: AdminStatement,
+    ) -> LunarisResult<ExecutionResult> {
+        let (columns, column_types, rows, message, kind) = match admin {
+            AdminStatement::Backup(dest) => {
+                self.backup(session, Path::new(&dest))?;
+                (
+                    Vec::new(),
+                    Vec::new(),
+                    Vec::new(),
+                    format!("Backup written to '{dest}'"),
+                    StatementKind::Other,
+                )
+            }
+            AdminStatement::Dump(table) => {
+                let rows: Vec<Vec<Value>> = self
+                    .dump(session, &table)?
+                    .into_iter()
+                    .map(|sql| vec![Value::Text(sql)])
+                    .collect();
+                let message = format!("{} statement(s)", rows.len());
+                (
+                    vec!["sql".to_string()],
+                    vec![ValueType::Text],
+                    rows,
+                    message,
+                    StatementKind::Query,
+                )
+            }
+        };
+        Ok(ExecutionResult {
+            columns,
+            column_types,
+            rows,
+            rows_affected: 0,
+            rows_examined: 0,
+            elapsed_micros: 0,
+            message,
+            kind,
+        })
+    }
+
+    /// Statements that recreate the table: its CREATE TABLE, an INSERT per
+    /// row and a CREATE INDEX per index.
+    pub fn dump(&self, session: &mut Session, table_name: &str) -> LunarisResult<Vec<String>> {
+        let schema = self.get_schema(table_name)?;
+        let mut statements = vec![schema.to_create_sql()];
+        self.with_table_ref(session, table_name, |tree| {
+            let mut cursor = Cursor::new(schema.clone());
+            let mut has_row = cursor.rewind(tree)?;
+            while has_row {
+                statements.push(schema.to_insert_sql(&cursor.read_row(tree)?));
+                has_row = cursor.next(tree)?;
+            }
+            Ok(())
+        })?;
+        for index in self.indexes_on(table_name) {
+            let column = &schema.columns[index.column].name;
+            statements.push(format!(
+                "CREATE INDEX {} ON {table_name} ({column})",
+                index.index_name
+            ));
+        }
+        Ok(statements)
+    }
+
+    /// One row per column of the table: name, type, nullability, whether it
+    /// is the primary key and the default used when an INSERT omits it.
+    fn execute_describe(&self, table_name: &str) -> LunarisResult<ExecutionResult> {
+        let schema = self.get_schema(table_name)?;
+        let rows: Vec<Vec<Value>> = schema
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, col)| {
+                let default = match (&col.col_type, &col.default) {
+                    (ColumnType::Timestamp, Some(Value::Integer(millis))) => {
+                        Value::Text(timestamp::format_timestamp(*millis))
+                    }
+                    (_, default) => default.clone().unwrap_or(Value::Null),
+                };
+                vec![
+                    Value::Text(col.name.clone()),
+                    Value::Text(col.col_type.to_string()),
+                    Value::Boolean(col.nullable),
+                    Value::Boolean(
+                        schema.primary_key == Some(i) || schema.key_columns.contains(&i),
+                    ),
+                    default,
+                ]
+            })
+            .collect();
+
+        Ok(ExecutionResult {
+            columns: [
+                "column_name",
+                "type",
+                "nullable",
+                "is_primary_key",
+                "default",
+            ]
+            .map(String::from)
+            .to_vec(),
+            column_types: vec![
+                ValueType::Text,
+                ValueType::Text,
+                ValueType::Boolean,
+                ValueType::Boolean,
+                ValueType::Unknown,
+            ],
+            rows,
+            rows_affected: 0,
+            rows_examined: 0,
+            elapsed_micros: 0,
+            message: format!("{} column(s)", schema.columns.len()),
+            kind: StatementKind::Query,
         })
     }
 
@@ -69,36 +645,494 @@ impl Database {
     }
 
     pub fn create_table(&self, schema: &TableSchema) -> LunarisResult<()> {
+        if self.read_only {
+            return Err(LunarisError::ReadOnly);
+        }
         let mut catalog = self.catalog.write().unwrap();
         catalog.register_table(schema)?;
 
-        let path = self.db_dir.join(format!("{}.db", schema.table_name));
-        let btree = BTreeTable::open_or_create(&path)?;
-
         let mut tables = self.tables.write().unwrap();
-        tables.insert(schema.table_name.clone(), Mutex::new(btree));
+        let key_index =
+            (!schema.key_columns.is_empty()).then(|| key_index_name(&schema.table_name));
+        for name in std::iter::once(schema.table_name.clone()).chain(key_index) {
+            let btree = BTreeTable::open_or_create(&self.db_dir.join(format!("{name}.db")))?;
+            tables.insert(name, RwLock::new(btree));
+        }
         Ok(())
     }
 
-    pub fn insert_row(&self, table_name: &str, key: u64, values: &[Value]) -> LunarisResult<()> {
+    pub fn insert_row(
+        &self,
+        session: &mut Session,
+        table_name: &str,
+        rowid: i64,
+        values: &[Value],
+    ) -> LunarisResult<()> {
         let schema = self.get_schema(table_name)?;
         let data = serialize_row(&schema, values)?;
+        let key = btree::row_key(rowid);
+        let key_index = key_index_name(table_name);
+        let key_value =
+            (!schema.key_columns.is_empty()).then(|| Value::Blob(schema.encode_key(values)));
 
-        self.with_table_mut(table_name, |tree| {
+        if let Some(value) = &key_value {
+            self.with_table_for_write(session, &key_index, |tree| {
+                check_key(tree, &schema, value, None)
+            })?;
+        }
+        let probed = self.check_unique_indexed(session, &schema, values, None)?;
+        self.with_table_for_write(session, table_name, |tree| {
+            check_unique(tree, &schema, values, None, &probed)?;
             tree.insert(key, &data)?;
-            tree.flush()
-        })
+            tree.reserve_row_id(rowid);
+            Ok(())
+        })?;
+        *session
+            .row_deltas
+            .entry(table_name.to_string())
+            .or_default() += 1;
+
+        if let Some(value) = &key_value {
+            self.with_table_for_write(session, &key_index, |tree| {
+                index::insert_entry(tree, value, key)
+            })?;
+        }
+        for index in self.indexes_on(table_name) {
+            self.with_table_for_write(session, &index.index_name, |tree| {
+                index::insert_entry(tree, &values[index.column], key)
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Delete the row under `cursor` along with its index entries. Returns
+    /// false if no rows follow it.
+    pub fn delete_row(
+        &self,
+        session: &mut Session,
+        table_name: &str,
+        cursor: &mut Cursor,
+    ) -> LunarisResult<bool> {
+        let schema = self.get_schema(table_name)?;
+        let indexes = self.indexes_on(table_name);
+        let deleted = match indexes.is_empty() && schema.key_columns.is_empty() {
+            true => None,
+            false => Some(self.with_table_ref(session, table_name, |tree| {
+                Ok((cursor.row_id(tree)?, cursor.read_row(tree)?))
+            })?),
+        };
+        let has_more =
+            self.with_table_for_write(session, table_name, |tree| cursor.delete_current(tree))?;
+        *session
+            .row_deltas
+            .entry(table_name.to_string())
+            .or_default() -= 1;
+
+        if let Some((key, row)) = deleted {
+            if !schema.key_columns.is_empty() {
+                let value = Value::Blob(schema.encode_key(&row));
+                self.with_table_for_write(session, &key_index_name(table_name), |tree| {
+                    index::remove_entry(tree, &value, key)
+                })?;
+            }
+            for index in indexes {
+                self.with_table_for_write(session, &index.index_name, |tree| {
+                    index::remove_entry(tree, &row[index.column], key)
+                })?;
+            }
+        }
+        Ok(has_more)
+    }
+
+    /// Replace the row under `cursor` with `values`, keeping its rowid, and
+    /// move its index entries along. The cursor stays on the row.
+    pub fn update_row(
+        &self,
+        session: &mut Session,
+        table_name: &str,
+        cursor: &mut Cursor,
+        values: &[Value],
+    ) -> LunarisResult<()> {
+        let schema = self.get_schema(table_name)?;
+        let data = serialize_row(&schema, values)?;
+        let (key, old) = self.with_table_for_write(session, table_name, |tree| {
+            Ok((cursor.row_id(tree)?, cursor.read_row(tree)?))
+        })?;
+        let key_index = key_index_name(table_name);
+        let key_values = (!schema.key_columns.is_empty()).then(|| {
+            let old = Value::Blob(schema.encode_key(&old));
+            (old, Value::Blob(schema.encode_key(values)))
+        });
+        if let Some((_, new)) = &key_values {
+            self.with_table_for_write(session, &key_index, |tree| {
+                check_key(tree, &schema, new, Some(key))
+            })?;
+        }
+        let probed = self.check_unique_indexed(session, &schema, values, Some(key))?;
+        self.with_table_for_write(session, table_name, |tree| {
+            check_unique(tree, &schema, values, Some(key), &probed)?;
+            cursor.update_current(tree, &data)
+        })?;
+
+        if let Some((old, new)) = &key_values
+            && old != new
+        {
+            self.with_table_for_write(session, &key_index, |tree| {
+                index::remove_entry(tree, old, key)?;
+                index::insert_entry(tree, new, key)
+            })?;
+        }
+        for index in self.indexes_on(table_name) {
+            let (old, new) = (&old[index.column], &values[index.column]);
+            if old != new {
+                self.with_table_for_write(session, &index.index_name, |tree| {
+                    index::remove_entry(tree, old, key)?;
+                    index::insert_entry(tree, new, key)
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove every row of the table and empty its indexes. Returns the
+    /// number of rows removed.
+    pub fn truncate_table(&self, session: &mut Session, table_name: &str) -> LunarisResult<u64> {
+        let rows = self.with_table_for_write(session, table_name, |tree| tree.truncate())?;
+        // whatever was added or removed before, the table is now empty
+        let committed = self.get_schema(table_name)?.row_count.unwrap_or(0);
+        session
+            .row_deltas
+            .insert(table_name.to_string(), -(committed as i64));
+        for name in self.index_names(table_name)? {
+            self.with_table_for_write(session, &name, |tree| tree.truncate())?;
+        }
+        Ok(rows)
+    }
+
+    /// Build an index over the rows already in its table and register it.
+    pub fn create_index(&self, session: &mut Session, index: &IndexSchema) -> LunarisResult<()> {
+        // rows written by an open transaction must not end up in the index
+        self.acquire_writer(session)?;
+        let mut catalog = self.catalog.write().unwrap();
+        catalog.check_name_free(&index.index_name)?;
+        let schema = catalog.get_schema(&index.table_name)?;
+
+        let path = self.db_dir.join(format!("{}.db", index.index_name));
+        let mut index_tree = BTreeTable::open_or_create(&path)?;
+        // anything already in the file is left over from a failed build
+        index_tree.truncate()?;
+
+        self.with_table_ref(session, &index.table_name, |tree| {
+            let mut cursor = Cursor::new(schema);
+            let mut has_row = cursor.rewind(tree)?;
+            while has_row {
+                let key = cursor.row_id(tree)?;
+                let value = cursor.column(tree, index.column)?;
+                index::insert_entry(&mut index_tree, &value, key)?;
+                has_row = cursor.next(tree)?;
+            }
+            Ok(())
+        })?;
+        index_tree.flush()?;
+        catalog.register_index(index)?;
+
+        let mut tables = self.tables.write().unwrap();
+        tables.insert(index.index_name.clone(), RwLock::new(index_tree));
+        Ok(())
+    }
+
+    /// Give a table the columns of `schema` and rewrite its rows to match.
+    /// `sources` holds the current position of each new column, or None for
+    /// an added column, which takes its default (or NULL) in every row.
+    pub fn alter_table(
+        &self,
+        session: &mut Session,
+        schema: &TableSchema,
+        sources: &[Option<usize>],
+    ) -> LunarisResult<()> {
+        self.acquire_writer(session)?;
+        let mut catalog = self.catalog.write().unwrap();
+        let table_name = &schema.table_name;
+        let current = catalog.get_schema(table_name)?;
+        let mut indexes = catalog.indexes_on(table_name);
+        for index in &mut indexes {
+            index.column = sources
+                .iter()
+                .position(|s| *s == Some(index.column))
+                .ok_or_else(|| {
+                    LunarisError::Compile(format!(
+                        "column of index '{}' can't be dropped",
+                        index.index_name
+                    ))
+                })?;
+        }
+
+        self.with_table_for_write(session, table_name, |tree| {
+            let mut rows = Vec::new();
+            let mut cursor = Cursor::new(current.clone());
+            let mut has_row = cursor.rewind(tree)?;
+            while has_row {
+                rows.push((cursor.row_id(tree)?, cursor.read_row(tree)?));
+                has_row = cursor.next(tree)?;
+            }
+            for (i, col) in schema.columns.iter().enumerate() {
+                // every existing row gets the same value in a new column
+                if sources[i].is_none()
+                    && col.unique
+                    && col.default.as_ref().is_some_and(|v| *v != Value::Null)
+                    && rows.len() > 1
+                {
+                    return Err(LunarisError::UniqueConstraint(col.name.clone()));
+                }
+            }
+            for (key, row) in rows {
+                let values: Vec<Value> = sources
+                    .iter()
+                    .zip(&schema.columns)
+                    .map(|(source, col)| match source {
+                        Some(i) => row[*i].clone(),
+                        None => col.default.clone().unwrap_or(Value::Null),
+                    })
+                    .collect();
+                let data = serialize_row(schema, &values)?;
+                tree.delete(key)?;
+                tree.insert(key, &data)?;
+            }
+            Ok(())
+        })?;
+
+        let mut schema = schema.clone();
+        schema.row_count = current.row_count;
+        catalog.update_table(&schema)?;
+        for index in indexes {
+            catalog.update_index(&index)?;
+        }
+        Ok(())
+    }
+
+    /// Remove a table along with its indexes, their catalog entries and
+    /// their files.
+    pub fn drop_table(&self, session: &mut Session, table_name: &str) -> LunarisResult<()> {
+        // a transaction still writing to the table has to finish first
+        self.acquire_writer(session)?;
+        let mut catalog = self.catalog.write().unwrap();
+        let schema = catalog.get_schema(table_name)?;
+        let indexes = catalog.drop_table(table_name)?;
+
+        let mut tables = self.tables.write().unwrap();
+        let key_index = (!schema.key_columns.is_empty()).then(|| key_index_name(table_name));
+        let names = std::iter::once(table_name.to_string())
+            .chain(key_index)
+            .chain(indexes.into_iter().map(|i| i.index_name));
+        for name in names {
+            tables.remove(&name);
+            for path in BTreeTable::files(&self.db_dir.join(format!("{name}.db"))) {
+                match std::fs::remove_file(&path) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                    _ => {}
+                }
+            }
+        }
+        session.row_deltas.remove(table_name);
+        Ok(())
+    }
+
+    /// Write the catalog and every open table to disk. Tables in the middle
+    /// of a transaction are left alone, as their changes aren't committed.
+    pub fn flush_all(&self) -> LunarisResult<()> {
+        self.catalog.write().unwrap().flush()?;
+        let tables = self.tables.read().unwrap();
+        for lock in tables.values() {
+            let mut tree = lock.write().unwrap();
+            if !tree.pager.in_transaction() {
+                tree.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy the catalog and every table and index file into `dest`, which
+    /// must not already hold a database. The session takes the writer slot
+    /// for the duration, so no transaction commits half way through the copy.
+    pub fn backup(&self, session: &mut Session, dest: &Path) -> LunarisResult<()> {
+        if session.explicit {
+            return Err(LunarisError::Transaction(
+                "BACKUP cannot run inside a transaction".into(),
+            ));
+        }
+        if dest.join("catalog.db").exists() {
+            return Err(LunarisError::Storage(format!(
+                "backup destination {} already holds a database",
+                dest.display()
+            )));
+        }
+        storage::create_private_dir(dest)?;
+        // a read-only database has no writers to hold off
+        if !self.read_only {
+            self.acquire_writer(session)?;
+        }
+        let result = self.copy_files(dest);
+        self.release_writer(session);
+        result
+    }
+
+    fn copy_files(&self, dest: &Path) -> LunarisResult<()> {
+        // whatever a log still holds is replayed when the copy is opened
+        let copy = |name: &str| -> LunarisResult<()> {
+            for path in BTreeTable::files(&self.db_dir.join(name)) {
+                if let Some(file_name) = path.file_name()
+                    && path.exists()
+                {
+                    std::fs::copy(&path, dest.join(file_name))?;
+                }
+            }
+            Ok(())
+        };
+
+        let mut catalog = self.catalog.write().unwrap();
+        catalog.flush()?;
+        copy("catalog.db")?;
+        for table in catalog.table_names() {
+            let schema = catalog.get_schema(&table)?;
+            let key_index = (!schema.key_columns.is_empty()).then(|| key_index_name(&table));
+            let indexes = catalog.indexes_on(&table);
+            let names = std::iter::once(table)
+                .chain(key_index)
+                .chain(indexes.into_iter().map(|i| i.index_name));
+            for name in names {
+                self.with_table_mut(&name, |tree| {
+                    tree.flush()?;
+                    copy(&format!("{name}.db"))
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn indexes_on(&self, table_name: &str) -> Vec<IndexSchema> {
+        self.catalog.read().unwrap().indexes_on(table_name)
+    }
+
+    /// Look `values` up in the indexes over the table's UNIQUE columns and
+    /// fail if a row other than `replacing` holds one of them. Index keys
+    /// compare bytewise, so only BINARY columns are probed. Returns the
+    /// columns checked, which `check_unique` then doesn't scan for.
+    fn check_unique_indexed(
+        &self,
+        session: &mut Session,
+        schema: &TableSchema,
+        values: &[Value],
+        replacing: Option<u64>,
+    ) -> LunarisResult<Vec<usize>> {
+        let mut probed = Vec::new();
+        for index in self.indexes_on(&schema.table_name) {
+            let i = index.column;
+            let col = &schema.columns[i];
+            if !col.unique
+                || col.collation != Collation::Binary
+                || values[i] == Value::Null
+                || probed.contains(&i)
+            {
+                continue;
+            }
+            let rowids = self.with_table_for_write(session, &index.index_name, |tree| {
+                index::lookup(tree, &values[i])
+            })?;
+            if rowids.into_iter().any(|id| Some(id) != replacing) {
+                return Err(LunarisError::UniqueConstraint(col.name.clone()));
+            }
+            probed.push(i);
+        }
+        Ok(probed)
+    }
+
+    /// Names of every index kept on the table, its key index included.
+    fn index_names(&self, table_name: &str) -> LunarisResult<Vec<String>> {
+        let schema = self.get_schema(table_name)?;
+        let key_index = (!schema.key_columns.is_empty()).then(|| key_index_name(table_name));
+        let indexes = self.indexes_on(table_name);
+        Ok(key_index
+            .into_iter()
+            .chain(indexes.into_iter().map(|i| i.index_name))
+            .collect())
+    }
+
+    /// Rowids of the table's rows, ordered by their `PRIMARY KEY (...)`
+    /// values. Tables without such a key are in rowid order already.
+    pub fn key_order(&self, session: &mut Session, table_name: &str) -> LunarisResult<Vec<u64>> {
+        let entries = self.with_table_ref(session, &key_index_name(table_name), index::scan)?;
+        Ok(entries.into_iter().map(|(key, _)| key).collect())
+    }
+
+    /// Rowids of the rows whose indexed column holds `value`.
+    pub fn index_lookup(
+        &self,
+        session: &mut Session,
+        index_name: &str,
+        value: &Value,
+    ) -> LunarisResult<Vec<i64>> {
+        let keys = self.with_table_ref(session, index_name, |tree| index::lookup(tree, value))?;
+        Ok(keys.into_iter().map(btree::key_row_id).collect())
     }
 
     pub fn with_table_mut<F, R>(&self, table_name: &str, f: F) -> LunarisResult<R>
     where
         F: FnOnce(&mut BTreeTable) -> LunarisResult<R>,
+    {
+        self.with_table_lock(table_name, |lock| f(&mut lock.write().unwrap()))
+    }
+
+    /// Like `with_table_mut` for reads, which run alongside other reads of
+    /// the same table. A table the session hasn't written to is read as of
+    /// its last commit, without the changes of another session's
+    /// transaction. During a statement that is the commit it first read the
+    /// table at, however many other sessions commit while it runs.
+    pub fn with_table_ref<F, R>(
+        &self,
+        session: &mut Session,
+        table_name: &str,
+        f: F,
+    ) -> LunarisResult<R>
+    where
+        F: FnOnce(&BTreeTable) -> LunarisResult<R>,
+    {
+        if session.written.contains(table_name) {
+            return self.with_table_lock(table_name, |lock| f(&lock.read().unwrap()));
+        }
+        let Some(snapshots) = &mut session.snapshots else {
+            return self.with_committed_table(table_name, f);
+        };
+        if !snapshots.contains_key(table_name) {
+            let view =
+                self.with_table_lock(table_name, |lock| Ok(lock.read().unwrap().committed()))?;
+            snapshots.insert(table_name.to_string(), view);
+        }
+        f(&snapshots[table_name])
+    }
+
+    /// Run `f` on the table as of its last commit.
+    fn with_committed_table<F, R>(&self, table_name: &str, f: F) -> LunarisResult<R>
+    where
+        F: FnOnce(&BTreeTable) -> LunarisResult<R>,
+    {
+        self.with_table_lock(table_name, |lock| {
+            let tree = lock.read().unwrap();
+            match tree.pager.in_transaction() {
+                true => f(&tree.committed()),
+                false => f(&tree),
+            }
+        })
+    }
+
+    /// Run `f` on the lock of the table, opening its file on first use.
+    fn with_table_lock<F, R>(&self, table_name: &str, f: F) -> LunarisResult<R>
+    where
+        F: FnOnce(&RwLock<BTreeTable>) -> LunarisResult<R>,
     {
         {
             let tables = self.tables.read().unwrap();
-            if let Some(table_mutex) = tables.get(table_name) {
-                let mut tree = table_mutex.lock().unwrap();
-                return f(&mut tree);
+            if let Some(lock) = tables.get(table_name) {
+                return f(lock);
             }
         }
 
@@ -106,12 +1140,3968 @@ impl Database {
         if !path.exists() {
             return Err(LunarisError::TableNotFound(table_name.to_string()));
         }
-        let btree = BTreeTable::open_or_create(&path)?;
+        let btree = match self.read_only {
+            true => BTreeTable::open_read_only(&path)?,
+            false => BTreeTable::open_or_create(&path)?,
+        };
 
         let mut tables = self.tables.write().unwrap();
-        tables.insert(table_name.to_string(), Mutex::new(btree));
-        let table_mutex = tables.get(table_name).unwrap();
-        let mut tree = table_mutex.lock().unwrap();
-        f(&mut tree)
+        // another thread may have opened it meanwhile
+        tables
+            .entry(table_name.to_string())
+            .or_insert_with(|| RwLock::new(btree));
+        drop(tables);
+        let tables = self.tables.read().unwrap();
+        f(&tables[table_name])
+    }
+}
+
+/// Name of the index over a table's `PRIMARY KEY (...)` values, keyed by
+/// `TableSchema::encode_key`. Table names can't contain dots, so it never
+/// clashes with a table or index the user created.
+fn key_index_name(table_name: &str) -> String {
+    format!("{table_name}.key")
+}
+
+/// Look `key` up in the table's key index and fail if a row other than
+/// `replacing` already holds it.
+fn check_key(
+    key_index: &BTreeTable,
+    schema: &TableSchema,
+    key: &Value,
+    replacing: Option<u64>,
+) -> LunarisResult<()> {
+    let rowids = index::lookup(key_index, key)?;
+    if rowids.into_iter().all(|id| Some(id) == replacing) {
+        return Ok(());
+    }
+    let names: Vec<&str> = schema
+        .key_columns
+        .iter()
+        .map(|&i| schema.columns[i].name.as_str())
+        .collect();
+    Err(LunarisError::UniqueConstraint(format!(
+        "({})",
+        names.join(", ")
+    )))
+}
+
+/// Scan the table for a row that clashes with `values` on any UNIQUE column
+/// not in `probed`, the columns already checked against an index. NULLs
+/// never clash, so any number of rows may hold NULL in a unique column. The
+/// row `replacing`, which `values` are about to overwrite, doesn't count.
+fn check_unique(
+    tree: &mut BTreeTable,
+    schema: &TableSchema,
+    values: &[Value],
+    replacing: Option<u64>,
+    probed: &[usize],
+) -> LunarisResult<()> {
+    let unique_cols: Vec<usize> = schema
+        .columns
+        .iter()
+        .enumerate()
+        .filter(|(i, col)| col.unique && values[*i] != Value::Null && !probed.contains(i))
+        .map(|(i, _)| i)
+        .collect();
+    if unique_cols.is_empty() {
+        return Ok(());
+    }
+
+    let mut cursor = Cursor::new(schema.clone());
+    let mut has_row = cursor.rewind(tree)?;
+    while has_row {
+        if replacing == Some(cursor.row_id(tree)?) {
+            has_row = cursor.next(tree)?;
+            continue;
+        }
+        let row = cursor.read_row(tree)?;
+        for &i in &unique_cols {
+            let collation = schema.columns[i].collation;
+            if value::compare_collated(&row[i], &values[i], collation) == Some(Ordering::Equal) {
+                return Err(LunarisError::UniqueConstraint(
+                    schema.columns[i].name.clone(),
+                ));
+            }
+        }
+        has_row = cursor.next(tree)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{META_PAGE_SIZE, PAGE_SIZE};
+    use lunaris_common::protocol::ErrorCode;
+
+    fn temp_db(name: &str) -> Database {
+        let dir = std::env::temp_dir().join("lunaris_test").join(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        Database::open(dir).unwrap()
+    }
+
+    #[test]
+    fn test_not_null_constraint() {
+        let db = temp_db("db_not_null");
+        let mut s = db.new_session();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE t (id INTEGER, name VARCHAR(16) NOT NULL, age INTEGER NULL)",
+        )
+        .unwrap();
+
+        let err = db.execute_sql(&mut s, "INSERT INTO t VALUES (1, NULL, 20)");
+        assert!(matches!(err, Err(LunarisError::NullConstraint(col)) if col == "name"));
+
+        db.execute_sql(&mut s, "INSERT INTO t VALUES (2, 'bob', NULL)")
+            .unwrap();
+        let result = db.execute_sql(&mut s, "SELECT * FROM t").unwrap();
+        assert_eq!(
+            result.rows,
+            vec![vec![
+                Value::Integer(2),
+                Value::Text("bob".into()),
+                Value::Null
+            ]]
+        );
+    }
+
+    #[test]
+    fn test_explicit_nulls() {
+        let db = temp_db("db_explicit_nulls");
+        let mut s = db.new_session();
+        // more than eight columns, so the null bitmap takes two bytes
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, i INTEGER, f FLOAT, b BOOLEAN, \
+             v VARCHAR(8), t TEXT, x BLOB(4), at TIMESTAMP, \
+             code VARCHAR(4) NOT NULL DEFAULT 'none', last INTEGER)",
+        )
+        .unwrap();
+        db.execute_sql(
+            &mut s,
+            "INSERT INTO t VALUES (1, NULL, NULL, NULL, NULL, NULL, NULL, NULL, 'a', NULL)",
+        )
+        .unwrap();
+        db.execute_sql(
+            &mut s,
+            "INSERT INTO t VALUES (2, 5, 1.5, TRUE, 'v', 't', X'01', '2024-01-01T00:00:00Z', \
+             'b', 9)",
+        )
+        .unwrap();
+        db.execute_sql(
+            &mut s,
+            "INSERT INTO t (id, i, last, v) VALUES (3, NULL, NULL, NULL)",
+        )
+        .unwrap();
+
+        let mut nulls = vec![Value::Null; 10];
+        nulls[0] = Value::Integer(1);
+        nulls[8] = Value::Text("a".into());
+        let result = db.execute_sql(&mut s, "SELECT * FROM t").unwrap();
+        assert_eq!(result.rows[0], nulls);
+        assert!(result.rows[1].iter().all(|v| *v != Value::Null));
+        // left out columns are NULL, or their default
+        nulls[0] = Value::Integer(3);
+        nulls[8] = Value::Text("none".into());
+        assert_eq!(result.rows[2], nulls);
+
+        // NULLs read from a row stay NULL through expressions
+        let result = db
+            .execute_sql(
+                &mut s,
+                "SELECT last, i + 1, t || 'x', COALESCE(f, 0.5) FROM t WHERE id = 1",
+            )
+            .unwrap();
+        assert_eq!(
+            result.rows,
+            vec![vec![
+                Value::Null,
+                Value::Null,
+                Value::Null,
+                Value::Float(0.5)
+            ]]
+        );
+        let result = db
+            .execute_sql(&mut s, "SELECT id FROM t WHERE last IS NULL")
+            .unwrap();
+        assert_eq!(
+            result.rows,
+            vec![vec![Value::Integer(1)], vec![Value::Integer(3)]]
+        );
+
+        // an explicit NULL doesn't fall back to the default
+        for (sql, column) in [
+            ("INSERT INTO t (id, code) VALUES (4, NULL)", "code"),
+            ("UPDATE t SET code = NULL WHERE id = 2", "code"),
+            ("INSERT INTO t (id) VALUES (NULL)", "id"),
+            // checked before the first row is written
+            (
+                "INSERT INTO t (id, code) VALUES (5, 'c'), (6, NULL)",
+                "code",
+            ),
+        ] {
+            assert!(
+                matches!(
+                    db.execute_sql(&mut s, sql),
+                    Err(LunarisError::NullConstraint(col)) if col == column
+                ),
+                "{sql}"
+            );
+        }
+        assert_eq!(db.row_count(&s, "t").unwrap(), 3);
+        db.execute_sql(&mut s, "UPDATE t SET last = NULL, i = NULL WHERE id = 2")
+            .unwrap();
+        let result = db
+            .execute_sql(&mut s, "SELECT i, last FROM t WHERE id = 2")
+            .unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Null, Value::Null]]);
+    }
+
+    #[test]
+    fn test_integer_primary_key_is_rowid() {
+        let db = temp_db("db_primary_key");
+        let mut s = db.new_session();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE t (name VARCHAR(16), id INTEGER PRIMARY KEY)",
+        )
+        .unwrap();
+        assert_eq!(db.get_schema("t").unwrap().primary_key, Some(1));
+
+        db.execute_sql(&mut s, "INSERT INTO t VALUES ('b', 2), ('a', 1)")
+            .unwrap();
+        let err = db.execute_sql(&mut s, "INSERT INTO t VALUES ('c', 2)");
+        assert!(matches!(err, Err(LunarisError::DuplicateKey(2))));
+
+        // rows come back in key order, not insertion order
+        let result = db.execute_sql(&mut s, "SELECT id FROM t").unwrap();
+        assert_eq!(
+            result.rows,
+            vec![vec![Value::Integer(1)], vec![Value::Integer(2)]]
+        );
+
+        // negative keys come before the others, and rowids assigned later
+        // continue after the largest key
+        db.execute_sql(&mut s, "INSERT INTO t VALUES ('d', -1), ('e', -9)")
+            .unwrap();
+        db.execute_sql(&mut s, "INSERT INTO t (name) VALUES ('f')")
+            .unwrap();
+        let result = db.execute_sql(&mut s, "SELECT id FROM t").unwrap();
+        let ids: Vec<Value> = [-9, -1, 1, 2, 3].map(Value::Integer).to_vec();
+        assert_eq!(
+            result.rows,
+            ids.into_iter().map(|id| vec![id]).collect::<Vec<_>>()
+        );
+
+        // once the largest rowid is taken there is none left to assign
+        db.execute_sql(&mut s, &format!("INSERT INTO t VALUES ('g', {})", i64::MAX))
+            .unwrap();
+        assert!(matches!(
+            db.execute_sql(&mut s, "INSERT INTO t (name) VALUES ('h')"),
+            Err(LunarisError::Storage(_))
+        ));
+        let result = db.execute_sql(&mut s, "SELECT COUNT(*) FROM t").unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Integer(6)]]);
+    }
+
+    #[test]
+    fn test_auto_rowid_without_primary_key() {
+        let db = temp_db("db_auto_rowid");
+        let mut s = db.new_session();
+        db.execute_sql(&mut s, "CREATE TABLE t (id INTEGER, name VARCHAR(16))")
+            .unwrap();
+        assert_eq!(db.get_schema("t").unwrap().primary_key, None);
+
+        // the first column is no longer the key, so repeated values are fine
+        db.execute_sql(&mut s, "INSERT INTO t VALUES (5, 'x'), (5, 'y')")
+            .unwrap();
+        db.execute_sql(&mut s, "INSERT INTO t VALUES (1, 'z')")
+            .unwrap();
+
+        let result = db.execute_sql(&mut s, "SELECT name FROM t").unwrap();
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![Value::Text("x".into())],
+                vec![Value::Text("y".into())],
+                vec![Value::Text("z".into())],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unique_constraint() {
+        let db = temp_db("db_unique");
+        let mut s = db.new_session();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, email VARCHAR(32) UNIQUE)",
+        )
+        .unwrap();
+        assert!(db.get_schema("t").unwrap().columns[1].unique);
+
+        db.execute_sql(&mut s, "INSERT INTO t VALUES (1, 'a@x.org')")
+            .unwrap();
+        let err = db.execute_sql(&mut s, "INSERT INTO t VALUES (2, 'a@x.org')");
+        assert!(matches!(err, Err(LunarisError::UniqueConstraint(col)) if col == "email"));
+
+        // multiple NULLs do not violate uniqueness
+        db.execute_sql(&mut s, "INSERT INTO t VALUES (3, NULL)")
+            .unwrap();
+        db.execute_sql(&mut s, "INSERT INTO t VALUES (4, NULL)")
+            .unwrap();
+        db.execute_sql(&mut s, "INSERT INTO t VALUES (5, 'b@x.org')")
+            .unwrap();
+
+        let result = db.execute_sql(&mut s, "SELECT id FROM t").unwrap();
+        assert_eq!(result.rows.len(), 4);
+    }
+
+    #[test]
+    fn test_unique_constraint_with_index() {
+        let db = temp_db("db_unique_index");
+        let mut s = db.new_session();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, email VARCHAR(32) UNIQUE)",
+        )
+        .unwrap();
+        db.execute_sql(&mut s, "CREATE INDEX idx_email ON t (email)")
+            .unwrap();
+        db.execute_sql(
+            &mut s,
+            "INSERT INTO t VALUES (1, 'a@x.org'), (2, 'b@x.org')",
+        )
+        .unwrap();
+
+        let err = db.execute_sql(&mut s, "INSERT INTO t VALUES (3, 'a@x.org')");
+        assert!(matches!(err, Err(LunarisError::UniqueConstraint(col)) if col == "email"));
+        let err = db.execute_sql(&mut s, "UPDATE t SET email = 'b@x.org' WHERE id = 1");
+        assert!(matches!(err, Err(LunarisError::UniqueConstraint(col)) if col == "email"));
+        // a row may keep its own value, and a freed value may be taken again
+        db.execute_sql(&mut s, "UPDATE t SET email = 'a@x.org' WHERE id = 1")
+            .unwrap();
+        db.execute_sql(&mut s, "DELETE FROM t WHERE id = 2")
+            .unwrap();
+        db.execute_sql(&mut s, "INSERT INTO t VALUES (3, 'b@x.org')")
+            .unwrap();
+
+        // the index is what's checked: drop a row behind its back and the
+        // value it listed stays taken, where a scan would miss it
+        db.with_table_for_write(&mut s, "t", |tree| tree.delete(btree::row_key(3)))
+            .unwrap();
+        db.commit(&mut s).unwrap();
+        let err = db.execute_sql(&mut s, "INSERT INTO t VALUES (4, 'b@x.org')");
+        assert!(matches!(err, Err(LunarisError::UniqueConstraint(col)) if col == "email"));
+    }
+
+    #[test]
+    fn test_composite_primary_key() {
+        let db = temp_db("db_composite_key");
+        let mut s = db.new_session();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE t (region TEXT, id INTEGER, name TEXT, PRIMARY KEY (region, id))",
+        )
+        .unwrap();
+        db.execute_sql(
+            &mut s,
+            "INSERT INTO t VALUES ('west', 2, 'd'), ('east', 10, 'b'), ('west', 1, 'c'), \
+             ('east', 9, 'a'), ('eas', 50, 'z')",
+        )
+        .unwrap();
+
+        let names = |db: &Database, s: &mut Session, sql: &str| -> Vec<Value> {
+            let rows = db.execute_sql(s, sql).unwrap().rows;
+            rows.into_iter().map(|mut row| row.pop().unwrap()).collect()
+        };
+        let text =
+            |v: &[&str]| -> Vec<Value> { v.iter().map(|s| Value::Text(s.to_string())).collect() };
+        assert_eq!(
+            names(&db, &mut s, "SELECT name FROM t"),
+            text(&["z", "a", "b", "c", "d"])
+        );
+
+        let err = db.execute_sql(&mut s, "INSERT INTO t VALUES ('east', 9, 'dup')");
+        assert!(matches!(err, Err(LunarisError::UniqueConstraint(cols)) if cols == "(region, id)"));
+        let err = db.execute_sql(&mut s, "INSERT INTO t VALUES (NULL, 3, 'x')");
+        assert!(matches!(err, Err(LunarisError::NullConstraint(col)) if col == "region"));
+        db.execute_sql(&mut s, "INSERT INTO t VALUES ('east', 11, 'e')")
+            .unwrap();
+
+        let result = db
+            .execute_sql(&mut s, "DELETE FROM t WHERE region = 'east'")
+            .unwrap();
+        assert_eq!(result.rows_affected, 3);
+        assert_eq!(
+            names(&db, &mut s, "SELECT name FROM t WHERE id < 10"),
+            text(&["c", "d"])
+        );
+        assert_eq!(
+            db.dump(&mut s, "t").unwrap()[0],
+            "CREATE TABLE t (region TEXT NOT NULL, id INTEGER NOT NULL, name TEXT, \
+             PRIMARY KEY (region, id))"
+        );
+    }
+
+    #[test]
+    fn test_composite_key_index() {
+        let db = temp_db("db_composite_key_index");
+        let mut s = db.new_session();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE t (code TEXT, n INTEGER, PRIMARY KEY (code, n))",
+        )
+        .unwrap();
+        // the codes share their first 8 bytes, and so their key index cell
+        db.execute_sql(
+            &mut s,
+            "INSERT INTO t VALUES ('shared__b', 1), ('shared__a', 2), ('shared__a', 1)",
+        )
+        .unwrap();
+        let rows =
+            |db: &Database, s: &mut Session| db.execute_sql(s, "SELECT * FROM t").unwrap().rows;
+        let row = |code: &str, n: i64| vec![Value::Text(code.into()), Value::Integer(n)];
+        assert_eq!(
+            rows(&db, &mut s),
+            vec![
+                row("shared__a", 1),
+                row("shared__a", 2),
+                row("shared__b", 1)
+            ]
+        );
+
+        let err = db.execute_sql(&mut s, "UPDATE t SET n = 1 WHERE n = 2");
+        assert!(matches!(err, Err(LunarisError::UniqueConstraint(_))));
+        db.execute_sql(&mut s, "UPDATE t SET n = 3 WHERE code = 'shared__b'")
+            .unwrap();
+        // the old key is free again, the new one taken
+        db.execute_sql(&mut s, "INSERT INTO t VALUES ('shared__b', 1)")
+            .unwrap();
+        let err = db.execute_sql(&mut s, "INSERT INTO t VALUES ('shared__b', 3)");
+        assert!(matches!(err, Err(LunarisError::UniqueConstraint(_))));
+
+        db.execute_sql(&mut s, "BEGIN").unwrap();
+        db.execute_sql(&mut s, "DELETE FROM t WHERE code = 'shared__a'")
+            .unwrap();
+        db.execute_sql(&mut s, "INSERT INTO t VALUES ('shared__a', 9)")
+            .unwrap();
+        db.execute_sql(&mut s, "ROLLBACK").unwrap();
+        let err = db.execute_sql(&mut s, "INSERT INTO t VALUES ('shared__a', 2)");
+        assert!(matches!(err, Err(LunarisError::UniqueConstraint(_))));
+        db.execute_sql(&mut s, "INSERT INTO t VALUES ('shared__a', 9)")
+            .unwrap();
+
+        // the key index is written with the table and found again on open
+        drop(db);
+        let dir = std::env::temp_dir()
+            .join("lunaris_test")
+            .join("db_composite_key_index");
+        let db = Database::open(dir).unwrap();
+        let mut s = db.new_session();
+        assert_eq!(
+            rows(&db, &mut s),
+            vec![
+                row("shared__a", 1),
+                row("shared__a", 2),
+                row("shared__a", 9),
+                row("shared__b", 1),
+                row("shared__b", 3),
+            ]
+        );
+        let err = db.execute_sql(&mut s, "INSERT INTO t VALUES ('shared__b', 3)");
+        assert!(matches!(err, Err(LunarisError::UniqueConstraint(_))));
+
+        db.execute_sql(&mut s, "TRUNCATE TABLE t").unwrap();
+        db.execute_sql(&mut s, "INSERT INTO t VALUES ('shared__b', 3)")
+            .unwrap();
+        db.execute_sql(&mut s, "DROP TABLE t").unwrap();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE t (code TEXT, n INTEGER, PRIMARY KEY (code, n))",
+        )
+        .unwrap();
+        db.execute_sql(&mut s, "INSERT INTO t VALUES ('shared__b', 3)")
+            .unwrap();
+        assert_eq!(rows(&db, &mut s), vec![row("shared__b", 3)]);
+    }
+
+    #[test]
+    fn test_column_defaults() {
+        let db = temp_db("db_defaults");
+        let mut s = db.new_session();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, qty INTEGER DEFAULT 0, \
+             label VARCHAR(8) DEFAULT 'n/a', active BOOLEAN NOT NULL DEFAULT TRUE, \
+             score FLOAT DEFAULT -1, note VARCHAR(8))",
+        )
+        .unwrap();
+
+        db.execute_sql(&mut s, "INSERT INTO t (id) VALUES (1)")
+            .unwrap();
+        db.execute_sql(&mut s, "INSERT INTO t (label, id, qty) VALUES ('x', 2, 5)")
+            .unwrap();
+
+        let result = db.execute_sql(&mut s, "SELECT * FROM t").unwrap();
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![
+                    Value::Integer(1),
+                    Value::Integer(0),
+                    Value::Text("n/a".into()),
+                    Value::Boolean(true),
+                    Value::Float(-1.0),
+                    Value::Null,
+                ],
+                vec![
+                    Value::Integer(2),
+                    Value::Integer(5),
+                    Value::Text("x".into()),
+                    Value::Boolean(true),
+                    Value::Float(-1.0),
+                    Value::Null,
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_omitted_not_null_column_without_default() {
+        let db = temp_db("db_defaults_missing");
+        let mut s = db.new_session();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, name VARCHAR(8) NOT NULL)",
+        )
+        .unwrap();
+
+        let err = db.execute_sql(&mut s, "INSERT INTO t (id) VALUES (1)");
+        assert!(matches!(err, Err(LunarisError::NullConstraint(col)) if col == "name"));
+
+        let err = db.execute_sql(&mut s, "CREATE TABLE u (flag BOOLEAN DEFAULT 'yes')");
+        assert!(matches!(err, Err(LunarisError::TypeMismatch { .. })));
+    }
+
+    #[test]
+    fn test_insert_value_types() {
+        let db = temp_db("db_insert_types");
+        let mut s = db.new_session();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE t (id INTEGER, score FLOAT, name VARCHAR(8))",
+        )
+        .unwrap();
+
+        // the second row is rejected while compiling, so the first is never
+        // written either
+        let err = db.execute_sql(&mut s, "INSERT INTO t (id) VALUES (1), ('hello')");
+        assert!(matches!(
+            err,
+            Err(LunarisError::TypeMismatch { expected, .. }) if expected.contains("'id'")
+        ));
+        let err = db.execute_sql(&mut s, "INSERT INTO t (id, name) VALUES (2, TRUE)");
+        assert!(matches!(
+            err,
+            Err(LunarisError::TypeMismatch { expected, .. }) if expected.contains("'name'")
+        ));
+
+        let err = db.execute_sql(&mut s, "INSERT INTO t VALUES (3, 4, 'abcdefghi')");
+        assert!(matches!(
+            err,
+            Err(LunarisError::ValueTooLong { max: 8, got: 9, .. })
+        ));
+        db.execute_sql(&mut s, "INSERT INTO t VALUES (3, 4, 'abcdefgh')")
+            .unwrap();
+        let result = db.execute_sql(&mut s, "SELECT * FROM t").unwrap();
+        assert_eq!(
+            result.rows,
+            vec![vec![
+                Value::Integer(3),
+                Value::Float(4.0),
+                Value::Text("abcdefgh".into()),
+            ]]
+        );
+    }
+
+    #[test]
+    fn test_rows_larger_than_a_page() {
+        let db = temp_db("db_overflow");
+        let mut s = db.new_session();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE docs (id INTEGER PRIMARY KEY, body VARCHAR(6000))",
+        )
+        .unwrap();
+
+        let body = "lunaris ".repeat(700);
+        for id in 1..=3 {
+            db.execute_sql(&mut s, &format!("INSERT INTO docs VALUES ({id}, '{body}')"))
+                .unwrap();
+        }
+        db.execute_sql(&mut s, "DELETE FROM docs WHERE id = 2")
+            .unwrap();
+
+        let result = db.execute_sql(&mut s, "SELECT * FROM docs").unwrap();
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![Value::Integer(1), Value::Text(body.clone())],
+                vec![Value::Integer(3), Value::Text(body.clone())],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_text_columns() {
+        let db = temp_db("db_text");
+        let mut s = db.new_session();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE notes (id INTEGER PRIMARY KEY, body TEXT, tag VARCHAR)",
+        )
+        .unwrap();
+        let schema = db.get_schema("notes").unwrap();
+        assert_eq!(schema.columns[1].col_type, ColumnType::Text);
+        assert_eq!(schema.columns[2].col_type, ColumnType::Text);
+
+        let body = "abcdefghij".repeat(100);
+        db.execute_sql(
+            &mut s,
+            &format!("INSERT INTO notes VALUES (1, '{body}', 'long'), (2, 'short', NULL)"),
+        )
+        .unwrap();
+
+        let result = db
+            .execute_sql(&mut s, "SELECT body, LENGTH(body) FROM notes WHERE id = 1")
+            .unwrap();
+        assert_eq!(
+            result.rows,
+            vec![vec![Value::Text(body), Value::Integer(1000)]]
+        );
+        let result = db
+            .execute_sql(&mut s, "SELECT id FROM notes WHERE body = 'short'")
+            .unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Integer(2)]]);
+    }
+
+    #[test]
+    fn test_blob_columns() {
+        let db = temp_db("db_blob");
+        let mut s = db.new_session();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, data BLOB(16), tag BLOB(4) DEFAULT X'00ff')",
+        )
+        .unwrap();
+
+        db.execute_sql(
+            &mut s,
+            "INSERT INTO t (id, data) VALUES (1, X'00DEAD00BEEF00')",
+        )
+        .unwrap();
+        db.execute_sql(&mut s, "INSERT INTO t VALUES (2, X'', NULL)")
+            .unwrap();
+        assert!(
+            db.execute_sql(&mut s, "INSERT INTO t (id, data) VALUES (3, X'abc')")
+                .is_err()
+        );
+        assert!(
+            db.execute_sql(&mut s, "INSERT INTO t (id, data) VALUES (3, 'text')")
+                .is_err()
+        );
+
+        let result = db.execute_sql(&mut s, "SELECT * FROM t").unwrap();
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![
+                    Value::Integer(1),
+                    Value::Blob(vec![0, 0xde, 0xad, 0, 0xbe, 0xef, 0]),
+                    Value::Blob(vec![0, 0xff]),
+                ],
+                vec![Value::Integer(2), Value::Blob(vec![]), Value::Null],
+            ]
+        );
+
+        let result = db
+            .execute_sql(&mut s, "SELECT id FROM t WHERE data = X'00dead00beef00'")
+            .unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Integer(1)]]);
+    }
+
+    #[test]
+    fn test_timestamp_columns() {
+        let db = temp_db("db_timestamp");
+        let mut s = db.new_session();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE events (id INTEGER PRIMARY KEY, at TIMESTAMP, day DATE \
+             DEFAULT '2024-01-01')",
+        )
+        .unwrap();
+        assert_eq!(
+            db.get_schema("events").unwrap().columns[1].col_type,
+            ColumnType::Timestamp
+        );
+
+        db.execute_sql(
+            &mut s,
+            "INSERT INTO events (id, at) VALUES (1, '2023-12-31T23:59:59Z'), \
+             (2, '2024-01-01T00:00:00Z'), (3, '2024-02-29T12:30:00.250Z'), \
+             (4, '2024-03-01T00:00:00Z')",
+        )
+        .unwrap();
+        let err = db.execute_sql(&mut s, "INSERT INTO events (id, at) VALUES (5, 'soon')");
+        assert!(matches!(err, Err(LunarisError::TypeMismatch { .. })));
+
+        let result = db
+            .execute_sql(
+                &mut s,
+                "SELECT id, at FROM events \
+                 WHERE at >= '2024-01-01T00:00:00Z' AND at < '2024-03-01T00:00:00Z'",
+            )
+            .unwrap();
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![
+                    Value::Integer(2),
+                    Value::Text("2024-01-01T00:00:00Z".into())
+                ],
+                vec![
+                    Value::Integer(3),
+                    Value::Text("2024-02-29T12:30:00.250Z".into())
+                ],
+            ]
+        );
+
+        let result = db
+            .execute_sql(&mut s, "SELECT day FROM events WHERE '2024-01-01' > at")
+            .unwrap();
+        assert_eq!(
+            result.rows,
+            vec![vec![Value::Text("2024-01-01T00:00:00Z".into())]]
+        );
+
+        let result = db.execute_sql(&mut s, "DESCRIBE events").unwrap();
+        assert_eq!(
+            result.rows[2][4],
+            Value::Text("2024-01-01T00:00:00Z".into())
+        );
+    }
+
+    #[test]
+    fn test_string_functions() {
+        let db = temp_db("db_string_functions");
+        let mut s = db.new_session();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, name VARCHAR(16))",
+        )
+        .unwrap();
+        db.execute_sql(
+            &mut s,
+            "INSERT INTO t VALUES (1, 'Ann'), (2, 'Bartholomew'), (3, NULL), (4, 'Zoë')",
+        )
+        .unwrap();
+
+        let result = db
+            .execute_sql(&mut s, "SELECT UPPER(name), lower(name) AS low FROM t")
+            .unwrap();
+        assert_eq!(result.columns, vec!["UPPER(name)", "low"]);
+        let text = |s: &str| Value::Text(s.into());
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![text("ANN"), text("ann")],
+                vec![text("BARTHOLOMEW"), text("bartholomew")],
+                vec![Value::Null, Value::Null],
+                vec![text("ZOË"), text("zoë")],
+            ]
+        );
+
+        // LENGTH counts characters, not bytes
+        let result = db
+            .execute_sql(
+                &mut s,
+                "SELECT id, LENGTH(name) FROM t WHERE LENGTH(name) > 3",
+            )
+            .unwrap();
+        assert_eq!(
+            result.rows,
+            vec![vec![Value::Integer(2), Value::Integer(11)]]
+        );
+        let result = db
+            .execute_sql(&mut s, "SELECT id FROM t WHERE LENGTH(name) = 3")
+            .unwrap();
+        assert_eq!(
+            result.rows,
+            vec![vec![Value::Integer(1)], vec![Value::Integer(4)]]
+        );
+
+        assert!(db.execute_sql(&mut s, "SELECT UPPER(id) FROM t").is_err());
+        assert!(
+            db.execute_sql(&mut s, "SELECT UPPER(name, id) FROM t")
+                .is_err()
+        );
+        assert!(
+            db.execute_sql(&mut s, "SELECT REVERSE(name) FROM t")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_coalesce() {
+        let db = temp_db("db_coalesce");
+        let mut s = db.new_session();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, nickname VARCHAR(16), name VARCHAR(16))",
+        )
+        .unwrap();
+        db.execute_sql(
+            &mut s,
+            "INSERT INTO t VALUES (1, 'Al', 'Alice'), (2, NULL, 'Bob'), (3, NULL, NULL)",
+        )
+        .unwrap();
+
+        let result = db
+            .execute_sql(
+                &mut s,
+                "SELECT COALESCE(nickname, name, 'unknown') AS who, COALESCE(nickname, name) FROM t",
+            )
+            .unwrap();
+        assert_eq!(result.columns[0], "who");
+        let text = |s: &str| Value::Text(s.into());
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![text("Al"), text("Al")],
+                vec![text("Bob"), text("Bob")],
+                vec![text("unknown"), Value::Null],
+            ]
+        );
+
+        // arguments of different types
+        let result = db
+            .execute_sql(&mut s, "SELECT COALESCE(name, id) FROM t WHERE id > 1")
+            .unwrap();
+        assert_eq!(
+            result.rows,
+            vec![vec![text("Bob")], vec![Value::Integer(3)]]
+        );
+
+        let result = db
+            .execute_sql(
+                &mut s,
+                "SELECT id FROM t WHERE COALESCE(nickname, name) IS NULL",
+            )
+            .unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Integer(3)]]);
+        assert!(db.execute_sql(&mut s, "SELECT COALESCE() FROM t").is_err());
+    }
+
+    #[test]
+    fn test_string_concatenation() {
+        let db = temp_db("db_concat");
+        let mut s = db.new_session();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, first_name VARCHAR(16), last_name VARCHAR(16))",
+        )
+        .unwrap();
+        db.execute_sql(
+            &mut s,
+            "INSERT INTO t VALUES (1, 'Ada', 'Lovelace'), (2, 'Alan', NULL)",
+        )
+        .unwrap();
+
+        let result = db
+            .execute_sql(
+                &mut s,
+                "SELECT first_name || ' ' || last_name AS full, id || ':' || UPPER(first_name) FROM t",
+            )
+            .unwrap();
+        assert_eq!(result.columns[0], "full");
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![
+                    Value::Text("Ada Lovelace".into()),
+                    Value::Text("1:ADA".into())
+                ],
+                vec![Value::Null, Value::Text("2:ALAN".into())],
+            ]
+        );
+
+        let result = db
+            .execute_sql(&mut s, "SELECT id FROM t WHERE first_name || 'x' = 'Alanx'")
+            .unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Integer(2)]]);
+    }
+
+    #[test]
+    fn test_select_without_from() {
+        let db = temp_db("db_select_constant");
+        let mut s = db.new_session();
+
+        let result = db.execute_sql(&mut s, "SELECT 1 + 1").unwrap();
+        assert_eq!(result.columns, vec!["1 + 1"]);
+        assert_eq!(result.rows, vec![vec![Value::Integer(2)]]);
+
+        let result = db
+            .execute_sql(
+                &mut s,
+                "SELECT 'hello' AS greeting, TRUE, 7 / 2, 7 % 4, 1.5 * 2, 2 - 5",
+            )
+            .unwrap();
+        assert_eq!(result.columns[0], "greeting");
+        assert_eq!(
+            result.rows,
+            vec![vec![
+                Value::Text("hello".into()),
+                Value::Boolean(true),
+                Value::Integer(3),
+                Value::Integer(3),
+                Value::Float(3.0),
+                Value::Integer(-3),
+            ]]
+        );
+
+        let result = db
+            .execute_sql(&mut s, "SELECT 2 * (3 + 4), NULL + 1")
+            .unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Integer(14), Value::Null]]);
+
+        let result = db.execute_sql(&mut s, "SELECT 1 WHERE 1 = 0").unwrap();
+        assert!(result.rows.is_empty());
+
+        assert!(matches!(
+            db.execute_sql(&mut s, "SELECT 1 / 0"),
+            Err(LunarisError::Vm(_))
+        ));
+        assert!(matches!(
+            db.execute_sql(&mut s, "SELECT id"),
+            Err(LunarisError::ColumnNotFound(_))
+        ));
+        assert!(db.execute_sql(&mut s, "SELECT *").is_err());
+    }
+
+    #[test]
+    fn test_division() {
+        let db = temp_db("db_division");
+        let mut s = db.new_session();
+        let eval = |s: &mut Session, sql: &str| db.execute_sql(s, sql).unwrap().rows;
+
+        // integers divide into an integer, truncating toward zero
+        assert_eq!(
+            eval(&mut s, "SELECT 7 / 2, -7 / 2, 7 / -2, 7.0 / 2, 7 / 2.0"),
+            vec![vec![
+                Value::Integer(3),
+                Value::Integer(-3),
+                Value::Integer(-3),
+                Value::Float(3.5),
+                Value::Float(3.5),
+            ]]
+        );
+        // the remainder has the sign of the dividend
+        assert_eq!(
+            eval(&mut s, "SELECT 7 % 3, -7 % 3, 7 % -3, -7 % -3, -7.5 % 2"),
+            vec![vec![
+                Value::Integer(1),
+                Value::Integer(-1),
+                Value::Integer(1),
+                Value::Integer(-1),
+                Value::Float(-1.5),
+            ]]
+        );
+
+        db.execute_sql(&mut s, "CREATE TABLE t (id INTEGER PRIMARY KEY, x FLOAT)")
+            .unwrap();
+        db.execute_sql(&mut s, "INSERT INTO t VALUES (0, 0.0)")
+            .unwrap();
+        for sql in [
+            "SELECT 1 / 0",
+            "SELECT 1 % 0",
+            "SELECT 1.0 / 0",
+            "SELECT 1 / 0.0",
+            "SELECT 1.5 % 0",
+            "SELECT 1 / id FROM t",
+            "SELECT 1 / x FROM t",
+            "SELECT id % x FROM t",
+        ] {
+            let err = db.execute_sql(&mut s, sql);
+            assert!(
+                matches!(&err, Err(LunarisError::Vm(msg)) if msg == "division by zero"),
+                "{sql}: {:?}",
+                err.map(|r| r.rows)
+            );
+        }
+    }
+
+    #[test]
+    fn test_integer_overflow() {
+        let db = temp_db("db_integer_overflow");
+        let mut s = db.new_session();
+
+        for sql in [
+            "SELECT 9223372036854775807 + 1",
+            "SELECT 4611686018427387904 * 2",
+            "SELECT 0 - 9223372036854775807 - 2",
+        ] {
+            let err = db.execute_sql(&mut s, sql);
+            assert!(
+                matches!(&err, Err(LunarisError::Vm(msg)) if msg.starts_with("integer overflow")),
+                "{sql}: {:?}",
+                err.map(|r| r.rows)
+            );
+        }
+        // floats don't overflow into an error
+        let result = db
+            .execute_sql(&mut s, "SELECT 9223372036854775807 + 1.0")
+            .unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Float(9223372036854775808.0)]]);
+
+        db.execute_sql(&mut s, "CREATE TABLE t (id INTEGER PRIMARY KEY, x INTEGER)")
+            .unwrap();
+        db.execute_sql(
+            &mut s,
+            "INSERT INTO t VALUES (1, 1), (2, 9223372036854775000)",
+        )
+        .unwrap();
+        let result = db
+            .execute_sql(&mut s, "SELECT x + 807 FROM t WHERE id = 2")
+            .unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Integer(i64::MAX)]]);
+        assert!(matches!(
+            db.execute_sql(&mut s, "SELECT x * 2 FROM t"),
+            Err(LunarisError::Vm(_))
+        ));
+
+        // SUM checks every addition, and fails the same way whatever the
+        // order of the rows
+        let sum = |s: &mut Session, sql: &str| db.execute_sql(s, sql).map(|r| r.rows);
+        assert_eq!(
+            sum(&mut s, "SELECT SUM(x) FROM t").unwrap(),
+            vec![vec![Value::Integer(9223372036854775001)]]
+        );
+        db.execute_sql(&mut s, "INSERT INTO t VALUES (0, 1000), (3, -5)")
+            .unwrap();
+        for sql in ["SELECT SUM(x) FROM t", "SELECT SUM(x) FROM t WHERE x > 0"] {
+            let err = sum(&mut s, sql);
+            assert!(
+                matches!(&err, Err(LunarisError::Vm(msg)) if msg.starts_with("integer overflow")),
+                "{sql}: {err:?}"
+            );
+        }
+        assert_eq!(
+            sum(&mut s, "SELECT SUM(x) FROM t WHERE id <> 2").unwrap(),
+            vec![vec![Value::Integer(996)]]
+        );
+        // a float sum doesn't overflow
+        assert_eq!(
+            sum(&mut s, "SELECT SUM(x * 1.0) FROM t WHERE id < 3").unwrap(),
+            vec![vec![Value::Float(9223372036854776000.0)]]
+        );
+    }
+
+    #[test]
+    fn test_transaction_rollback() {
+        let db = temp_db("db_rollback");
+        let mut s = db.new_session();
+        db.execute_sql(&mut s, "CREATE TABLE t (id INTEGER PRIMARY KEY, v INTEGER)")
+            .unwrap();
+        db.execute_sql(&mut s, "INSERT INTO t VALUES (1, 10)")
+            .unwrap();
+
+        db.execute_sql(&mut s, "BEGIN").unwrap();
+        db.execute_sql(&mut s, "INSERT INTO t VALUES (2, 20), (3, 30)")
+            .unwrap();
+        db.execute_sql(&mut s, "DELETE FROM t WHERE id = 1")
+            .unwrap();
+        let rows = db.execute_sql(&mut s, "SELECT id FROM t").unwrap().rows;
+        assert_eq!(rows, vec![vec![Value::Integer(2)], vec![Value::Integer(3)]]);
+        db.execute_sql(&mut s, "ROLLBACK").unwrap();
+
+        let rows = db.execute_sql(&mut s, "SELECT * FROM t").unwrap().rows;
+        assert_eq!(rows, vec![vec![Value::Integer(1), Value::Integer(10)]]);
+
+        // nothing from the rolled back transaction reached the file
+        drop(db);
+        let dir = std::env::temp_dir()
+            .join("lunaris_test")
+            .join("db_rollback");
+        let db = Database::open(dir).unwrap();
+        let mut s = db.new_session();
+        let rows = db.execute_sql(&mut s, "SELECT * FROM t").unwrap().rows;
+        assert_eq!(rows, vec![vec![Value::Integer(1), Value::Integer(10)]]);
+    }
+
+    #[test]
+    fn test_recover_session() {
+        let db = temp_db("db_recover_session");
+        let mut s = db.new_session();
+        db.execute_sql(&mut s, "CREATE TABLE t (id INTEGER PRIMARY KEY, v INTEGER)")
+            .unwrap();
+        db.execute_sql(&mut s, "INSERT INTO t VALUES (1, 10)")
+            .unwrap();
+
+        // a statement that panics halfway through its write
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            db.with_table_for_write::<_, ()>(&mut s, "t", |tree| {
+                tree.insert(btree::row_key(2), b"half written").unwrap();
+                panic!("bug in the statement");
+            })
+        }));
+        assert!(panicked.is_err());
+        db.recover_session(&mut s);
+
+        let mut other = db.new_session();
+        db.execute_sql(&mut other, "INSERT INTO t VALUES (3, 30)")
+            .unwrap();
+        let rows = db.execute_sql(&mut s, "SELECT id FROM t").unwrap().rows;
+        assert_eq!(rows, vec![vec![Value::Integer(1)], vec![Value::Integer(3)]]);
+
+        // schema changes panic with the catalog locked
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _catalog = db.catalog.write().unwrap();
+            panic!("bug in a schema change");
+        }));
+        assert!(panicked.is_err());
+        db.recover_session(&mut s);
+        db.execute_sql(&mut other, "CREATE TABLE u (id INTEGER)")
+            .unwrap();
+        let rows = db.execute_sql(&mut s, "SELECT id FROM t").unwrap().rows;
+        assert_eq!(rows.len(), 2);
+    }
+
+    #[test]
+    fn test_transaction_commit() {
+        let dir = std::env::temp_dir().join("lunaris_test").join("db_commit");
+        let db = temp_db("db_commit");
+        let mut s = db.new_session();
+        db.execute_sql(&mut s, "CREATE TABLE t (id INTEGER PRIMARY KEY)")
+            .unwrap();
+
+        db.execute_sql(&mut s, "BEGIN").unwrap();
+        assert!(matches!(
+            db.execute_sql(&mut s, "BEGIN"),
+            Err(LunarisError::Transaction(_))
+        ));
+        assert!(matches!(
+            db.execute_sql(&mut s, "CREATE TABLE u (id INTEGER)"),
+            Err(LunarisError::Transaction(_))
+        ));
+        db.execute_sql(&mut s, "INSERT INTO t VALUES (1)").unwrap();
+        db.execute_sql(&mut s, "INSERT INTO t VALUES (2)").unwrap();
+
+        // a failing statement inside the transaction keeps earlier ones
+        assert!(db.execute_sql(&mut s, "INSERT INTO t VALUES (1)").is_err());
+
+        // the session keeps the writer slot until the transaction ends
+        assert_eq!(*db.writer.lock().unwrap(), Some(s.id));
+        db.execute_sql(&mut s, "COMMIT").unwrap();
+        assert_eq!(*db.writer.lock().unwrap(), None);
+
+        let mut other = db.new_session();
+        db.execute_sql(&mut other, "INSERT INTO t VALUES (3)")
+            .unwrap();
+
+        assert!(matches!(
+            db.execute_sql(&mut s, "COMMIT"),
+            Err(LunarisError::Transaction(_))
+        ));
+
+        drop(db);
+        let db = Database::open(dir).unwrap();
+        let mut s = db.new_session();
+        let rows = db.execute_sql(&mut s, "SELECT * FROM t").unwrap().rows;
+        assert_eq!(
+            rows,
+            vec![
+                vec![Value::Integer(1)],
+                vec![Value::Integer(2)],
+                vec![Value::Integer(3)]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_concurrent_scans() {
+        use std::sync::mpsc;
+
+        let db = temp_db("db_concurrent_scans");
+        let mut s = db.new_session();
+        db.execute_sql(&mut s, "CREATE TABLE t (id INTEGER PRIMARY KEY)")
+            .unwrap();
+        let values: Vec<String> = (1..=500).map(|i| format!("({i})")).collect();
+        db.execute_sql(
+            &mut s,
+            &format!("INSERT INTO t VALUES {}", values.join(", ")),
+        )
+        .unwrap();
+        let schema = db.get_schema("t").unwrap();
+
+        let (started_tx, started_rx) = mpsc::channel();
+        let (done_tx, done_rx) = mpsc::channel();
+        std::thread::scope(|scope| {
+            // holds the table for reading until the other scan has finished
+            let db = &db;
+            let mut reader = db.new_session();
+            let first = scope.spawn(move || {
+                db.with_table_ref(&mut reader, "t", |tree| {
+                    let mut cursor = Cursor::new(schema);
+                    assert!(cursor.rewind(tree)?);
+                    started_tx.send(()).unwrap();
+                    let other_finished = done_rx.recv_timeout(Duration::from_secs(5)).is_ok();
+                    assert!(cursor.next(tree)?);
+                    Ok(other_finished)
+                })
+            });
+
+            started_rx.recv().unwrap();
+            let mut other = db.new_session();
+            let rows = db.execute_sql(&mut other, "SELECT id FROM t").unwrap().rows;
+            assert_eq!(rows.len(), 500);
+            done_tx.send(()).unwrap();
+
+            assert!(first.join().unwrap().unwrap(), "the scans were serialized");
+        });
+    }
+
+    #[test]
+    fn test_scan_reads_one_commit() {
+        use std::sync::atomic::AtomicBool;
+
+        let db = temp_db("db_scan_one_commit");
+        let mut s = db.new_session();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, name VARCHAR(64))",
+        )
+        .unwrap();
+        let values = |ids: std::ops::RangeInclusive<usize>| {
+            let rows: Vec<String> = ids
+                .map(|i| format!("({i}, '{}')", "x".repeat(i % 60)))
+                .collect();
+            format!("INSERT INTO t VALUES {}", rows.join(", "))
+        };
+        db.execute_sql(&mut s, &values(1..=2000)).unwrap();
+
+        // the writer takes the first 500 rows out and puts them back, so
+        // a scan must count either all 2000 rows or 1500
+        let writing = AtomicBool::new(true);
+        std::thread::scope(|scope| {
+            let db = &db;
+            let writing = &writing;
+            let reader = scope.spawn(move || {
+                let mut reader = db.new_session();
+                let mut scans = 0;
+                while writing.load(AtomicOrdering::Relaxed) || scans == 0 {
+                    let rows = db
+                        .execute_sql(&mut reader, "SELECT COUNT(*) FROM t WHERE id > 0")
+                        .unwrap()
+                        .rows;
+                    assert!(
+                        rows == [[Value::Integer(2000)]] || rows == [[Value::Integer(1500)]],
+                        "scan counted {rows:?}"
+                    );
+                    scans += 1;
+                }
+            });
+
+            let mut writer = db.new_session();
+            for _ in 0..20 {
+                db.execute_sql(&mut writer, "DELETE FROM t WHERE id <= 500")
+                    .unwrap();
+                db.execute_sql(&mut writer, &values(1..=500)).unwrap();
+            }
+            writing.store(false, AtomicOrdering::Relaxed);
+            reader.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn test_flush_all() {
+        let dir = std::env::temp_dir()
+            .join("lunaris_test")
+            .join("db_flush_all");
+        let db = temp_db("db_flush_all");
+        let mut s = db.new_session();
+        db.execute_sql(&mut s, "CREATE TABLE t (id INTEGER PRIMARY KEY)")
+            .unwrap();
+        db.execute_sql(&mut s, "CREATE TABLE u (id INTEGER PRIMARY KEY)")
+            .unwrap();
+
+        // written to the tree without the commit that would flush it
+        let schema = db.get_schema("t").unwrap();
+        let row = serialize_row(&schema, &[Value::Integer(1)]).unwrap();
+        db.with_table_mut("t", |tree| tree.insert(1, &row)).unwrap();
+        // an open transaction is not flushed
+        db.execute_sql(&mut s, "BEGIN").unwrap();
+        db.execute_sql(&mut s, "INSERT INTO u VALUES (1)").unwrap();
+
+        db.flush_all().unwrap();
+        drop(db);
+
+        let db = Database::open(dir).unwrap();
+        let mut s = db.new_session();
+        let rows = db.execute_sql(&mut s, "SELECT id FROM t").unwrap().rows;
+        assert_eq!(rows, vec![vec![Value::Integer(1)]]);
+        let rows = db.execute_sql(&mut s, "SELECT id FROM u").unwrap().rows;
+        assert!(rows.is_empty());
+    }
+
+    #[test]
+    fn test_failed_statement_is_rolled_back() {
+        let db = temp_db("db_statement_rollback");
+        let mut s = db.new_session();
+        db.execute_sql(&mut s, "CREATE TABLE t (id INTEGER PRIMARY KEY)")
+            .unwrap();
+        db.execute_sql(&mut s, "INSERT INTO t VALUES (1)").unwrap();
+
+        // the duplicate key aborts the whole statement, including row 2
+        assert!(
+            db.execute_sql(&mut s, "INSERT INTO t VALUES (2), (1)")
+                .is_err()
+        );
+        let rows = db.execute_sql(&mut s, "SELECT * FROM t").unwrap().rows;
+        assert_eq!(rows, vec![vec![Value::Integer(1)]]);
+    }
+
+    #[test]
+    fn test_order_by_primary_key() {
+        let db = temp_db("db_order_by");
+        let mut s = db.new_session();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, name VARCHAR(300))",
+        )
+        .unwrap();
+        for i in 1..=50 {
+            db.execute_sql(&mut s, &format!("INSERT INTO t VALUES ({i}, 'row {i}')"))
+                .unwrap();
+        }
+
+        let ids = |sql: &str| -> Vec<Value> {
+            let rows = db.execute_sql(&mut db.new_session(), sql).unwrap().rows;
+            rows.into_iter().map(|row| row[0].clone()).collect()
+        };
+        assert_eq!(
+            ids("SELECT id FROM t ORDER BY id DESC"),
+            (1..=50).rev().map(Value::Integer).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            ids("SELECT id FROM t WHERE id > 45 ORDER BY id ASC"),
+            (46..=50).map(Value::Integer).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            ids("SELECT id FROM t WHERE id <= 3 ORDER BY ID desc"),
+            vec![Value::Integer(3), Value::Integer(2), Value::Integer(1)]
+        );
+
+        assert!(matches!(
+            db.execute_sql(&mut s, "SELECT * FROM t ORDER BY missing"),
+            Err(LunarisError::ColumnNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_order_by_negative_primary_key() {
+        let db = temp_db("db_order_by_negative");
+        let mut s = db.new_session();
+        db.execute_sql(&mut s, "CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT)")
+            .unwrap();
+        db.execute_sql(
+            &mut s,
+            "INSERT INTO t VALUES (-5, 'a'), (3, 'b'), (0, 'c'), (-1, 'd')",
+        )
+        .unwrap();
+
+        let ids = |sql: &str| -> Vec<Value> {
+            let rows = db.execute_sql(&mut db.new_session(), sql).unwrap().rows;
+            rows.into_iter().map(|row| row[0].clone()).collect()
+        };
+        let ascending: Vec<Value> = [-5, -1, 0, 3].into_iter().map(Value::Integer).collect();
+        assert_eq!(ids("SELECT id FROM t ORDER BY id"), ascending);
+        assert_eq!(
+            ids("SELECT id FROM t ORDER BY id DESC"),
+            ascending.iter().rev().cloned().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            ids("SELECT id FROM t WHERE id < 1 ORDER BY id DESC"),
+            vec![Value::Integer(0), Value::Integer(-1), Value::Integer(-5)]
+        );
+    }
+
+    #[test]
+    fn test_order_by_result_columns() {
+        let db = temp_db("db_order_by_results");
+        let mut s = db.new_session();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE emp (id INTEGER PRIMARY KEY, name TEXT, dept TEXT, salary INTEGER)",
+        )
+        .unwrap();
+        db.execute_sql(
+            &mut s,
+            "INSERT INTO emp VALUES (1, 'cy', 'ops', 300), (2, 'al', 'dev', 500), \
+             (3, 'bo', 'ops', NULL), (4, 'di', 'dev', 100)",
+        )
+        .unwrap();
+
+        let names = |sql: &str| -> Vec<Value> {
+            let rows = db.execute_sql(&mut db.new_session(), sql).unwrap().rows;
+            rows.into_iter().map(|row| row[0].clone()).collect()
+        };
+        let text =
+            |v: &[&str]| -> Vec<Value> { v.iter().map(|s| Value::Text(s.to_string())).collect() };
+        // by alias, NULL sorting last when descending
+        assert_eq!(
+            names("SELECT name, salary * 2 AS pay FROM emp ORDER BY pay DESC"),
+            text(&["al", "cy", "di", "bo"])
+        );
+        // by position, ties broken by the next key
+        assert_eq!(
+            names("SELECT name, dept FROM emp ORDER BY 2, 1 DESC"),
+            text(&["di", "al", "cy", "bo"])
+        );
+        // by a column that isn't selected, NULL first unless asked otherwise
+        assert_eq!(
+            names("SELECT name FROM emp ORDER BY salary"),
+            text(&["bo", "di", "cy", "al"])
+        );
+        assert_eq!(
+            names("SELECT name FROM emp ORDER BY salary NULLS LAST"),
+            text(&["di", "cy", "al", "bo"])
+        );
+        // by a selected expression
+        let result = db
+            .execute_sql(&mut s, "SELECT UPPER(name) FROM emp ORDER BY UPPER(name)")
+            .unwrap();
+        assert_eq!(result.columns, vec!["UPPER(name)"]);
+        assert_eq!(result.rows[0], vec![Value::Text("AL".into())]);
+
+        // by an aggregate, which has a single row to order
+        for sql in [
+            "SELECT COUNT(*) AS n FROM emp ORDER BY n",
+            "SELECT COUNT(*) FROM emp ORDER BY COUNT(*) DESC",
+            "SELECT COUNT(*) FROM emp ORDER BY 1",
+        ] {
+            assert_eq!(names(sql), vec![Value::Integer(4)], "{sql}");
+        }
+        assert!(matches!(
+            db.execute_sql(&mut s, "SELECT COUNT(*) FROM emp ORDER BY name"),
+            Err(LunarisError::Compile(_))
+        ));
+        // by an aggregate of groups, through its alias, the call or its
+        // position
+        for sql in [
+            "SELECT dept, COUNT(salary) c FROM emp GROUP BY dept ORDER BY c DESC",
+            "SELECT dept, COUNT(*), SUM(salary) FROM emp GROUP BY dept ORDER BY SUM(salary) DESC",
+            "SELECT dept, MAX(name), SUM(salary) FROM emp GROUP BY dept ORDER BY 3 DESC",
+        ] {
+            assert_eq!(names(sql), text(&["dev", "ops"]), "{sql}");
+        }
+        let result = db
+            .execute_sql(
+                &mut s,
+                "SELECT dept, COUNT(*) c FROM emp WHERE salary > 200 GROUP BY dept \
+                 ORDER BY c DESC, dept",
+            )
+            .unwrap();
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![Value::Text("dev".into()), Value::Integer(1)],
+                vec![Value::Text("ops".into()), Value::Integer(1)],
+            ]
+        );
+        assert!(matches!(
+            db.execute_sql(
+                &mut s,
+                "SELECT dept, COUNT(*) FROM emp GROUP BY dept ORDER BY salary"
+            ),
+            Err(LunarisError::Compile(_))
+        ));
+        for sql in [
+            "SELECT name, dept FROM emp ORDER BY 3",
+            "SELECT name FROM emp ORDER BY 0",
+        ] {
+            assert!(
+                matches!(db.execute_sql(&mut s, sql), Err(LunarisError::Compile(_))),
+                "{sql}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_joins() {
+        let db = temp_db("db_joins");
+        let mut s = db.new_session();
+        for sql in [
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT)",
+            "CREATE TABLE orders (id INTEGER PRIMARY KEY, user_id INTEGER, total INTEGER)",
+            "CREATE TABLE items (id INTEGER PRIMARY KEY, order_id INTEGER, sku TEXT)",
+            "INSERT INTO users VALUES (1, 'al'), (2, 'bo'), (3, 'cy')",
+            "INSERT INTO orders VALUES (10, 1, 5), (11, 2, 7), (12, 1, 9)",
+            "INSERT INTO items VALUES (100, 10, 'pen'), (101, 12, 'ink')",
+        ] {
+            db.execute_sql(&mut s, sql).unwrap();
+        }
+        let rows = |sql: &str| db.execute_sql(&mut db.new_session(), sql).unwrap().rows;
+        let row = |name: &str, total: Option<i64>| {
+            vec![
+                Value::Text(name.into()),
+                total.map_or(Value::Null, Value::Integer),
+            ]
+        };
+
+        let inner = vec![row("al", Some(5)), row("al", Some(9)), row("bo", Some(7))];
+        assert_eq!(
+            rows("SELECT u.name, total FROM users u JOIN orders ON user_id = u.id"),
+            inner
+        );
+        // users without orders are kept, with NULL for the order columns
+        let mut left = inner.clone();
+        left.push(row("cy", None));
+        assert_eq!(
+            rows(
+                "SELECT users.name, orders.total FROM users \
+                 LEFT JOIN orders ON orders.user_id = users.id"
+            ),
+            left
+        );
+        assert_eq!(
+            rows(
+                "SELECT name, total FROM users LEFT OUTER JOIN orders \
+                 ON orders.user_id = users.id WHERE orders.id IS NULL"
+            ),
+            vec![row("cy", None)]
+        );
+        // every order has a user, so nothing is padded
+        assert_eq!(
+            rows(
+                "SELECT name, total FROM orders LEFT JOIN users \
+                 ON users.id = orders.user_id ORDER BY name, total"
+            ),
+            inner
+        );
+
+        // a NULL row carries on into the joins after it
+        let skus: Vec<Value> = rows(
+            "SELECT items.sku, users.name FROM users LEFT JOIN orders \
+             ON orders.user_id = users.id LEFT JOIN items ON items.order_id = orders.id",
+        )
+        .into_iter()
+        .map(|row| row[0].clone())
+        .collect();
+        let text = |s: &str| Value::Text(s.into());
+        assert_eq!(
+            skus,
+            vec![text("pen"), text("ink"), Value::Null, Value::Null]
+        );
+        assert_eq!(rows("SELECT * FROM users CROSS JOIN orders").len(), 9);
+        assert_eq!(rows("SELECT * FROM users JOIN items ON 1 = 1")[0].len(), 5);
+
+        assert!(matches!(
+            db.execute_sql(&mut s, "SELECT id FROM users JOIN orders ON user_id = 1"),
+            Err(LunarisError::AmbiguousColumn(_))
+        ));
+        for sql in [
+            // ON can't look at tables joined later
+            "SELECT * FROM users JOIN orders ON items.id = 1 JOIN items ON 1 = 1",
+            "SELECT * FROM users RIGHT JOIN orders ON user_id = users.id",
+            "SELECT * FROM users JOIN orders USING (id)",
+        ] {
+            assert!(
+                matches!(db.execute_sql(&mut s, sql), Err(LunarisError::Compile(_))),
+                "{sql}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_limit_and_offset() {
+        let db = temp_db("db_limit");
+        let mut s = db.new_session();
+        db.execute_sql(&mut s, "CREATE TABLE t (id INTEGER PRIMARY KEY, v INTEGER)")
+            .unwrap();
+        let values: Vec<String> = (1..=100).map(|i| format!("({i}, {})", i % 10)).collect();
+        db.execute_sql(
+            &mut s,
+            &format!("INSERT INTO t VALUES {}", values.join(", ")),
+        )
+        .unwrap();
+        let ids = |sql: &str| -> Vec<i64> {
+            let rows = db.execute_sql(&mut db.new_session(), sql).unwrap().rows;
+            rows.into_iter()
+                .map(|row| match row[0] {
+                    Value::Integer(i) => i,
+                    ref other => panic!("not an id: {other:?}"),
+                })
+                .collect()
+        };
+
+        assert_eq!(ids("SELECT id FROM t LIMIT 2 + 3"), vec![1, 2, 3, 4, 5]);
+        assert_eq!(ids("SELECT id FROM t OFFSET 97"), vec![98, 99, 100]);
+        assert_eq!(
+            ids("SELECT id FROM t OFFSET 5 ROWS LIMIT (4 - 1) * 1"),
+            vec![6, 7, 8]
+        );
+        assert_eq!(
+            ids("SELECT id FROM t WHERE v = 0 ORDER BY id DESC LIMIT 2 OFFSET 1"),
+            vec![90, 80]
+        );
+        // sorted rows are limited after sorting
+        assert_eq!(
+            ids("SELECT id FROM t ORDER BY v, id DESC LIMIT 3"),
+            vec![100, 90, 80]
+        );
+        assert_eq!(
+            ids("SELECT id FROM t WHERE id < 3 UNION ALL SELECT 7 LIMIT 5"),
+            vec![1, 2, 7]
+        );
+        assert!(ids("SELECT id FROM t LIMIT 0").is_empty());
+        assert!(ids("SELECT id FROM t OFFSET 1000").is_empty());
+
+        // the scan stops at the limit
+        let result = db.execute_sql(&mut s, "SELECT id FROM t LIMIT 10").unwrap();
+        assert_eq!(result.rows_examined, 10);
+        assert_eq!(result.message, "10 row(s) returned");
+        let result = db.execute_sql(&mut s, "SELECT id FROM t LIMIT 0").unwrap();
+        assert_eq!(result.message, "0 row(s) returned");
+
+        for sql in [
+            "SELECT id FROM t LIMIT -1",
+            "SELECT id FROM t LIMIT 1 - 2",
+            "SELECT id FROM t OFFSET -3",
+            "SELECT id FROM t LIMIT 2.5",
+            "SELECT id FROM t LIMIT 'ten'",
+            "SELECT id FROM t LIMIT id",
+            "SELECT id FROM t LIMIT 1 / 0",
+        ] {
+            assert!(
+                matches!(db.execute_sql(&mut s, sql), Err(LunarisError::Compile(_))),
+                "{sql}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_union() {
+        let db = temp_db("db_union");
+        let mut s = db.new_session();
+        for sql in [
+            "CREATE TABLE a (id INTEGER PRIMARY KEY, name TEXT)",
+            "CREATE TABLE b (id INTEGER PRIMARY KEY, label VARCHAR(10), flag BOOLEAN)",
+            "INSERT INTO a VALUES (1, 'x'), (2, 'y'), (3, 'x')",
+            "INSERT INTO b VALUES (1, 'z', TRUE), (2, 'x', FALSE), (3, NULL, NULL)",
+        ] {
+            db.execute_sql(&mut s, sql).unwrap();
+        }
+        let text = |v: &str| Value::Text(v.into());
+        let names = |sql: &str| -> Vec<Value> {
+            let rows = db.execute_sql(&mut db.new_session(), sql).unwrap().rows;
+            rows.into_iter().map(|row| row[0].clone()).collect()
+        };
+
+        let result = db
+            .execute_sql(
+                &mut s,
+                "SELECT name, id FROM a UNION ALL SELECT label, id FROM b",
+            )
+            .unwrap();
+        assert_eq!(result.columns, vec!["name", "id"]);
+        assert_eq!(result.rows.len(), 6);
+        assert_eq!(result.rows[3], vec![text("z"), Value::Integer(1)]);
+
+        // UNION keeps one of each row, NULLs counting as equal
+        assert_eq!(
+            names("SELECT name FROM a UNION SELECT label FROM b UNION SELECT NULL"),
+            vec![Value::Null, text("x"), text("y"), text("z")]
+        );
+        assert_eq!(
+            names("SELECT name FROM a UNION SELECT label FROM b ORDER BY name DESC"),
+            vec![text("z"), text("y"), text("x"), Value::Null]
+        );
+        assert_eq!(
+            names("SELECT id FROM a UNION ALL SELECT id FROM b ORDER BY 1 DESC").len(),
+            6
+        );
+        // rows after the last UNION are kept as they are
+        assert_eq!(
+            names("SELECT name FROM a UNION SELECT 'y' UNION ALL SELECT name FROM a"),
+            vec![text("x"), text("y"), text("x"), text("y"), text("x")]
+        );
+        assert_eq!(names("SELECT 1 UNION SELECT 1.0"), vec![Value::Integer(1)]);
+
+        for sql in [
+            "SELECT id, name FROM a UNION SELECT id FROM b",
+            "SELECT name FROM a UNION ALL SELECT id FROM b",
+            "SELECT id FROM a UNION SELECT flag FROM b",
+            "SELECT id FROM a UNION SELECT id FROM b ORDER BY missing",
+            "SELECT id FROM a EXCEPT SELECT id FROM b",
+        ] {
+            assert!(
+                matches!(db.execute_sql(&mut s, sql), Err(LunarisError::Compile(_))),
+                "{sql}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_delete_by_primary_key() {
+        let db = temp_db("db_delete_seek");
+        let mut s = db.new_session();
+        db.execute_sql(&mut s, "CREATE TABLE t (id INTEGER PRIMARY KEY, v INTEGER)")
+            .unwrap();
+        let values: Vec<String> = (1..=2000).map(|i| format!("({i}, {})", i % 10)).collect();
+        let insert = format!("INSERT INTO t VALUES {}", values.join(", "));
+        db.execute_sql(&mut s, &insert).unwrap();
+
+        let result = db
+            .execute_sql(&mut s, "DELETE FROM t WHERE id = 1234")
+            .unwrap();
+        assert_eq!(result.rows_affected, 1);
+        assert_eq!(result.rows_examined, 1);
+        let result = db
+            .execute_sql(&mut s, "DELETE FROM t WHERE id = 1234")
+            .unwrap();
+        assert_eq!(result.rows_affected, 0);
+        assert_eq!(result.rows_examined, 0);
+
+        // a range starts at its lower bound and still checks the rest of WHERE
+        let result = db
+            .execute_sql(&mut s, "DELETE FROM t WHERE id > 1990 AND v <> 5")
+            .unwrap();
+        assert_eq!(result.rows_affected, 9);
+        assert_eq!(result.rows_examined, 10);
+
+        let rows = db
+            .execute_sql(&mut s, "SELECT id FROM t WHERE id >= 1990")
+            .unwrap()
+            .rows;
+        assert_eq!(
+            rows,
+            vec![vec![Value::Integer(1990)], vec![Value::Integer(1995)]]
+        );
+        let count = db.execute_sql(&mut s, "SELECT COUNT(*) FROM t").unwrap();
+        assert_eq!(count.rows, vec![vec![Value::Integer(1990)]]);
+
+        // rows right after a deleted one are checked too
+        let result = db
+            .execute_sql(&mut s, "DELETE FROM t WHERE id < 100")
+            .unwrap();
+        assert_eq!(result.rows_affected, 99);
+        let count = db.execute_sql(&mut s, "SELECT COUNT(*) FROM t").unwrap();
+        assert_eq!(count.rows, vec![vec![Value::Integer(1891)]]);
+    }
+
+    #[test]
+    fn test_min_max_primary_key() {
+        let db = temp_db("db_min_max");
+        let mut s = db.new_session();
+        db.execute_sql(&mut s, "CREATE TABLE t (id INTEGER PRIMARY KEY, v INTEGER)")
+            .unwrap();
+        let mut query = |sql: &str| {
+            let result = db.execute_sql(&mut s, sql).unwrap();
+            (result.rows, result.rows_examined)
+        };
+        assert_eq!(query("SELECT MAX(id) FROM t"), (vec![vec![Value::Null]], 0));
+        assert_eq!(query("SELECT MIN(id) FROM t"), (vec![vec![Value::Null]], 0));
+
+        query("BEGIN");
+        for i in 1..=3000 {
+            query(&format!("INSERT INTO t VALUES ({}, {i})", i * 2));
+        }
+        query("COMMIT");
+        // a single row is read from the end of the tree, not 3000
+        assert_eq!(
+            query("SELECT MAX(id) FROM t"),
+            (vec![vec![Value::Integer(6000)]], 1)
+        );
+        assert_eq!(
+            query("SELECT min(id) AS low FROM t ORDER BY low"),
+            (vec![vec![Value::Integer(2)]], 1)
+        );
+
+        // negative keys come first
+        query("INSERT INTO t VALUES (-7, 0)");
+        query("INSERT INTO t VALUES (-3, 0)");
+        assert_eq!(
+            query("SELECT MAX(id) FROM t").0,
+            vec![vec![Value::Integer(6000)]]
+        );
+        assert_eq!(
+            query("SELECT MIN(id) FROM t").0,
+            vec![vec![Value::Integer(-7)]]
+        );
+        query("DELETE FROM t WHERE id > 0");
+        assert_eq!(
+            query("SELECT MAX(id) FROM t").0,
+            vec![vec![Value::Integer(-3)]]
+        );
+        assert_eq!(
+            query("SELECT MIN(id) FROM t").0,
+            vec![vec![Value::Integer(-7)]]
+        );
+
+        // anything else scans the table
+        assert_eq!(
+            query("SELECT MAX(v) FROM t"),
+            (vec![vec![Value::Integer(0)]], 2)
+        );
+        assert_eq!(
+            query("SELECT MAX(id) FROM t WHERE v = 1"),
+            (vec![vec![Value::Null]], 2)
+        );
+        assert!(matches!(
+            db.execute_sql(&mut s, "SELECT MIN(id) FROM t ORDER BY v"),
+            Err(LunarisError::Compile(_))
+        ));
+    }
+
+    #[test]
+    fn test_aggregates() {
+        let db = temp_db("db_aggregates");
+        let mut s = db.new_session();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT, score FLOAT, n INTEGER)",
+        )
+        .unwrap();
+        let rows = |s: &mut Session, sql: &str| db.execute_sql(s, sql).unwrap().rows;
+
+        // over no rows there is still one row of results
+        assert_eq!(
+            rows(
+                &mut s,
+                "SELECT COUNT(*), COUNT(n), SUM(n), AVG(n), MIN(name), MAX(score) FROM t"
+            ),
+            vec![vec![
+                Value::Integer(0),
+                Value::Integer(0),
+                Value::Null,
+                Value::Null,
+                Value::Null,
+                Value::Null,
+            ]]
+        );
+
+        db.execute_sql(
+            &mut s,
+            "INSERT INTO t VALUES (1, 'cy', 2.5, 4), (2, 'al', NULL, 6), \
+             (3, NULL, 0.5, NULL), (4, 'bo', 1.0, 2)",
+        )
+        .unwrap();
+        let result = db
+            .execute_sql(
+                &mut s,
+                "SELECT COUNT(*), COUNT(n) AS counted, SUM(n), AVG(n), SUM(score), \
+                 MIN(name), MAX(name), MIN(score) FROM t WHERE id > 0",
+            )
+            .unwrap();
+        assert_eq!(result.columns[..2], ["COUNT(*)", "counted"]);
+        assert_eq!(
+            result.rows,
+            vec![vec![
+                Value::Integer(4),
+                Value::Integer(3),
+                Value::Integer(12),
+                Value::Float(4.0),
+                Value::Float(4.0),
+                Value::Text("al".into()),
+                Value::Text("cy".into()),
+                Value::Float(0.5),
+            ]]
+        );
+        assert_eq!(
+            rows(
+                &mut s,
+                "SELECT SUM(n + score) FROM t WHERE id = 1 OR id = 4"
+            ),
+            vec![vec![Value::Float(9.5)]]
+        );
+
+        for sql in [
+            "SELECT name, COUNT(*) FROM t",
+            "SELECT SUM(n, score) FROM t",
+            "SELECT COUNT(DISTINCT n) FROM t",
+            "SELECT SUM(n) FROM t ORDER BY name",
+        ] {
+            assert!(
+                matches!(db.execute_sql(&mut s, sql), Err(LunarisError::Compile(_))),
+                "{sql}"
+            );
+        }
+        assert!(matches!(
+            db.execute_sql(&mut s, "SELECT SUM(name) FROM t"),
+            Err(LunarisError::Vm(_))
+        ));
+    }
+
+    #[test]
+    fn test_group_by() {
+        let db = temp_db("db_group_by");
+        let mut s = db.new_session();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE e (id INTEGER PRIMARY KEY, dept TEXT, team INTEGER, sal INTEGER)",
+        )
+        .unwrap();
+        let rows = |s: &mut Session, sql: &str| db.execute_sql(s, sql).unwrap().rows;
+        // no rows, no groups
+        assert!(rows(&mut s, "SELECT dept, COUNT(*) FROM e GROUP BY dept").is_empty());
+
+        db.execute_sql(
+            &mut s,
+            "INSERT INTO e VALUES (1, 'ops', 1, 300), (2, 'dev', 1, 500), (3, 'ops', 2, NULL), \
+             (4, 'dev', 1, 100), (5, NULL, 2, 50), (6, 'ops', 2, 10)",
+        )
+        .unwrap();
+        let text = |s: &str| Value::Text(s.into());
+        // groups come out in key order, NULL first
+        let result = db
+            .execute_sql(
+                &mut s,
+                "SELECT COUNT(*), dept, SUM(sal) AS total, AVG(sal) FROM e GROUP BY dept",
+            )
+            .unwrap();
+        assert_eq!(
+            result.columns,
+            vec!["COUNT(*)", "dept", "total", "AVG(sal)"]
+        );
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![
+                    Value::Integer(1),
+                    Value::Null,
+                    Value::Integer(50),
+                    Value::Float(50.0)
+                ],
+                vec![
+                    Value::Integer(2),
+                    text("dev"),
+                    Value::Integer(600),
+                    Value::Float(300.0)
+                ],
+                vec![
+                    Value::Integer(3),
+                    text("ops"),
+                    Value::Integer(310),
+                    Value::Float(155.0)
+                ],
+            ]
+        );
+        // by several keys, an expression among them, after WHERE
+        assert_eq!(
+            rows(
+                &mut s,
+                "SELECT dept, team * 10, MIN(id) FROM e WHERE id > 1 \
+                 GROUP BY dept, team * 10 ORDER BY 3"
+            ),
+            vec![
+                vec![text("dev"), Value::Integer(10), Value::Integer(2)],
+                vec![text("ops"), Value::Integer(20), Value::Integer(3)],
+                vec![Value::Null, Value::Integer(20), Value::Integer(5)],
+            ]
+        );
+        // without aggregates, one row per group
+        assert_eq!(
+            rows(
+                &mut s,
+                "SELECT team FROM e GROUP BY team ORDER BY team DESC"
+            ),
+            vec![vec![Value::Integer(2)], vec![Value::Integer(1)]]
+        );
+        assert_eq!(
+            rows(&mut s, "SELECT dept FROM e GROUP BY dept LIMIT 1 OFFSET 1"),
+            vec![vec![text("dev")]]
+        );
+
+        for sql in [
+            "SELECT dept, sal FROM e GROUP BY dept",
+            "SELECT * FROM e GROUP BY dept",
+            "SELECT dept, COUNT(*) FROM e GROUP BY dept HAVING COUNT(*) > 1",
+            "SELECT dept FROM e GROUP BY ALL",
+        ] {
+            assert!(
+                matches!(db.execute_sql(&mut s, sql), Err(LunarisError::Compile(_))),
+                "{sql}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_update() {
+        let db = temp_db("db_update");
+        let mut s = db.new_session();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, name VARCHAR(8) UNIQUE, score FLOAT, \
+             tag TEXT)",
+        )
+        .unwrap();
+        db.execute_sql(&mut s, "CREATE INDEX t_tag ON t (tag)")
+            .unwrap();
+        let values: Vec<String> = (1..=10)
+            .map(|i| format!("({i}, 'n{i}', {i}.5, 'small')"))
+            .collect();
+        let insert = format!("INSERT INTO t VALUES {}", values.join(", "));
+        db.execute_sql(&mut s, &insert).unwrap();
+
+        // only the rows that get past WHERE are counted
+        let result = db
+            .execute_sql(&mut s, "DELETE FROM t WHERE id > 7")
+            .unwrap();
+        assert_eq!(result.rows_affected, 3);
+        assert_eq!(result.message, "3 row(s) affected");
+        let result = db
+            .execute_sql(&mut s, "UPDATE t SET score = 0 WHERE id > 100")
+            .unwrap();
+        assert_eq!(result.rows_affected, 0);
+        assert_eq!(result.message, "0 row(s) affected");
+        let result = db
+            .execute_sql(&mut s, "DELETE FROM t WHERE id = 99")
+            .unwrap();
+        assert_eq!(result.message, "0 row(s) affected");
+
+        let result = db
+            .execute_sql(
+                &mut s,
+                "UPDATE t SET score = score * 2, tag = 'big' WHERE id <= 2",
+            )
+            .unwrap();
+        assert_eq!(result.rows_affected, 2);
+        assert_eq!(result.message, "2 row(s) affected");
+        let rows = db
+            .execute_sql(&mut s, "SELECT id, name, score FROM t WHERE tag = 'big'")
+            .unwrap()
+            .rows;
+        assert_eq!(
+            rows,
+            vec![
+                vec![
+                    Value::Integer(1),
+                    Value::Text("n1".into()),
+                    Value::Float(3.0)
+                ],
+                vec![
+                    Value::Integer(2),
+                    Value::Text("n2".into()),
+                    Value::Float(5.0)
+                ],
+            ]
+        );
+        // the index entries moved along with the values
+        assert_eq!(
+            db.index_lookup(&mut s, "t_tag", &Value::Text("small".into()))
+                .unwrap(),
+            vec![3, 4, 5, 6, 7]
+        );
+
+        // a row may keep its own unique value, but not take another's
+        db.execute_sql(&mut s, "UPDATE t SET name = name WHERE id = 2")
+            .unwrap();
+        assert!(matches!(
+            db.execute_sql(&mut s, "UPDATE t SET name = 'n1' WHERE id = 2"),
+            Err(LunarisError::UniqueConstraint(col)) if col == "name"
+        ));
+        assert!(matches!(
+            db.execute_sql(&mut s, "UPDATE t SET score = 'high'"),
+            Err(LunarisError::TypeMismatch { .. })
+        ));
+        for sql in [
+            "UPDATE t SET id = 5 WHERE id = 1",
+            "UPDATE t SET missing = 1",
+            "UPDATE t SET score = 1, score = 2",
+        ] {
+            assert!(db.execute_sql(&mut s, sql).is_err(), "{sql}");
+        }
+
+        // rows that grow past their page are still visited once each
+        let tag = "x".repeat(1000);
+        let result = db
+            .execute_sql(&mut s, &format!("UPDATE t SET tag = '{tag}'"))
+            .unwrap();
+        assert_eq!(result.rows_affected, 7);
+        let result = db
+            .execute_sql(&mut s, &format!("SELECT id FROM t WHERE tag = '{tag}'"))
+            .unwrap();
+        assert_eq!(result.rows.len(), 7);
+    }
+
+    #[test]
+    fn test_primary_key_seek() {
+        let db = temp_db("db_pk_seek");
+        let mut s = db.new_session();
+        db.execute_sql(&mut s, "CREATE TABLE t (id INTEGER PRIMARY KEY, v INTEGER)")
+            .unwrap();
+        for i in 1..=200 {
+            db.execute_sql(&mut s, &format!("INSERT INTO t VALUES ({i}, {})", i % 10))
+                .unwrap();
+        }
+
+        let full = db
+            .execute_sql(&mut s, "SELECT id FROM t WHERE v = 3")
+            .unwrap();
+        assert_eq!(full.rows.len(), 20);
+        assert_eq!(full.rows_examined, 200);
+
+        let result = db
+            .execute_sql(&mut s, "SELECT v FROM t WHERE id = 42")
+            .unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Integer(2)]]);
+        assert_eq!(result.rows_examined, 1);
+        let result = db
+            .execute_sql(&mut s, "SELECT v FROM t WHERE id = 500")
+            .unwrap();
+        assert!(result.rows.is_empty());
+        assert_eq!(result.rows_examined, 0);
+
+        let result = db
+            .execute_sql(&mut s, "SELECT id FROM t WHERE id > 190 AND v <> 5")
+            .unwrap();
+        assert_eq!(result.rows.len(), 9);
+        assert_eq!(result.rows_examined, 10);
+        let result = db
+            .execute_sql(&mut s, "SELECT id FROM t WHERE (195 <= id)")
+            .unwrap();
+        assert_eq!(result.rows.len(), 6);
+        assert_eq!(result.rows_examined, 6);
+
+        // bounds that can't be used fall back to a full scan
+        let result = db
+            .execute_sql(&mut s, "SELECT id FROM t WHERE id < 3")
+            .unwrap();
+        assert_eq!(
+            result.rows,
+            vec![vec![Value::Integer(1)], vec![Value::Integer(2)]]
+        );
+        assert_eq!(result.rows_examined, 200);
+        let result = db
+            .execute_sql(&mut s, "SELECT id FROM t WHERE id = 7 OR id = 8")
+            .unwrap();
+        assert_eq!(result.rows.len(), 2);
+        assert_eq!(result.rows_examined, 200);
+
+        // negative keys sort before the others, so bounds below zero work
+        for id in [-5, -2] {
+            db.execute_sql(&mut s, &format!("INSERT INTO t VALUES ({id}, 9)"))
+                .unwrap();
+        }
+        let result = db
+            .execute_sql(&mut s, "SELECT v FROM t WHERE id = -5")
+            .unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Integer(9)]]);
+        assert_eq!(result.rows_examined, 1);
+        let result = db
+            .execute_sql(&mut s, "SELECT id FROM t WHERE id < 2")
+            .unwrap();
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![Value::Integer(-5)],
+                vec![Value::Integer(-2)],
+                vec![Value::Integer(1)]
+            ]
+        );
+        let result = db
+            .execute_sql(&mut s, "SELECT id FROM t WHERE id > -3 AND id < 2")
+            .unwrap();
+        assert_eq!(
+            result.rows,
+            vec![vec![Value::Integer(-2)], vec![Value::Integer(1)]]
+        );
+        assert_eq!(result.rows_examined, 201);
+    }
+
+    #[test]
+    fn test_numeric_literals() {
+        let db = temp_db("db_numeric_literals");
+        let mut s = db.new_session();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, i INTEGER, f FLOAT)",
+        )
+        .unwrap();
+        db.execute_sql(
+            &mut s,
+            "INSERT INTO t VALUES (1, -42, 1.5e3), (2, -9223372036854775808, -0.001), \
+             (3, 0, -2E-2), (4, 7, 12)",
+        )
+        .unwrap();
+
+        let result = db.execute_sql(&mut s, "SELECT i, f FROM t").unwrap();
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![Value::Integer(-42), Value::Float(1500.0)],
+                vec![Value::Integer(i64::MIN), Value::Float(-0.001)],
+                vec![Value::Integer(0), Value::Float(-0.02)],
+                vec![Value::Integer(7), Value::Float(12.0)],
+            ]
+        );
+
+        let ids = |db: &Database, s: &mut Session, filter: &str| {
+            let sql = format!("SELECT id FROM t WHERE {filter}");
+            db.execute_sql(s, &sql).unwrap().rows.len()
+        };
+        assert_eq!(ids(&db, &mut s, "i = -42"), 1);
+        assert_eq!(ids(&db, &mut s, "f < -1e-3"), 1);
+        assert_eq!(ids(&db, &mut s, "f <= -1e-3"), 2);
+        assert_eq!(ids(&db, &mut s, "-f = -1500"), 1);
+        assert_eq!(ids(&db, &mut s, "f = 1.5E+3"), 1);
+
+        let result = db
+            .execute_sql(&mut s, "SELECT -f, -i, 2.5e-1, -(3) FROM t WHERE id = 1")
+            .unwrap();
+        assert_eq!(
+            result.rows,
+            vec![vec![
+                Value::Float(-1500.0),
+                Value::Integer(42),
+                Value::Float(0.25),
+                Value::Integer(-3),
+            ]]
+        );
+    }
+
+    #[test]
+    fn test_index_scan() {
+        let db = temp_db("db_index_scan");
+        let mut s = db.new_session();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, grp INTEGER, score FLOAT, tag TEXT)",
+        )
+        .unwrap();
+        let values: Vec<String> = (1..=1000)
+            .map(|i| format!("({i}, {}, {}.5, 'tag{}')", i % 100, i % 7, i % 3))
+            .collect();
+        db.execute_sql(
+            &mut s,
+            &format!("INSERT INTO t VALUES {}", values.join(", ")),
+        )
+        .unwrap();
+        db.execute_sql(&mut s, "CREATE INDEX idx_grp ON t (grp)")
+            .unwrap();
+        db.execute_sql(&mut s, "CREATE INDEX idx_score ON t (score)")
+            .unwrap();
+
+        let full = db
+            .execute_sql(&mut s, "SELECT id FROM t WHERE grp + 0 = 42")
+            .unwrap();
+        assert_eq!(full.rows_examined, 1000);
+        let indexed = db
+            .execute_sql(&mut s, "SELECT id FROM t WHERE grp = 42")
+            .unwrap();
+        assert_eq!(indexed.rows_examined, 10);
+        assert_eq!(indexed.rows, full.rows);
+
+        // the rest of WHERE still applies, in either order
+        let ids = |sql: &str| -> Vec<Value> {
+            let rows = db.execute_sql(&mut db.new_session(), sql).unwrap().rows;
+            rows.into_iter().map(|row| row[0].clone()).collect()
+        };
+        assert_eq!(
+            ids("SELECT id FROM t WHERE tag = 'tag0' AND 42 = grp"),
+            vec![
+                Value::Integer(42),
+                Value::Integer(342),
+                Value::Integer(642),
+                Value::Integer(942)
+            ]
+        );
+        assert_eq!(
+            ids("SELECT id FROM t WHERE score = 3.5 AND grp = 10"),
+            vec![Value::Integer(10), Value::Integer(710)]
+        );
+        assert!(matches!(
+            db.execute_sql(&mut s, "SELECT id FROM t WHERE grp = 'x'"),
+            Err(LunarisError::TypeMismatch { .. })
+        ));
+        assert!(ids("SELECT id FROM t WHERE grp = NULL").is_empty());
+
+        // later writes are found too
+        db.execute_sql(&mut s, "INSERT INTO t VALUES (1001, 42, 2.0, 'new')")
+            .unwrap();
+        db.execute_sql(&mut s, "DELETE FROM t WHERE id = 42")
+            .unwrap();
+        let result = db.execute_sql(&mut s, "SELECT COUNT(*) FROM t").unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Integer(1000)]]);
+        let found = ids("SELECT id FROM t WHERE grp = 42 ORDER BY id");
+        assert_eq!(found.len(), 10);
+        assert_eq!(found[0], Value::Integer(142));
+        assert_eq!(found[9], Value::Integer(1001));
+        // an integer finds the float it equals
+        assert_eq!(
+            ids("SELECT id FROM t WHERE score = 2"),
+            vec![Value::Integer(1001)]
+        );
+
+        // a row the index lists but the table lost is reported, not
+        // replaced by the row after it
+        db.with_table_for_write(&mut s, "t", |tree| tree.delete(btree::row_key(142)))
+            .unwrap();
+        db.commit(&mut s).unwrap();
+        assert!(matches!(
+            db.execute_sql(&mut s, "SELECT id FROM t WHERE grp = 42"),
+            Err(LunarisError::Storage(msg)) if msg.contains("row 142")
+        ));
+    }
+
+    #[test]
+    fn test_create_index() {
+        let db = temp_db("db_create_index");
+        let mut s = db.new_session();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, email VARCHAR(40))",
+        )
+        .unwrap();
+        db.execute_sql(
+            &mut s,
+            "INSERT INTO users VALUES (1, 'a@x.io'), (2, 'b@x.io'), (3, NULL)",
+        )
+        .unwrap();
+
+        // existing rows are indexed when the index is built
+        db.execute_sql(&mut s, "CREATE INDEX idx_email ON users (email)")
+            .unwrap();
+        let email = |e: &str| Value::Text(e.into());
+        assert_eq!(
+            db.index_lookup(&mut s, "idx_email", &email("a@x.io"))
+                .unwrap(),
+            vec![1]
+        );
+        assert_eq!(
+            db.index_lookup(&mut s, "idx_email", &email("b@x.io"))
+                .unwrap(),
+            vec![2]
+        );
+        assert!(
+            db.index_lookup(&mut s, "idx_email", &Value::Null)
+                .unwrap()
+                .is_empty()
+        );
+
+        db.execute_sql(
+            &mut s,
+            "INSERT INTO users VALUES (4, 'a@x.io'), (5, 'c@x.io')",
+        )
+        .unwrap();
+        db.execute_sql(&mut s, "DELETE FROM users WHERE id = 1 OR id = 5")
+            .unwrap();
+        assert_eq!(
+            db.index_lookup(&mut s, "idx_email", &email("a@x.io"))
+                .unwrap(),
+            vec![4]
+        );
+        assert!(
+            db.index_lookup(&mut s, "idx_email", &email("c@x.io"))
+                .unwrap()
+                .is_empty()
+        );
+
+        // rolled back changes don't stay in the index
+        db.execute_sql(&mut s, "BEGIN").unwrap();
+        db.execute_sql(&mut s, "INSERT INTO users VALUES (6, 'd@x.io')")
+            .unwrap();
+        assert_eq!(
+            db.index_lookup(&mut s, "idx_email", &email("d@x.io"))
+                .unwrap(),
+            vec![6]
+        );
+        assert!(matches!(
+            db.execute_sql(&mut s, "CREATE INDEX idx_id ON users (id)"),
+            Err(LunarisError::Transaction(_))
+        ));
+        db.execute_sql(&mut s, "ROLLBACK").unwrap();
+        assert!(
+            db.index_lookup(&mut s, "idx_email", &email("d@x.io"))
+                .unwrap()
+                .is_empty()
+        );
+
+        assert!(matches!(
+            db.execute_sql(&mut s, "CREATE INDEX idx_email ON users (id)"),
+            Err(LunarisError::IndexAlreadyExists(_))
+        ));
+        db.execute_sql(&mut s, "CREATE INDEX IF NOT EXISTS idx_email ON users (id)")
+            .unwrap();
+        assert!(matches!(
+            db.execute_sql(&mut s, "CREATE INDEX users ON users (id)"),
+            Err(LunarisError::TableAlreadyExists(_))
+        ));
+        assert!(matches!(
+            db.execute_sql(&mut s, "CREATE TABLE idx_email (id INTEGER)"),
+            Err(LunarisError::IndexAlreadyExists(_))
+        ));
+        assert!(matches!(
+            db.execute_sql(&mut s, "CREATE INDEX idx_name ON users (name)"),
+            Err(LunarisError::ColumnNotFound(_))
+        ));
+
+        // the index is kept in the catalog and maintained after reopening
+        drop(db);
+        let dir = std::env::temp_dir()
+            .join("lunaris_test")
+            .join("db_create_index");
+        let db = Database::open(dir).unwrap();
+        let mut s = db.new_session();
+        assert_eq!(db.indexes_on("users").len(), 1);
+        db.execute_sql(&mut s, "INSERT INTO users VALUES (7, 'b@x.io')")
+            .unwrap();
+        assert_eq!(
+            db.index_lookup(&mut s, "idx_email", &email("b@x.io"))
+                .unwrap(),
+            vec![2, 7]
+        );
+
+        db.execute_sql(&mut s, "TRUNCATE users").unwrap();
+        assert!(
+            db.index_lookup(&mut s, "idx_email", &email("b@x.io"))
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_truncate_table() {
+        let db = temp_db("db_truncate");
+        let mut s = db.new_session();
+        db.execute_sql(&mut s, "CREATE TABLE t (id INTEGER, name VARCHAR(200))")
+            .unwrap();
+        for i in 0..100 {
+            db.execute_sql(&mut s, &format!("INSERT INTO t VALUES ({i}, 'row {i}')"))
+                .unwrap();
+        }
+
+        // rolling back brings every row back
+        db.execute_sql(&mut s, "BEGIN").unwrap();
+        let result = db.execute_sql(&mut s, "TRUNCATE TABLE t").unwrap();
+        assert_eq!(result.rows_affected, 100);
+        assert!(
+            db.execute_sql(&mut s, "SELECT * FROM t")
+                .unwrap()
+                .rows
+                .is_empty()
+        );
+        db.execute_sql(&mut s, "ROLLBACK").unwrap();
+        let rows = db.execute_sql(&mut s, "SELECT id FROM t").unwrap().rows;
+        assert_eq!(rows.len(), 100);
+
+        let result = db.execute_sql(&mut s, "TRUNCATE t").unwrap();
+        assert_eq!(result.rows_affected, 100);
+        assert!(
+            db.execute_sql(&mut s, "SELECT * FROM t")
+                .unwrap()
+                .rows
+                .is_empty()
+        );
+        let path = std::env::temp_dir()
+            .join("lunaris_test")
+            .join("db_truncate")
+            .join("t.db");
+        let size = std::fs::metadata(&path).unwrap().len();
+        assert_eq!(size, (META_PAGE_SIZE + PAGE_SIZE) as u64);
+
+        // the emptied table takes rows again
+        db.execute_sql(&mut s, "INSERT INTO t VALUES (7, 'again')")
+            .unwrap();
+        let result = db.execute_sql(&mut s, "DELETE FROM t").unwrap();
+        assert_eq!(result.rows_affected, 1);
+        assert!(matches!(
+            db.execute_sql(&mut s, "TRUNCATE missing"),
+            Err(LunarisError::TableNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_read_only() {
+        let dir = std::env::temp_dir()
+            .join("lunaris_test")
+            .join("db_read_only");
+        let db = temp_db("db_read_only");
+        let mut s = db.new_session();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, name VARCHAR(20))",
+        )
+        .unwrap();
+        db.execute_sql(&mut s, "INSERT INTO t VALUES (1, 'a'), (2, 'b')")
+            .unwrap();
+        drop(db);
+
+        let files = |dir: &PathBuf| -> Vec<(PathBuf, Vec<u8>)> {
+            let mut files: Vec<_> = std::fs::read_dir(dir)
+                .unwrap()
+                .map(|entry| {
+                    let path = entry.unwrap().path();
+                    let bytes = std::fs::read(&path).unwrap();
+                    (path, bytes)
+                })
+                .collect();
+            files.sort();
+            files
+        };
+        let before = files(&dir);
+
+        let db = Database::open_read_only(dir.clone()).unwrap();
+        let mut s = db.new_session();
+        let rows = db
+            .execute_sql(&mut s, "SELECT name FROM t WHERE id = 2")
+            .unwrap()
+            .rows;
+        assert_eq!(rows, vec![vec![Value::Text("b".into())]]);
+        assert_eq!(db.execute_sql(&mut s, "SHOW TABLES").unwrap().rows.len(), 1);
+
+        for sql in [
+            "INSERT INTO t VALUES (3, 'c')",
+            "DELETE FROM t",
+            "TRUNCATE t",
+            "CREATE TABLE u (id INTEGER)",
+            "CREATE INDEX t_name ON t (name)",
+        ] {
+            assert!(
+                matches!(db.execute_sql(&mut s, sql), Err(LunarisError::ReadOnly)),
+                "{sql}"
+            );
+        }
+        // a read-only transaction can still be opened and closed
+        db.execute_sql(&mut s, "BEGIN").unwrap();
+        db.execute_sql(&mut s, "SELECT * FROM t").unwrap();
+        db.execute_sql(&mut s, "COMMIT").unwrap();
+        drop(db);
+
+        assert_eq!(files(&dir), before);
+        assert!(Database::open_read_only(dir.join("missing")).is_err());
+    }
+
+    #[test]
+    fn test_show_tables() {
+        let db = temp_db("db_show_tables");
+        let mut s = db.new_session();
+        db.execute_sql(&mut s, "CREATE TABLE users (id INTEGER)")
+            .unwrap();
+        db.execute_sql(&mut s, "CREATE TABLE orders (id INTEGER)")
+            .unwrap();
+
+        let result = db.execute_sql(&mut s, "SHOW TABLES").unwrap();
+        assert_eq!(result.columns, vec!["table_name".to_string()]);
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![Value::Text("orders".into())],
+                vec![Value::Text("users".into())]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_elapsed_time() {
+        let db = temp_db("db_elapsed");
+        let mut s = db.new_session();
+        db.execute_sql(&mut s, "CREATE TABLE t (id INTEGER PRIMARY KEY, x INTEGER)")
+            .unwrap();
+        let values: Vec<String> = (0..500).map(|i| format!("({i}, {})", i * 2)).collect();
+        let insert = format!("INSERT INTO t VALUES {}", values.join(", "));
+        assert!(db.execute_sql(&mut s, &insert).unwrap().elapsed_micros > 0);
+
+        let result = db
+            .execute_sql(&mut s, "SELECT id FROM t WHERE x % 3 = 0")
+            .unwrap();
+        assert!(result.elapsed_micros > 0);
+    }
+
+    #[test]
+    fn test_result_row_limit() {
+        let mut db = temp_db("db_result_limit");
+        db.set_max_result_rows(10);
+        let mut s = db.new_session();
+        db.execute_sql(&mut s, "CREATE TABLE t (id INTEGER PRIMARY KEY)")
+            .unwrap();
+        let values: Vec<String> = (1..=20).map(|i| format!("({i})")).collect();
+        let insert = format!("INSERT INTO t VALUES {}", values.join(", "));
+        // inserting isn't returning rows
+        db.execute_sql(&mut s, &insert).unwrap();
+
+        let result = db.execute_sql(&mut s, "SELECT * FROM t WHERE id <= 10");
+        assert_eq!(result.unwrap().rows.len(), 10);
+        let err = db.execute_sql(&mut s, "SELECT * FROM t");
+        assert!(matches!(err, Err(LunarisError::Vm(msg)) if msg.contains("result set too large")));
+
+        // the limit holds while streaming too
+        let mut streamed = 0;
+        let result = db.execute_sql_streaming(&mut s, "SELECT * FROM t", |_, _, _| {
+            streamed += 1;
+            Ok(())
+        });
+        assert!(matches!(result, Err(LunarisError::Vm(_))));
+        assert_eq!(streamed, 10);
+    }
+
+    #[test]
+    fn test_statement_timeout() {
+        use crate::vm::bytecode::Instruction;
+
+        let mut db = temp_db("db_statement_timeout");
+        db.set_statement_timeout(Duration::from_millis(50));
+        let mut s = db.new_session();
+        let mut prog = Program::new();
+        prog.emit(Instruction::Goto { target: 0 });
+
+        let started = Instant::now();
+        let result = Lvm::new().execute(&db, &mut s, &prog);
+        assert!(matches!(result, Err(LunarisError::Timeout(50))));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        // the session is still usable afterwards
+        db.execute_sql(&mut s, "CREATE TABLE t (id INTEGER PRIMARY KEY)")
+            .unwrap();
+    }
+
+    #[test]
+    fn test_catalog_table() {
+        let db = temp_db("db_catalog_table");
+        let mut s = db.new_session();
+        let result = db
+            .execute_sql(&mut s, "SELECT * FROM lunaris_tables")
+            .unwrap();
+        assert_eq!(result.columns, vec!["name", "column_count"]);
+        assert!(result.rows.is_empty());
+
+        db.execute_sql(&mut s, "CREATE TABLE users (id INTEGER, name TEXT)")
+            .unwrap();
+        db.execute_sql(&mut s, "CREATE TABLE orders (id INTEGER)")
+            .unwrap();
+        let result = db
+            .execute_sql(&mut s, "SELECT * FROM lunaris_tables")
+            .unwrap();
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![Value::Text("orders".into()), Value::Integer(1)],
+                vec![Value::Text("users".into()), Value::Integer(2)],
+            ]
+        );
+        let result = db
+            .execute_sql(&mut s, "SELECT column_count AS n, name FROM LUNARIS_TABLES")
+            .unwrap();
+        assert_eq!(result.columns, vec!["n", "name"]);
+        assert_eq!(
+            result.rows[1],
+            vec![Value::Integer(2), Value::Text("users".into())]
+        );
+
+        for sql in [
+            "SELECT * FROM lunaris_tables WHERE name = 'users'",
+            "SELECT UPPER(name) FROM lunaris_tables",
+        ] {
+            assert!(matches!(
+                db.execute_sql(&mut s, sql),
+                Err(LunarisError::Compile(_))
+            ));
+        }
+        assert!(matches!(
+            db.execute_sql(&mut s, "CREATE TABLE lunaris_tables (id INTEGER)"),
+            Err(LunarisError::InvalidName(_))
+        ));
+    }
+
+    #[test]
+    fn test_status_table() {
+        let db = temp_db("db_status");
+        let mut s = db.new_session();
+        let stats = |s: &mut Session, db: &Database| -> Vec<(String, Value, i64)> {
+            let result = db.execute_sql(s, "SELECT * FROM lunaris_status").unwrap();
+            assert_eq!(result.columns, vec!["stat", "object", "value"]);
+            result
+                .rows
+                .into_iter()
+                .map(|row| match &row[..] {
+                    [Value::Text(stat), object, Value::Integer(value)] => {
+                        (stat.clone(), object.clone(), *value)
+                    }
+                    other => panic!("unexpected status row {other:?}"),
+                })
+                .collect()
+        };
+        let stat = |stats: &[(String, Value, i64)], name: &str| {
+            stats.iter().find(|(stat, ..)| stat == name).unwrap().2
+        };
+
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT)",
+        )
+        .unwrap();
+        db.execute_sql(&mut s, "CREATE TABLE orders (id INTEGER PRIMARY KEY)")
+            .unwrap();
+        db.execute_sql(&mut s, "CREATE INDEX users_name ON users (name)")
+            .unwrap();
+        for i in 0..200 {
+            db.execute_sql(
+                &mut s,
+                &format!("INSERT INTO users VALUES ({i}, 'user {i}')"),
+            )
+            .unwrap();
+        }
+        let rows = stats(&mut s, &db);
+        assert_eq!(stat(&rows, "open_tables"), 2);
+        assert_eq!(stat(&rows, "open_indexes"), 1);
+        assert!(stat(&rows, "cached_pages") >= 3);
+        assert!(stat(&rows, "uptime_seconds") >= 0);
+        let pages: Vec<(Value, i64)> = rows
+            .iter()
+            .filter(|(stat, ..)| stat == "pages")
+            .map(|(_, object, value)| (object.clone(), *value))
+            .collect();
+        assert_eq!(pages.len(), 3);
+        assert_eq!(pages[0], (Value::Text("orders".into()), 1));
+        assert!(pages[1].1 > 1, "{pages:?}");
+
+        // files are opened as statements use them
+        drop(db);
+        let dir = std::env::temp_dir().join("lunaris_test").join("db_status");
+        let db = Database::open(dir).unwrap();
+        let mut s = db.new_session();
+        assert_eq!(stat(&stats(&mut s, &db), "open_tables"), 0);
+        db.execute_sql(&mut s, "SELECT * FROM orders").unwrap();
+        assert_eq!(stat(&stats(&mut s, &db), "open_tables"), 1);
+
+        let result = db
+            .execute_sql(&mut s, "SELECT value, stat FROM lunaris_status")
+            .unwrap();
+        assert_eq!(result.columns, vec!["value", "stat"]);
+        for sql in [
+            "SELECT * FROM lunaris_status WHERE stat = 'open_tables'",
+            "SELECT * FROM lunaris_status ORDER BY value",
+            "SELECT * FROM lunaris_status JOIN users ON users.id = value",
+        ] {
+            assert!(
+                matches!(db.execute_sql(&mut s, sql), Err(LunarisError::Compile(_))),
+                "{sql}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_row_counts() {
+        let dir = std::env::temp_dir()
+            .join("lunaris_test")
+            .join("db_row_counts");
+        let db = temp_db("db_row_counts");
+        let mut s = db.new_session();
+        let count = |db: &Database, s: &mut Session| {
+            db.execute_sql(s, "SELECT COUNT(*) FROM t").unwrap().rows
+        };
+        db.execute_sql(&mut s, "CREATE TABLE t (id INTEGER PRIMARY KEY)")
+            .unwrap();
+        assert_eq!(count(&db, &mut s), vec![vec![Value::Integer(0)]]);
+
+        db.execute_sql(&mut s, "INSERT INTO t VALUES (1), (2), (3), (4)")
+            .unwrap();
+        db.execute_sql(&mut s, "DELETE FROM t WHERE id = 2")
+            .unwrap();
+        assert_eq!(count(&db, &mut s), vec![vec![Value::Integer(3)]]);
+        let result = db
+            .execute_sql(&mut s, "SELECT count(*) AS n FROM t")
+            .unwrap();
+        assert_eq!(result.columns, vec!["n"]);
+
+        // uncommitted changes count for their own session only
+        let mut other = db.new_session();
+        db.execute_sql(&mut s, "BEGIN").unwrap();
+        db.execute_sql(&mut s, "INSERT INTO t VALUES (5)").unwrap();
+        assert_eq!(count(&db, &mut s), vec![vec![Value::Integer(4)]]);
+        assert_eq!(count(&db, &mut other), vec![vec![Value::Integer(3)]]);
+        db.execute_sql(&mut s, "ROLLBACK").unwrap();
+        assert_eq!(count(&db, &mut s), vec![vec![Value::Integer(3)]]);
+
+        db.execute_sql(&mut s, "BEGIN").unwrap();
+        db.execute_sql(&mut s, "TRUNCATE TABLE t").unwrap();
+        db.execute_sql(&mut s, "INSERT INTO t VALUES (7), (8)")
+            .unwrap();
+        db.execute_sql(&mut s, "COMMIT").unwrap();
+        assert_eq!(count(&db, &mut s), vec![vec![Value::Integer(2)]]);
+
+        // with WHERE the matching rows are counted
+        let result = db
+            .execute_sql(&mut s, "SELECT COUNT(*) FROM t WHERE id > 7")
+            .unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Integer(1)]]);
+        assert_eq!(result.rows_examined, 1);
+
+        drop(db);
+        let db = Database::open(dir).unwrap();
+        let mut s = db.new_session();
+        assert_eq!(db.get_schema("t").unwrap().row_count, Some(2));
+        assert_eq!(count(&db, &mut s), vec![vec![Value::Integer(2)]]);
+    }
+
+    #[test]
+    fn test_row_count_after_crash() {
+        let dir = std::env::temp_dir()
+            .join("lunaris_test")
+            .join("db_row_count_crash");
+        let db = temp_db("db_row_count_crash");
+        let mut s = db.new_session();
+        db.execute_sql(&mut s, "CREATE TABLE t (id INTEGER PRIMARY KEY)")
+            .unwrap();
+        db.execute_sql(&mut s, "INSERT INTO t VALUES (1)").unwrap();
+
+        // stop a commit after its rows are written but before their count
+        db.execute_sql(&mut s, "BEGIN").unwrap();
+        db.execute_sql(&mut s, "INSERT INTO t VALUES (2), (3)")
+            .unwrap();
+        db.clear_stored_row_counts(&s.row_deltas).unwrap();
+        db.with_table_mut("t", |tree| tree.commit()).unwrap();
+        drop(db);
+
+        let db = Database::open(dir).unwrap();
+        assert_eq!(db.get_schema("t").unwrap().row_count, Some(3));
+    }
+
+    #[test]
+    fn test_backup() {
+        let dest = std::env::temp_dir()
+            .join("lunaris_test")
+            .join("db_backup_copy");
+        let _ = std::fs::remove_dir_all(&dest);
+        let db = temp_db("db_backup");
+        let mut s = db.new_session();
+        db.execute_sql(&mut s, "CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT)")
+            .unwrap();
+        db.execute_sql(&mut s, "CREATE INDEX t_name ON t (name)")
+            .unwrap();
+        db.execute_sql(&mut s, "INSERT INTO t VALUES (1, 'a'), (2, 'b')")
+            .unwrap();
+
+        let sql = format!("BACKUP TO '{}'", dest.display());
+        let result = db.execute_sql(&mut s, &sql).unwrap();
+        assert!(result.message.starts_with("Backup written to"));
+        // later writes don't reach the copy
+        db.execute_sql(&mut s, "INSERT INTO t VALUES (3, 'c')")
+            .unwrap();
+
+        let copy = Database::open(dest.clone()).unwrap();
+        let mut cs = copy.new_session();
+        let rows = copy.execute_sql(&mut cs, "SELECT * FROM t").unwrap().rows;
+        assert_eq!(
+            rows,
+            vec![
+                vec![Value::Integer(1), Value::Text("a".into())],
+                vec![Value::Integer(2), Value::Text("b".into())],
+            ]
+        );
+        assert_eq!(
+            copy.index_lookup(&mut cs, "t_name", &Value::Text("b".into()))
+                .unwrap(),
+            vec![2]
+        );
+        assert_eq!(copy.get_schema("t").unwrap().row_count, Some(2));
+
+        // an existing database is never overwritten
+        assert!(matches!(
+            db.execute_sql(&mut s, &sql),
+            Err(LunarisError::Storage(_))
+        ));
+        db.execute_sql(&mut s, "BEGIN").unwrap();
+        assert!(matches!(
+            db.execute_sql(&mut s, &sql),
+            Err(LunarisError::Transaction(_))
+        ));
+    }
+
+    #[test]
+    fn test_copy_from_csv() {
+        let dir = std::env::temp_dir().join("lunaris_test");
+        let good = dir.join("copy_users.csv");
+        let bad = dir.join("copy_users_bad.csv");
+        let db = temp_db("db_copy_csv");
+        std::fs::write(
+            &good,
+            "active,id,name\ntrue,1,alice\nf,2,\"smith, bob\"\n0,3,\n",
+        )
+        .unwrap();
+        std::fs::write(&bad, "4,dave,true\n5,erin,maybe\n").unwrap();
+        let mut s = db.new_session();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT, active BOOLEAN)",
+        )
+        .unwrap();
+
+        let sql = format!(
+            "COPY users FROM '{}' (FORMAT csv, HEADER true)",
+            good.display()
+        );
+        assert_eq!(db.execute_sql(&mut s, &sql).unwrap().rows_affected, 3);
+        let rows = db.execute_sql(&mut s, "SELECT * FROM users").unwrap().rows;
+        assert_eq!(
+            rows,
+            vec![
+                vec![
+                    Value::Integer(1),
+                    Value::Text("alice".into()),
+                    Value::Boolean(true)
+                ],
+                vec![
+                    Value::Integer(2),
+                    Value::Text("smith, bob".into()),
+                    Value::Boolean(false)
+                ],
+                vec![Value::Integer(3), Value::Null, Value::Boolean(false)],
+            ]
+        );
+
+        // one bad field and none of the file is imported
+        let sql = format!("COPY users FROM '{}' (FORMAT csv)", bad.display());
+        match db.execute_sql(&mut s, &sql) {
+            Err(LunarisError::Import { line, message }) => {
+                assert_eq!(line, 2);
+                assert!(message.contains("'maybe'"), "{message}");
+            }
+            Err(e) => panic!("expected an import error, got {e}"),
+            Ok(_) => panic!("expected an import error"),
+        }
+        let count = db
+            .execute_sql(&mut s, "SELECT COUNT(*) FROM users")
+            .unwrap();
+        assert_eq!(count.rows, vec![vec![Value::Integer(3)]]);
+
+        let _ = std::fs::remove_file(&good);
+        let _ = std::fs::remove_file(&bad);
+    }
+
+    #[test]
+    fn test_dump_roundtrip() {
+        let db = temp_db("db_dump");
+        let mut s = db.new_session();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, name VARCHAR(16) NOT NULL UNIQUE, \
+             note TEXT DEFAULT 'it''s', ok BOOLEAN, score FLOAT, at TIMESTAMP, raw BLOB(4))",
+        )
+        .unwrap();
+        db.execute_sql(&mut s, "CREATE INDEX t_score ON t (score)")
+            .unwrap();
+        db.execute_sql(
+            &mut s,
+            "INSERT INTO t VALUES \
+             (-9223372036854775808, 'a', NULL, TRUE, -0.1, '2024-02-29T12:30:15.250Z', X'00ff'), \
+             (2, 'b''c', '', FALSE, 1e300, NULL, NULL)",
+        )
+        .unwrap();
+        db.execute_sql(&mut s, "INSERT INTO t (id, name) VALUES (3, 'c')")
+            .unwrap();
+
+        let result = db.execute_sql(&mut s, "DUMP t").unwrap();
+        assert_eq!(result.columns, vec!["sql"]);
+        assert_eq!(result.rows.len(), 5);
+
+        let copy = temp_db("db_dump_copy");
+        let mut cs = copy.new_session();
+        for row in &result.rows {
+            let Value::Text(sql) = &row[0] else {
+                panic!("expected SQL text, got {row:?}");
+            };
+            parser::parse_sql(sql).unwrap();
+            copy.execute_sql(&mut cs, sql).unwrap();
+        }
+        let select = "SELECT * FROM t";
+        assert_eq!(
+            copy.execute_sql(&mut cs, select).unwrap().rows,
+            db.execute_sql(&mut s, select).unwrap().rows
+        );
+        assert_eq!(
+            copy.get_schema("t").unwrap().columns,
+            db.get_schema("t").unwrap().columns
+        );
+        assert_eq!(copy.indexes_on("t"), db.indexes_on("t"));
+    }
+
+    #[test]
+    fn test_qualified_columns() {
+        let db = temp_db("db_qualified_columns");
+        let mut s = db.new_session();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT)",
+        )
+        .unwrap();
+        db.execute_sql(&mut s, "INSERT INTO users VALUES (1, 'ann'), (2, 'bob')")
+            .unwrap();
+
+        let result = db
+            .execute_sql(&mut s, "SELECT users.id FROM users")
+            .unwrap();
+        assert_eq!(result.columns, vec!["id"]);
+        assert_eq!(
+            result.rows,
+            vec![vec![Value::Integer(1)], vec![Value::Integer(2)]]
+        );
+
+        let sql = "SELECT u.name, users.id AS key FROM users AS u WHERE u.id = 2";
+        let result = db.execute_sql(&mut s, sql).unwrap();
+        assert_eq!(result.columns, vec!["name", "key"]);
+        assert_eq!(
+            result.rows,
+            vec![vec![Value::Text("bob".into()), Value::Integer(2)]]
+        );
+        // the qualified key still seeks straight to the row
+        assert_eq!(result.rows_examined, 1);
+
+        db.execute_sql(&mut s, "DELETE FROM users WHERE users.name = 'ann'")
+            .unwrap();
+        let rows = db.execute_sql(&mut s, "SELECT id FROM users ORDER BY users.id DESC");
+        assert_eq!(rows.unwrap().rows, vec![vec![Value::Integer(2)]]);
+
+        assert!(matches!(
+            db.execute_sql(&mut s, "SELECT orders.id FROM users"),
+            Err(LunarisError::Compile(msg)) if msg.contains("'orders' is not in FROM")
+        ));
+        assert!(matches!(
+            db.execute_sql(&mut s, "SELECT * FROM users WHERE users.age > 1"),
+            Err(LunarisError::ColumnNotFound(name)) if name == "users.age"
+        ));
+    }
+
+    #[test]
+    fn test_create_table_if_not_exists() {
+        let db = temp_db("db_create_if_not_exists");
+        let mut s = db.new_session();
+        let sql = "CREATE TABLE IF NOT EXISTS t (id INTEGER PRIMARY KEY)";
+        let result = db.execute_sql(&mut s, sql).unwrap();
+        assert_eq!(result.message, "Table 't' created");
+        db.execute_sql(&mut s, "INSERT INTO t VALUES (1)").unwrap();
+
+        let result = db.execute_sql(&mut s, sql).unwrap();
+        assert_eq!(result.message, "Table 't' already exists, nothing done");
+        let rows = db.execute_sql(&mut s, "SELECT * FROM t").unwrap().rows;
+        assert_eq!(rows, vec![vec![Value::Integer(1)]]);
+
+        assert!(matches!(
+            db.execute_sql(&mut s, "CREATE TABLE t (id INTEGER)"),
+            Err(LunarisError::TableAlreadyExists(_))
+        ));
+        db.execute_sql(&mut s, "CREATE INDEX t_id ON t (id)")
+            .unwrap();
+        assert!(matches!(
+            db.execute_sql(&mut s, "CREATE TABLE IF NOT EXISTS t_id (id INTEGER)"),
+            Err(LunarisError::IndexAlreadyExists(_))
+        ));
+    }
+
+    #[test]
+    fn test_alter_table() {
+        let db = temp_db("db_alter_table");
+        let mut s = db.new_session();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, Name TEXT, score INTEGER)",
+        )
+        .unwrap();
+        db.execute_sql(&mut s, "CREATE INDEX t_score ON t (score)")
+            .unwrap();
+        db.execute_sql(&mut s, "INSERT INTO t VALUES (1, 'ann', 5), (2, 'bob', 7)")
+            .unwrap();
+        let text = |v: &str| Value::Text(v.into());
+
+        // added columns come last and existing rows get their default
+        db.execute_sql(&mut s, "ALTER TABLE t ADD COLUMN Level INTEGER DEFAULT 1")
+            .unwrap();
+        db.execute_sql(&mut s, "ALTER TABLE t ADD note TEXT")
+            .unwrap();
+        let result = db.execute_sql(&mut s, "SELECT * FROM t").unwrap();
+        assert_eq!(result.columns, vec!["id", "Name", "score", "Level", "note"]);
+        assert_eq!(
+            result.rows[1],
+            vec![
+                Value::Integer(2),
+                text("bob"),
+                Value::Integer(7),
+                Value::Integer(1),
+                Value::Null
+            ]
+        );
+
+        db.execute_sql(&mut s, "ALTER TABLE t DROP COLUMN name")
+            .unwrap();
+        db.execute_sql(&mut s, "INSERT INTO t VALUES (3, 7, 2, 'new')")
+            .unwrap();
+        let result = db.execute_sql(&mut s, "SELECT * FROM t").unwrap();
+        assert_eq!(result.columns, vec!["id", "score", "Level", "note"]);
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![
+                    Value::Integer(1),
+                    Value::Integer(5),
+                    Value::Integer(1),
+                    Value::Null
+                ],
+                vec![
+                    Value::Integer(2),
+                    Value::Integer(7),
+                    Value::Integer(1),
+                    Value::Null
+                ],
+                vec![
+                    Value::Integer(3),
+                    Value::Integer(7),
+                    Value::Integer(2),
+                    text("new")
+                ],
+            ]
+        );
+        // the index follows its column to its new position
+        assert_eq!(db.indexes_on("t")[0].column, 1);
+        assert_eq!(
+            db.execute_sql(&mut s, "SELECT id FROM t WHERE score = 7")
+                .unwrap()
+                .rows,
+            vec![vec![Value::Integer(2)], vec![Value::Integer(3)]]
+        );
+
+        let fails = [
+            "ALTER TABLE t DROP COLUMN id",
+            "ALTER TABLE t DROP COLUMN score",
+            "ALTER TABLE t DROP COLUMN missing",
+            "ALTER TABLE t ADD COLUMN note TEXT",
+            "ALTER TABLE t ADD COLUMN code INTEGER UNIQUE DEFAULT 0",
+            "ALTER TABLE t ADD COLUMN code INTEGER NOT NULL",
+            "ALTER TABLE missing ADD COLUMN code INTEGER",
+        ];
+        for sql in fails {
+            assert!(db.execute_sql(&mut s, sql).is_err(), "{sql}");
+        }
+        db.execute_sql(&mut s, "ALTER TABLE t DROP COLUMN IF EXISTS missing")
+            .unwrap();
+        db.execute_sql(
+            &mut s,
+            "ALTER TABLE IF EXISTS missing ADD COLUMN code INTEGER",
+        )
+        .unwrap();
+        db.execute_sql(&mut s, "BEGIN").unwrap();
+        assert!(matches!(
+            db.execute_sql(&mut s, "ALTER TABLE t DROP COLUMN note"),
+            Err(LunarisError::Transaction(_))
+        ));
+        db.execute_sql(&mut s, "ROLLBACK").unwrap();
+
+        // the new schema is kept in the catalog
+        drop(db);
+        let dir = std::env::temp_dir()
+            .join("lunaris_test")
+            .join("db_alter_table");
+        let db = Database::open(dir).unwrap();
+        let mut s = db.new_session();
+        let result = db
+            .execute_sql(&mut s, "SELECT * FROM t WHERE id = 3")
+            .unwrap();
+        assert_eq!(result.columns, vec!["id", "score", "Level", "note"]);
+        assert_eq!(
+            result.rows,
+            vec![vec![
+                Value::Integer(3),
+                Value::Integer(7),
+                Value::Integer(2),
+                text("new")
+            ]]
+        );
+        assert_eq!(db.row_count(&s, "t").unwrap(), 3);
+    }
+
+    #[test]
+    fn test_insert_returning() {
+        let db = temp_db("db_insert_returning");
+        let mut s = db.new_session();
+        db.execute_sql(&mut s, "CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT)")
+            .unwrap();
+
+        let result = db
+            .execute_sql(&mut s, "INSERT INTO t (name) VALUES ('a') RETURNING id")
+            .unwrap();
+        assert_eq!(result.columns, vec!["id"]);
+        let Value::Integer(first) = result.rows[0][0] else {
+            panic!("expected an integer id, got {:?}", result.rows);
+        };
+        let result = db
+            .execute_sql(&mut s, "INSERT INTO t (name) VALUES ('b') RETURNING id")
+            .unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Integer(first + 1)]]);
+        assert_eq!(result.rows_affected, 1);
+
+        // explicit keys are returned as given, and auto ids continue past them
+        let result = db
+            .execute_sql(
+                &mut s,
+                "INSERT INTO t VALUES (10, 'c'), (-3, 'd') RETURNING id AS key",
+            )
+            .unwrap();
+        assert_eq!(result.columns, vec!["key"]);
+        assert_eq!(
+            result.rows,
+            vec![vec![Value::Integer(10)], vec![Value::Integer(-3)]]
+        );
+        let result = db
+            .execute_sql(&mut s, "INSERT INTO t (name) VALUES ('e') RETURNING id")
+            .unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Integer(11)]]);
+
+        for sql in [
+            "INSERT INTO t VALUES (20, 'f') RETURNING name",
+            "INSERT INTO t VALUES (20, 'f') RETURNING *",
+        ] {
+            assert!(matches!(
+                db.execute_sql(&mut s, sql),
+                Err(LunarisError::Compile(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_delete_returning() {
+        let db = temp_db("db_delete_returning");
+        let mut s = db.new_session();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT, score FLOAT)",
+        )
+        .unwrap();
+        db.execute_sql(
+            &mut s,
+            "INSERT INTO t VALUES (1, 'a', 1.5), (2, 'b', NULL), (3, 'c', 3.5), (4, 'd', 4.5)",
+        )
+        .unwrap();
+
+        let result = db
+            .execute_sql(
+                &mut s,
+                "DELETE FROM t WHERE id = 2 OR name = 'c' RETURNING *",
+            )
+            .unwrap();
+        assert_eq!(result.columns, vec!["id", "name", "score"]);
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![Value::Integer(2), Value::Text("b".into()), Value::Null],
+                vec![
+                    Value::Integer(3),
+                    Value::Text("c".into()),
+                    Value::Float(3.5)
+                ],
+            ]
+        );
+        assert_eq!(result.rows_affected, 2);
+
+        // expressions, and no WHERE, which can't take the TRUNCATE shortcut
+        let result = db
+            .execute_sql(&mut s, "DELETE FROM t RETURNING id, UPPER(name) AS upper")
+            .unwrap();
+        assert_eq!(result.columns, vec!["id", "upper"]);
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![Value::Integer(1), Value::Text("A".into())],
+                vec![Value::Integer(4), Value::Text("D".into())],
+            ]
+        );
+        let count = db.execute_sql(&mut s, "SELECT COUNT(*) FROM t").unwrap();
+        assert_eq!(count.rows, vec![vec![Value::Integer(0)]]);
+
+        let result = db
+            .execute_sql(&mut s, "DELETE FROM t WHERE id = 1 RETURNING id")
+            .unwrap();
+        assert!(result.rows.is_empty());
+        assert!(matches!(
+            db.execute_sql(&mut s, "DELETE FROM t WHERE id = 1 RETURNING missing"),
+            Err(LunarisError::ColumnNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_nocase_collation() {
+        let db = temp_db("db_nocase");
+        let mut s = db.new_session();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT COLLATE NOCASE UNIQUE, \
+             tag VARCHAR(8))",
+        )
+        .unwrap();
+        db.execute_sql(
+            &mut s,
+            "INSERT INTO users VALUES (1, 'Alice', 'x'), (2, 'bob', 'X'), (3, 'Carol', 'y')",
+        )
+        .unwrap();
+        assert!(matches!(
+            db.execute_sql(&mut s, "INSERT INTO users VALUES (4, 'ALICE', 'z')"),
+            Err(LunarisError::UniqueConstraint(_))
+        ));
+
+        let check = |s: &mut Session, db: &Database| {
+            let ids = |s: &mut Session, sql: &str| -> Vec<Value> {
+                let rows = db.execute_sql(s, sql).unwrap().rows;
+                rows.into_iter().map(|row| row[0].clone()).collect()
+            };
+            let int = |ids: &[i64]| ids.iter().map(|&i| Value::Integer(i)).collect::<Vec<_>>();
+            assert_eq!(
+                ids(s, "SELECT id FROM users WHERE name = 'alice'"),
+                int(&[1])
+            );
+            assert_eq!(ids(s, "SELECT id FROM users WHERE 'BOB' = name"), int(&[2]));
+            assert_eq!(
+                ids(s, "SELECT id FROM users WHERE name > 'b'"),
+                int(&[2, 3])
+            );
+            assert_eq!(
+                ids(s, "SELECT id FROM users WHERE name <> 'CAROL'"),
+                int(&[1, 2])
+            );
+            // bytewise, 'Carol' would sort before 'bob'
+            assert_eq!(
+                ids(s, "SELECT id FROM users ORDER BY name"),
+                int(&[1, 2, 3])
+            );
+            assert_eq!(
+                ids(s, "SELECT id FROM users ORDER BY name DESC"),
+                int(&[3, 2, 1])
+            );
+
+            // other columns compare exactly unless a comparison says otherwise
+            assert_eq!(ids(s, "SELECT id FROM users WHERE tag = 'x'"), int(&[1]));
+            assert_eq!(
+                ids(s, "SELECT id FROM users WHERE tag = 'x' COLLATE NOCASE"),
+                int(&[1, 2])
+            );
+            assert_eq!(ids(s, "SELECT id FROM users ORDER BY tag"), int(&[2, 1, 3]));
+            assert_eq!(
+                ids(
+                    s,
+                    "SELECT id FROM users WHERE name COLLATE BINARY = 'alice'"
+                ),
+                int(&[])
+            );
+        };
+        check(&mut s, &db);
+
+        // the collation is part of the schema
+        drop(db);
+        let dir = std::env::temp_dir().join("lunaris_test").join("db_nocase");
+        let db = Database::open(dir).unwrap();
+        let mut s = db.new_session();
+        check(&mut s, &db);
+        let create = &db.dump(&mut s, "users").unwrap()[0];
+        assert!(
+            create.contains("name TEXT UNIQUE COLLATE NOCASE"),
+            "{create}"
+        );
+
+        // the index stores text as is, so it is not used for NOCASE lookups
+        db.execute_sql(&mut s, "CREATE INDEX users_name ON users (name)")
+            .unwrap();
+        check(&mut s, &db);
+
+        for sql in [
+            "CREATE TABLE bad (n INTEGER COLLATE NOCASE)",
+            "CREATE TABLE bad (n TEXT COLLATE GERMAN)",
+            "SELECT id FROM users WHERE name = 'a' COLLATE GERMAN",
+        ] {
+            assert!(
+                matches!(db.execute_sql(&mut s, sql), Err(LunarisError::Compile(_))),
+                "{sql}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_boolean_literals() {
+        let db = temp_db("db_boolean_literals");
+        let mut s = db.new_session();
+        let sql = "CREATE TABLE t (id INTEGER PRIMARY KEY, active BOOLEAN DEFAULT TRUE)";
+        db.execute_sql(&mut s, sql).unwrap();
+        db.execute_sql(
+            &mut s,
+            "INSERT INTO t VALUES (1, TRUE), (2, false), (3, NULL)",
+        )
+        .unwrap();
+        db.execute_sql(&mut s, "INSERT INTO t (id) VALUES (4)")
+            .unwrap();
+
+        let ids = |s: &mut Session, filter: &str| -> Vec<Vec<Value>> {
+            let sql = format!("SELECT id FROM t WHERE {filter}");
+            db.execute_sql(s, &sql).unwrap().rows
+        };
+        let int = |i| vec![Value::Integer(i)];
+        assert_eq!(ids(&mut s, "active = FALSE"), vec![int(2)]);
+        assert_eq!(ids(&mut s, "TRUE = active"), vec![int(1), int(4)]);
+        assert_eq!(ids(&mut s, "active <> true"), vec![int(2)]);
+        assert_eq!(ids(&mut s, "NOT (active = TRUE)"), vec![int(2)]);
+
+        let sql = "SELECT active, TRUE AS yes FROM t WHERE id <= 3";
+        let result = db.execute_sql(&mut s, sql).unwrap();
+        assert_eq!(result.columns, vec!["active", "yes"]);
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![Value::Boolean(true), Value::Boolean(true)],
+                vec![Value::Boolean(false), Value::Boolean(true)],
+                vec![Value::Null, Value::Boolean(true)],
+            ]
+        );
+        let result = db.execute_sql(&mut s, "SELECT FALSE").unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Boolean(false)]]);
+
+        assert!(matches!(
+            db.execute_sql(&mut s, "INSERT INTO t VALUES (5, 1)"),
+            Err(LunarisError::TypeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_is_true_and_is_false() {
+        let db = temp_db("db_is_true");
+        let mut s = db.new_session();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, active BOOLEAN)",
+        )
+        .unwrap();
+        db.execute_sql(
+            &mut s,
+            "INSERT INTO t VALUES (1, true), (2, false), (3, NULL)",
+        )
+        .unwrap();
+
+        let cases: &[(&str, &[i64])] = &[
+            ("active IS TRUE", &[1]),
+            ("active IS FALSE", &[2]),
+            ("active IS NOT TRUE", &[2, 3]),
+            ("active IS NOT FALSE", &[1, 3]),
+            // the result is never UNKNOWN, so NOT flips it for NULL too
+            ("NOT (active IS TRUE)", &[2, 3]),
+            ("NOT active IS NOT FALSE", &[2]),
+            ("active IS TRUE OR id = 3", &[1, 3]),
+            ("active IS NOT TRUE AND id > 2", &[3]),
+        ];
+        for (filter, expected) in cases {
+            let sql = format!("SELECT id FROM t WHERE {filter}");
+            let rows = db.execute_sql(&mut s, &sql).unwrap().rows;
+            let expected: Vec<Vec<Value>> = expected
+                .iter()
+                .map(|id| vec![Value::Integer(*id)])
+                .collect();
+            assert_eq!(rows, expected, "{filter}");
+        }
+    }
+
+    #[test]
+    fn test_null_comparisons() {
+        let db = temp_db("db_three_valued");
+        let mut s = db.new_session();
+        db.execute_sql(&mut s, "CREATE TABLE t (id INTEGER PRIMARY KEY, x INTEGER)")
+            .unwrap();
+        db.execute_sql(&mut s, "INSERT INTO t VALUES (1, 1), (2, 2), (3, NULL)")
+            .unwrap();
+
+        let ids = |db: &Database, s: &mut Session, filter: &str| -> Vec<i64> {
+            let sql = format!("SELECT id FROM t WHERE {filter}");
+            let result = db.execute_sql(s, &sql).unwrap();
+            result
+                .rows
+                .iter()
+                .map(|row| match row[0] {
+                    Value::Integer(id) => id,
+                    _ => panic!("non-integer id"),
+                })
+                .collect()
+        };
+
+        let cases: &[(&str, &[i64])] = &[
+            ("x = NULL", &[]),
+            ("NOT (x = NULL)", &[]),
+            ("x <> NULL", &[]),
+            ("x <> 1", &[2]),
+            ("NOT (x = 1)", &[2]),
+            ("x IS NULL", &[3]),
+            ("x IS NOT NULL", &[1, 2]),
+            ("NOT (x IS NULL)", &[1, 2]),
+            ("NOT x IS NULL", &[1, 2]),
+            ("NOT NOT (x = 1)", &[1]),
+            ("NOT (NOT (x = NULL))", &[]),
+            // UNKNOWN AND FALSE is FALSE, so NOT makes it TRUE
+            ("NOT (x = 1 AND id = 0)", &[1, 2, 3]),
+            // UNKNOWN AND TRUE stays UNKNOWN either way
+            ("x = 1 AND id > 0", &[1]),
+            ("NOT (x > 0 AND id > 0)", &[]),
+            // UNKNOWN OR TRUE is TRUE, UNKNOWN OR FALSE stays UNKNOWN
+            ("x = 1 OR id = 3", &[1, 3]),
+            ("NOT (x = 1 OR id = 3)", &[2]),
+            ("x > 5 OR id = 0", &[]),
+            ("NOT (x > 5 OR id = 0)", &[1, 2]),
+            ("(x = 1 OR x IS NULL) AND id < 3", &[1]),
+        ];
+        for (filter, expected) in cases {
+            assert_eq!(ids(&db, &mut s, filter), *expected, "WHERE {filter}");
+        }
+
+        db.execute_sql(&mut s, "DELETE FROM t WHERE NOT (x = 2)")
+            .unwrap();
+        assert_eq!(ids(&db, &mut s, "id > 0"), vec![2, 3]);
+    }
+
+    #[test]
+    fn test_comparison_operators() {
+        let db = temp_db("db_comparison_operators");
+        let mut s = db.new_session();
+        db.execute_sql(&mut s, "CREATE TABLE t (id INTEGER PRIMARY KEY, x INTEGER)")
+            .unwrap();
+        db.execute_sql(
+            &mut s,
+            "INSERT INTO t VALUES (1, 1), (2, 2), (3, 3), (4, 4), (5, 5), (6, NULL)",
+        )
+        .unwrap();
+
+        // three-valued logic, with None for UNKNOWN
+        fn and(a: Option<bool>, b: Option<bool>) -> Option<bool> {
+            match (a, b) {
+                (Some(false), _) | (_, Some(false)) => Some(false),
+                (Some(true), Some(true)) => Some(true),
+                _ => None,
+            }
+        }
+        fn or(a: Option<bool>, b: Option<bool>) -> Option<bool> {
+            and(a.map(|a| !a), b.map(|b| !b)).map(|v| !v)
+        }
+
+        type Compare = fn(&i64, &i64) -> bool;
+        let operators: &[(&str, Compare)] = &[
+            ("=", i64::eq),
+            ("<>", i64::ne),
+            ("!=", i64::ne),
+            ("<", i64::lt),
+            ("<=", i64::le),
+            (">", i64::gt),
+            (">=", i64::ge),
+        ];
+        // `{c}` stands for the comparison, whose value is passed in
+        type Context = fn(i64, Option<bool>) -> Option<bool>;
+        let contexts: &[(&str, Context)] = &[
+            ("{c}", |_, c| c),
+            ("{c} AND id > 1", |id, c| and(c, Some(id > 1))),
+            ("id = 1 OR {c}", |id, c| or(Some(id == 1), c)),
+            ("{c} OR id = 6", |id, c| or(c, Some(id == 6))),
+            ("NOT ({c} OR id = 1)", |id, c| {
+                or(c, Some(id == 1)).map(|v| !v)
+            }),
+            ("(id = 2 OR {c}) AND NOT id = 5", |id, c| {
+                and(or(Some(id == 2), c), Some(id != 5))
+            }),
+        ];
+        // on the primary key, a comparison may also turn into a seek
+        type Column = fn(i64) -> Option<i64>;
+        let columns: [(&str, Column); 2] = [("x", |id| (id < 6).then_some(id)), ("id", Some)];
+        for ((op, compare), (column, value)) in operators
+            .iter()
+            .flat_map(|op| columns.iter().map(move |col| (op, col)))
+        {
+            for reversed in [false, true] {
+                let comparison = match reversed {
+                    false => format!("{column} {op} 3"),
+                    true => format!("3 {op} {column}"),
+                };
+                for (context, expected) in contexts {
+                    let filter = context.replace("{c}", &comparison);
+                    let sql = format!("SELECT id FROM t WHERE {filter}");
+                    let rows = db.execute_sql(&mut s, &sql).unwrap().rows;
+                    let matching: Vec<Vec<Value>> = (1..=6)
+                        .filter(|&id| {
+                            let c = value(id).map(|v| match reversed {
+                                false => compare(&v, &3),
+                                true => compare(&3, &v),
+                            });
+                            expected(id, c) == Some(true)
+                        })
+                        .map(|id| vec![Value::Integer(id)])
+                        .collect();
+                    assert_eq!(rows, matching, "WHERE {filter}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_nested_and_or() {
+        let db = temp_db("db_nested_where");
+        let mut s = db.new_session();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, a INTEGER, b INTEGER, c INTEGER, d INTEGER)",
+        )
+        .unwrap();
+        // every combination of four flags, id = a*8 + b*4 + c*2 + d
+        for id in 0..16 {
+            let bits: Vec<i64> = (0..4).rev().map(|i| (id >> i) & 1).collect();
+            db.execute_sql(
+                &mut s,
+                &format!(
+                    "INSERT INTO t VALUES ({id}, {}, {}, {}, {})",
+                    bits[0], bits[1], bits[2], bits[3]
+                ),
+            )
+            .unwrap();
+        }
+
+        type Predicate = fn(bool, bool, bool, bool) -> bool;
+        let cases: &[(&str, Predicate)] = &[
+            ("a = 1 OR (b = 1 AND c = 1)", |a, b, c, _| a || (b && c)),
+            ("(a = 1 OR b = 1) AND c = 1", |a, b, c, _| (a || b) && c),
+            ("a = 1 AND b = 1 OR c = 1", |a, b, c, _| a && b || c),
+            ("a = 1 OR b = 1 AND c = 1", |a, b, c, _| a || b && c),
+            ("(a = 1 OR b = 1) AND (c = 1 OR d = 1)", |a, b, c, d| {
+                (a || b) && (c || d)
+            }),
+            ("a = 1 AND (b = 1 OR (c = 1 AND d = 1))", |a, b, c, d| {
+                a && (b || (c && d))
+            }),
+            (
+                "(a = 1 OR (b = 1 AND (c = 1 OR d = 1))) AND NOT (a = 1 AND d = 1)",
+                |a, b, c, d| (a || (b && (c || d))) && !(a && d),
+            ),
+            (
+                "NOT (a = 1 OR b = 1) OR (c = 1 AND NOT d = 1)",
+                |a, b, c, d| !(a || b) || (c && !d),
+            ),
+        ];
+        for (filter, predicate) in cases {
+            let sql = format!("SELECT id FROM t WHERE {filter}");
+            let rows = db.execute_sql(&mut s, &sql).unwrap().rows;
+            let expected: Vec<Vec<Value>> = (0..16)
+                .filter(|id| predicate(id & 8 != 0, id & 4 != 0, id & 2 != 0, id & 1 != 0))
+                .map(|id| vec![Value::Integer(id)])
+                .collect();
+            assert_eq!(rows, expected, "WHERE {filter}");
+        }
+    }
+
+    #[test]
+    fn test_execute_script() {
+        let db = temp_db("db_script");
+        let mut s = db.new_session();
+        let script = "CREATE TABLE t (id INTEGER PRIMARY KEY, s VARCHAR(16));\n\
+                      INSERT INTO t VALUES (1, 'a;b');\n\
+                      INSERT INTO t VALUES (1, 'dup');\n\
+                      SELECT s FROM t;\n   \n";
+        let results = db.execute_script(&mut s, script).unwrap();
+        assert_eq!(results.len(), 4);
+        assert!(matches!(results[2], Err(LunarisError::DuplicateKey(1))));
+        let select = results[3].as_ref().unwrap();
+        assert_eq!(select.rows, vec![vec![Value::Text("a;b".into())]]);
+    }
+
+    #[test]
+    fn test_execute_sql_many_statements() {
+        let db = temp_db("db_many_statements");
+        let mut s = db.new_session();
+        let result = db
+            .execute_sql(
+                &mut s,
+                "CREATE TABLE t (id INTEGER PRIMARY KEY, s TEXT); \
+                 INSERT INTO t VALUES (1, 'a;b'), (2, 'c'); \
+                 SELECT s FROM t WHERE id > 0;",
+            )
+            .unwrap();
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![Value::Text("a;b".into())],
+                vec![Value::Text("c".into())]
+            ]
+        );
+        assert!(!s.in_transaction());
+
+        // the statements commit together or not at all
+        assert!(matches!(
+            db.execute_sql(
+                &mut s,
+                "INSERT INTO t VALUES (3, 'd'); INSERT INTO t VALUES (1, 'dup')"
+            ),
+            Err(LunarisError::DuplicateKey(1))
+        ));
+        let result = db
+            .execute_sql(&mut s, "DELETE FROM t WHERE id = 2; SELECT id FROM t")
+            .unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Integer(1)]]);
+
+        // the batch is committed once it is done
+        let mut other = db.new_session();
+        let result = db
+            .execute_sql(&mut other, "SELECT COUNT(*) FROM t")
+            .unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Integer(1)]]);
+    }
+
+    #[test]
+    fn test_describe_table() {
+        let db = temp_db("db_describe");
+        let mut s = db.new_session();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, name VARCHAR(16) NOT NULL, \
+             score FLOAT DEFAULT 0.5, active BOOLEAN)",
+        )
+        .unwrap();
+
+        let result = db.execute_sql(&mut s, "DESCRIBE t").unwrap();
+        assert_eq!(
+            result.columns,
+            vec![
+                "column_name",
+                "type",
+                "nullable",
+                "is_primary_key",
+                "default"
+            ]
+        );
+        let text = |s: &str| Value::Text(s.into());
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![
+                    text("id"),
+                    text("INTEGER"),
+                    Value::Boolean(false),
+                    Value::Boolean(true),
+                    Value::Null
+                ],
+                vec![
+                    text("name"),
+                    text("VARCHAR(16)"),
+                    Value::Boolean(false),
+                    Value::Boolean(false),
+                    Value::Null
+                ],
+                vec![
+                    text("score"),
+                    text("FLOAT"),
+                    Value::Boolean(true),
+                    Value::Boolean(false),
+                    Value::Float(0.5)
+                ],
+                vec![
+                    text("active"),
+                    text("BOOLEAN"),
+                    Value::Boolean(true),
+                    Value::Boolean(false),
+                    Value::Null
+                ],
+            ]
+        );
+
+        let err = db.execute_sql(&mut s, "DESCRIBE missing");
+        assert!(matches!(err, Err(LunarisError::TableNotFound(t)) if t == "missing"));
+    }
+
+    #[test]
+    fn test_result_column_types() {
+        let db = temp_db("db_column_types");
+        let mut s = db.new_session();
+        db.execute_sql(
+            &mut s,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, score FLOAT, name TEXT, \
+             seen TIMESTAMP, ok BOOLEAN)",
+        )
+        .unwrap();
+        let types = |s: &mut Session, sql: &str| db.execute_sql(s, sql).unwrap().column_types;
+
+        use ValueType::*;
+        assert_eq!(
+            types(&mut s, "SELECT * FROM t"),
+            vec![Integer, Float, Text, Timestamp, Boolean]
+        );
+        assert_eq!(
+            types(
+                &mut s,
+                "SELECT UPPER(name), id + 1, score * 2, -id, name || 'x', NULL, 'a', \
+                 COALESCE(NULL, score), seen + 1000 FROM t"
+            ),
+            vec![
+                Text, Integer, Float, Integer, Text, Unknown, Text, Float, Integer
+            ]
+        );
+        assert_eq!(types(&mut s, "SELECT COUNT(*) FROM t"), vec![Integer]);
+        assert_eq!(types(&mut s, "SELECT MAX(id) FROM t"), vec![Integer]);
+        assert_eq!(
+            types(&mut s, "SELECT NULL FROM t UNION SELECT name FROM t"),
+            vec![Text]
+        );
+        assert_eq!(types(&mut s, "SHOW TABLES"), vec![Text]);
+    }
+
+    #[test]
+    fn test_empty_statement() {
+        let db = temp_db("db_empty_statement");
+        let mut s = db.new_session();
+        for sql in ["", "   \n\t", "-- just a comment", "/* nothing */;"] {
+            let result = db.execute_sql(&mut s, sql).unwrap();
+            assert_eq!(result.message, "empty statement", "{sql:?}");
+            assert!(result.columns.is_empty() && result.rows.is_empty());
+        }
+        // a no-op leaves an open transaction as it is
+        db.execute_sql(&mut s, "CREATE TABLE t (id INTEGER PRIMARY KEY)")
+            .unwrap();
+        db.begin(&mut s).unwrap();
+        db.execute_sql(&mut s, "INSERT INTO t VALUES (1)").unwrap();
+        db.execute_sql(&mut s, "-- still in the transaction")
+            .unwrap();
+        db.rollback(&mut s).unwrap();
+        let result = db.execute_sql(&mut s, "SELECT * FROM t").unwrap();
+        assert!(result.rows.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_private_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir()
+            .join("lunaris_test")
+            .join("db_permissions");
+        let _ = std::fs::remove_dir_all(&dir);
+        let db = Database::open(dir.join("data")).unwrap();
+        let mut s = db.new_session();
+        db.execute_sql(&mut s, "CREATE TABLE t (id INTEGER PRIMARY KEY)")
+            .unwrap();
+        db.execute_sql(&mut s, "INSERT INTO t VALUES (1)").unwrap();
+
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        // the umask can only take permissions away
+        assert_eq!(mode(&dir) & 0o700, 0o700);
+        assert_eq!(mode(&dir) & 0o077, 0);
+        assert_eq!(mode(&dir.join("data")) & 0o077, 0);
+        for name in ["catalog.db", "t.db"] {
+            assert_eq!(mode(&dir.join("data").join(name)) & 0o077, 0, "{name}");
+        }
+    }
+
+    #[test]
+    fn test_drop_table() {
+        let db = temp_db("db_drop_table");
+        let mut s = db.new_session();
+        db.execute_sql(&mut s, "CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT)")
+            .unwrap();
+        db.execute_sql(&mut s, "CREATE TABLE other (id INTEGER PRIMARY KEY)")
+            .unwrap();
+        db.execute_sql(&mut s, "CREATE INDEX t_name ON t (name)")
+            .unwrap();
+        db.execute_sql(&mut s, "INSERT INTO t VALUES (1, 'a'), (2, 'b')")
+            .unwrap();
+        db.execute_sql(&mut s, "INSERT INTO other VALUES (1)")
+            .unwrap();
+
+        db.begin(&mut s).unwrap();
+        assert!(matches!(
+            db.execute_sql(&mut s, "DROP TABLE t"),
+            Err(LunarisError::Transaction(_))
+        ));
+        db.rollback(&mut s).unwrap();
+
+        let result = db.execute_sql(&mut s, "DROP TABLE t").unwrap();
+        assert_eq!(result.message, "Table 't' dropped");
+        let dir = std::env::temp_dir()
+            .join("lunaris_test")
+            .join("db_drop_table");
+        for file in ["t.db", "t.db-wal", "t_name.db", "t_name.db-wal"] {
+            assert!(!dir.join(file).exists(), "{file}");
+        }
+        assert!(db.indexes_on("t").is_empty());
+        assert!(matches!(
+            db.execute_sql(&mut s, "SELECT * FROM t"),
+            Err(LunarisError::TableNotFound(_))
+        ));
+        assert!(matches!(
+            db.execute_sql(&mut s, "DROP TABLE t"),
+            Err(LunarisError::TableNotFound(_))
+        ));
+        let result = db.execute_sql(&mut s, "DROP TABLE IF EXISTS t").unwrap();
+        assert_eq!(result.message, "Table 't' does not exist, nothing done");
+
+        // the names are free again, and nothing of the old table is left
+        drop(db);
+        let db = Database::open(&dir).unwrap();
+        let mut s = db.new_session();
+        assert!(db.indexes_on("t").is_empty());
+        db.execute_sql(&mut s, "CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT)")
+            .unwrap();
+        db.execute_sql(&mut s, "CREATE INDEX t_name ON t (name)")
+            .unwrap();
+        let result = db.execute_sql(&mut s, "SELECT * FROM t").unwrap();
+        assert!(result.rows.is_empty());
+        let result = db.execute_sql(&mut s, "SELECT * FROM other").unwrap();
+        assert_eq!(result.rows, vec![vec![Value::Integer(1)]]);
+    }
+
+    #[test]
+    fn test_invalid_names() {
+        let db = temp_db("db_invalid_names");
+        let mut s = db.new_session();
+        for sql in [
+            "CREATE TABLE catalog (id INTEGER)",
+            "CREATE TABLE Catalog (id INTEGER)",
+            "CREATE TABLE lunaris_status (id INTEGER)",
+            "CREATE TABLE \"a/b\" (id INTEGER)",
+            "CREATE TABLE \"..\" (id INTEGER)",
+            "CREATE TABLE s.t (id INTEGER)",
+        ] {
+            let err = db.execute_sql(&mut s, sql);
+            assert!(
+                matches!(&err, Err(e @ LunarisError::InvalidName(_)) if e.code() == ErrorCode::InvalidName),
+                "{sql}: {err:?}"
+            );
+        }
+        db.execute_sql(&mut s, "CREATE TABLE users_2 (id INTEGER, name TEXT)")
+            .unwrap();
+        assert!(matches!(
+            db.execute_sql(&mut s, "CREATE INDEX catalog ON users_2 (name)"),
+            Err(LunarisError::InvalidName(_))
+        ));
+        db.execute_sql(&mut s, "CREATE INDEX users_2_name ON users_2 (name)")
+            .unwrap();
+        assert_eq!(db.execute_sql(&mut s, "SHOW TABLES").unwrap().rows.len(), 1);
+    }
+
+    #[test]
+    fn test_incomparable_types() {
+        let db = temp_db("db_incomparable_types");
+        let mut s = db.new_session();
+        db.execute_sql(&mut s, "CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT)")
+            .unwrap();
+        db.execute_sql(&mut s, "INSERT INTO t VALUES (1, 'a'), (2, NULL)")
+            .unwrap();
+
+        for sql in [
+            "SELECT * FROM t WHERE name > 5",
+            "SELECT * FROM t WHERE id = 'a'",
+        ] {
+            let err = db.execute_sql(&mut s, sql);
+            assert!(
+                matches!(&err, Err(e @ LunarisError::TypeMismatch { .. }) if e.code() == ErrorCode::TypeMismatch),
+                "{sql}: {err:?}"
+            );
+        }
+        // NULL compares with anything, and numbers with each other
+        let result = db.execute_sql(&mut s, "SELECT * FROM t WHERE name = NULL");
+        assert!(result.unwrap().rows.is_empty());
+        let result = db.execute_sql(&mut s, "SELECT id FROM t WHERE id > 1.5");
+        assert_eq!(result.unwrap().rows, vec![vec![Value::Integer(2)]]);
     }
 }
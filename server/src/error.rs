@@ -1,3 +1,4 @@
+use lunaris_common::protocol::{ErrorCode, SourceLocation};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -17,14 +18,26 @@ pub enum LunarisError {
     #[error("Table already exists: {0}")]
     TableAlreadyExists(String),
 
+    #[error("Invalid name: {0}")]
+    InvalidName(String),
+
+    #[error("Index already exists: {0}")]
+    IndexAlreadyExists(String),
+
     #[error("Column not found: {0}")]
     ColumnNotFound(String),
 
+    #[error("Ambiguous column name: {0}")]
+    AmbiguousColumn(String),
+
     #[error("Type mismatch: expected {expected}, got {got}")]
     TypeMismatch { expected: String, got: String },
 
-    #[error("Parse error: {0}")]
-    Parse(String),
+    #[error("Parse error: {message}{}", fmt_location(.location))]
+    Parse {
+        message: String,
+        location: Option<SourceLocation>,
+    },
 
     #[error("Compile error: {0}")]
     Compile(String),
@@ -32,6 +45,9 @@ pub enum LunarisError {
     #[error("VM error: {0}")]
     Vm(String),
 
+    #[error("Transaction error: {0}")]
+    Transaction(String),
+
     #[error("WAL error: {0}")]
     Wal(String),
 
@@ -42,10 +58,81 @@ pub enum LunarisError {
     ValueCountMismatch { expected: usize, got: usize },
 
     #[error("Duplicate key: {0}")]
-    DuplicateKey(u64),
+    DuplicateKey(i64),
 
     #[error("Null value for non-nullable column: {0}")]
     NullConstraint(String),
+
+    #[error("Duplicate value for unique column: {0}")]
+    UniqueConstraint(String),
+
+    #[error("Database is read-only")]
+    ReadOnly,
+
+    #[error("Value too long for column {column}: {got} bytes (max: {max})")]
+    ValueTooLong {
+        column: String,
+        max: usize,
+        got: usize,
+    },
+
+    #[error("Import error on line {line}: {message}")]
+    Import { line: usize, message: String },
+
+    #[error("Statement timed out after {0} ms")]
+    Timeout(u64),
+}
+
+impl LunarisError {
+    /// Error without a position in the SQL text.
+    pub fn parse(message: impl Into<String>) -> Self {
+        LunarisError::Parse {
+            message: message.into(),
+            location: None,
+        }
+    }
+
+    /// Where in the statement the error was found, for syntax errors.
+    pub fn location(&self) -> Option<SourceLocation> {
+        match self {
+            LunarisError::Parse { location, .. } => *location,
+            _ => None,
+        }
+    }
+
+    /// Code sent to clients along with the error message.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            LunarisError::Parse { .. } => ErrorCode::SyntaxError,
+            LunarisError::Compile(_) => ErrorCode::CompileError,
+            LunarisError::TableNotFound(_) => ErrorCode::TableNotFound,
+            LunarisError::TableAlreadyExists(_) => ErrorCode::TableAlreadyExists,
+            LunarisError::IndexAlreadyExists(_) => ErrorCode::IndexAlreadyExists,
+            LunarisError::InvalidName(_) => ErrorCode::InvalidName,
+            LunarisError::ColumnNotFound(_) => ErrorCode::ColumnNotFound,
+            LunarisError::AmbiguousColumn(_) => ErrorCode::AmbiguousColumn,
+            LunarisError::TypeMismatch { .. } => ErrorCode::TypeMismatch,
+            LunarisError::ValueCountMismatch { .. } => ErrorCode::ValueCountMismatch,
+            LunarisError::DuplicateKey(_) => ErrorCode::DuplicateKey,
+            LunarisError::NullConstraint(_) => ErrorCode::NotNullViolation,
+            LunarisError::UniqueConstraint(_) => ErrorCode::UniqueViolation,
+            LunarisError::Transaction(_) => ErrorCode::TransactionError,
+            LunarisError::Vm(_) => ErrorCode::ExecutionError,
+            LunarisError::RowTooLarge { .. } | LunarisError::PageFull { .. } => {
+                ErrorCode::RowTooLarge
+            }
+            LunarisError::Storage(_) | LunarisError::Wal(_) => ErrorCode::StorageError,
+            LunarisError::Io(_) => ErrorCode::IoError,
+            LunarisError::ReadOnly => ErrorCode::ReadOnly,
+            LunarisError::ValueTooLong { .. } => ErrorCode::ValueTooLong,
+            LunarisError::Import { .. } => ErrorCode::ImportError,
+            LunarisError::Timeout(_) => ErrorCode::Timeout,
+        }
+    }
+}
+
+fn fmt_location(location: &Option<SourceLocation>) -> String {
+    location.map(|l| format!(" at {l}")).unwrap_or_default()
 }
 
 pub type LunarisResult<T> = Result<T, LunarisError>;
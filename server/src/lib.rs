@@ -1,4 +1,22 @@
+//! The Lunaris database engine. The server binary serves it over TCP, but it
+//! can also be embedded: open a [`Database`], start a [`Session`] and run
+//! statements with [`Database::execute_sql`].
+//!
+//! ```no_run
+//! use lunaris_server::{Database, Value};
+//!
+//! let db = Database::open("data")?;
+//! let mut session = db.new_session();
+//! db.execute_sql(&mut session, "CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT)")?;
+//! db.execute_sql(&mut session, "INSERT INTO t VALUES (1, 'one')")?;
+//! let result = db.execute_sql(&mut session, "SELECT name FROM t")?;
+//! assert_eq!(result.rows, vec![vec![Value::Text("one".into())]]);
+//! # Ok::<(), lunaris_server::LunarisError>(())
+//! ```
+
 pub mod catalog;
+pub mod config;
+pub mod csv;
 pub mod database;
 pub mod error;
 pub mod server;
@@ -6,3 +24,8 @@ pub mod storage;
 pub mod vm;
 
 mod constants;
+
+pub use database::{Database, Session};
+pub use error::{LunarisError, LunarisResult};
+pub use lunaris_common::value::Value;
+pub use vm::vm::ExecutionResult;
@@ -1,38 +1,57 @@
-use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
 use tokio::net::TcpListener;
 
+use lunaris_server::config::{self, Command};
 use lunaris_server::database::Database;
 use lunaris_server::server;
 
-const DEFAULT_PORT: u16 = 7435;
-
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let db_dir = std::env::var("LUNARIS_DATA_DIR")
-        .map(PathBuf::from)
-        .unwrap_or_else(|_| dirs_home().join(".lunaris"));
-    eprintln!("[server] data directory: {}", db_dir.display());
-
-    let db = Database::open(db_dir)?;
+    let config = match config::parse(std::env::args().skip(1), |name| std::env::var(name).ok()) {
+        Ok(Command::Run(config)) => config,
+        Ok(Command::Help) => {
+            println!("{}", config::USAGE);
+            return Ok(());
+        }
+        Err(e) => {
+            eprintln!("[server] {e}");
+            std::process::exit(2);
+        }
+    };
+    eprintln!("[server] data directory: {}", config.data_dir.display());
+
+    let addr = config.addr();
+    let mut db = if config.read_only {
+        eprintln!("[server] opening the database read-only");
+        Database::open_read_only(config.data_dir)?
+    } else {
+        Database::open(config.data_dir)?
+    };
+    db.set_max_result_rows(config.max_result_rows);
+    db.set_statement_timeout(Duration::from_millis(config.statement_timeout_ms));
     let db = Arc::new(db);
 
-    let addr = format!("127.0.0.1:{DEFAULT_PORT}");
     let listener = TcpListener::bind(&addr).await?;
-    eprintln!("[server] listening on {addr}");
+    eprintln!("[server] listening on {}", listener.local_addr()?);
 
-    loop {
-        let (stream, _) = listener.accept().await?;
-        let db = Arc::clone(&db);
-        tokio::spawn(async move {
-            server::handle_connection(stream, db).await;
-        });
-    }
+    server::serve(listener, db, shutdown_signal()).await?;
+    eprintln!("[server] all tables flushed, exiting");
+    Ok(())
 }
 
-fn dirs_home() -> PathBuf {
-    std::env::var("HOME")
-        .map(PathBuf::from)
-        .unwrap_or_else(|_| PathBuf::from("."))
+/// Completes on SIGINT (Ctrl-C) or SIGTERM.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        let mut terminate = signal(SignalKind::terminate()).expect("can't listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
 }
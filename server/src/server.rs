@@ -1,62 +1,710 @@
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::Arc;
 
-use tokio::net::TcpStream;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinSet;
 
-use crate::database::Database;
-use lunaris_common::protocol::{self, QueryResult, Request, Response, ResultSet};
+use crate::constants::{STREAM_CHANNEL_CAPACITY, STREAM_CHUNK_ROWS};
+use crate::database::{Database, Session};
+use crate::error::{LunarisError, LunarisResult};
+use crate::vm::{bytecode, parser};
+use lunaris_common::protocol::{
+    self, Format, Handshake, Request, Response, ResultSet, StatementKind, ValueType,
+};
+use lunaris_common::value::Value;
+
+/// Accept connections until `shutdown` completes. Then every connection
+/// finishes the statement it is running and closes, rolling back its open
+/// transaction, and the tables are written to disk.
+pub async fn serve<F>(listener: TcpListener, db: Arc<Database>, shutdown: F) -> LunarisResult<()>
+where
+    F: Future<Output = ()>,
+{
+    let (stop_tx, stop_rx) = watch::channel(false);
+    let mut connections = JoinSet::new();
+    tokio::pin!(shutdown);
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, _) = accepted?;
+                connections.spawn(run_connection(stream, Arc::clone(&db), stop_rx.clone()));
+            }
+            _ = &mut shutdown => break,
+        }
+        while connections.try_join_next().is_some() {}
+    }
+
+    drop(listener);
+    eprintln!(
+        "[server] shutting down, waiting for {} connection(s)",
+        connections.len()
+    );
+    let _ = stop_tx.send(true);
+    while connections.join_next().await.is_some() {}
+    db.flush_all()
+}
 
 pub async fn handle_connection(stream: TcpStream, db: Arc<Database>) {
+    // the sender is dropped right away, so the connection never stops early
+    let (_, stop) = watch::channel(false);
+    run_connection(stream, db, stop).await;
+}
+
+/// Serve one connection until the client leaves or `stop` turns true while
+/// no statement is running.
+async fn run_connection(stream: TcpStream, db: Arc<Database>, mut stop: watch::Receiver<bool>) {
     let addr = stream.peer_addr().ok();
     if let Some(a) = &addr {
         eprintln!("[server] client connected: {a}");
     }
 
     let (mut reader, mut writer) = stream.into_split();
+    let mut session = db.new_session();
+    let mut format = Format::Json;
 
     loop {
-        let request: Option<Request> = match protocol::recv_message(&mut reader).await {
-            Ok(Some(req)) => Some(req),
+        let received = tokio::select! {
+            received = protocol::recv_frame(&mut reader) => received,
+            Ok(_) = stop.wait_for(|stop| *stop) => break,
+        };
+        let frame = match received {
+            Ok(Some(frame)) => frame,
             Ok(None) => {
                 if let Some(a) = &addr {
                     eprintln!("[server] client disconnected: {a}");
                 }
-                return;
+                break;
+            }
+            Err(e) => {
+                eprintln!("[server] read error: {e}");
+                break;
+            }
+        };
+
+        // the handshake always travels as JSON
+        let handshake = match frame.format {
+            Format::Json => frame.decode::<Handshake>().ok(),
+            Format::Binary => None,
+        };
+        if let Some(handshake) = handshake {
+            format = handshake.format;
+            let ack = Handshake { format };
+            if let Err(e) = protocol::send_message(&mut writer, &ack, Format::Json).await {
+                eprintln!("[server] write error: {e}");
+                break;
+            }
+            continue;
+        }
+
+        let request: Request = match frame.decode() {
+            // answered here, so a ping never waits for a worker thread
+            Ok(Request::Ping) => {
+                if let Err(e) = protocol::send_message(&mut writer, &Response::Pong, format).await {
+                    eprintln!("[server] write error: {e}");
+                    break;
+                }
+                continue;
             }
+            Ok(request) => request,
             Err(e) => {
                 eprintln!("[server] read error: {e}");
+                break;
+            }
+        };
+
+        // statements run on a blocking thread and stream their rows back
+        // through the channel while this task writes them out
+        let (tx, mut rx) = mpsc::channel(STREAM_CHANNEL_CAPACITY);
+        let worker_db = Arc::clone(&db);
+        let worker = tokio::task::spawn_blocking(move || {
+            let run = panic::catch_unwind(AssertUnwindSafe(|| match request {
+                Request::Query { sql } => {
+                    let response = run_request(&worker_db, &mut session, &sql, &tx);
+                    let _ = tx.blocking_send(response);
+                }
+                Request::Script { sql } => run_script(&worker_db, &mut session, &sql, &tx),
+                Request::Ping => unreachable!("pings are answered without a worker"),
+            }));
+            // a bug in one statement fails that statement, not the server
+            if run.is_err() {
+                worker_db.recover_session(&mut session);
+                let e = LunarisError::Vm("the statement panicked and was rolled back".into());
+                let _ = tx.blocking_send(error_response(&e));
+            }
+            session
+        });
+
+        let mut write_failed = false;
+        while let Some(response) = rx.recv().await {
+            if let Err(e) = protocol::send_message(&mut writer, &response, format).await {
+                eprintln!("[server] write error: {e}");
+                write_failed = true;
+                break;
+            }
+        }
+        // closing the channel makes a statement that is still producing rows
+        // fail, so the worker always finishes
+        drop(rx);
+        session = match worker.await {
+            Ok(session) => session,
+            // only a panic while recovering gets here, and then the session
+            // is gone with the worker
+            Err(e) => {
+                eprintln!("[server] statement worker failed: {e}");
                 return;
             }
         };
+        if write_failed {
+            break;
+        }
+    }
+
+    // uncommitted work of a dropped connection is discarded
+    db.close_session(&mut session);
+}
+
+/// Execute one statement, sending its result rows to `tx` in chunks, and
+/// return the response that ends the stream.
+fn run_request(
+    db: &Database,
+    session: &mut Session,
+    sql: &str,
+    tx: &mpsc::Sender<Response>,
+) -> Response {
+    let mut chunk = Vec::new();
+    let mut streamed = false;
+    let result = db.execute_sql_streaming(session, sql, |columns, types, row| {
+        chunk.push(row);
+        if chunk.len() >= STREAM_CHUNK_ROWS {
+            send_rows(tx, columns, types, &mut chunk)?;
+            streamed = true;
+        }
+        Ok(())
+    });
+
+    match result {
+        Ok(result) => {
+            // statements like SHOW TABLES return their rows directly
+            chunk.extend(result.rows);
+            // a query that matched nothing still tells the client its columns
+            let kind = match result.kind {
+                bytecode::StatementKind::Query => StatementKind::Query,
+                bytecode::StatementKind::Dml | bytecode::StatementKind::Other => {
+                    StatementKind::Mutation
+                }
+            };
+            if (!chunk.is_empty() || (kind == StatementKind::Query && !streamed))
+                && let Err(e) = send_rows(tx, &result.columns, &result.column_types, &mut chunk)
+            {
+                return error_response(&e);
+            }
+            Response::Done {
+                message: result.message,
+                kind,
+                rows_affected: result.rows_affected,
+                elapsed_micros: result.elapsed_micros,
+            }
+        }
+        Err(e) => error_response(&e),
+    }
+}
+
+fn error_response(e: &LunarisError) -> Response {
+    Response::Error {
+        code: e.code(),
+        message: e.to_string(),
+        location: e.location(),
+    }
+}
+
+/// Execute the statements of a script one by one, sending the answer to each
+/// of them and then `ScriptDone`.
+fn run_script(db: &Database, session: &mut Session, sql: &str, tx: &mpsc::Sender<Response>) {
+    match parser::split_statements(sql) {
+        Ok(statements) => {
+            for stmt in statements {
+                let response = run_request(db, session, &stmt, tx);
+                if tx.blocking_send(response).is_err() {
+                    return;
+                }
+            }
+        }
+        Err(e) => {
+            let response = error_response(&e);
+            if tx.blocking_send(response).is_err() {
+                return;
+            }
+        }
+    }
+    let _ = tx.blocking_send(Response::ScriptDone);
+}
+
+fn send_rows(
+    tx: &mpsc::Sender<Response>,
+    columns: &[String],
+    column_types: &[ValueType],
+    rows: &mut Vec<Vec<Value>>,
+) -> LunarisResult<()> {
+    let chunk = ResultSet {
+        columns: columns.to_vec(),
+        column_types: column_types.to_vec(),
+        rows: std::mem::take(rows),
+    };
+    tx.blocking_send(Response::Rows(chunk)).map_err(|_| {
+        LunarisError::Io(io::Error::new(
+            io::ErrorKind::BrokenPipe,
+            "client disconnected",
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lunaris_common::protocol::ErrorCode;
+    use tokio::net::TcpListener;
+    use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+
+    /// Client side of a JSON connection, for tests that drive several
+    /// connections at once.
+    struct Connection {
+        reader: OwnedReadHalf,
+        writer: OwnedWriteHalf,
+    }
+
+    impl Connection {
+        async fn open(addr: std::net::SocketAddr) -> Self {
+            let (reader, writer) = TcpStream::connect(addr).await.unwrap().into_split();
+            Self { reader, writer }
+        }
+
+        async fn send(&mut self, sql: &str) {
+            let request = Request::Query { sql: sql.into() };
+            protocol::send_message(&mut self.writer, &request, Format::Json)
+                .await
+                .unwrap();
+        }
+
+        /// Rows of the answer to the statement sent last, or its error code.
+        async fn recv(&mut self) -> Result<Vec<Vec<Value>>, ErrorCode> {
+            let mut rows = Vec::new();
+            loop {
+                match protocol::recv_message(&mut self.reader).await.unwrap() {
+                    Some(Response::Rows(chunk)) => rows.extend(chunk.rows),
+                    Some(Response::Done { .. }) => return Ok(rows),
+                    Some(Response::Error { code, .. }) => return Err(code),
+                    other => panic!("unexpected response: {other:?}"),
+                }
+            }
+        }
+
+        async fn query(&mut self, sql: &str) -> Result<Vec<Vec<Value>>, ErrorCode> {
+            self.send(sql).await;
+            self.recv().await
+        }
+    }
+
+    #[test]
+    fn test_error_code_in_response() {
+        let dir = std::env::temp_dir()
+            .join("lunaris_test")
+            .join("server_error_code");
+        let _ = std::fs::remove_dir_all(&dir);
+        let db = Database::open(dir).unwrap();
+        let mut session = db.new_session();
+        let (tx, _rx) = mpsc::channel(STREAM_CHANNEL_CAPACITY);
+
+        let response = run_request(&db, &mut session, "SELECT * FROM missing", &tx);
+        assert_eq!(
+            response,
+            Response::Error {
+                code: ErrorCode::TableNotFound,
+                message: "Table not found: missing".into(),
+                location: None,
+            }
+        );
+        let response = run_request(&db, &mut session, "SELEC 1", &tx);
+        assert!(matches!(
+            response,
+            Response::Error {
+                code: ErrorCode::SyntaxError,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_query_and_mutation_responses() {
+        let dir = std::env::temp_dir()
+            .join("lunaris_test")
+            .join("server_statement_kind");
+        let _ = std::fs::remove_dir_all(&dir);
+        let db = Database::open(dir).unwrap();
+        let mut session = db.new_session();
+        let (tx, mut rx) = mpsc::channel(STREAM_CHANNEL_CAPACITY);
+
+        run_request(
+            &db,
+            &mut session,
+            "CREATE TABLE t (id INTEGER, name VARCHAR(10))",
+            &tx,
+        );
+        let response = run_request(
+            &db,
+            &mut session,
+            "INSERT INTO t VALUES (1, 'a'), (2, 'b')",
+            &tx,
+        );
+        assert!(matches!(
+            response,
+            Response::Done {
+                kind: StatementKind::Mutation,
+                rows_affected: 2,
+                ..
+            }
+        ));
+        assert!(rx.try_recv().is_err());
+
+        // a mutation's RETURNING rows arrive, and it is still a mutation
+        let response = run_request(
+            &db,
+            &mut session,
+            "DELETE FROM t WHERE id = 1 RETURNING id, name",
+            &tx,
+        );
+        assert!(matches!(
+            response,
+            Response::Done {
+                kind: StatementKind::Mutation,
+                rows_affected: 1,
+                ..
+            }
+        ));
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            Response::Rows(ResultSet {
+                columns: vec!["id".into(), "name".into()],
+                column_types: vec![ValueType::Integer, ValueType::Text],
+                rows: vec![vec![Value::Integer(1), Value::Text("a".into())]],
+            })
+        );
+        assert!(rx.try_recv().is_err());
 
-        let request = match request {
-            Some(r) => r,
-            None => return,
+        // no rows, but the columns still arrive
+        let response = run_request(&db, &mut session, "SELECT * FROM t WHERE 1 = 0", &tx);
+        assert!(matches!(
+            response,
+            Response::Done {
+                kind: StatementKind::Query,
+                rows_affected: 0,
+                ..
+            }
+        ));
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            Response::Rows(ResultSet {
+                columns: vec!["id".into(), "name".into()],
+                column_types: vec![ValueType::Integer, ValueType::Text],
+                rows: vec![],
+            })
+        );
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_transactions_are_per_connection() {
+        let dir = std::env::temp_dir()
+            .join("lunaris_test")
+            .join("server_sessions");
+        let _ = std::fs::remove_dir_all(&dir);
+        let db = Arc::new(Database::open(dir).unwrap());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(handle_connection(stream, Arc::clone(&db)));
+            }
+        });
+
+        let ids = |ids: &[i64]| -> Vec<Vec<Value>> {
+            ids.iter().map(|id| vec![Value::Integer(*id)]).collect()
         };
+        let mut a = Connection::open(addr).await;
+        let mut b = Connection::open(addr).await;
+        a.query("CREATE TABLE t (id INTEGER PRIMARY KEY)")
+            .await
+            .unwrap();
+
+        a.query("BEGIN").await.unwrap();
+        a.query("INSERT INTO t VALUES (1)").await.unwrap();
+        // b has no transaction of its own to end
+        assert_eq!(b.query("COMMIT").await, Err(ErrorCode::TransactionError));
+        assert_eq!(b.query("ROLLBACK").await, Err(ErrorCode::TransactionError));
+
+        // b's statement waits for a's transaction to end, then commits on
+        // its own
+        b.send("INSERT INTO t VALUES (2)").await;
+        a.query("ROLLBACK").await.unwrap();
+        b.recv().await.unwrap();
+        assert_eq!(a.query("SELECT id FROM t").await, Ok(ids(&[2])));
 
-        let response = match db.execute_sql(&request.sql) {
-            Ok(result) => {
-                let result_set = if !result.rows.is_empty() {
-                    Some(ResultSet {
-                        columns: result.columns,
-                        rows: result.rows,
-                    })
-                } else {
-                    None
-                };
+        a.query("BEGIN").await.unwrap();
+        a.query("INSERT INTO t VALUES (3)").await.unwrap();
+        b.send("INSERT INTO t VALUES (4)").await;
+        a.query("COMMIT").await.unwrap();
+        b.recv().await.unwrap();
+        assert_eq!(b.query("SELECT id FROM t").await, Ok(ids(&[2, 3, 4])));
 
-                Response::Ok(QueryResult {
-                    message: result.message,
-                    result_set,
-                })
+        // a connection dropped mid-transaction loses its changes and lets
+        // the others write again
+        a.query("BEGIN").await.unwrap();
+        a.query("INSERT INTO t VALUES (5)").await.unwrap();
+        drop(a);
+        b.query("INSERT INTO t VALUES (6)").await.unwrap();
+        assert_eq!(b.query("SELECT id FROM t").await, Ok(ids(&[2, 3, 4, 6])));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_uncommitted_changes_stay_private() {
+        let dir = std::env::temp_dir()
+            .join("lunaris_test")
+            .join("server_read_isolation");
+        let _ = std::fs::remove_dir_all(&dir);
+        let db = Arc::new(Database::open(dir).unwrap());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(handle_connection(stream, Arc::clone(&db)));
             }
-            Err(e) => Response::Error {
-                message: e.to_string(),
-            },
+        });
+
+        let mut a = Connection::open(addr).await;
+        let mut b = Connection::open(addr).await;
+        a.query("CREATE TABLE t (id INTEGER PRIMARY KEY, tag TEXT)")
+            .await
+            .unwrap();
+        a.query("CREATE INDEX t_tag ON t (tag)").await.unwrap();
+        a.query("CREATE TABLE k (g TEXT, n INTEGER, PRIMARY KEY (g, n))")
+            .await
+            .unwrap();
+        a.query("INSERT INTO t VALUES (1, 'x'), (2, 'y')")
+            .await
+            .unwrap();
+        a.query("INSERT INTO k VALUES ('b', 1)").await.unwrap();
+
+        a.query("BEGIN").await.unwrap();
+        // enough rows to split pages, so the tree itself changes shape
+        let values: Vec<String> = (3..=400).map(|i| format!("({i}, 'x')")).collect();
+        a.query(&format!("INSERT INTO t VALUES {}", values.join(", ")))
+            .await
+            .unwrap();
+        a.query("UPDATE t SET tag = 'z' WHERE id = 2")
+            .await
+            .unwrap();
+        a.query("DELETE FROM t WHERE id = 1").await.unwrap();
+        a.query("INSERT INTO k VALUES ('a', 1)").await.unwrap();
+
+        let text = |s: &str| Value::Text(s.into());
+        let committed = vec![
+            vec![Value::Integer(1), text("x")],
+            vec![Value::Integer(2), text("y")],
+        ];
+        assert_eq!(b.query("SELECT * FROM t").await, Ok(committed.clone()));
+        assert_eq!(
+            b.query("SELECT id FROM t WHERE id = 1").await,
+            Ok(vec![vec![Value::Integer(1)]])
+        );
+        assert_eq!(
+            b.query("SELECT id FROM t WHERE tag = 'x'").await,
+            Ok(vec![vec![Value::Integer(1)]])
+        );
+        assert_eq!(b.query("SELECT g FROM k").await, Ok(vec![vec![text("b")]]));
+        // while a sees its own changes
+        assert_eq!(
+            a.query("SELECT COUNT(*) FROM t WHERE tag = 'x'").await,
+            Ok(vec![vec![Value::Integer(398)]])
+        );
+        assert_eq!(
+            a.query("SELECT g FROM k").await,
+            Ok(vec![vec![text("a")], vec![text("b")]])
+        );
+
+        a.query("COMMIT").await.unwrap();
+        assert_eq!(
+            b.query("SELECT id FROM t WHERE tag = 'z'").await,
+            Ok(vec![vec![Value::Integer(2)]])
+        );
+        assert_eq!(
+            b.query("SELECT COUNT(*) FROM t").await,
+            Ok(vec![vec![Value::Integer(399)]])
+        );
+        assert_eq!(
+            b.query("SELECT g FROM k").await,
+            Ok(vec![vec![text("a")], vec![text("b")]])
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_ping_while_busy() {
+        let dir = std::env::temp_dir()
+            .join("lunaris_test")
+            .join("server_ping");
+        let _ = std::fs::remove_dir_all(&dir);
+        let db = Arc::new(Database::open(dir).unwrap());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(handle_connection(stream, Arc::clone(&db)));
+            }
+        });
+
+        let mut a = Connection::open(addr).await;
+        let mut b = Connection::open(addr).await;
+        let mut c = Connection::open(addr).await;
+        a.query("CREATE TABLE t (id INTEGER PRIMARY KEY)")
+            .await
+            .unwrap();
+        a.query("BEGIN").await.unwrap();
+        a.query("INSERT INTO t VALUES (1)").await.unwrap();
+        // b's statement blocks until a's transaction ends
+        b.send("INSERT INTO t VALUES (2)").await;
+
+        let start = std::time::Instant::now();
+        protocol::send_message(&mut c.writer, &Request::Ping, Format::Json)
+            .await
+            .unwrap();
+        let pong = protocol::recv_message::<Response, _>(&mut c.reader).await;
+        assert_eq!(pong.unwrap(), Some(Response::Pong));
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+
+        a.query("COMMIT").await.unwrap();
+        b.recv().await.unwrap();
+        // the connection is still good for statements afterwards
+        assert_eq!(
+            c.query("SELECT COUNT(*) FROM t").await,
+            Ok(vec![vec![Value::Integer(2)]])
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_graceful_shutdown() {
+        let dir = std::env::temp_dir()
+            .join("lunaris_test")
+            .join("server_shutdown");
+        let _ = std::fs::remove_dir_all(&dir);
+        let db = Arc::new(Database::open(dir.clone()).unwrap());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(serve(listener, Arc::clone(&db), async {
+            let _ = stop_rx.await;
+        }));
+
+        let mut a = Connection::open(addr).await;
+        a.query("CREATE TABLE t (id INTEGER PRIMARY KEY)")
+            .await
+            .unwrap();
+        a.query("INSERT INTO t VALUES (1)").await.unwrap();
+        a.query("BEGIN").await.unwrap();
+        a.query("INSERT INTO t VALUES (2)").await.unwrap();
+
+        stop_tx.send(()).unwrap();
+        server.await.unwrap().unwrap();
+        // the server closed the idle connection
+        assert!(
+            protocol::recv_message::<Response, _>(&mut a.reader)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(TcpStream::connect(addr).await.is_err());
+
+        // the open transaction was rolled back rather than flushed
+        drop(db);
+        let db = Database::open(dir).unwrap();
+        let mut s = db.new_session();
+        let rows = db.execute_sql(&mut s, "SELECT id FROM t").unwrap().rows;
+        assert_eq!(rows, vec![vec![Value::Integer(1)]]);
+    }
+
+    #[tokio::test]
+    async fn test_large_result_is_streamed() {
+        let dir = std::env::temp_dir()
+            .join("lunaris_test")
+            .join("server_stream");
+        let _ = std::fs::remove_dir_all(&dir);
+        let db = Arc::new(Database::open(dir).unwrap());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            handle_connection(stream, db).await;
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (mut reader, mut writer) = stream.into_split();
+        let hello = Handshake {
+            format: Format::Binary,
         };
+        protocol::send_message(&mut writer, &hello, Format::Json)
+            .await
+            .unwrap();
+        let ack: Handshake = protocol::recv_message(&mut reader).await.unwrap().unwrap();
+        assert_eq!(ack.format, Format::Binary);
+
+        let total = STREAM_CHUNK_ROWS * 2 + 500;
+        let values: Vec<String> = (1..=total).map(|i| format!("({i})")).collect();
+        let statements = [
+            "CREATE TABLE t (id INTEGER PRIMARY KEY)".to_string(),
+            format!("INSERT INTO t VALUES {}", values.join(", ")),
+        ];
+        for sql in statements {
+            let request = Request::Query { sql };
+            protocol::send_message(&mut writer, &request, Format::Binary)
+                .await
+                .unwrap();
+            let response: Response = protocol::recv_message(&mut reader).await.unwrap().unwrap();
+            assert!(matches!(response, Response::Done { .. }));
+        }
 
-        if let Err(e) = protocol::send_message(&mut writer, &response).await {
-            eprintln!("[server] write error: {e}");
-            return;
+        let request = Request::Query {
+            sql: "SELECT id FROM t".into(),
+        };
+        protocol::send_message(&mut writer, &request, Format::Binary)
+            .await
+            .unwrap();
+        let mut frames = 0;
+        let mut next_id = 1;
+        loop {
+            match protocol::recv_message(&mut reader).await.unwrap().unwrap() {
+                Response::Rows(chunk) => {
+                    frames += 1;
+                    assert_eq!(chunk.columns, vec!["id".to_string()]);
+                    for row in chunk.rows {
+                        assert_eq!(row, vec![Value::Integer(next_id)]);
+                        next_id += 1;
+                    }
+                }
+                Response::Done { message, .. } => {
+                    assert_eq!(message, format!("{total} row(s) returned"));
+                    break;
+                }
+                other => panic!("unexpected response: {other:?}"),
+            }
         }
+        assert_eq!(frames, 3);
+        assert_eq!(next_id as usize, total + 1);
     }
 }
@@ -1,9 +1,32 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
+use crate::constants::{CELL_AREA_SIZE, CELL_POINTER_SIZE, MAX_LOCAL_CELL_SIZE, MAX_ROW_SIZE};
 use crate::error::{LunarisError, LunarisResult};
 use crate::storage::cursor::Cursor;
 use crate::storage::page::{Page, PageKind};
 use crate::storage::pager::Pager;
+use crate::storage::wal::Wal;
+
+/// Pages whose cells take up less than this many bytes after a delete are
+/// merged with or refilled from a sibling.
+const MIN_FILL: usize = CELL_AREA_SIZE / 4;
+
+const INTERIOR_CELL_SIZE: usize = 12;
+const LEAF_CELL_HEADER_SIZE: usize = 10;
+const OVERFLOW_CELL_HEADER_SIZE: usize = 18;
+
+const KEY_SIGN: u64 = 1 << 63;
+
+/// Tree key of the row with rowid `id`. Keys compare as unsigned numbers, so
+/// the sign bit is flipped to keep negative rowids ahead of the others.
+pub fn row_key(id: i64) -> u64 {
+    id as u64 ^ KEY_SIGN
+}
+
+/// Rowid of the row stored under `key`, undoing `row_key`.
+pub fn key_row_id(key: u64) -> i64 {
+    (key ^ KEY_SIGN) as i64
+}
 
 pub struct BTreeTable {
     pub pager: Pager,
@@ -15,50 +38,173 @@ impl BTreeTable {
         Ok(Self { pager })
     }
 
+    /// Open an existing table file that must not be written to.
+    pub fn open_read_only(path: &Path) -> LunarisResult<Self> {
+        let pager = Pager::open_read_only(path)?;
+        Ok(Self { pager })
+    }
+
+    /// Read-only view of the table as of its last commit, without the
+    /// changes of an open transaction.
+    pub fn committed(&self) -> BTreeTable {
+        Self {
+            pager: self.pager.committed(),
+        }
+    }
+
+    /// Files making up the table stored at `path`: the table file and its
+    /// log, which may not exist.
+    pub fn files(path: &Path) -> [PathBuf; 2] {
+        [path.to_path_buf(), Wal::path_for(path)]
+    }
+
     pub fn root_page_id(&self) -> u32 {
         self.pager.meta.root_page_id
     }
 
-    pub fn next_row_id(&mut self) -> u64 {
-        let id = self.pager.meta.next_row_id;
+    /// Hand out the next unused rowid. Fails once the largest rowid has been
+    /// taken rather than wrapping around to negative ones.
+    pub fn next_row_id(&mut self) -> LunarisResult<i64> {
+        let id = i64::try_from(self.pager.meta.next_row_id).map_err(|_| {
+            LunarisError::Storage(format!(
+                "no rowid left to assign: the largest, {}, is taken",
+                i64::MAX
+            ))
+        })?;
         self.pager.meta.next_row_id += 1;
-        id
+        Ok(id)
+    }
+
+    /// Keep `next_row_id` past `id`, a rowid the caller chose itself.
+    /// Negative rowids are left out, new rows still count up from 1.
+    pub fn reserve_row_id(&mut self, id: i64) {
+        if id >= 0 && id as u64 >= self.pager.meta.next_row_id {
+            self.pager.meta.next_row_id = id as u64 + 1;
+        }
     }
 
     pub fn flush(&mut self) -> LunarisResult<()> {
         self.pager.flush_all()
     }
 
+    /// Keep changes in memory until `commit`, so they can be rolled back.
+    pub fn begin(&mut self) {
+        self.pager.begin();
+    }
+
+    pub fn commit(&mut self) -> LunarisResult<()> {
+        self.pager.commit()
+    }
+
+    pub fn rollback(&mut self) {
+        self.pager.rollback();
+    }
+
+    /// Remove every row at once, leaving a single empty leaf and restarting
+    /// rowids from 1. Returns the number of rows removed.
+    pub fn truncate(&mut self) -> LunarisResult<u64> {
+        let mut rows = 0;
+        let mut page_id = self.root_page_id();
+        loop {
+            let page = self.pager.get_page(page_id)?;
+            match page.kind {
+                PageKind::Interior if page.cells_count > 0 => {
+                    page_id = Page::interior_cell_left_child(page.read_cell(0));
+                }
+                PageKind::Interior => page_id = page.right_pointer,
+                _ => break,
+            }
+        }
+        // count along the leaf chain
+        while page_id != 0 {
+            let page = self.pager.get_page(page_id)?;
+            rows += page.cells_count as u64;
+            page_id = page.right_pointer;
+        }
+
+        self.pager.truncate()?;
+        Ok(rows)
+    }
+
     /// Find the leaf page containing `key` and the cell index (Ok = found, Err = insertion point).
-    pub fn search(&mut self, key: u64) -> LunarisResult<(u32, Result<u16, u16>)> {
+    pub fn search(&self, key: u64) -> LunarisResult<(u32, Result<u16, u16>)> {
+        let (_, page_id, result) = self.search_path(key)?;
+        Ok((page_id, result))
+    }
+
+    /// Like `search`, but also returns the interior pages visited on the way
+    /// down as (page id, index of the child that was followed).
+    fn search_path(&self, key: u64) -> LunarisResult<(SearchPath, u32, Result<u16, u16>)> {
+        let mut path = Vec::new();
         let mut page_id = self.root_page_id();
         loop {
             let page = self.pager.get_page(page_id)?;
             match page.kind {
                 PageKind::Leaf => {
                     let result = page.binary_search_leaf(key);
-                    return Ok((page_id, result));
+                    return Ok((path, page_id, result));
                 }
                 PageKind::Interior => {
                     let idx = page.binary_search_interior(key);
-                    if idx < page.cells_count {
-                        let cell = page.read_cell(idx);
-                        page_id = Page::interior_cell_left_child(cell);
-                    } else {
-                        page_id = page.right_pointer;
-                    }
+                    path.push((page_id, idx));
+                    page_id = Page::interior_child(&page, idx);
                 }
-                PageKind::Invalid => {
-                    return Err(LunarisError::Storage("hit free page during search".into()));
+                PageKind::Invalid | PageKind::Overflow => {
+                    return Err(LunarisError::Storage(
+                        "hit non-tree page during search".into(),
+                    ));
                 }
             }
         }
     }
 
-    /// Insert a row with the given key and data bytes.
+    /// The last non-empty leaf holding keys below `key`, found by stepping
+    /// left from the path that leads to `key`.
+    pub fn leaf_before(&self, key: u64) -> LunarisResult<Option<u32>> {
+        let (path, _, _) = self.search_path(key)?;
+        for &(page_id, idx) in path.iter().rev() {
+            for child_idx in (0..idx).rev() {
+                let child = Page::interior_child(&*self.pager.get_page(page_id)?, child_idx);
+                if let Some(leaf) = self.last_leaf(child)? {
+                    return Ok(Some(leaf));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// The rightmost non-empty leaf under `page_id`.
+    pub fn last_leaf(&self, page_id: u32) -> LunarisResult<Option<u32>> {
+        let page = self.pager.get_page(page_id)?;
+        let cells_count = page.cells_count;
+        match page.kind {
+            PageKind::Leaf => Ok((cells_count > 0).then_some(page_id)),
+            PageKind::Interior => {
+                for idx in (0..=cells_count).rev() {
+                    let child = Page::interior_child(&*self.pager.get_page(page_id)?, idx);
+                    if let Some(leaf) = self.last_leaf(child)? {
+                        return Ok(Some(leaf));
+                    }
+                }
+                Ok(None)
+            }
+            PageKind::Invalid | PageKind::Overflow => Err(LunarisError::Storage(
+                "hit non-tree page during search".into(),
+            )),
+        }
+    }
+
+    /// Insert a row with the given key and data bytes. Rows larger than
+    /// `MAX_ROW_SIZE` are refused before the tree is touched.
     pub fn insert(&mut self, key: u64, data: &[u8]) -> LunarisResult<()> {
+        if data.len() > MAX_ROW_SIZE {
+            return Err(LunarisError::RowTooLarge {
+                size: data.len(),
+                max: MAX_ROW_SIZE,
+            });
+        }
         let root_id = self.root_page_id();
-        let cell = Page::make_leaf_cell(key, data);
+        let cell = self.make_cell(key, data)?;
 
         match self.insert_into_page(root_id, key, &cell)? {
             InsertResult::Done => Ok(()),
@@ -67,7 +213,7 @@ impl BTreeTable {
                 median_key,
             } => {
                 // root was split — create a new root interior page
-                let new_root_id = self.pager.allocate_page();
+                let new_root_id = self.pager.allocate_page()?;
                 let new_root = self.pager.get_page_mut(new_root_id)?;
                 *new_root = Page::new_interior(new_root_id);
 
@@ -81,6 +227,38 @@ impl BTreeTable {
         }
     }
 
+    /// Build the leaf cell for a row, moving everything past a local prefix
+    /// into a chain of overflow pages when the row is too large to inline.
+    fn make_cell(&mut self, key: u64, data: &[u8]) -> LunarisResult<Vec<u8>> {
+        if LEAF_CELL_HEADER_SIZE + data.len() <= MAX_LOCAL_CELL_SIZE {
+            return Ok(Page::make_leaf_cell(key, data));
+        }
+
+        // check before allocating the chain, which would otherwise leak
+        if self.search(key)?.1.is_ok() {
+            return Err(LunarisError::DuplicateKey(key_row_id(key)));
+        }
+
+        let (prefix, rest) = data.split_at(MAX_LOCAL_CELL_SIZE - OVERFLOW_CELL_HEADER_SIZE);
+        let mut next = 0;
+        for chunk in rest.chunks(CELL_AREA_SIZE).rev() {
+            let page_id = self.pager.allocate_page()?;
+            *self.pager.get_page_mut(page_id)? = Page::new_overflow(page_id, chunk, next);
+            next = page_id;
+        }
+        Ok(Page::make_overflow_leaf_cell(key, prefix, data.len(), next))
+    }
+
+    fn free_overflow_chain(&mut self, first_page: u32) -> LunarisResult<()> {
+        let mut page_id = first_page;
+        while page_id != 0 {
+            let next = self.pager.get_page(page_id)?.right_pointer;
+            self.pager.free_page(page_id)?;
+            page_id = next;
+        }
+        Ok(())
+    }
+
     fn insert_into_page(
         &mut self,
         page_id: u32,
@@ -92,7 +270,9 @@ impl BTreeTable {
         match page_type {
             PageKind::Leaf => self.insert_into_leaf(page_id, key, cell),
             PageKind::Interior => self.insert_into_interior(page_id, key, cell),
-            PageKind::Invalid => Err(LunarisError::Storage("insert hit free page".into())),
+            PageKind::Invalid | PageKind::Overflow => {
+                Err(LunarisError::Storage("insert hit non-tree page".into()))
+            }
         }
     }
 
@@ -104,7 +284,7 @@ impl BTreeTable {
     ) -> LunarisResult<InsertResult> {
         let page = self.pager.get_page(page_id)?;
         let insert_pos = match page.binary_search_leaf(key) {
-            Ok(_) => return Err(LunarisError::DuplicateKey(key)),
+            Ok(_) => return Err(LunarisError::DuplicateKey(key_row_id(key))),
             Err(pos) => pos,
         };
 
@@ -193,11 +373,17 @@ impl BTreeTable {
         let pos = all_cells.partition_point(|(k, _)| *k < new_key);
         all_cells.insert(pos, (new_key, new_cell.to_vec()));
 
-        let mid = all_cells.len() / 2;
+        let sizes: Vec<usize> = all_cells
+            .iter()
+            .map(|(_, c)| c.len() + CELL_POINTER_SIZE)
+            .collect();
+        let mid = balanced_split(&sizes).ok_or_else(|| {
+            LunarisError::Storage(format!("cannot split leaf {page_id} into two pages"))
+        })?;
         let median_key = all_cells[mid].0;
 
         // left half stays in the original page
-        let new_right_id = self.pager.allocate_page();
+        let new_right_id = self.pager.allocate_page()?;
 
         let left_page = self.pager.get_page_mut(page_id)?;
         *left_page = Page::new_leaf(page_id);
@@ -266,7 +452,7 @@ impl BTreeTable {
         let right_right_ptr = rightmost;
 
         // rebuild left page
-        let new_right_id = self.pager.allocate_page();
+        let new_right_id = self.pager.allocate_page()?;
         let left_page = self.pager.get_page_mut(page_id)?;
         *left_page = Page::new_interior(page_id);
         for (i, (key, left_child)) in all_cells[..mid].iter().enumerate() {
@@ -292,38 +478,284 @@ impl BTreeTable {
 
     /// Delete the row with the given key. Returns true if found and deleted.
     pub fn delete(&mut self, key: u64) -> LunarisResult<bool> {
-        let (page_id, search_result) = self.search(key)?;
-        match search_result {
-            Ok(index) => {
-                let page = self.pager.get_page_mut(page_id)?;
-                page.remove_cell(index);
-                Ok(true)
+        let (path, page_id, search_result) = self.search_path(key)?;
+        let Ok(index) = search_result else {
+            return Ok(false);
+        };
+
+        let page = self.pager.get_page_mut(page_id)?;
+        let overflow = Page::leaf_get_cell_overflow(page.read_cell(index))?;
+        page.remove_cell(index);
+        let page_empty = page.cells_count == 0;
+
+        if let Some((_, first_page)) = overflow {
+            self.free_overflow_chain(first_page)?;
+        }
+
+        if path.is_empty() {
+            let page = self.pager.get_page_mut(page_id)?;
+            if page_empty {
+                // an empty root leaf - start over with an unfragmented page
+                *page = Page::new_leaf(page_id);
             }
-            Err(_) => Ok(false),
+            return Ok(true);
         }
+
+        self.rebalance(page_id, &path)?;
+        Ok(true)
+    }
+
+    /// Restore the fill of `page_id` after a delete by merging it with a
+    /// sibling or borrowing cells from one. Merges remove a separator from
+    /// the parent, so they continue upwards and may collapse the root.
+    fn rebalance(&mut self, page_id: u32, path: &[(u32, u16)]) -> LunarisResult<()> {
+        let Some((&(parent_id, child_idx), ancestors)) = path.split_last() else {
+            return self.collapse_root();
+        };
+
+        let page = self.pager.get_page(page_id)?;
+        if page.used_space() >= MIN_FILL {
+            return Ok(());
+        }
+        let kind = page.kind;
+
+        let parent = self.pager.get_page(parent_id)?;
+        if parent.cells_count == 0 {
+            return Ok(());
+        }
+
+        // rebalance with the left sibling if there is one, else the right
+        let sep = child_idx.saturating_sub(1);
+        let left = Page::interior_child(&parent, sep);
+        let right = Page::interior_child(&parent, sep + 1);
+        let sep_key = Page::interior_cell_key(parent.read_cell(sep));
+
+        let merged = match kind {
+            PageKind::Leaf => self.rebalance_leaves(parent_id, sep, left, right)?,
+            PageKind::Interior => self.rebalance_interiors(parent_id, sep, sep_key, left, right)?,
+            PageKind::Invalid | PageKind::Overflow => {
+                return Err(LunarisError::Storage("delete hit non-tree page".into()));
+            }
+        };
+
+        if merged {
+            self.rebalance(parent_id, ancestors)?;
+        }
+        Ok(())
+    }
+
+    /// Merge two adjacent leaves if their cells fit in one page, otherwise
+    /// spread the cells evenly between them. Returns true on merge.
+    fn rebalance_leaves(
+        &mut self,
+        parent_id: u32,
+        sep: u16,
+        left: u32,
+        right: u32,
+    ) -> LunarisResult<bool> {
+        let mut cells = self.collect_cells(left)?;
+        cells.extend(self.collect_cells(right)?);
+        let right_next = self.pager.get_page(right)?.right_pointer;
+
+        let sizes: Vec<usize> = cells.iter().map(|c| c.len() + CELL_POINTER_SIZE).collect();
+        let total: usize = sizes.iter().sum();
+
+        if total <= CELL_AREA_SIZE {
+            self.write_leaf(left, &cells, right_next)?;
+            self.remove_separator(parent_id, sep, left)?;
+            self.pager.free_page(right)?;
+            return Ok(true);
+        }
+
+        let Some(mid) = balanced_split(&sizes) else {
+            return Ok(false);
+        };
+
+        self.write_leaf(left, &cells[..mid], right)?;
+        self.write_leaf(right, &cells[mid..], right_next)?;
+        let new_key = Page::leaf_get_cell_key(&cells[mid]);
+        self.replace_separator(parent_id, sep, left, new_key)?;
+        Ok(false)
+    }
+
+    /// Interior counterpart of `rebalance_leaves` - the parent's separator is
+    /// pulled down between the two pages' entries. Returns true on merge.
+    fn rebalance_interiors(
+        &mut self,
+        parent_id: u32,
+        sep: u16,
+        sep_key: u64,
+        left: u32,
+        right: u32,
+    ) -> LunarisResult<bool> {
+        let mut entries = self.collect_interior_entries(left)?;
+        let left_last = self.pager.get_page(left)?.right_pointer;
+        entries.push((left_last, sep_key));
+        entries.extend(self.collect_interior_entries(right)?);
+        let right_last = self.pager.get_page(right)?.right_pointer;
+
+        let entry_size = INTERIOR_CELL_SIZE + CELL_POINTER_SIZE;
+        if entries.len() * entry_size <= CELL_AREA_SIZE {
+            self.write_interior(left, &entries, right_last)?;
+            self.remove_separator(parent_id, sep, left)?;
+            self.pager.free_page(right)?;
+            return Ok(true);
+        }
+
+        let mid = entries.len() / 2;
+        let (mid_child, mid_key) = entries[mid];
+        self.write_interior(left, &entries[..mid], mid_child)?;
+        self.write_interior(right, &entries[mid + 1..], right_last)?;
+        self.replace_separator(parent_id, sep, left, mid_key)?;
+        Ok(false)
+    }
+
+    /// Replace an interior root that has a single child with that child.
+    fn collapse_root(&mut self) -> LunarisResult<()> {
+        let root_id = self.root_page_id();
+        let root = self.pager.get_page(root_id)?;
+        if root.kind == PageKind::Interior && root.cells_count == 0 {
+            self.pager.meta.root_page_id = root.right_pointer;
+            self.pager.free_page(root_id)?;
+        }
+        Ok(())
+    }
+
+    /// Drop separator `sep` after its right child was merged into `left`,
+    /// leaving `left` in the merged child's place.
+    fn remove_separator(&mut self, parent_id: u32, sep: u16, left: u32) -> LunarisResult<()> {
+        let parent = self.pager.get_page_mut(parent_id)?;
+        parent.remove_cell(sep);
+
+        if sep < parent.cells_count {
+            let key = Page::interior_cell_key(parent.read_cell(sep));
+            parent.remove_cell(sep);
+            parent.insert_cell(sep, &Page::make_interior_cell(left, key))?;
+        } else {
+            parent.right_pointer = left;
+        }
+        Ok(())
+    }
+
+    fn replace_separator(
+        &mut self,
+        parent_id: u32,
+        sep: u16,
+        left: u32,
+        key: u64,
+    ) -> LunarisResult<()> {
+        let parent = self.pager.get_page_mut(parent_id)?;
+        parent.remove_cell(sep);
+        parent.insert_cell(sep, &Page::make_interior_cell(left, key))
+    }
+
+    fn collect_cells(&self, page_id: u32) -> LunarisResult<Vec<Vec<u8>>> {
+        let page = self.pager.get_page(page_id)?;
+        Ok((0..page.cells_count)
+            .map(|i| page.read_cell(i).to_vec())
+            .collect())
+    }
+
+    fn collect_interior_entries(&self, page_id: u32) -> LunarisResult<Vec<(u32, u64)>> {
+        let page = self.pager.get_page(page_id)?;
+        Ok((0..page.cells_count)
+            .map(|i| {
+                let cell = page.read_cell(i);
+                (
+                    Page::interior_cell_left_child(cell),
+                    Page::interior_cell_key(cell),
+                )
+            })
+            .collect())
+    }
+
+    fn write_leaf(&mut self, page_id: u32, cells: &[Vec<u8>], right: u32) -> LunarisResult<()> {
+        let page = self.pager.get_page_mut(page_id)?;
+        *page = Page::new_leaf(page_id);
+        for (i, cell) in cells.iter().enumerate() {
+            page.insert_cell(i as u16, cell)?;
+        }
+        page.right_pointer = right;
+        Ok(())
+    }
+
+    fn write_interior(
+        &mut self,
+        page_id: u32,
+        entries: &[(u32, u64)],
+        right: u32,
+    ) -> LunarisResult<()> {
+        let page = self.pager.get_page_mut(page_id)?;
+        *page = Page::new_interior(page_id);
+        for (i, (left_child, key)) in entries.iter().enumerate() {
+            page.insert_cell(i as u16, &Page::make_interior_cell(*left_child, *key))?;
+        }
+        page.right_pointer = right;
+        Ok(())
     }
 
     /// Read the row data for a given key (without the key prefix).
-    pub fn get(&mut self, key: u64) -> LunarisResult<Option<Vec<u8>>> {
+    pub fn get(&self, key: u64) -> LunarisResult<Option<Vec<u8>>> {
         let (page_id, search_result) = self.search(key)?;
         match search_result {
-            Ok(index) => {
-                let page = self.pager.get_page(page_id)?;
-                let cell = page.read_cell(index);
-                Ok(Some(Page::leaf_get_cell_data(cell).to_vec()))
-            }
+            Ok(index) => Ok(Some(self.read_cell_data(page_id, index)?)),
             Err(_) => Ok(None),
         }
     }
 
     /// Read the cell data at a given `Cursor` position.
-    pub fn get_cell_data_at(&mut self, cursor: &Cursor) -> LunarisResult<&[u8]> {
-        let page = self.pager.get_page(cursor.current_page_id())?;
-        let cell = page.read_cell(cursor.current_cell_index());
-        Ok(Page::leaf_get_cell_data(cell))
+    pub fn get_cell_data_at(&self, cursor: &Cursor) -> LunarisResult<Vec<u8>> {
+        self.read_cell_data(cursor.current_page_id(), cursor.current_cell_index())
     }
+
+    /// Read the full row data of a leaf cell, following its overflow chain.
+    pub fn read_cell_data(&self, page_id: u32, index: u16) -> LunarisResult<Vec<u8>> {
+        let page = self.pager.get_page(page_id)?;
+        let cell = page.read_cell(index);
+        let mut data = Page::leaf_get_cell_data(cell)?.to_vec();
+        let Some((total_len, mut next)) = Page::leaf_get_cell_overflow(cell)? else {
+            return Ok(data);
+        };
+
+        data.reserve(total_len.saturating_sub(data.len()));
+        while data.len() < total_len {
+            let page = self.pager.get_page(next)?;
+            if page.kind != PageKind::Overflow {
+                return Err(LunarisError::Storage(format!(
+                    "broken overflow chain at page {next}"
+                )));
+            }
+            let take = (total_len - data.len()).min(CELL_AREA_SIZE);
+            data.extend_from_slice(&page.data[..take]);
+            next = page.right_pointer;
+        }
+        Ok(data)
+    }
+}
+
+/// Index at which to split cells of the given sizes so the two halves are as
+/// close in size as possible while each still fits in a page.
+fn balanced_split(sizes: &[usize]) -> Option<usize> {
+    let total: usize = sizes.iter().sum();
+    let mut best: Option<(usize, usize)> = None;
+    let mut left_size = 0;
+    for mid in 1..sizes.len() {
+        left_size += sizes[mid - 1];
+        let right_size = total - left_size;
+        if left_size > CELL_AREA_SIZE || right_size > CELL_AREA_SIZE {
+            continue;
+        }
+        let diff = left_size.abs_diff(right_size);
+        if best.is_none_or(|(_, d)| diff < d) {
+            best = Some((mid, diff));
+        }
+    }
+    best.map(|(mid, _)| mid)
 }
 
+/// Interior pages visited during a search, as (page id, child index).
+type SearchPath = Vec<(u32, u16)>;
+
 enum InsertResult {
     Done,
     Split { new_page_id: u32, median_key: u64 },
@@ -406,4 +838,180 @@ mod tests {
 
         let _ = std::fs::remove_file(&path);
     }
+
+    #[test]
+    fn test_deleted_pages_are_reused() {
+        let path = temp_path("btree_free_pages.db");
+        let _ = std::fs::remove_file(&path);
+
+        let mut tree = BTreeTable::open_or_create(&path).unwrap();
+        let insert_all = |tree: &mut BTreeTable| {
+            for i in 1u64..=200 {
+                let data = format!("data_{i:0>100}");
+                tree.insert(i, data.as_bytes()).unwrap();
+            }
+            tree.flush().unwrap();
+        };
+
+        insert_all(&mut tree);
+        let first_size = std::fs::metadata(&path).unwrap().len();
+
+        for i in 1u64..=200 {
+            assert!(tree.delete(i).unwrap());
+        }
+        tree.flush().unwrap();
+        assert_eq!(
+            tree.pager.get_page(tree.root_page_id()).unwrap().kind,
+            PageKind::Leaf
+        );
+
+        insert_all(&mut tree);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), first_size);
+        for i in 1u64..=200 {
+            assert!(tree.get(i).unwrap().is_some());
+        }
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    fn scan_keys(tree: &mut BTreeTable) -> Vec<u64> {
+        let mut cursor = Cursor::new(crate::storage::row::TableSchema::new("t".into(), vec![]));
+        let mut keys = Vec::new();
+        let mut has_row = cursor.rewind(tree).unwrap();
+        while has_row {
+            keys.push(cursor.row_id(tree).unwrap());
+            has_row = cursor.next(tree).unwrap();
+        }
+        keys
+    }
+
+    fn tree_depth(tree: &mut BTreeTable) -> usize {
+        let mut depth = 1;
+        let mut page_id = tree.root_page_id();
+        while tree.pager.get_page(page_id).unwrap().kind == PageKind::Interior {
+            page_id = Page::interior_child(&tree.pager.get_page(page_id).unwrap(), 0);
+            depth += 1;
+        }
+        depth
+    }
+
+    #[test]
+    fn test_delete_rebalances_multi_level_tree() {
+        let path = temp_path("btree_rebalance.db");
+        let _ = std::fs::remove_file(&path);
+
+        let mut tree = BTreeTable::open_or_create(&path).unwrap();
+        // ~4 rows per leaf, so a few thousand rows need three levels
+        let payload = [7u8; 900];
+        for i in 1u64..=1500 {
+            tree.insert(i, &payload).unwrap();
+        }
+        assert_eq!(tree_depth(&mut tree), 3);
+
+        // delete most keys, mixing sequential runs with a scattered pattern
+        let keep = |k: u64| k.is_multiple_of(50) || (700..720).contains(&k);
+        for i in (1u64..=1500).rev().filter(|k| k.is_multiple_of(2)) {
+            if !keep(i) {
+                assert!(tree.delete(i).unwrap());
+            }
+        }
+        for i in (1u64..=1500).filter(|k| !k.is_multiple_of(2)) {
+            if !keep(i) {
+                assert!(tree.delete(i).unwrap());
+            }
+        }
+
+        let expected: Vec<u64> = (1u64..=1500).filter(|k| keep(*k)).collect();
+        for &k in &expected {
+            let (page_id, result) = tree.search(k).unwrap();
+            let index = result.unwrap_or_else(|_| panic!("key {k} not found"));
+            let page = tree.pager.get_page(page_id).unwrap();
+            assert_eq!(
+                Page::leaf_get_cell_data(page.read_cell(index)).unwrap(),
+                &payload
+            );
+        }
+        assert!(tree.get(1).unwrap().is_none());
+        assert_eq!(scan_keys(&mut tree), expected);
+        assert_eq!(tree_depth(&mut tree), 2);
+
+        for k in expected {
+            assert!(tree.delete(k).unwrap());
+        }
+        assert_eq!(tree_depth(&mut tree), 1);
+        assert!(scan_keys(&mut tree).is_empty());
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_row_too_large() {
+        let path = temp_path("btree_row_too_large.db");
+        let _ = std::fs::remove_file(&path);
+        let mut tree = BTreeTable::open_or_create(&path).unwrap();
+        let pages = tree.pager.page_count;
+
+        let err = tree.insert(1, &vec![0; MAX_ROW_SIZE + 1]);
+        assert!(matches!(
+            err,
+            Err(LunarisError::RowTooLarge { size, max: MAX_ROW_SIZE }) if size == MAX_ROW_SIZE + 1
+        ));
+        // nothing was allocated for the refused row
+        assert_eq!(tree.pager.page_count, pages);
+        assert!(tree.get(1).unwrap().is_none());
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_overflow_rows() {
+        let path = temp_path("btree_overflow.db");
+        let _ = std::fs::remove_file(&path);
+
+        let big = |i: u64| -> Vec<u8> { (0..10_000u64).map(|b| (b * 31 + i) as u8).collect() };
+
+        {
+            let mut tree = BTreeTable::open_or_create(&path).unwrap();
+            for i in 1u64..=20 {
+                let data = if i % 3 == 0 {
+                    vec![i as u8; 50]
+                } else {
+                    big(i)
+                };
+                tree.insert(i, &data).unwrap();
+            }
+            assert!(matches!(
+                tree.insert(5, &big(5)),
+                Err(LunarisError::DuplicateKey(_))
+            ));
+            tree.flush().unwrap();
+        }
+
+        let mut tree = BTreeTable::open_or_create(&path).unwrap();
+        for i in 1u64..=20 {
+            let expected = if i % 3 == 0 {
+                vec![i as u8; 50]
+            } else {
+                big(i)
+            };
+            assert_eq!(tree.get(i).unwrap().unwrap(), expected);
+        }
+
+        let mut cursor = Cursor::new(crate::storage::row::TableSchema::new("t".into(), vec![]));
+        assert!(cursor.rewind(&tree).unwrap());
+        assert_eq!(tree.get_cell_data_at(&cursor).unwrap(), big(1));
+
+        // deleting spilled rows returns their overflow pages for reuse
+        for i in 1u64..=20 {
+            tree.delete(i).unwrap();
+        }
+        tree.flush().unwrap();
+        let size = std::fs::metadata(&path).unwrap().len();
+        tree.insert(1, &big(1)).unwrap();
+        tree.flush().unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), size);
+        assert_eq!(tree.get(1).unwrap().unwrap(), big(1));
+
+        let _ = std::fs::remove_file(&path);
+    }
 }
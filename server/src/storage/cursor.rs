@@ -25,7 +25,7 @@ impl Cursor {
 
     /// Position the cursor at the first row (leftmost leaf, cell 0).
     /// Returns false if the table is empty.
-    pub fn rewind(&mut self, tree: &mut BTreeTable) -> LunarisResult<bool> {
+    pub fn rewind(&mut self, tree: &BTreeTable) -> LunarisResult<bool> {
         let mut page_id = tree.root_page_id();
         loop {
             let page = tree.pager.get_page(page_id)?;
@@ -45,7 +45,7 @@ impl Cursor {
                     self.done = page.cells_count == 0;
                     return Ok(!self.done);
                 }
-                PageKind::Invalid => {
+                PageKind::Invalid | PageKind::Overflow => {
                     self.done = true;
                     return Ok(false);
                 }
@@ -54,7 +54,7 @@ impl Cursor {
     }
 
     /// Advance to the next row, returns false when there are no more rows.
-    pub fn next(&mut self, tree: &mut BTreeTable) -> LunarisResult<bool> {
+    pub fn next(&mut self, tree: &BTreeTable) -> LunarisResult<bool> {
         if self.done {
             return Ok(false);
         }
@@ -79,6 +79,54 @@ impl Cursor {
         Ok(!self.done)
     }
 
+    /// Position the cursor at the last row (rightmost leaf, last cell).
+    /// Returns false if the table is empty.
+    pub fn rewind_last(&mut self, tree: &BTreeTable) -> LunarisResult<bool> {
+        let leaf = tree.last_leaf(tree.root_page_id())?;
+        self.position_at_end(tree, leaf)
+    }
+
+    /// Position the cursor at the first row with a key of at least `key`.
+    /// Returns false if there is none.
+    pub fn seek(&mut self, tree: &BTreeTable, key: u64) -> LunarisResult<bool> {
+        let (page_id, result) = tree.search(key)?;
+        let index = match result {
+            Ok(index) | Err(index) => index,
+        };
+        self.position_at(tree, page_id, index)
+    }
+
+    /// Step back to the previous row, returns false when there are no more
+    /// rows. Leaves don't link backwards, so moving to the previous leaf
+    /// descends from the root again.
+    pub fn prev(&mut self, tree: &BTreeTable) -> LunarisResult<bool> {
+        if self.done {
+            return Ok(false);
+        }
+
+        if self.current_cell > 0 {
+            self.current_cell -= 1;
+            return Ok(true);
+        }
+
+        let first_key = self.row_id(tree)?;
+        let leaf = tree.leaf_before(first_key)?;
+        self.position_at_end(tree, leaf)
+    }
+
+    fn position_at_end(&mut self, tree: &BTreeTable, leaf: Option<u32>) -> LunarisResult<bool> {
+        let Some(page_id) = leaf else {
+            self.done = true;
+            return Ok(false);
+        };
+        let page = tree.pager.get_page(page_id)?;
+        self.current_page = page_id;
+        self.num_cells = page.cells_count;
+        self.current_cell = page.cells_count - 1;
+        self.done = false;
+        Ok(true)
+    }
+
     pub fn is_done(&self) -> bool {
         self.done
     }
@@ -92,50 +140,72 @@ impl Cursor {
     }
 
     /// Read the row_id (key) of the current cell.
-    pub fn row_id(&self, tree: &mut BTreeTable) -> LunarisResult<u64> {
+    pub fn row_id(&self, tree: &BTreeTable) -> LunarisResult<u64> {
         let page = tree.pager.get_page(self.current_page)?;
         let cell = page.read_cell(self.current_cell);
         Ok(Page::leaf_get_cell_key(cell))
     }
 
     /// Read a single column from the current row.
-    pub fn column(&self, tree: &mut BTreeTable, col_index: usize) -> LunarisResult<Value> {
+    pub fn column(&self, tree: &BTreeTable, col_index: usize) -> LunarisResult<Value> {
         let row = self.read_row(tree)?;
         Ok(row.into_iter().nth(col_index).unwrap_or(Value::Null))
     }
 
     /// Deserialize the full current row.
-    pub fn read_row(&self, tree: &mut BTreeTable) -> LunarisResult<Vec<Value>> {
-        let page = tree.pager.get_page(self.current_page)?;
-        let cell = page.read_cell(self.current_cell);
-        let data = Page::leaf_get_cell_data(cell);
-        deserialize_row(&self.schema, data)
+    pub fn read_row(&self, tree: &BTreeTable) -> LunarisResult<Vec<Value>> {
+        let data = tree.read_cell_data(self.current_page, self.current_cell)?;
+        deserialize_row(&self.schema, &data)
     }
 
     /// Delete the current cell and reposition the cursor. Returns false if
     /// the cursor becomes invalid, i.e. no more rows.
     pub fn delete_current(&mut self, tree: &mut BTreeTable) -> LunarisResult<bool> {
-        let page = tree.pager.get_page_mut(self.current_page)?;
-        page.remove_cell(self.current_cell);
-        self.num_cells -= 1;
+        // the delete may restructure the tree, so find the following row by
+        // searching for the deleted key again
+        let key = self.row_id(tree)?;
+        tree.delete(key)?;
 
-        if self.current_cell < self.num_cells {
-            return Ok(true);
-        }
+        let (page_id, result) = tree.search(key)?;
+        let index = match result {
+            Ok(index) => index + 1,
+            Err(index) => index,
+        };
+        self.position_at(tree, page_id, index)
+    }
 
-        let page = tree.pager.get_page(self.current_page)?;
-        let next_page = page.right_pointer;
-        if next_page == 0 {
-            self.done = true;
-            return Ok(false);
-        }
+    /// Replace the data of the current cell, keeping its key, and stay on it.
+    pub fn update_current(&mut self, tree: &mut BTreeTable, data: &[u8]) -> LunarisResult<()> {
+        // a row of a different size may move to another page
+        let key = self.row_id(tree)?;
+        tree.delete(key)?;
+        tree.insert(key, data)?;
+        self.seek(tree, key)?;
+        Ok(())
+    }
 
-        let next = tree.pager.get_page(next_page)?;
-        self.current_page = next_page;
-        self.current_cell = 0;
-        self.num_cells = next.cells_count;
-        self.done = next.cells_count == 0;
-        Ok(!self.done)
+    /// Point the cursor at `index` in leaf `page_id`, moving on to the next
+    /// leaves if the index is past the end of the page.
+    fn position_at(&mut self, tree: &BTreeTable, page_id: u32, index: u16) -> LunarisResult<bool> {
+        let mut page_id = page_id;
+        let mut index = index;
+        loop {
+            let page = tree.pager.get_page(page_id)?;
+            if index < page.cells_count {
+                self.current_page = page_id;
+                self.current_cell = index;
+                self.num_cells = page.cells_count;
+                self.done = false;
+                return Ok(true);
+            }
+
+            if page.right_pointer == 0 {
+                self.done = true;
+                return Ok(false);
+            }
+            page_id = page.right_pointer;
+            index = 0;
+        }
     }
 }
 
@@ -162,15 +232,15 @@ mod tests {
 
         let schema = TableSchema::new("test".into(), vec![]);
         let mut cursor = Cursor::new(schema);
-        let has_data = cursor.rewind(&mut tree).unwrap();
+        let has_data = cursor.rewind(&tree).unwrap();
         assert!(has_data);
 
         let mut count = 0u64;
         loop {
-            let key = cursor.row_id(&mut tree).unwrap();
+            let key = cursor.row_id(&tree).unwrap();
             count += 1;
             assert_eq!(key, count);
-            if !cursor.next(&mut tree).unwrap() {
+            if !cursor.next(&tree).unwrap() {
                 break;
             }
         }
@@ -178,4 +248,89 @@ mod tests {
 
         let _ = std::fs::remove_file(&path);
     }
+
+    #[test]
+    fn test_reverse_scan() {
+        let path = temp_path("cursor_reverse.db");
+        let _ = std::fs::remove_file(&path);
+
+        let mut tree = BTreeTable::open_or_create(&path).unwrap();
+        let mut cursor = Cursor::new(TableSchema::new("test".into(), vec![]));
+        assert!(!cursor.rewind_last(&tree).unwrap());
+
+        // big enough rows to spread the keys over several leaves
+        for i in 1u64..=50 {
+            tree.insert(i, &[0u8; 500]).unwrap();
+        }
+        assert_ne!(tree.root_page_id(), 1);
+
+        let mut keys = Vec::new();
+        let mut has_row = cursor.rewind_last(&tree).unwrap();
+        while has_row {
+            keys.push(cursor.row_id(&tree).unwrap());
+            has_row = cursor.prev(&tree).unwrap();
+        }
+        assert_eq!(keys, (1u64..=50).rev().collect::<Vec<_>>());
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_seek() {
+        let path = temp_path("cursor_seek.db");
+        let _ = std::fs::remove_file(&path);
+
+        let mut tree = BTreeTable::open_or_create(&path).unwrap();
+        for i in (2u64..=100).step_by(2) {
+            tree.insert(i, &[0u8; 500]).unwrap();
+        }
+
+        let mut cursor = Cursor::new(TableSchema::new("test".into(), vec![]));
+        assert!(cursor.seek(&tree, 40).unwrap());
+        assert_eq!(cursor.row_id(&tree).unwrap(), 40);
+        assert!(cursor.seek(&tree, 41).unwrap());
+        assert_eq!(cursor.row_id(&tree).unwrap(), 42);
+        assert!(cursor.next(&tree).unwrap());
+        assert_eq!(cursor.row_id(&tree).unwrap(), 44);
+        assert!(!cursor.seek(&tree, 101).unwrap());
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_delete_during_scan() {
+        let path = temp_path("cursor_delete.db");
+        let _ = std::fs::remove_file(&path);
+
+        let mut tree = BTreeTable::open_or_create(&path).unwrap();
+        for i in 1u64..=200 {
+            tree.insert(i, &[0u8; 100]).unwrap();
+        }
+
+        // delete a contiguous run spanning several leaves plus every 7th key
+        let schema = TableSchema::new("test".into(), vec![]);
+        let mut cursor = Cursor::new(schema);
+        let mut has_row = cursor.rewind(&tree).unwrap();
+        while has_row {
+            let key = cursor.row_id(&tree).unwrap();
+            has_row = if (20..=150).contains(&key) || key.is_multiple_of(7) {
+                cursor.delete_current(&mut tree).unwrap()
+            } else {
+                cursor.next(&tree).unwrap()
+            };
+        }
+
+        let expected: Vec<u64> = (1u64..=200)
+            .filter(|k| !(20..=150).contains(k) && !k.is_multiple_of(7))
+            .collect();
+        let mut remaining = Vec::new();
+        let mut has_row = cursor.rewind(&tree).unwrap();
+        while has_row {
+            remaining.push(cursor.row_id(&tree).unwrap());
+            has_row = cursor.next(&tree).unwrap();
+        }
+        assert_eq!(remaining, expected);
+
+        let _ = std::fs::remove_file(&path);
+    }
 }
@@ -0,0 +1,215 @@
+//! Secondary indexes, each stored as a B+ tree in its own file.
+//!
+//! The tree is keyed by an order-preserving 64-bit prefix of the indexed
+//! value (see `index_key`), and every cell lists the (rowid, value) pairs
+//! whose values map to that key. Most keys hold a single distinct value;
+//! long strings that share their first 8 bytes end up in the same cell.
+
+use std::cmp::Ordering;
+
+use crate::error::{LunarisError, LunarisResult};
+use crate::storage::btree::BTreeTable;
+use crate::storage::cursor::Cursor;
+use crate::storage::row::TableSchema;
+use lunaris_common::binary::BinaryCodec;
+use lunaris_common::value::{self, Value};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IndexSchema {
+    pub index_name: String,
+    pub table_name: String,
+    /// Position of the indexed column in the table schema.
+    pub column: usize,
+}
+
+/// Tree key of `value`, ordered like the values themselves. NULLs are not
+/// indexed.
+pub fn index_key(value: &Value) -> Option<u64> {
+    const SIGN: u64 = 1 << 63;
+    match value {
+        Value::Null => None,
+        Value::Integer(v) => Some(*v as u64 ^ SIGN),
+        Value::Float(v) => {
+            // -0.0 equals 0.0, so both need the same key
+            let bits = if *v == 0.0 { 0 } else { v.to_bits() };
+            Some(if bits & SIGN != 0 { !bits } else { bits | SIGN })
+        }
+        Value::Boolean(v) => Some(*v as u64),
+        Value::Text(s) => Some(prefix_key(s.as_bytes())),
+        Value::Blob(bytes) => Some(prefix_key(bytes)),
+    }
+}
+
+fn prefix_key(bytes: &[u8]) -> u64 {
+    let mut prefix = [0u8; 8];
+    let len = bytes.len().min(8);
+    prefix[..len].copy_from_slice(&bytes[..len]);
+    u64::from_be_bytes(prefix)
+}
+
+/// Record that row `rowid` holds `value`.
+pub fn insert_entry(tree: &mut BTreeTable, value: &Value, rowid: u64) -> LunarisResult<()> {
+    let Some(key) = index_key(value) else {
+        return Ok(());
+    };
+    let mut entries = read_entries(tree, key)?;
+    entries.push((rowid, value.clone()));
+    write_entries(tree, key, &entries)
+}
+
+/// Drop the entry recording that row `rowid` holds `value`, if there is one.
+pub fn remove_entry(tree: &mut BTreeTable, value: &Value, rowid: u64) -> LunarisResult<()> {
+    let Some(key) = index_key(value) else {
+        return Ok(());
+    };
+    let mut entries = read_entries(tree, key)?;
+    entries.retain(|(id, v)| !(*id == rowid && v == value));
+    write_entries(tree, key, &entries)
+}
+
+/// Rowids of the rows holding `value`, in the order they were indexed.
+pub fn lookup(tree: &BTreeTable, value: &Value) -> LunarisResult<Vec<u64>> {
+    let Some(key) = index_key(value) else {
+        return Ok(Vec::new());
+    };
+    Ok(read_entries(tree, key)?
+        .into_iter()
+        .filter(|(_, v)| value::compare(v, value) == Some(Ordering::Equal))
+        .map(|(id, _)| id)
+        .collect())
+}
+
+/// Every (rowid, value) entry of the index, ordered by value. Entries with
+/// equal values keep the order they were indexed in.
+pub fn scan(tree: &BTreeTable) -> LunarisResult<Vec<(u64, Value)>> {
+    let mut entries = Vec::new();
+    // only the cell keys are read, so the cursor needs no columns
+    let mut cursor = Cursor::new(TableSchema::new(String::new(), Vec::new()));
+    let mut has_cell = cursor.rewind(tree)?;
+    while has_cell {
+        let mut cell = read_entries(tree, cursor.row_id(tree)?)?;
+        cell.sort_by(|(_, a), (_, b)| value::compare(a, b).unwrap_or(Ordering::Equal));
+        entries.append(&mut cell);
+        has_cell = cursor.next(tree)?;
+    }
+    Ok(entries)
+}
+
+// Cells hold a sequence of [rowid (u64) | value (binary codec)] pairs.
+fn read_entries(tree: &BTreeTable, key: u64) -> LunarisResult<Vec<(u64, Value)>> {
+    let Some(data) = tree.get(key)? else {
+        return Ok(Vec::new());
+    };
+    let mut input = data.as_slice();
+    let mut entries = Vec::new();
+    while !input.is_empty() {
+        let corrupt = || LunarisError::Storage(format!("corrupt index entry under key {key}"));
+        let (id, rest) = input.split_first_chunk::<8>().ok_or_else(corrupt)?;
+        input = rest;
+        let value = Value::decode(&mut input).map_err(|_| corrupt())?;
+        entries.push((u64::from_le_bytes(*id), value));
+    }
+    Ok(entries)
+}
+
+fn write_entries(tree: &mut BTreeTable, key: u64, entries: &[(u64, Value)]) -> LunarisResult<()> {
+    tree.delete(key)?;
+    if entries.is_empty() {
+        return Ok(());
+    }
+    let mut data = Vec::new();
+    for (id, value) in entries {
+        data.extend_from_slice(&id.to_le_bytes());
+        value.encode(&mut data);
+    }
+    tree.insert(key, &data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join("lunaris_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join(name)
+    }
+
+    #[test]
+    fn test_keys_keep_value_order() {
+        let ordered = [
+            vec![
+                Value::Integer(i64::MIN),
+                Value::Integer(-1),
+                Value::Integer(0),
+                Value::Integer(7),
+                Value::Integer(i64::MAX),
+            ],
+            vec![
+                Value::Float(f64::NEG_INFINITY),
+                Value::Float(-2.5),
+                Value::Float(-0.0),
+                Value::Float(1e-300),
+                Value::Float(3.0),
+            ],
+            vec![
+                Value::Text(String::new()),
+                Value::Text("a".into()),
+                Value::Text("ab".into()),
+                Value::Text("b".into()),
+            ],
+        ];
+        for values in ordered {
+            let keys: Vec<u64> = values.iter().map(|v| index_key(v).unwrap()).collect();
+            assert!(keys.is_sorted(), "{values:?}");
+        }
+        assert_eq!(
+            index_key(&Value::Float(0.0)),
+            index_key(&Value::Float(-0.0))
+        );
+        assert_eq!(index_key(&Value::Null), None);
+    }
+
+    #[test]
+    fn test_entries() {
+        let path = temp_path("index_entries.db");
+        let _ = std::fs::remove_file(&path);
+        let mut tree = BTreeTable::open_or_create(&path).unwrap();
+
+        let long = |s: &str| Value::Text(format!("prefix__{s}"));
+        insert_entry(&mut tree, &Value::Integer(5), 1).unwrap();
+        insert_entry(&mut tree, &Value::Integer(5), 2).unwrap();
+        insert_entry(&mut tree, &Value::Integer(6), 3).unwrap();
+        insert_entry(&mut tree, &Value::Null, 4).unwrap();
+        insert_entry(&mut tree, &long("a"), 5).unwrap();
+        insert_entry(&mut tree, &long("b"), 6).unwrap();
+
+        assert_eq!(lookup(&tree, &Value::Integer(5)).unwrap(), vec![1, 2]);
+        assert_eq!(lookup(&tree, &Value::Integer(6)).unwrap(), vec![3]);
+        assert!(lookup(&tree, &Value::Null).unwrap().is_empty());
+        // same key, different values
+        assert_eq!(lookup(&tree, &long("a")).unwrap(), vec![5]);
+        assert_eq!(lookup(&tree, &long("b")).unwrap(), vec![6]);
+
+        remove_entry(&mut tree, &Value::Integer(5), 1).unwrap();
+        remove_entry(&mut tree, &long("a"), 5).unwrap();
+        remove_entry(&mut tree, &Value::Integer(6), 3).unwrap();
+        assert_eq!(lookup(&tree, &Value::Integer(5)).unwrap(), vec![2]);
+        assert!(lookup(&tree, &long("a")).unwrap().is_empty());
+        assert_eq!(lookup(&tree, &long("b")).unwrap(), vec![6]);
+        assert!(
+            tree.get(index_key(&Value::Integer(6)).unwrap())
+                .unwrap()
+                .is_none()
+        );
+
+        insert_entry(&mut tree, &long("0"), 7).unwrap();
+        insert_entry(&mut tree, &Value::Integer(-1), 8).unwrap();
+        let ids: Vec<u64> = scan(&tree).unwrap().into_iter().map(|(id, _)| id).collect();
+        // the strings' keys start with b'p', below any integer's
+        assert_eq!(ids, vec![7, 6, 8, 2]);
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -1,5 +1,30 @@
 pub mod btree;
 pub mod cursor;
+pub mod index;
 mod page;
 mod pager;
 pub mod row;
+mod wal;
+
+use std::fs::{DirBuilder, OpenOptions};
+use std::io;
+use std::path::Path;
+
+/// Create `dir` and any missing parents, readable by the owner only on Unix
+/// since they hold the data. Existing directories are left as they are.
+pub(crate) fn create_private_dir(dir: &Path) -> io::Result<()> {
+    let mut builder = DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(dir)
+}
+
+/// Options to open a file with, which give a newly created one owner-only
+/// permissions on Unix.
+pub(crate) fn private_file_options() -> OpenOptions {
+    let mut options = OpenOptions::new();
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+}
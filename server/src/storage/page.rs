@@ -2,12 +2,42 @@ use crate::error::{LunarisError, LunarisResult};
 
 use crate::constants::{CELL_AREA_SIZE, CELL_POINTER_SIZE, PAGE_HEADER_SIZE, PAGE_SIZE};
 
+/// Set in a leaf cell's data_len when the row continues in overflow pages.
+const OVERFLOW_FLAG: u16 = 0x8000;
+
+/// CRC32 over the whole page except the checksum field itself.
+fn page_checksum(buf: &[u8; PAGE_SIZE]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&buf[..12]);
+    hasher.update(&buf[16..]);
+    hasher.finalize()
+}
+
+fn leaf_cell_len_field(cell: &[u8]) -> LunarisResult<u16> {
+    cell.get(8..10)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| truncated_cell(cell))
+}
+
+fn truncated_cell(cell: &[u8]) -> LunarisError {
+    LunarisError::Storage(format!("truncated leaf cell ({} bytes)", cell.len()))
+}
+
+fn leaf_cell_header_size(len_field: u16) -> usize {
+    if len_field & OVERFLOW_FLAG != 0 {
+        18
+    } else {
+        10
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum PageKind {
     Invalid = 0,
     Leaf = 1,
     Interior = 2,
+    Overflow = 3,
 }
 
 impl PageKind {
@@ -15,6 +45,7 @@ impl PageKind {
         match v {
             1 => PageKind::Leaf,
             2 => PageKind::Interior,
+            3 => PageKind::Overflow,
             _ => PageKind::Invalid,
         }
     }
@@ -49,16 +80,26 @@ impl Page {
         buf[4..6].copy_from_slice(&self.cell_bodies_start.to_le_bytes());
         buf[6..8].copy_from_slice(&self.free_space.to_le_bytes());
         buf[8..12].copy_from_slice(&self.right_pointer.to_le_bytes());
-        // bytes [12; 16] - reserved
         buf[PAGE_HEADER_SIZE..].copy_from_slice(&self.data);
+        // bytes [12; 16] - checksum of the rest of the page
+        let checksum = page_checksum(&buf);
+        buf[12..16].copy_from_slice(&checksum.to_le_bytes());
         buf
     }
 
-    pub fn from_bytes(id: u32, buf: &[u8; PAGE_SIZE]) -> Self {
+    pub fn from_bytes(id: u32, buf: &[u8; PAGE_SIZE]) -> LunarisResult<Self> {
+        let stored = u32::from_le_bytes(buf[12..16].try_into().unwrap());
+        let computed = page_checksum(buf);
+        if stored != computed {
+            return Err(LunarisError::Storage(format!(
+                "checksum mismatch on page {id}: stored 0x{stored:08X}, computed 0x{computed:08X}"
+            )));
+        }
+
         let mut data = [0u8; CELL_AREA_SIZE];
         data.copy_from_slice(&buf[PAGE_HEADER_SIZE..]);
 
-        Self {
+        let page = Self {
             id,
             dirty: false,
             kind: PageKind::from_u8(buf[0]),
@@ -67,7 +108,9 @@ impl Page {
             free_space: u16::from_le_bytes([buf[6], buf[7]]),
             right_pointer: u32::from_le_bytes(buf[8..12].try_into().unwrap()),
             data,
-        }
+        };
+        page.validate()?;
+        Ok(page)
     }
 
     pub fn new_leaf(id: u32) -> Self {
@@ -96,6 +139,37 @@ impl Page {
         }
     }
 
+    /// A page holding part of a spilled row; `right_pointer` links to the next
+    /// page of the chain and the whole cell area is payload.
+    pub fn new_overflow(id: u32, chunk: &[u8], next: u32) -> Self {
+        let mut data = [0u8; CELL_AREA_SIZE];
+        data[..chunk.len()].copy_from_slice(chunk);
+        Self {
+            id,
+            dirty: true,
+            kind: PageKind::Overflow,
+            cells_count: 0,
+            cell_bodies_start: CELL_AREA_SIZE as u16,
+            free_space: 0,
+            right_pointer: next,
+            data,
+        }
+    }
+
+    /// A page on the free list; `right_pointer` links to the next free page.
+    pub fn new_free(id: u32, next_free: u32) -> Self {
+        Self {
+            id,
+            dirty: true,
+            kind: PageKind::Invalid,
+            cells_count: 0,
+            cell_bodies_start: CELL_AREA_SIZE as u16,
+            free_space: CELL_AREA_SIZE as u16,
+            right_pointer: next_free,
+            data: [0u8; CELL_AREA_SIZE],
+        }
+    }
+
     fn cell_pointer_offset(index: u16) -> usize {
         (index as usize) * CELL_POINTER_SIZE
     }
@@ -123,28 +197,127 @@ impl Page {
         content_start.saturating_sub(pointers_end)
     }
 
+    /// Space available once dead cell bodies are reclaimed by `compact`.
+    pub fn reclaimable_space(&self) -> usize {
+        let live: usize = (0..self.cells_count).map(|i| self.read_cell(i).len()).sum();
+        CELL_AREA_SIZE - self.cell_pointers_end() - live
+    }
+
+    /// Bytes taken up by live cells and their pointers.
+    pub fn used_space(&self) -> usize {
+        CELL_AREA_SIZE - self.reclaimable_space()
+    }
+
+    /// Rewrite the live cell bodies contiguously at the end of the page,
+    /// reclaiming the bytes left behind by removed cells.
+    pub fn compact(&mut self) {
+        let cells: Vec<Vec<u8>> = (0..self.cells_count)
+            .map(|i| self.read_cell(i).to_vec())
+            .collect();
+
+        let mut start = CELL_AREA_SIZE;
+        for (i, cell) in cells.iter().enumerate() {
+            start -= cell.len();
+            self.data[start..start + cell.len()].copy_from_slice(cell);
+            self.set_cell_offset(i as u16, start as u16);
+        }
+        let pointers_end = self.cell_pointers_end();
+        self.data[pointers_end..start].fill(0);
+
+        self.cell_bodies_start = start as u16;
+        self.free_space = self.usable_space() as u16;
+        self.dirty = true;
+    }
+
+    /// Read the cell at `index`. Pages are validated when loaded from disk,
+    /// so this only panics if the in-memory page was built incorrectly.
     pub fn read_cell(&self, index: u16) -> &[u8] {
+        match self.try_read_cell(index) {
+            Ok(cell) => cell,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    /// Bounds-checked variant of `read_cell`.
+    pub fn try_read_cell(&self, index: u16) -> LunarisResult<&[u8]> {
+        if matches!(self.kind, PageKind::Invalid | PageKind::Overflow) {
+            return Ok(&[]);
+        }
+        if index >= self.cells_count {
+            return Err(LunarisError::Storage(format!(
+                "page {}: cell {index} out of range ({} cells)",
+                self.id, self.cells_count
+            )));
+        }
+
         let offset = self.get_cell_offset(index) as usize;
-        match self.kind {
+        let overrun = || {
+            LunarisError::Storage(format!(
+                "page {}: cell {index} at offset {offset} overruns the page",
+                self.id
+            ))
+        };
+        if offset < self.cell_bodies_start as usize {
+            return Err(overrun());
+        }
+
+        let len = match self.kind {
             PageKind::Leaf => {
-                let data_len =
-                    u16::from_le_bytes([self.data[offset + 8], self.data[offset + 9]]) as usize;
-                &self.data[offset..offset + 10 + data_len]
+                let len_field = self.data.get(offset + 8..offset + 10).ok_or_else(overrun)?;
+                let len_field = u16::from_le_bytes([len_field[0], len_field[1]]);
+                leaf_cell_header_size(len_field) + (len_field & !OVERFLOW_FLAG) as usize
             }
-            PageKind::Interior => &self.data[offset..offset + 12],
-            PageKind::Invalid => &[],
+            _ => 12,
+        };
+        self.data.get(offset..offset + len).ok_or_else(overrun)
+    }
+
+    /// Check the header and every cell pointer of a page read from disk.
+    fn validate(&self) -> LunarisResult<()> {
+        if matches!(self.kind, PageKind::Invalid | PageKind::Overflow) {
+            return Ok(());
+        }
+        if self.cell_bodies_start as usize > CELL_AREA_SIZE
+            || self.cell_pointers_end() > self.cell_bodies_start as usize
+        {
+            return Err(LunarisError::Storage(format!(
+                "page {}: corrupt header ({} cells, bodies start at {})",
+                self.id, self.cells_count, self.cell_bodies_start
+            )));
         }
+        for i in 0..self.cells_count {
+            self.try_read_cell(i)?;
+        }
+        Ok(())
     }
 
     // -- Leaf cell accessors: [row_id: u64][data_len: u16][row bytes...] --
+    // A spilled row sets the top bit of data_len, which then holds the length
+    // of the locally stored prefix, and inserts [total_len: u32]
+    // [first_overflow_page: u32] before the prefix.
 
     pub fn leaf_get_cell_key(cell: &[u8]) -> u64 {
         u64::from_le_bytes(cell[0..8].try_into().unwrap())
     }
 
-    pub fn leaf_get_cell_data(cell: &[u8]) -> &[u8] {
-        let data_len = u16::from_le_bytes([cell[8], cell[9]]) as usize;
-        &cell[10..10 + data_len]
+    /// The row bytes stored in the cell itself - only a prefix for spilled rows.
+    pub fn leaf_get_cell_data(cell: &[u8]) -> LunarisResult<&[u8]> {
+        let len_field = leaf_cell_len_field(cell)?;
+        let header = leaf_cell_header_size(len_field);
+        cell.get(header..header + (len_field & !OVERFLOW_FLAG) as usize)
+            .ok_or_else(|| truncated_cell(cell))
+    }
+
+    /// `(total row length, first overflow page)` if the row spilled.
+    pub fn leaf_get_cell_overflow(cell: &[u8]) -> LunarisResult<Option<(usize, u32)>> {
+        let len_field = leaf_cell_len_field(cell)?;
+        if len_field & OVERFLOW_FLAG == 0 {
+            return Ok(None);
+        }
+        let header = cell.get(10..18).ok_or_else(|| truncated_cell(cell))?;
+        let total = u32::from_le_bytes(header[0..4].try_into().unwrap()) as usize;
+        let first_page = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        Ok(Some((total, first_page)))
     }
 
     // -- Interior cell accessors: [left_child: u32][separator_key: u64] --
@@ -157,14 +330,28 @@ impl Page {
         u64::from_le_bytes(cell[4..12].try_into().unwrap())
     }
 
+    /// Child page at `index` of an interior page, where `index == cells_count`
+    /// refers to the rightmost child.
+    pub fn interior_child(page: &Page, index: u16) -> u32 {
+        if index < page.cells_count {
+            Self::interior_cell_left_child(page.read_cell(index))
+        } else {
+            page.right_pointer
+        }
+    }
+
     /// Insert `cell_data` at sorted position `index`, shifting later pointers.
+    /// Compacts the page first if the cell only fits in reclaimed space.
     pub fn insert_cell(&mut self, sorted_index: u16, cell_data: &[u8]) -> LunarisResult<()> {
         let cell_size = cell_data.len();
         let needed = cell_size + CELL_POINTER_SIZE;
-        let available = self.usable_space();
 
-        if needed > available {
-            return Err(LunarisError::PageFull { needed, available });
+        if needed > self.usable_space() {
+            let available = self.reclaimable_space();
+            if needed > available {
+                return Err(LunarisError::PageFull { needed, available });
+            }
+            self.compact();
         }
 
         let new_content_start = self.cell_bodies_start as usize - cell_size;
@@ -184,7 +371,7 @@ impl Page {
     }
 
     /// Remove the cell at `index`, shifting later pointers left.
-    /// Does not reclaim the cell body bytes (fragmentation is tolerated).
+    /// Does not reclaim the cell body bytes - that is deferred to `compact`.
     pub fn remove_cell(&mut self, index: u16) {
         for i in index..self.cells_count - 1 {
             let off = self.get_cell_offset(i + 1);
@@ -235,6 +422,21 @@ impl Page {
         cell
     }
 
+    pub fn make_overflow_leaf_cell(
+        row_id: u64,
+        prefix: &[u8],
+        total_len: usize,
+        first_overflow_page: u32,
+    ) -> Vec<u8> {
+        let mut cell = Vec::with_capacity(18 + prefix.len());
+        cell.extend_from_slice(&row_id.to_le_bytes());
+        cell.extend_from_slice(&(prefix.len() as u16 | OVERFLOW_FLAG).to_le_bytes());
+        cell.extend_from_slice(&(total_len as u32).to_le_bytes());
+        cell.extend_from_slice(&first_overflow_page.to_le_bytes());
+        cell.extend_from_slice(prefix);
+        cell
+    }
+
     pub fn make_interior_cell(left_child: u32, separator_key: u64) -> Vec<u8> {
         let mut cell = Vec::with_capacity(12);
         cell.extend_from_slice(&left_child.to_le_bytes());
@@ -279,6 +481,60 @@ mod tests {
         assert_eq!(Page::leaf_get_cell_key(page.read_cell(1)), 2);
     }
 
+    #[test]
+    fn test_insert_reclaims_fragmented_space() {
+        let mut page = Page::new_leaf(1);
+        let mut key = 0u64;
+        loop {
+            let cell = Page::make_leaf_cell(key, &[key as u8; 90]);
+            if page.insert_cell(key as u16, &cell).is_err() {
+                break;
+            }
+            key += 1;
+        }
+        let full_count = page.cells_count;
+
+        // drop every other cell; the freed bodies are not part of the gap yet
+        for i in (0..full_count).rev().filter(|i| i % 2 == 1) {
+            page.remove_cell(i);
+        }
+        let gap = page.usable_space();
+        assert!(page.reclaimable_space() > gap);
+
+        // cells larger than the gap only fit after compaction
+        let big = Page::make_leaf_cell(1000, &[0xAB; 300]);
+        assert!(big.len() + CELL_POINTER_SIZE > gap);
+        let remaining = page.cells_count;
+        page.insert_cell(remaining, &big).unwrap();
+
+        assert_eq!(page.cells_count, remaining + 1);
+        for i in 0..remaining {
+            let cell = page.read_cell(i);
+            let key = Page::leaf_get_cell_key(cell);
+            assert_eq!(key, i as u64 * 2);
+            assert_eq!(Page::leaf_get_cell_data(cell).unwrap(), &[key as u8; 90]);
+        }
+        assert_eq!(
+            Page::leaf_get_cell_data(page.read_cell(remaining)).unwrap(),
+            &[0xAB; 300]
+        );
+    }
+
+    #[test]
+    fn test_compact_rejects_cell_that_never_fits() {
+        let mut page = Page::new_leaf(1);
+        for i in 0u16..3 {
+            let cell = Page::make_leaf_cell(i as u64, &[0u8; 1000]);
+            page.insert_cell(i, &cell).unwrap();
+        }
+        page.remove_cell(1);
+
+        let cell = Page::make_leaf_cell(9, &[0u8; 3000]);
+        let err = page.insert_cell(2, &cell);
+        assert!(matches!(err, Err(LunarisError::PageFull { .. })));
+        assert_eq!(page.cells_count, 2);
+    }
+
     #[test]
     fn test_interior_page_binary_search() {
         let mut page = Page::new_interior(1);
@@ -301,13 +557,60 @@ mod tests {
         page.right_pointer = 99;
 
         let bytes = page.to_bytes();
-        let restored = Page::from_bytes(7, &bytes);
+        let restored = Page::from_bytes(7, &bytes).unwrap();
 
         assert_eq!(restored.kind, PageKind::Leaf);
         assert_eq!(restored.cells_count, 1);
         assert_eq!(restored.right_pointer, 99);
         let c = restored.read_cell(0);
         assert_eq!(Page::leaf_get_cell_key(c), 42);
-        assert_eq!(Page::leaf_get_cell_data(c), b"hello");
+        assert_eq!(Page::leaf_get_cell_data(c).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_checksum_detects_corruption() {
+        let mut page = Page::new_leaf(3);
+        page.insert_cell(0, &Page::make_leaf_cell(1, b"payload"))
+            .unwrap();
+
+        let mut bytes = page.to_bytes();
+        bytes[PAGE_SIZE - 3] ^= 0x01;
+        assert!(matches!(
+            Page::from_bytes(3, &bytes),
+            Err(LunarisError::Storage(msg)) if msg.contains("checksum")
+        ));
+    }
+
+    #[test]
+    fn test_malformed_pages_are_rejected() {
+        let mut page = Page::new_leaf(4);
+        page.insert_cell(0, &Page::make_leaf_cell(1, b"abc"))
+            .unwrap();
+
+        // cell pointer past the end of the page; the checksum is recomputed so
+        // only the structural check can catch it
+        page.set_cell_offset(0, CELL_AREA_SIZE as u16 - 4);
+        assert!(matches!(
+            Page::from_bytes(4, &page.to_bytes()),
+            Err(LunarisError::Storage(msg)) if msg.contains("overruns")
+        ));
+
+        // more cell pointers than fit in the page
+        let mut page = Page::new_leaf(4);
+        page.cells_count = u16::MAX;
+        assert!(matches!(
+            Page::from_bytes(4, &page.to_bytes()),
+            Err(LunarisError::Storage(msg)) if msg.contains("corrupt header")
+        ));
+
+        // truncated cell buffers
+        let cell = Page::make_overflow_leaf_cell(1, b"xyz", 10_000, 9);
+        assert!(Page::leaf_get_cell_data(&cell[..9]).is_err());
+        assert!(Page::leaf_get_cell_data(&cell[..cell.len() - 1]).is_err());
+        assert!(Page::leaf_get_cell_overflow(&cell[..12]).is_err());
+        assert_eq!(
+            Page::leaf_get_cell_overflow(&cell).unwrap(),
+            Some((10_000, 9))
+        );
     }
 }
@@ -1,15 +1,30 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::Path;
+use std::sync::{Arc, Mutex};
 
-use crate::constants::{MAGIC, META_PAGE_SIZE, PAGE_SIZE};
+use crate::constants::{
+    DEFAULT_PAGE_CACHE_CAPACITY, MAGIC, MAX_TRANSACTION_PAGES, META_PAGE_SIZE, PAGE_SIZE,
+    WAL_CHECKPOINT_SIZE,
+};
 use crate::error::{LunarisError, LunarisResult};
+use crate::storage;
 use crate::storage::page::Page;
+use crate::storage::wal::Wal;
 
+const META_CHECKSUM_OFFSET: usize = META_PAGE_SIZE - 4;
+
+/// The file header, represented in the following format:
+/// [magic | root page id | next row id | free list head | wal position | checksum]
+#[derive(Debug, Clone)]
 pub struct FileMetadata {
     pub root_page_id: u32,
     pub next_row_id: u64,
+    /// First page of the singly linked list of freed pages, 0 if empty.
+    pub free_list_head: u32,
+    /// Write-ahead log position up to which batches are in the main file.
+    pub wal_applied: u64,
 }
 
 impl FileMetadata {
@@ -18,53 +33,148 @@ impl FileMetadata {
         buf[0..4].copy_from_slice(&MAGIC.to_le_bytes());
         buf[4..8].copy_from_slice(&self.root_page_id.to_le_bytes());
         buf[8..16].copy_from_slice(&self.next_row_id.to_le_bytes());
+        buf[16..20].copy_from_slice(&self.free_list_head.to_le_bytes());
+        buf[20..28].copy_from_slice(&self.wal_applied.to_le_bytes());
+        let checksum = crc32fast::hash(&buf[..META_CHECKSUM_OFFSET]);
+        buf[META_CHECKSUM_OFFSET..].copy_from_slice(&checksum.to_le_bytes());
         buf
     }
 
-    pub fn from_bytes(buf: &[u8; META_PAGE_SIZE]) -> LunarisResult<Self> {
+    pub fn from_bytes(buf: &[u8]) -> LunarisResult<Self> {
+        let Some(buf) = buf.get(..META_PAGE_SIZE) else {
+            return Err(LunarisError::Storage(format!(
+                "file header truncated: expected {META_PAGE_SIZE} bytes, got {}",
+                buf.len()
+            )));
+        };
+
         let magic = u32::from_le_bytes(buf[0..4].try_into().unwrap());
         if magic != MAGIC {
             return Err(LunarisError::Storage(format!(
                 "bad magic: expected 0x{MAGIC:08X}, got 0x{magic:08X}"
             )));
         }
+
+        let stored = u32::from_le_bytes(buf[META_CHECKSUM_OFFSET..].try_into().unwrap());
+        if stored != crc32fast::hash(&buf[..META_CHECKSUM_OFFSET]) {
+            return Err(LunarisError::Storage(
+                "checksum mismatch in file header".into(),
+            ));
+        }
+
         let root_page_id = u32::from_le_bytes(buf[4..8].try_into().unwrap());
         let next_row_id = u64::from_le_bytes(buf[8..16].try_into().unwrap());
+        let free_list_head = u32::from_le_bytes(buf[16..20].try_into().unwrap());
+        let wal_applied = u64::from_le_bytes(buf[20..28].try_into().unwrap());
         Ok(Self {
             root_page_id,
             next_row_id,
+            free_list_head,
+            wal_applied,
         })
     }
 }
 
+struct CachedPage {
+    page: Arc<Page>,
+    last_used: u64,
+}
+
+/// Pages of a `committed` view that a flush has since overwritten, as they
+/// were when the view was taken.
+struct PinnedView {
+    page_count: u32,
+    pages: HashMap<u32, Arc<Page>>,
+}
+
+/// The clean cached pages and their recency. Kept behind a lock so pages can
+/// be read through a shared `&Pager`.
+struct PageCache {
+    pages: HashMap<u32, CachedPage>,
+    /// Access tick -> page id, oldest first.
+    lru: BTreeMap<u64, u32>,
+    tick: u64,
+    capacity: usize,
+    /// The open `committed` views, by id.
+    views: HashMap<u64, PinnedView>,
+    next_view: u64,
+}
+
+/// Pages are cached up to `capacity`, after which the least recently used
+/// clean one is evicted. `get_page` only needs a shared reference and hands
+/// out its own handle to the page, so several readers can use the pager at
+/// once; a page they hold stays valid after it is evicted or changed.
+///
+/// Changed pages are kept apart and never evicted: they only reach the file
+/// through `flush_all`, which goes through the write-ahead log, so a crash
+/// midway leaves either the old state or one that recovery on `open`
+/// completes. Between `begin` and `commit`/`rollback` they stay in memory,
+/// so the file keeps its state from before the transaction until commit,
+/// and a transaction changing more than `transaction_limit` pages fails.
+/// Outside a transaction they are flushed once they fill the cache.
+///
+/// `committed` gives readers a view of the file without the changes of an
+/// open transaction, sharing the file and the clean pages with this pager.
+/// A view keeps its state for as long as it lives: a flush saves the old
+/// contents of the pages it overwrites for the views still open.
 pub struct Pager {
-    file: File,
+    file: Arc<File>,
+    /// `None` for a pager opened with `open_read_only` and for the views
+    /// `committed` hands out.
+    wal: Option<Wal>,
+    /// Id of the view among the cache's `views`, for a view.
+    view: Option<u64>,
     pub page_count: u32,
-    cache: HashMap<u32, Page>,
+    cache: Arc<Mutex<PageCache>>,
+    /// Pages changed since the last flush.
+    dirty: HashMap<u32, Arc<Page>>,
+    transaction_limit: usize,
     pub meta: FileMetadata,
+    /// Header and page count at `begin`, restored on rollback.
+    snapshot: Option<(FileMetadata, u32)>,
+    /// First and last of the pages freed since `begin`, linked to each other
+    /// but not to the free list until commit.
+    freed: Option<(u32, u32)>,
+    /// Header and page count as of the last flush.
+    on_disk: (FileMetadata, u32),
 }
 
 impl Pager {
     pub fn open(path: &Path) -> LunarisResult<Self> {
         let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+        let mut meta = read_meta(&mut file)?;
 
-        let mut meta_buf = [0u8; META_PAGE_SIZE];
-        file.read_exact(&mut meta_buf)?;
-        let meta = FileMetadata::from_bytes(&meta_buf)?;
+        let mut wal = Wal::open(&Wal::path_for(path))?;
+        recover(&mut file, &mut wal, &mut meta)?;
 
-        let file_len = file.seek(SeekFrom::End(0))?;
-        let page_count = ((file_len as usize - META_PAGE_SIZE) / PAGE_SIZE) as u32;
+        let page_count = count_pages(&mut file, &meta)?;
+        Ok(Self::new(file, Some(wal), page_count, meta))
+    }
 
-        Ok(Self {
-            file,
-            page_count,
-            cache: HashMap::new(),
-            meta,
-        })
+    /// Open an existing file without write access. Every method that would
+    /// change the file fails with `LunarisError::ReadOnly`. Commits still
+    /// waiting in the write-ahead log can't be applied without writing, so
+    /// such a file is refused until it has been opened read-write once.
+    pub fn open_read_only(path: &Path) -> LunarisResult<Self> {
+        let mut file = OpenOptions::new().read(true).open(path)?;
+        let meta = read_meta(&mut file)?;
+
+        if let Some(mut wal) = Wal::open_read_only(&Wal::path_for(path))?
+            && meta.wal_applied <= wal.len()
+            && !wal.read_batches(meta.wal_applied)?.is_empty()
+        {
+            return Err(LunarisError::Wal(format!(
+                "{} has committed changes that were never applied, open it for writing once to recover them",
+                path.display()
+            )));
+        }
+
+        let page_count = count_pages(&mut file, &meta)?;
+        Ok(Self::new(file, None, page_count, meta))
     }
 
     pub fn create(path: &Path) -> LunarisResult<Self> {
-        let mut file = OpenOptions::new()
+        let mut file = storage::private_file_options()
             .read(true)
             .write(true)
             .create(true)
@@ -74,6 +184,8 @@ impl Pager {
         let meta = FileMetadata {
             root_page_id: 1,
             next_row_id: 1,
+            free_list_head: 0,
+            wal_applied: 0,
         };
         file.write_all(&meta.to_bytes())?;
 
@@ -81,12 +193,12 @@ impl Pager {
         file.write_all(&root.to_bytes())?;
         file.sync_all()?;
 
-        Ok(Self {
-            file,
-            page_count: 1,
-            cache: HashMap::new(),
-            meta,
-        })
+        // a log left over from an earlier file of the same name must not be
+        // replayed into this one
+        let mut wal = Wal::open(&Wal::path_for(path))?;
+        wal.reset()?;
+
+        Ok(Self::new(file, Some(wal), 1, meta))
     }
 
     pub fn open_or_create(path: &Path) -> LunarisResult<Self> {
@@ -97,64 +209,479 @@ impl Pager {
         }
     }
 
-    pub fn get_page(&mut self, id: u32) -> LunarisResult<&Page> {
-        if !self.cache.contains_key(&id) {
-            let page = self.read_page_from_disk(id)?;
-            self.cache.insert(id, page);
+    /// Open or create the file at `path`, caching at most `capacity` pages.
+    pub fn with_capacity(path: &Path, capacity: usize) -> LunarisResult<Self> {
+        let pager = Self::open_or_create(path)?;
+        pager.cache.lock().unwrap().capacity = capacity.max(1);
+        Ok(pager)
+    }
+
+    /// Fail transactions once they change more than `pages` pages.
+    pub fn set_transaction_limit(&mut self, pages: usize) {
+        self.transaction_limit = pages;
+    }
+
+    fn new(file: File, wal: Option<Wal>, page_count: u32, meta: FileMetadata) -> Self {
+        Self {
+            file: Arc::new(file),
+            wal,
+            view: None,
+            page_count,
+            cache: Arc::new(Mutex::new(PageCache {
+                pages: HashMap::new(),
+                lru: BTreeMap::new(),
+                tick: 0,
+                capacity: DEFAULT_PAGE_CACHE_CAPACITY,
+                views: HashMap::new(),
+                next_view: 0,
+            })),
+            dirty: HashMap::new(),
+            transaction_limit: MAX_TRANSACTION_PAGES,
+            on_disk: (meta.clone(), page_count),
+            meta,
+            snapshot: None,
+            freed: None,
         }
-        Ok(self.cache.get(&id).unwrap())
+    }
+
+    /// A read-only view of the file as of the last flush, leaving out the
+    /// pages changed since then. Later flushes don't change what it reads.
+    pub fn committed(&self) -> Pager {
+        let (meta, page_count) = self.on_disk.clone();
+        let view = {
+            let mut cache = self.cache.lock().unwrap();
+            let id = cache.next_view;
+            cache.next_view += 1;
+            cache.views.insert(
+                id,
+                PinnedView {
+                    page_count,
+                    pages: HashMap::new(),
+                },
+            );
+            id
+        };
+        Pager {
+            file: Arc::clone(&self.file),
+            wal: None,
+            view: Some(view),
+            page_count,
+            cache: Arc::clone(&self.cache),
+            dirty: HashMap::new(),
+            transaction_limit: self.transaction_limit,
+            meta,
+            snapshot: None,
+            freed: None,
+            on_disk: self.on_disk.clone(),
+        }
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.wal.is_none()
+    }
+
+    fn check_writable(&self) -> LunarisResult<()> {
+        if self.is_read_only() {
+            return Err(LunarisError::ReadOnly);
+        }
+        Ok(())
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.snapshot.is_some()
+    }
+
+    /// Start holding changes in memory until `commit` or `rollback`.
+    pub fn begin(&mut self) {
+        if self.snapshot.is_none() {
+            self.snapshot = Some((self.meta.clone(), self.page_count));
+        }
+    }
+
+    /// Write the changes made since `begin` to disk.
+    pub fn commit(&mut self) -> LunarisResult<()> {
+        if let Some((first, last)) = self.freed.take() {
+            let next = self.meta.free_list_head;
+            self.get_page_mut(last)?.right_pointer = next;
+            self.meta.free_list_head = first;
+        }
+        self.flush_all()?;
+        self.snapshot = None;
+        Ok(())
+    }
+
+    /// Drop every change made since `begin`, returning to the state on disk.
+    pub fn rollback(&mut self) {
+        let Some((meta, page_count)) = self.snapshot.take() else {
+            return;
+        };
+        self.dirty.clear();
+        self.freed = None;
+        self.meta = meta;
+        self.page_count = page_count;
+    }
+
+    pub fn get_page(&self, id: u32) -> LunarisResult<Arc<Page>> {
+        if let Some(page) = self.dirty.get(&id) {
+            return Ok(Arc::clone(page));
+        }
+        let mut cache = self.cache.lock().unwrap();
+        if let Some(page) = self
+            .view
+            .and_then(|view| cache.views.get(&view)?.pages.get(&id))
+        {
+            return Ok(Arc::clone(page));
+        }
+        let entry = cache.load(&self.file, self.page_count, self.dirty.len(), id)?;
+        Ok(Arc::clone(&entry.page))
     }
 
     /// Get a mutable reference to a page (marks it dirty for later flush).
+    /// Handles to the page returned by `get_page` earlier keep the old
+    /// contents.
     pub fn get_page_mut(&mut self, id: u32) -> LunarisResult<&mut Page> {
-        if !self.cache.contains_key(&id) {
-            let page = self.read_page_from_disk(id)?;
-            self.cache.insert(id, page);
+        self.check_writable()?;
+        if !self.dirty.contains_key(&id) {
+            let page = self.get_page(id)?;
+            self.cache.lock().unwrap().remove(id);
+            self.make_room()?;
+            self.dirty.insert(id, page);
         }
-        let page = self.cache.get_mut(&id).unwrap();
+        let page = Arc::make_mut(self.dirty.get_mut(&id).unwrap());
         page.dirty = true;
         Ok(page)
     }
 
-    /// Allocate a new zeroed page at the end of the file and return its id.
-    pub fn allocate_page(&mut self) -> u32 {
-        self.page_count += 1;
-        let id = self.page_count;
-        let page = Page::new_leaf(id);
-        self.cache.insert(id, page);
-        id
+    /// Number of pages currently held in memory.
+    pub fn cached_pages(&self) -> usize {
+        self.cache.lock().unwrap().pages.len() + self.dirty.len()
+    }
+
+    /// Put a changed page in memory, replacing any copy with the same id.
+    fn cache_page(&mut self, page: Page) -> LunarisResult<()> {
+        self.cache.lock().unwrap().remove(page.id);
+        self.make_room()?;
+        self.dirty.insert(page.id, Arc::new(page));
+        Ok(())
+    }
+
+    /// Evict clean pages to make room for one more dirty page. Outside a
+    /// transaction the dirty pages are flushed once they fill the cache;
+    /// inside one they stay in memory past the capacity, up to the limit.
+    fn make_room(&mut self) -> LunarisResult<()> {
+        let capacity = self.cache.lock().unwrap().capacity;
+        if self.in_transaction() && self.dirty.len() >= self.transaction_limit {
+            return Err(LunarisError::Transaction(format!(
+                "transaction changes more than {} pages of one table, split it into smaller ones",
+                self.transaction_limit
+            )));
+        }
+        if self.dirty.len() >= capacity && !self.in_transaction() {
+            self.flush_all()?;
+        }
+        let mut cache = self.cache.lock().unwrap();
+        while cache.pages.len() + self.dirty.len() >= capacity && cache.evict_oldest() {}
+        Ok(())
     }
 
-    /// Write the meta header and all dirty pages to disk.
+    /// Allocate a new zeroed page and return its id. Pages on the free list
+    /// are reused before the file is grown.
+    pub fn allocate_page(&mut self) -> LunarisResult<u32> {
+        self.check_writable()?;
+        let id = if self.meta.free_list_head != 0 {
+            let id = self.meta.free_list_head;
+            self.meta.free_list_head = self.get_page(id)?.right_pointer;
+            id
+        } else {
+            self.page_count += 1;
+            self.page_count
+        };
+
+        self.cache_page(Page::new_leaf(id))?;
+        Ok(id)
+    }
+
+    /// Return a page that is no longer referenced by the tree to the free list.
+    /// Inside a transaction it only joins the list on commit, so the page
+    /// isn't handed out again while a cursor may still hold its id.
+    pub fn free_page(&mut self, id: u32) -> LunarisResult<()> {
+        self.check_writable()?;
+        if !self.in_transaction() {
+            self.cache_page(Page::new_free(id, self.meta.free_list_head))?;
+            self.meta.free_list_head = id;
+            return Ok(());
+        }
+        let (next, last) = match self.freed {
+            Some((first, last)) => (first, last),
+            None => (0, id),
+        };
+        self.cache_page(Page::new_free(id, next))?;
+        self.freed = Some((id, last));
+        Ok(())
+    }
+
+    /// Drop every page and start over with an empty leaf as the root. The
+    /// file shrinks to that single page on the next flush.
+    pub fn truncate(&mut self) -> LunarisResult<()> {
+        self.check_writable()?;
+        {
+            let mut cache = self.cache.lock().unwrap();
+            cache.pages.clear();
+            cache.lru.clear();
+        }
+        self.dirty.clear();
+        self.freed = None;
+        self.meta.root_page_id = 1;
+        self.meta.next_row_id = 1;
+        self.meta.free_list_head = 0;
+        self.page_count = 1;
+        self.cache_page(Page::new_leaf(1))
+    }
+
+    /// Write all dirty pages and then the meta header to disk, logging them
+    /// to the write-ahead log first. A read-only pager has nothing to write.
     pub fn flush_all(&mut self) -> LunarisResult<()> {
-        self.file.seek(SeekFrom::Start(0))?;
-        self.file.write_all(&self.meta.to_bytes())?;
-
-        for page in self.cache.values_mut() {
-            if page.dirty {
-                let offset = META_PAGE_SIZE as u64 + (page.id as u64 - 1) * PAGE_SIZE as u64;
-                self.file.seek(SeekFrom::Start(offset))?;
-                self.file.write_all(&page.to_bytes())?;
-                page.dirty = false;
+        if self.is_read_only() {
+            return Ok(());
+        }
+        let dirty = self.log_dirty_pages()?;
+        self.write_pages(&dirty)?;
+        self.write_meta()?;
+        {
+            let mut cache = self.cache.lock().unwrap();
+            for (_, mut page) in self.dirty.drain() {
+                Arc::make_mut(&mut page).dirty = false;
+                cache.insert(page, 0);
             }
         }
 
+        if let Some(wal) = &mut self.wal
+            && wal.len() >= WAL_CHECKPOINT_SIZE
+        {
+            wal.reset()?;
+            self.meta.wal_applied = 0;
+            self.write_meta()?;
+        }
+        self.on_disk = (self.meta.clone(), self.page_count);
+        Ok(())
+    }
+
+    /// Append the dirty pages and the header to the log, returning the ids of
+    /// the logged pages. Once this returns the commit survives a crash.
+    fn log_dirty_pages(&mut self) -> LunarisResult<Vec<u32>> {
+        let Some(wal) = &mut self.wal else {
+            return Err(LunarisError::ReadOnly);
+        };
+        let mut dirty: Vec<u32> = self.dirty.keys().copied().collect();
+        dirty.sort_unstable();
+
+        let pages: Vec<&Page> = dirty.iter().map(|id| &*self.dirty[id]).collect();
+        wal.append(&mut self.meta, &pages)?;
+        Ok(dirty)
+    }
+
+    /// Write the pages `dirty` to the main file and sync it. The header is
+    /// only written once they are on disk, so a crash in between leaves the
+    /// old header in place, pointing at the log batch that recovery replays.
+    fn write_pages(&mut self, dirty: &[u32]) -> LunarisResult<()> {
+        {
+            // views read the file under the cache lock, so they see either
+            // the old pages or the copies pinned for them, never a mix
+            let mut cache = self.cache.lock().unwrap();
+            let cut = self.page_count + 1..=self.on_disk.1;
+            for id in dirty.iter().copied().chain(cut) {
+                self.pin_for_views(&mut cache, id)?;
+            }
+            for id in dirty {
+                write_page_to_disk(&self.file, &self.dirty[id])?;
+            }
+            // drop the pages cut off by `truncate`. A crash before this point
+            // leaves them behind as unreachable garbage, which is harmless.
+            let end = page_offset(self.page_count + 1);
+            if self.file.metadata()?.len() > end {
+                self.file.set_len(end)?;
+            }
+        }
+        self.file.sync_all()?;
+        Ok(())
+    }
+
+    /// Keep the contents page `id` has on disk for the open views that can
+    /// reach it and haven't kept a copy yet, before a flush overwrites it.
+    fn pin_for_views(&self, cache: &mut PageCache, id: u32) -> LunarisResult<()> {
+        let on_disk = self.on_disk.1;
+        let needed = |view: &PinnedView| id <= view.page_count && !view.pages.contains_key(&id);
+        if id > on_disk || !cache.views.values().any(needed) {
+            return Ok(());
+        }
+        let page = match cache.pages.get(&id) {
+            Some(entry) => Arc::clone(&entry.page),
+            None => Arc::new(read_page_from_disk(&self.file, on_disk, id)?),
+        };
+        for view in cache.views.values_mut().filter(|view| needed(view)) {
+            view.pages.insert(id, Arc::clone(&page));
+        }
+        Ok(())
+    }
+
+    fn write_meta(&mut self) -> LunarisResult<()> {
+        {
+            let _cache = self.cache.lock().unwrap();
+            let mut file = &*self.file;
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(&self.meta.to_bytes())?;
+        }
         self.file.sync_all()?;
         Ok(())
     }
+}
 
-    fn read_page_from_disk(&mut self, id: u32) -> LunarisResult<Page> {
-        let offset = META_PAGE_SIZE as u64 + (id as u64 - 1) * PAGE_SIZE as u64;
-        self.file.seek(SeekFrom::Start(offset))?;
-        let mut buf = [0u8; PAGE_SIZE];
-        self.file.read_exact(&mut buf)?;
-        Ok(Page::from_bytes(id, &buf))
+impl Drop for Pager {
+    fn drop(&mut self) {
+        if let Some(view) = self.view
+            && let Ok(mut cache) = self.cache.lock()
+        {
+            cache.views.remove(&view);
+        }
     }
 }
 
+impl PageCache {
+    /// Get page `id`, reading it from `file` if it isn't cached, and mark it
+    /// as the most recently used one. `reserved` pages of the capacity are
+    /// taken by dirty pages held elsewhere.
+    fn load(
+        &mut self,
+        file: &File,
+        page_count: u32,
+        reserved: usize,
+        id: u32,
+    ) -> LunarisResult<&mut CachedPage> {
+        if !self.pages.contains_key(&id) {
+            let page = read_page_from_disk(file, page_count, id)?;
+            self.insert(Arc::new(page), reserved);
+        }
+        Ok(self.touch(id))
+    }
+
+    fn touch(&mut self, id: u32) -> &mut CachedPage {
+        self.tick += 1;
+        let entry = self.pages.get_mut(&id).unwrap();
+        self.lru.remove(&entry.last_used);
+        entry.last_used = self.tick;
+        self.lru.insert(self.tick, id);
+        entry
+    }
+
+    /// Put a page into the cache, replacing any cached copy with the same id
+    /// and evicting the least recently used pages to stay within capacity,
+    /// `reserved` pages of which are taken elsewhere.
+    fn insert(&mut self, page: Arc<Page>, reserved: usize) {
+        let id = page.id;
+        self.remove(id);
+        while self.pages.len() + reserved >= self.capacity && self.evict_oldest() {}
+
+        self.tick += 1;
+        self.lru.insert(self.tick, id);
+        self.pages.insert(
+            id,
+            CachedPage {
+                page,
+                last_used: self.tick,
+            },
+        );
+    }
+
+    fn remove(&mut self, id: u32) {
+        if let Some(entry) = self.pages.remove(&id) {
+            self.lru.remove(&entry.last_used);
+        }
+    }
+
+    /// Evict the least recently used page. Returns false if there is none.
+    fn evict_oldest(&mut self) -> bool {
+        let Some((_, id)) = self.lru.pop_first() else {
+            return false;
+        };
+        self.pages.remove(&id);
+        true
+    }
+}
+
+// Both take `&File` so pages can be read through a shared `Pager`. Callers
+// hold the cache lock, so the seek and the read or write can't interleave
+// with another thread's, the views sharing the file included.
+fn write_page_to_disk(mut file: &File, page: &Page) -> LunarisResult<()> {
+    file.seek(SeekFrom::Start(page_offset(page.id)))?;
+    file.write_all(&page.to_bytes())?;
+    Ok(())
+}
+
+fn read_page_from_disk(mut file: &File, page_count: u32, id: u32) -> LunarisResult<Page> {
+    if id == 0 || id > page_count {
+        return Err(LunarisError::Storage(format!(
+            "page {id} outside the file ({page_count} pages)"
+        )));
+    }
+
+    file.seek(SeekFrom::Start(page_offset(id)))?;
+    let mut buf = [0u8; PAGE_SIZE];
+    file.read_exact(&mut buf)?;
+    Page::from_bytes(id, &buf)
+}
+
+/// Apply the log batches that were committed but not yet written to the main
+/// file before the last shutdown.
+fn recover(file: &mut File, wal: &mut Wal, meta: &mut FileMetadata) -> LunarisResult<()> {
+    // the log was emptied by a checkpoint whose header update didn't land
+    if meta.wal_applied > wal.len() {
+        meta.wal_applied = 0;
+    }
+
+    let batches = wal.read_batches(meta.wal_applied)?;
+    if batches.is_empty() {
+        return Ok(());
+    }
+    for batch in batches {
+        for (id, bytes) in &batch.pages {
+            file.seek(SeekFrom::Start(page_offset(*id)))?;
+            file.write_all(bytes.as_slice())?;
+        }
+        *meta = batch.meta;
+    }
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&meta.to_bytes())?;
+    file.sync_all()?;
+    Ok(())
+}
+
+fn read_meta(file: &mut File) -> LunarisResult<FileMetadata> {
+    let mut meta_buf = Vec::with_capacity(META_PAGE_SIZE);
+    file.take(META_PAGE_SIZE as u64)
+        .read_to_end(&mut meta_buf)?;
+    FileMetadata::from_bytes(&meta_buf)
+}
+
+fn count_pages(file: &mut File, meta: &FileMetadata) -> LunarisResult<u32> {
+    let file_len = file.seek(SeekFrom::End(0))?;
+    let page_count = ((file_len as usize - META_PAGE_SIZE) / PAGE_SIZE) as u32;
+    if meta.root_page_id == 0 || meta.root_page_id > page_count {
+        return Err(LunarisError::Storage(format!(
+            "root page {} outside the file ({page_count} pages)",
+            meta.root_page_id
+        )));
+    }
+    Ok(page_count)
+}
+
+fn page_offset(id: u32) -> u64 {
+    META_PAGE_SIZE as u64 + (id as u64 - 1) * PAGE_SIZE as u64
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::storage::page::PageKind;
     use std::path::PathBuf;
 
     fn temp_path(name: &str) -> PathBuf {
@@ -177,14 +704,474 @@ mod tests {
         }
 
         {
-            let mut pager = Pager::open(&path).unwrap();
+            let pager = Pager::open(&path).unwrap();
             assert_eq!(pager.meta.root_page_id, 1);
             let page = pager.get_page(1).unwrap();
             assert_eq!(page.cells_count, 1);
             let cell = page.read_cell(0);
-            assert_eq!(Page::leaf_get_cell_data(cell), b"hello");
+            assert_eq!(Page::leaf_get_cell_data(cell).unwrap(), b"hello");
+        }
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_free_list_reuse() {
+        let path = temp_path("pager_free_list.db");
+        let _ = std::fs::remove_file(&path);
+
+        {
+            let mut pager = Pager::create(&path).unwrap();
+            let a = pager.allocate_page().unwrap();
+            let b = pager.allocate_page().unwrap();
+            assert_eq!((a, b), (2, 3));
+
+            pager.free_page(a).unwrap();
+            pager.free_page(b).unwrap();
+            pager.flush_all().unwrap();
+        }
+
+        {
+            let mut pager = Pager::open(&path).unwrap();
+            assert_eq!(pager.meta.free_list_head, 3);
+            // most recently freed first, then the file grows again
+            assert_eq!(pager.allocate_page().unwrap(), 3);
+            assert_eq!(pager.allocate_page().unwrap(), 2);
+            assert_eq!(pager.allocate_page().unwrap(), 4);
+            assert_eq!(pager.meta.free_list_head, 0);
+
+            // pages freed in a transaction are only reused after it commits
+            pager.begin();
+            pager.free_page(2).unwrap();
+            pager.free_page(3).unwrap();
+            assert_eq!(pager.allocate_page().unwrap(), 5);
+            pager.commit().unwrap();
+            assert_eq!(pager.allocate_page().unwrap(), 3);
+            assert_eq!(pager.allocate_page().unwrap(), 2);
+
+            pager.begin();
+            pager.free_page(4).unwrap();
+            pager.rollback();
+            assert_eq!(pager.meta.free_list_head, 0);
+        }
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_cache_stays_bounded() {
+        let path = temp_path("pager_lru.db");
+        let _ = std::fs::remove_file(&path);
+
+        {
+            let mut pager = Pager::with_capacity(&path, 4).unwrap();
+            for i in 0..50u64 {
+                let id = pager.allocate_page().unwrap();
+                let cell = Page::make_leaf_cell(i, format!("page {id}").as_bytes());
+                pager
+                    .get_page_mut(id)
+                    .unwrap()
+                    .insert_cell(0, &cell)
+                    .unwrap();
+                assert!(pager.cached_pages() <= 4);
+            }
+
+            // pages flushed to make room were written out and read back intact
+            for id in 2..=51u32 {
+                let page = pager.get_page(id).unwrap();
+                let cell = page.read_cell(0);
+                assert_eq!(
+                    Page::leaf_get_cell_data(cell).unwrap(),
+                    format!("page {id}").as_bytes()
+                );
+                assert!(pager.cached_pages() <= 4);
+            }
+
+            // modify pages that are about to be evicted again
+            for id in 2..=51u32 {
+                let page = pager.get_page_mut(id).unwrap();
+                let cell = Page::make_leaf_cell(1000, b"second");
+                page.insert_cell(1, &cell).unwrap();
+            }
+            pager.flush_all().unwrap();
+        }
+
+        let pager = Pager::with_capacity(&path, 2).unwrap();
+        assert_eq!(pager.page_count, 51);
+        for id in 2..=51u32 {
+            let page = pager.get_page(id).unwrap();
+            assert_eq!(page.cells_count, 2);
+            assert_eq!(
+                Page::leaf_get_cell_data(page.read_cell(1)).unwrap(),
+                b"second"
+            );
+        }
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_dirty_pages_are_not_evicted() {
+        let path = temp_path("pager_no_dirty_eviction.db");
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(Wal::path_for(&path));
+
+        {
+            let mut pager = Pager::create(&path).unwrap();
+            for _ in 0..8 {
+                pager.allocate_page().unwrap();
+            }
+            pager.flush_all().unwrap();
+        }
+
+        let mut pager = Pager::with_capacity(&path, 4).unwrap();
+        let cell = Page::make_leaf_cell(1, b"unlogged");
+        pager
+            .get_page_mut(1)
+            .unwrap()
+            .insert_cell(0, &cell)
+            .unwrap();
+        // reading the other pages fills the cache, but the changed page stays
+        // in memory rather than being written behind the log's back
+        for id in 2..=9 {
+            pager.get_page(id).unwrap();
+            assert!(pager.cached_pages() <= 4);
+        }
+        assert_eq!(pager.get_page(1).unwrap().cells_count, 1);
+        assert_eq!(
+            Pager::open(&path).unwrap().get_page(1).unwrap().cells_count,
+            0
+        );
+
+        // "crash" without flushing
+        drop(pager);
+        assert_eq!(
+            Pager::open(&path).unwrap().get_page(1).unwrap().cells_count,
+            0
+        );
+
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(Wal::path_for(&path));
+    }
+
+    #[test]
+    fn test_corruption_on_disk_is_detected() {
+        let path = temp_path("pager_checksum.db");
+        let _ = std::fs::remove_file(&path);
+
+        {
+            let mut pager = Pager::create(&path).unwrap();
+            let cell = Page::make_leaf_cell(1, b"hello");
+            pager
+                .get_page_mut(1)
+                .unwrap()
+                .insert_cell(0, &cell)
+                .unwrap();
+            pager.flush_all().unwrap();
+        }
+
+        let flip_byte = |offset: u64| {
+            let mut file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .unwrap();
+            let mut byte = [0u8; 1];
+            file.seek(SeekFrom::Start(offset)).unwrap();
+            file.read_exact(&mut byte).unwrap();
+            file.seek(SeekFrom::Start(offset)).unwrap();
+            file.write_all(&[byte[0] ^ 0xFF]).unwrap();
+        };
+
+        // a corrupted cell body fails when the page is read
+        flip_byte(page_offset(1) + PAGE_SIZE as u64 - 2);
+        let pager = Pager::open(&path).unwrap();
+        assert!(matches!(pager.get_page(1), Err(LunarisError::Storage(_))));
+        drop(pager);
+
+        // a corrupted header fails on open
+        flip_byte(5);
+        assert!(matches!(Pager::open(&path), Err(LunarisError::Storage(_))));
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_truncated_files_are_rejected() {
+        let path = temp_path("pager_truncated.db");
+        let _ = std::fs::remove_file(&path);
+
+        let header = FileMetadata {
+            root_page_id: 1,
+            next_row_id: 1,
+            free_list_head: 0,
+            wal_applied: 0,
+        }
+        .to_bytes();
+        assert!(matches!(
+            FileMetadata::from_bytes(&header[..10]),
+            Err(LunarisError::Storage(msg)) if msg.contains("truncated")
+        ));
+
+        // header only, the root page is missing
+        std::fs::write(&path, header).unwrap();
+        assert!(matches!(Pager::open(&path), Err(LunarisError::Storage(_))));
+
+        // a dangling page id fails instead of reading past the end
+        {
+            let mut pager = Pager::create(&path).unwrap();
+            pager.flush_all().unwrap();
+        }
+        let pager = Pager::open(&path).unwrap();
+        assert!(matches!(pager.get_page(0), Err(LunarisError::Storage(_))));
+        assert!(matches!(pager.get_page(2), Err(LunarisError::Storage(_))));
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_rollback_discards_changes() {
+        let path = temp_path("pager_rollback.db");
+        let _ = std::fs::remove_file(&path);
+
+        let mut pager = Pager::with_capacity(&path, 2).unwrap();
+        let cell = Page::make_leaf_cell(1, b"kept");
+        pager
+            .get_page_mut(1)
+            .unwrap()
+            .insert_cell(0, &cell)
+            .unwrap();
+        pager.flush_all().unwrap();
+
+        pager.begin();
+        pager.get_page_mut(1).unwrap().remove_cell(0);
+        for _ in 0..5 {
+            pager.allocate_page().unwrap();
+        }
+        pager.meta.next_row_id = 100;
+        // dirty pages stay in memory past the capacity instead of hitting disk
+        assert_eq!(pager.cached_pages(), 6);
+
+        pager.rollback();
+        assert!(!pager.in_transaction());
+        assert_eq!(pager.page_count, 1);
+        assert_eq!(pager.meta.next_row_id, 1);
+        assert_eq!(pager.get_page(1).unwrap().cells_count, 1);
+
+        pager.begin();
+        pager.allocate_page().unwrap();
+        pager.commit().unwrap();
+        drop(pager);
+        assert_eq!(Pager::open(&path).unwrap().page_count, 2);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_committed_view_outlives_commits() {
+        let path = temp_path("pager_view.db");
+        let _ = std::fs::remove_file(&path);
+
+        let mut pager = Pager::with_capacity(&path, 2).unwrap();
+        let cell = Page::make_leaf_cell(1, b"kept");
+        pager
+            .get_page_mut(1)
+            .unwrap()
+            .insert_cell(0, &cell)
+            .unwrap();
+        pager.allocate_page().unwrap();
+        pager.flush_all().unwrap();
+
+        let view = pager.committed();
+        pager.begin();
+        pager.get_page_mut(1).unwrap().remove_cell(0);
+        pager.free_page(2).unwrap();
+        pager.commit().unwrap();
+        pager.truncate().unwrap();
+        pager.flush_all().unwrap();
+
+        // the view still reads the pages as they were when it was taken
+        assert_eq!(view.get_page(1).unwrap().cells_count, 1);
+        assert_eq!(view.get_page(2).unwrap().kind, PageKind::Leaf);
+        assert!(pager.get_page(2).is_err());
+
+        drop(view);
+        assert!(pager.cache.lock().unwrap().views.is_empty());
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_transaction_bigger_than_cache() {
+        let path = temp_path("pager_big_transaction.db");
+        let _ = std::fs::remove_file(&path);
+
+        let mut pager = Pager::with_capacity(&path, 4).unwrap();
+        pager.set_transaction_limit(16);
+        pager.begin();
+        for i in 0..12u64 {
+            let id = pager.allocate_page().unwrap();
+            let cell = Page::make_leaf_cell(i, b"big");
+            pager
+                .get_page_mut(id)
+                .unwrap()
+                .insert_cell(0, &cell)
+                .unwrap();
+        }
+        assert_eq!(pager.cached_pages(), 12);
+        pager.commit().unwrap();
+        // committed pages are clean and go back under the capacity
+        assert!(pager.cached_pages() <= 4);
+
+        pager.begin();
+        let err = (0..20).try_for_each(|_| pager.allocate_page().map(|_| ()));
+        assert!(matches!(err, Err(LunarisError::Transaction(_))));
+        assert_eq!(pager.cached_pages(), 16);
+        pager.rollback();
+        assert_eq!(pager.page_count, 13);
+        drop(pager);
+
+        let pager = Pager::open(&path).unwrap();
+        assert_eq!(pager.page_count, 13);
+        for id in 2..=13 {
+            assert_eq!(pager.get_page(id).unwrap().cells_count, 1);
+        }
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_wal_recovery_after_crash() {
+        let path = temp_path("pager_wal.db");
+        let _ = std::fs::remove_file(&path);
+
+        {
+            let mut pager = Pager::create(&path).unwrap();
+            let cell = Page::make_leaf_cell(1, b"before");
+            pager
+                .get_page_mut(1)
+                .unwrap()
+                .insert_cell(0, &cell)
+                .unwrap();
+            pager.flush_all().unwrap();
+
+            // commit to the log, then "crash" before the main file is touched
+            let cell = Page::make_leaf_cell(2, b"after");
+            pager
+                .get_page_mut(1)
+                .unwrap()
+                .insert_cell(1, &cell)
+                .unwrap();
+            let id = pager.allocate_page().unwrap();
+            let cell = Page::make_leaf_cell(3, b"new page");
+            pager
+                .get_page_mut(id)
+                .unwrap()
+                .insert_cell(0, &cell)
+                .unwrap();
+            pager.meta.next_row_id = 4;
+            pager.log_dirty_pages().unwrap();
         }
 
+        // the main file still has the state of the first commit
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().len(),
+            (META_PAGE_SIZE + PAGE_SIZE) as u64
+        );
+
+        // recovering needs write access
+        assert!(matches!(
+            Pager::open_read_only(&path),
+            Err(LunarisError::Wal(_))
+        ));
+
+        let pager = Pager::open(&path).unwrap();
+        assert_eq!(pager.page_count, 2);
+        assert_eq!(pager.meta.next_row_id, 4);
+        let page = pager.get_page(1).unwrap();
+        assert_eq!(page.cells_count, 2);
+        assert_eq!(
+            Page::leaf_get_cell_data(page.read_cell(1)).unwrap(),
+            b"after"
+        );
+        let page = pager.get_page(2).unwrap();
+        assert_eq!(
+            Page::leaf_get_cell_data(page.read_cell(0)).unwrap(),
+            b"new page"
+        );
+
+        // replaying is idempotent and later commits continue the log
+        let applied = pager.meta.wal_applied;
+        drop(pager);
+        let mut pager = Pager::open(&path).unwrap();
+        assert_eq!(pager.meta.wal_applied, applied);
+        pager.get_page_mut(2).unwrap().remove_cell(0);
+        pager.flush_all().unwrap();
+        assert!(pager.meta.wal_applied > applied);
+        drop(pager);
+        let mut pager = Pager::open_read_only(&path).unwrap();
+        assert_eq!(pager.get_page(2).unwrap().cells_count, 0);
+        assert!(matches!(pager.get_page_mut(2), Err(LunarisError::ReadOnly)));
+        assert!(matches!(pager.allocate_page(), Err(LunarisError::ReadOnly)));
+
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(Wal::path_for(&path));
+    }
+
+    #[test]
+    fn test_crash_before_header_write() {
+        let path = temp_path("pager_torn_flush.db");
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(Wal::path_for(&path));
+
+        {
+            let mut pager = Pager::create(&path).unwrap();
+            let cell = Page::make_leaf_cell(1, b"old root");
+            pager
+                .get_page_mut(1)
+                .unwrap()
+                .insert_cell(0, &cell)
+                .unwrap();
+            pager.flush_all().unwrap();
+
+            // move the root to a new page, then "crash" after the pages are
+            // synced but before the header is written
+            let id = pager.allocate_page().unwrap();
+            let cell = Page::make_leaf_cell(1, b"new root");
+            pager
+                .get_page_mut(id)
+                .unwrap()
+                .insert_cell(0, &cell)
+                .unwrap();
+            pager.meta.root_page_id = id;
+            pager.meta.next_row_id = 2;
+            let dirty = pager.log_dirty_pages().unwrap();
+            pager.write_pages(&dirty).unwrap();
+        }
+
+        // the header on disk still describes the first flush
+        let mut file = File::open(&path).unwrap();
+        let meta = read_meta(&mut file).unwrap();
+        assert_eq!(meta.root_page_id, 1);
+        assert_eq!(meta.next_row_id, 1);
+        drop(file);
+
+        // and recovery completes the second one from the log
+        let pager = Pager::open(&path).unwrap();
+        assert_eq!(pager.meta.root_page_id, 2);
+        assert_eq!(pager.meta.next_row_id, 2);
+        let page = pager.get_page(2).unwrap();
+        assert_eq!(
+            Page::leaf_get_cell_data(page.read_cell(0)).unwrap(),
+            b"new root"
+        );
+        let page = pager.get_page(1).unwrap();
+        assert_eq!(
+            Page::leaf_get_cell_data(page.read_cell(0)).unwrap(),
+            b"old root"
+        );
+
         let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(Wal::path_for(&path));
     }
 }
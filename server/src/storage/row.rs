@@ -1,5 +1,8 @@
 use crate::error::{LunarisError, LunarisResult};
-use lunaris_common::value::Value;
+use crate::storage::index;
+use lunaris_common::protocol::ValueType;
+use lunaris_common::timestamp;
+use lunaris_common::value::{self, Collation, Value};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -8,15 +11,35 @@ pub enum ColumnType {
     Float,
     Boolean,
     Varchar(u16),
+    /// Raw bytes, stored like VARCHAR but never interpreted as UTF-8.
+    Blob(u16),
+    /// Milliseconds since the Unix epoch (UTC), stored like INTEGER.
+    Timestamp,
+    /// Text of any length. The fixed part of the row holds a u32 length and
+    /// the bytes follow the fixed part, so only the space used is stored.
+    Text,
 }
 
 impl ColumnType {
+    /// How values of the column are sent to clients.
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            ColumnType::Integer => ValueType::Integer,
+            ColumnType::Float => ValueType::Float,
+            ColumnType::Boolean => ValueType::Boolean,
+            ColumnType::Varchar(_) | ColumnType::Text => ValueType::Text,
+            ColumnType::Blob(_) => ValueType::Blob,
+            ColumnType::Timestamp => ValueType::Timestamp,
+        }
+    }
+
     pub fn byte_size(&self) -> usize {
         match self {
-            ColumnType::Integer => 8,
+            ColumnType::Integer | ColumnType::Timestamp => 8,
             ColumnType::Float => 8,
             ColumnType::Boolean => 1,
-            ColumnType::Varchar(n) => 2 + *n as usize,
+            ColumnType::Varchar(n) | ColumnType::Blob(n) => 2 + *n as usize,
+            ColumnType::Text => 4,
         }
     }
 }
@@ -28,6 +51,9 @@ impl std::fmt::Display for ColumnType {
             ColumnType::Float => write!(f, "FLOAT"),
             ColumnType::Boolean => write!(f, "BOOLEAN"),
             ColumnType::Varchar(n) => write!(f, "VARCHAR({n})"),
+            ColumnType::Blob(n) => write!(f, "BLOB({n})"),
+            ColumnType::Timestamp => write!(f, "TIMESTAMP"),
+            ColumnType::Text => write!(f, "TEXT"),
         }
     }
 }
@@ -36,6 +62,34 @@ impl std::fmt::Display for ColumnType {
 pub struct ColumnDef {
     pub name: String,
     pub col_type: ColumnType,
+    #[serde(default = "default_nullable")]
+    pub nullable: bool,
+    #[serde(default)]
+    pub unique: bool,
+    /// Value used when an INSERT omits this column.
+    #[serde(default)]
+    pub default: Option<Value>,
+    /// How comparisons, ORDER BY and UNIQUE treat the column's text.
+    #[serde(default)]
+    pub collation: Collation,
+}
+
+impl ColumnDef {
+    pub fn new(name: String, col_type: ColumnType) -> Self {
+        Self {
+            name,
+            col_type,
+            nullable: true,
+            unique: false,
+            default: None,
+            collation: Collation::Binary,
+        }
+    }
+}
+
+// Schemas persisted before nullability was tracked allow NULLs everywhere.
+fn default_nullable() -> bool {
+    true
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -43,6 +97,20 @@ pub struct TableSchema {
     pub table_name: String,
     pub columns: Vec<ColumnDef>,
     pub row_size: usize,
+    /// Index of the INTEGER PRIMARY KEY column whose value becomes the rowid.
+    /// Tables without one get their rowids from `BTreeTable::next_row_id`.
+    #[serde(default)]
+    pub primary_key: Option<usize>,
+    /// Rows in the table as of the last commit, kept up to date by
+    /// `Database::commit`. None for tables created before rows were counted,
+    /// until `Database::open` has counted them.
+    #[serde(default)]
+    pub row_count: Option<u64>,
+    /// Columns of a `PRIMARY KEY (a, b, ...)` constraint, in key order. Rows
+    /// of such a table still get their rowids from `next_row_id`, but no two
+    /// may share a key and scans visit them in key order.
+    #[serde(default)]
+    pub key_columns: Vec<usize>,
 }
 
 impl TableSchema {
@@ -54,6 +122,9 @@ impl TableSchema {
             table_name,
             columns,
             row_size,
+            primary_key: None,
+            row_count: Some(0),
+            key_columns: Vec::new(),
         }
     }
 
@@ -66,6 +137,114 @@ impl TableSchema {
     pub fn bitmap_size(&self) -> usize {
         self.columns.len().div_ceil(8)
     }
+
+    /// Key of `row` under the `PRIMARY KEY (...)` constraint. Keys compare
+    /// bytewise like the key values do, column after column. Key columns are
+    /// NOT NULL, so the encoding carries no NULL marker and its first 8 bytes
+    /// come straight from the first key column.
+    pub fn encode_key(&self, row: &[Value]) -> Vec<u8> {
+        let mut key = Vec::new();
+        for &i in &self.key_columns {
+            match &row[i] {
+                Value::Text(s) => encode_key_bytes(s.as_bytes(), &mut key),
+                Value::Blob(bytes) => encode_key_bytes(bytes, &mut key),
+                other => {
+                    if let Some(fixed) = index::index_key(other) {
+                        key.extend_from_slice(&fixed.to_be_bytes());
+                    }
+                }
+            }
+        }
+        key
+    }
+
+    /// `CREATE TABLE` statement declaring this table, without its rows.
+    pub fn to_create_sql(&self) -> String {
+        let columns: Vec<String> = self
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, col)| {
+                let mut sql = format!("{} {}", col.name, col.col_type);
+                if self.primary_key == Some(i) {
+                    sql.push_str(" PRIMARY KEY");
+                } else if !col.nullable {
+                    sql.push_str(" NOT NULL");
+                }
+                if col.unique {
+                    sql.push_str(" UNIQUE");
+                }
+                if col.collation != Collation::Binary {
+                    sql.push_str(&format!(" COLLATE {}", col.collation));
+                }
+                if let Some(default) = &col.default {
+                    sql.push_str(" DEFAULT ");
+                    sql.push_str(&sql_literal(default, &col.col_type));
+                }
+                sql
+            })
+            .collect();
+        let mut sql = format!("CREATE TABLE {} ({}", self.table_name, columns.join(", "));
+        if !self.key_columns.is_empty() {
+            let names: Vec<&str> = self
+                .key_columns
+                .iter()
+                .map(|&i| self.columns[i].name.as_str())
+                .collect();
+            sql.push_str(&format!(", PRIMARY KEY ({})", names.join(", ")));
+        }
+        sql.push(')');
+        sql
+    }
+
+    /// `INSERT` statement adding `row` to this table.
+    pub fn to_insert_sql(&self, row: &[Value]) -> String {
+        let values: Vec<String> = row
+            .iter()
+            .zip(&self.columns)
+            .map(|(value, col)| sql_literal(value, &col.col_type))
+            .collect();
+        format!(
+            "INSERT INTO {} VALUES ({})",
+            self.table_name,
+            values.join(", ")
+        )
+    }
+}
+
+// Zero bytes are escaped as 00 ff and the end is marked with 00 00, so a
+// string sorts before every longer string it is a prefix of, and the next
+// key column can follow it.
+fn encode_key_bytes(bytes: &[u8], key: &mut Vec<u8>) {
+    for &b in bytes {
+        key.push(b);
+        if b == 0 {
+            key.push(0xff);
+        }
+    }
+    key.extend_from_slice(&[0, 0]);
+}
+
+/// `value`, stored in a column of type `col_type`, as SQL that reads back
+/// as the same value. NaN has no literal and comes out as NULL.
+fn sql_literal(value: &Value, col_type: &ColumnType) -> String {
+    match (value, col_type) {
+        (Value::Integer(millis), ColumnType::Timestamp) => {
+            format!("'{}'", timestamp::format_timestamp(*millis))
+        }
+        (Value::Null, _) => "NULL".to_string(),
+        (Value::Integer(v), _) => v.to_string(),
+        (Value::Float(v), _) if v.is_nan() => "NULL".to_string(),
+        // parses back as an overflowing float, i.e. infinity
+        (Value::Float(v), _) if v.is_infinite() => match v.is_sign_positive() {
+            true => "1e999".to_string(),
+            false => "-1e999".to_string(),
+        },
+        (Value::Float(v), _) => format!("{v:?}"),
+        (Value::Boolean(v), _) => v.to_string().to_uppercase(),
+        (Value::Text(s), _) => format!("'{}'", s.replace('\'', "''")),
+        (Value::Blob(bytes), _) => format!("X'{}'", value::to_hex(bytes)),
+    }
 }
 
 pub fn serialize_row(schema: &TableSchema, values: &[Value]) -> LunarisResult<Vec<u8>> {
@@ -77,9 +256,15 @@ pub fn serialize_row(schema: &TableSchema, values: &[Value]) -> LunarisResult<Ve
     }
 
     let mut buf = vec![0u8; schema.row_size];
+    // TEXT values, appended after the fixed part
+    let mut tail = Vec::new();
     let mut offset = schema.bitmap_size();
     for (i, (col, val)) in schema.columns.iter().zip(values.iter()).enumerate() {
         if *val == Value::Null {
+            if !col.nullable {
+                return Err(LunarisError::NullConstraint(col.name.clone()));
+            }
+
             // Set bit i in the null bitmap
             buf[i / 8] |= 1 << (i % 8);
             offset += col.col_type.byte_size();
@@ -87,7 +272,7 @@ pub fn serialize_row(schema: &TableSchema, values: &[Value]) -> LunarisResult<Ve
         }
 
         match (&col.col_type, val) {
-            (ColumnType::Integer, Value::Integer(v)) => {
+            (ColumnType::Integer | ColumnType::Timestamp, Value::Integer(v)) => {
                 buf[offset..offset + 8].copy_from_slice(&v.to_le_bytes());
                 offset += 8;
             }
@@ -100,13 +285,21 @@ pub fn serialize_row(schema: &TableSchema, values: &[Value]) -> LunarisResult<Ve
                 offset += 1;
             }
             (ColumnType::Varchar(max_len), Value::Text(s)) => {
-                let bytes = s.as_bytes();
-                let len = bytes.len().min(*max_len as usize);
-                buf[offset..offset + 2].copy_from_slice(&(len as u16).to_le_bytes());
-                buf[offset + 2..offset + 2 + len].copy_from_slice(&bytes[..len]);
-                // remaining bytes stay zero (padding)
+                write_prefixed(&mut buf[offset..], s.as_bytes(), col, *max_len)?;
+                offset += 2 + *max_len as usize;
+            }
+            (ColumnType::Blob(max_len), Value::Blob(bytes)) => {
+                write_prefixed(&mut buf[offset..], bytes, col, *max_len)?;
                 offset += 2 + *max_len as usize;
             }
+            (ColumnType::Text, Value::Text(s)) => {
+                let len = u32::try_from(s.len()).map_err(|_| {
+                    LunarisError::Storage(format!("value of column '{}' is too long", col.name))
+                })?;
+                buf[offset..offset + 4].copy_from_slice(&len.to_le_bytes());
+                tail.extend_from_slice(s.as_bytes());
+                offset += 4;
+            }
             _ => {
                 return Err(LunarisError::TypeMismatch {
                     expected: col.col_type.to_string(),
@@ -116,12 +309,67 @@ pub fn serialize_row(schema: &TableSchema, values: &[Value]) -> LunarisResult<Ve
         }
     }
 
+    buf.extend_from_slice(&tail);
     Ok(buf)
 }
 
+/// Write `bytes` behind a u16 length prefix. Values longer than `max_len`
+/// are refused rather than cut off, which could also split a UTF-8
+/// character. The remaining bytes of the column stay zero (padding).
+fn write_prefixed(
+    buf: &mut [u8],
+    bytes: &[u8],
+    col: &ColumnDef,
+    max_len: u16,
+) -> LunarisResult<()> {
+    let len = bytes.len();
+    if len > max_len as usize {
+        return Err(LunarisError::ValueTooLong {
+            column: col.name.clone(),
+            max: max_len as usize,
+            got: len,
+        });
+    }
+    buf[..2].copy_from_slice(&(len as u16).to_le_bytes());
+    buf[2..2 + len].copy_from_slice(bytes);
+    Ok(())
+}
+
+/// Read a length-prefixed column value, checking the length against the
+/// column's declared maximum.
+fn read_prefixed<'a>(data: &'a [u8], col: &ColumnDef, max_len: u16) -> LunarisResult<&'a [u8]> {
+    let len = u16::from_le_bytes([data[0], data[1]]) as usize;
+    if len > max_len as usize {
+        return Err(LunarisError::Storage(format!(
+            "column '{}' holds {len} bytes, more than {}",
+            col.name, col.col_type
+        )));
+    }
+    Ok(&data[2..2 + len])
+}
+
+/// Decode a stored string. Values are never cut off when written, so
+/// anything that isn't valid UTF-8 is damaged data and reported as such.
+fn read_text(bytes: &[u8], col: &ColumnDef) -> LunarisResult<String> {
+    String::from_utf8(bytes.to_vec())
+        .map_err(|_| LunarisError::Storage(format!("column '{}' holds invalid UTF-8", col.name)))
+}
+
 pub fn deserialize_row(schema: &TableSchema, data: &[u8]) -> LunarisResult<Vec<Value>> {
+    // every row is serialized at least at the full fixed width, so a single
+    // length check covers all the fixed-offset reads below
+    if data.len() < schema.row_size {
+        return Err(LunarisError::Storage(format!(
+            "row of table '{}' truncated: expected {} bytes, got {}",
+            schema.table_name,
+            schema.row_size,
+            data.len()
+        )));
+    }
+
     let mut values = Vec::with_capacity(schema.columns.len());
     let mut offset = schema.bitmap_size();
+    let mut tail = &data[schema.row_size..];
     for (i, col) in schema.columns.iter().enumerate() {
         let is_null = (data[i / 8] >> (i % 8)) & 1 == 1;
         if is_null {
@@ -131,7 +379,7 @@ pub fn deserialize_row(schema: &TableSchema, data: &[u8]) -> LunarisResult<Vec<V
         }
 
         let val = match &col.col_type {
-            ColumnType::Integer => {
+            ColumnType::Integer | ColumnType::Timestamp => {
                 let v = i64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
                 offset += 8;
                 Value::Integer(v)
@@ -147,10 +395,26 @@ pub fn deserialize_row(schema: &TableSchema, data: &[u8]) -> LunarisResult<Vec<V
                 Value::Boolean(v)
             }
             ColumnType::Varchar(max_len) => {
-                let len = u16::from_le_bytes([data[offset], data[offset + 1]]) as usize;
-                let s = String::from_utf8_lossy(&data[offset + 2..offset + 2 + len]).to_string();
+                let bytes = read_prefixed(&data[offset..], col, *max_len)?;
+                offset += 2 + *max_len as usize;
+                Value::Text(read_text(bytes, col)?)
+            }
+            ColumnType::Blob(max_len) => {
+                let bytes = read_prefixed(&data[offset..], col, *max_len)?;
                 offset += 2 + *max_len as usize;
-                Value::Text(s)
+                Value::Blob(bytes.to_vec())
+            }
+            ColumnType::Text => {
+                let len = u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap()) as usize;
+                offset += 4;
+                let (bytes, rest) = tail.split_at_checked(len).ok_or_else(|| {
+                    LunarisError::Storage(format!(
+                        "column '{}' holds {len} bytes, past the end of the row",
+                        col.name
+                    ))
+                })?;
+                tail = rest;
+                Value::Text(read_text(bytes, col)?)
             }
         };
         values.push(val);
@@ -167,22 +431,41 @@ mod tests {
         TableSchema::new(
             "test".into(),
             vec![
-                ColumnDef {
-                    name: "id".into(),
-                    col_type: ColumnType::Integer,
-                },
-                ColumnDef {
-                    name: "name".into(),
-                    col_type: ColumnType::Varchar(20),
-                },
-                ColumnDef {
-                    name: "active".into(),
-                    col_type: ColumnType::Boolean,
-                },
+                ColumnDef::new("id".into(), ColumnType::Integer),
+                ColumnDef::new("name".into(), ColumnType::Varchar(20)),
+                ColumnDef::new("active".into(), ColumnType::Boolean),
             ],
         )
     }
 
+    #[test]
+    fn test_key_order() {
+        let mut schema = test_schema();
+        schema.columns[1].col_type = ColumnType::Text;
+        schema.key_columns = vec![1, 0];
+        let row = |name: &str, id: i64| {
+            vec![
+                Value::Integer(id),
+                Value::Text(name.into()),
+                Value::Boolean(true),
+            ]
+        };
+        // sorted by name, then id
+        let rows = [
+            row("", 5),
+            row("a", -3),
+            row("a", 0),
+            row("a", 7),
+            row("a\0", -9),
+            row("a\0b", 1),
+            row("ab", i64::MIN),
+            row("b", 2),
+        ];
+        let keys: Vec<Vec<u8>> = rows.iter().map(|r| schema.encode_key(r)).collect();
+        assert!(keys.is_sorted(), "{keys:?}");
+        assert!(keys.windows(2).all(|w| w[0] != w[1]));
+    }
+
     #[test]
     fn test_roundtrip() {
         let schema = test_schema();
@@ -206,6 +489,161 @@ mod tests {
         assert_eq!(restored, values);
     }
 
+    #[test]
+    fn test_not_null_rejects_null() {
+        let mut schema = test_schema();
+        schema.columns[0].nullable = false;
+
+        let result = serialize_row(&schema, &[Value::Null, Value::Null, Value::Boolean(true)]);
+        assert!(matches!(result, Err(LunarisError::NullConstraint(col)) if col == "id"));
+
+        let values = vec![Value::Integer(7), Value::Null, Value::Boolean(true)];
+        let data = serialize_row(&schema, &values).unwrap();
+        assert_eq!(deserialize_row(&schema, &data).unwrap(), values);
+    }
+
+    #[test]
+    fn test_malformed_row_data() {
+        let schema = test_schema();
+        let values = vec![
+            Value::Integer(1),
+            Value::Text("Bob".into()),
+            Value::Boolean(true),
+        ];
+        let mut data = serialize_row(&schema, &values).unwrap();
+
+        assert!(matches!(
+            deserialize_row(&schema, &data[..data.len() - 1]),
+            Err(LunarisError::Storage(_))
+        ));
+        assert!(matches!(
+            deserialize_row(&schema, &[]),
+            Err(LunarisError::Storage(_))
+        ));
+
+        // string length prefix pointing past the column
+        let name_offset = schema.bitmap_size() + 8;
+        data[name_offset..name_offset + 2].copy_from_slice(&500u16.to_le_bytes());
+        assert!(matches!(
+            deserialize_row(&schema, &data),
+            Err(LunarisError::Storage(_))
+        ));
+    }
+
+    #[test]
+    fn test_blob_roundtrip() {
+        let schema = TableSchema::new(
+            "test".into(),
+            vec![
+                ColumnDef::new("data".into(), ColumnType::Blob(8)),
+                ColumnDef::new("id".into(), ColumnType::Integer),
+            ],
+        );
+        let values = vec![Value::Blob(vec![0, 0xff, 0, 0, 0x7f]), Value::Integer(3)];
+        let data = serialize_row(&schema, &values).unwrap();
+        assert_eq!(data.len(), schema.row_size);
+        assert_eq!(deserialize_row(&schema, &data).unwrap(), values);
+
+        let empty = vec![Value::Blob(vec![]), Value::Null];
+        let data = serialize_row(&schema, &empty).unwrap();
+        assert_eq!(deserialize_row(&schema, &data).unwrap(), empty);
+
+        let text = serialize_row(&schema, &[Value::Text("x".into()), Value::Integer(1)]);
+        assert!(matches!(text, Err(LunarisError::TypeMismatch { .. })));
+    }
+
+    #[test]
+    fn test_value_too_long() {
+        let schema = TableSchema::new(
+            "test".into(),
+            vec![
+                ColumnDef::new("code".into(), ColumnType::Varchar(3)),
+                ColumnDef::new("data".into(), ColumnType::Blob(2)),
+            ],
+        );
+        // the limit counts bytes, and 'é' takes two
+        for text in ["abc", "aé", ""] {
+            let values = vec![Value::Text(text.into()), Value::Blob(vec![1, 2])];
+            let data = serialize_row(&schema, &values).unwrap();
+            assert_eq!(deserialize_row(&schema, &data).unwrap(), values);
+        }
+
+        let err = serialize_row(&schema, &[Value::Text("abcdef".into()), Value::Null]);
+        assert!(matches!(
+            err,
+            Err(LunarisError::ValueTooLong { column, max: 3, got: 6 }) if column == "code"
+        ));
+        let err = serialize_row(&schema, &[Value::Text("aaé".into()), Value::Null]);
+        assert!(matches!(
+            err,
+            Err(LunarisError::ValueTooLong { got: 4, .. })
+        ));
+        let err = serialize_row(&schema, &[Value::Null, Value::Blob(vec![0; 3])]);
+        assert!(matches!(err, Err(LunarisError::ValueTooLong { column, .. }) if column == "data"));
+    }
+
+    #[test]
+    fn test_multibyte_text_at_limit() {
+        let schema = TableSchema::new(
+            "test".into(),
+            vec![ColumnDef::new("icons".into(), ColumnType::Varchar(12))],
+        );
+        // three 4-byte characters fill the column exactly
+        let values = vec![Value::Text("🌑🌓🌕".into())];
+        let data = serialize_row(&schema, &values).unwrap();
+        assert_eq!(deserialize_row(&schema, &data).unwrap(), values);
+        assert!(matches!(
+            serialize_row(&schema, &[Value::Text("🌑🌓🌕🌗".into())]),
+            Err(LunarisError::ValueTooLong { .. })
+        ));
+
+        // half a character on disk is an error, not a replacement character
+        let mut data = data;
+        data[schema.bitmap_size()..][..2].copy_from_slice(&10u16.to_le_bytes());
+        assert!(matches!(
+            deserialize_row(&schema, &data),
+            Err(LunarisError::Storage(_))
+        ));
+    }
+
+    #[test]
+    fn test_text_roundtrip() {
+        let schema = TableSchema::new(
+            "test".into(),
+            vec![
+                ColumnDef::new("a".into(), ColumnType::Text),
+                ColumnDef::new("id".into(), ColumnType::Integer),
+                ColumnDef::new("b".into(), ColumnType::Text),
+            ],
+        );
+        let long = "x".repeat(1000);
+        let values = vec![
+            Value::Text(long.clone()),
+            Value::Integer(1),
+            Value::Text("ü".into()),
+        ];
+        let data = serialize_row(&schema, &values).unwrap();
+        assert_eq!(data.len(), schema.row_size + long.len() + 2);
+        assert_eq!(deserialize_row(&schema, &data).unwrap(), values);
+
+        let values = vec![Value::Null, Value::Integer(2), Value::Text(String::new())];
+        let data = serialize_row(&schema, &values).unwrap();
+        assert_eq!(data.len(), schema.row_size);
+        assert_eq!(deserialize_row(&schema, &data).unwrap(), values);
+
+        // a length pointing past the end of the row
+        let mut data = serialize_row(
+            &schema,
+            &[Value::Text("abc".into()), Value::Null, Value::Null],
+        )
+        .unwrap();
+        data.pop();
+        assert!(matches!(
+            deserialize_row(&schema, &data),
+            Err(LunarisError::Storage(_))
+        ));
+    }
+
     #[test]
     fn test_wrong_count() {
         let schema = test_schema();
@@ -0,0 +1,192 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::constants::{META_PAGE_SIZE, PAGE_SIZE};
+use crate::error::{LunarisError, LunarisResult};
+use crate::storage;
+use crate::storage::page::Page;
+use crate::storage::pager::FileMetadata;
+
+// "WALB"
+const BATCH_MAGIC: u32 = 0x57414C42;
+const BATCH_HEADER_SIZE: usize = 8;
+const FRAME_SIZE: usize = 4 + PAGE_SIZE;
+
+/// Write-ahead log of committed page images, stored next to the table file.
+///
+/// Every commit appends one batch in the following format:
+/// [magic | page count | file header | (page id | page bytes)... | crc32]
+/// and the log is fsynced before any of the pages reach the main file. A
+/// batch that is cut short or fails its checksum was never acknowledged, so
+/// reading stops there.
+pub struct Wal {
+    file: File,
+    len: u64,
+}
+
+/// A committed batch read back from the log.
+pub struct WalBatch {
+    /// File header as of this commit; `wal_applied` points past the batch.
+    pub meta: FileMetadata,
+    pub pages: Vec<(u32, Box<[u8; PAGE_SIZE]>)>,
+}
+
+impl Wal {
+    pub fn open(path: &Path) -> LunarisResult<Self> {
+        let file = storage::private_file_options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let len = file.metadata()?.len();
+        Ok(Self { file, len })
+    }
+
+    /// Open an existing log without write access, or `None` if there is no
+    /// log file.
+    pub fn open_read_only(path: &Path) -> LunarisResult<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let file = OpenOptions::new().read(true).open(path)?;
+        let len = file.metadata()?.len();
+        Ok(Some(Self { file, len }))
+    }
+
+    /// Log file belonging to the table file at `db_path`.
+    pub fn path_for(db_path: &Path) -> PathBuf {
+        let mut path = db_path.as_os_str().to_owned();
+        path.push("-wal");
+        PathBuf::from(path)
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Append and fsync a batch with the given pages. `meta.wal_applied` is
+    /// set to the end of the batch, which is the log position the main file
+    /// reaches once the batch has been applied.
+    pub fn append(&mut self, meta: &mut FileMetadata, pages: &[&Page]) -> LunarisResult<()> {
+        let size = BATCH_HEADER_SIZE + META_PAGE_SIZE + pages.len() * FRAME_SIZE + 4;
+        meta.wal_applied = self.len + size as u64;
+
+        let mut buf = Vec::with_capacity(size);
+        buf.extend_from_slice(&BATCH_MAGIC.to_le_bytes());
+        buf.extend_from_slice(&(pages.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&meta.to_bytes());
+        for page in pages {
+            buf.extend_from_slice(&page.id.to_le_bytes());
+            buf.extend_from_slice(&page.to_bytes());
+        }
+        let checksum = crc32fast::hash(&buf);
+        buf.extend_from_slice(&checksum.to_le_bytes());
+
+        self.file.seek(SeekFrom::Start(self.len))?;
+        self.file.write_all(&buf)?;
+        self.file.sync_all()?;
+        self.len += size as u64;
+        Ok(())
+    }
+
+    /// Read every complete batch that starts at or after `position`.
+    pub fn read_batches(&mut self, position: u64) -> LunarisResult<Vec<WalBatch>> {
+        let mut buf = Vec::new();
+        self.file.seek(SeekFrom::Start(position))?;
+        self.file.read_to_end(&mut buf)?;
+
+        let mut batches = Vec::new();
+        let mut offset = 0;
+        while let Some((batch, size)) = parse_batch(&buf[offset..])? {
+            batches.push(batch);
+            offset += size;
+        }
+        Ok(batches)
+    }
+
+    /// Empty the log once everything in it has been applied.
+    pub fn reset(&mut self) -> LunarisResult<()> {
+        self.file.set_len(0)?;
+        self.file.sync_all()?;
+        self.len = 0;
+        Ok(())
+    }
+}
+
+/// Parse the batch at the start of `buf`, returning it with its size, or
+/// `None` if the log ends in a torn or incomplete batch.
+fn parse_batch(buf: &[u8]) -> LunarisResult<Option<(WalBatch, usize)>> {
+    if buf.len() < BATCH_HEADER_SIZE {
+        return Ok(None);
+    }
+    let magic = u32::from_le_bytes(buf[0..4].try_into().unwrap());
+    let count = u32::from_le_bytes(buf[4..8].try_into().unwrap()) as usize;
+    if magic != BATCH_MAGIC {
+        return Ok(None);
+    }
+
+    let body_end = BATCH_HEADER_SIZE + META_PAGE_SIZE + count * FRAME_SIZE;
+    let Some(stored) = buf.get(body_end..body_end + 4) else {
+        return Ok(None);
+    };
+    if u32::from_le_bytes(stored.try_into().unwrap()) != crc32fast::hash(&buf[..body_end]) {
+        return Ok(None);
+    }
+
+    let meta = FileMetadata::from_bytes(&buf[BATCH_HEADER_SIZE..])
+        .map_err(|e| LunarisError::Wal(format!("bad file header in log batch: {e}")))?;
+    let mut pages = Vec::with_capacity(count);
+    let mut offset = BATCH_HEADER_SIZE + META_PAGE_SIZE;
+    for _ in 0..count {
+        let id = u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap());
+        let mut bytes = Box::new([0u8; PAGE_SIZE]);
+        bytes.copy_from_slice(&buf[offset + 4..offset + FRAME_SIZE]);
+        pages.push((id, bytes));
+        offset += FRAME_SIZE;
+    }
+    Ok(Some((WalBatch { meta, pages }, body_end + 4)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join("lunaris_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join(name)
+    }
+
+    #[test]
+    fn test_torn_batch_is_ignored() {
+        let path = temp_path("wal_torn.db-wal");
+        let _ = std::fs::remove_file(&path);
+
+        let mut meta = FileMetadata {
+            root_page_id: 1,
+            next_row_id: 5,
+            free_list_head: 0,
+            wal_applied: 0,
+        };
+        let page = Page::new_leaf(1);
+        let mut wal = Wal::open(&path).unwrap();
+        wal.append(&mut meta, &[&page]).unwrap();
+        let first_end = wal.len();
+        assert_eq!(meta.wal_applied, first_end);
+        wal.append(&mut meta, &[&page, &page]).unwrap();
+
+        // cut the second batch short, as if the process died mid-write
+        wal.file.set_len(wal.len() - 10).unwrap();
+        let batches = wal.read_batches(0).unwrap();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].meta.next_row_id, 5);
+        assert_eq!(batches[0].meta.wal_applied, first_end);
+        assert_eq!(batches[0].pages[0].0, 1);
+
+        assert!(wal.read_batches(first_end).unwrap().is_empty());
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -1,4 +1,14 @@
+use std::collections::HashSet;
+
+use crate::error::{LunarisError, LunarisResult};
+use crate::storage::index::IndexSchema;
 use crate::storage::row::TableSchema;
+use lunaris_common::protocol::ValueType;
+use lunaris_common::value::{Collation, Value};
+
+/// Registers below this one have fixed uses, such as comparison operands and
+/// the record built by INSERT. `Program::alloc_registers` hands out the rest.
+const FIRST_ALLOCATED_REGISTER: usize = 32;
 
 #[derive(Debug, Clone)]
 pub enum Instruction {
@@ -9,6 +19,12 @@ pub enum Instruction {
         target: usize,
     },
     Halt,
+    /// Skip the first `offset` result rows, and halt once `limit` rows are
+    /// written after them.
+    ResultLimit {
+        limit: Option<u64>,
+        offset: u64,
+    },
 
     OpenReadCursor {
         cursor: i32,
@@ -18,14 +34,58 @@ pub enum Instruction {
         cursor: i32,
         table: String,
     },
+    /// Open a read cursor on `table` that visits the rows `index` finds for
+    /// an `IndexSeek` key instead of scanning the table.
+    OpenIndexCursor {
+        cursor: i32,
+        table: String,
+        index: String,
+    },
+    /// Position an index cursor at the first row whose indexed column equals
+    /// `key_reg`, jumping to `empty_target` if there is none. `CursorAdvance`
+    /// moves on to the next such row.
+    IndexSeek {
+        cursor: i32,
+        key_reg: usize,
+        empty_target: usize,
+    },
     RewindCursor {
         cursor: i32,
         empty_target: usize,
     },
+    /// Position at the first row with a key of at least `key_reg`, jumping to
+    /// `empty_target` if there is none.
+    SeekGE {
+        cursor: i32,
+        key_reg: usize,
+        empty_target: usize,
+    },
+    /// Position at the row with key `key_reg`, jumping to `empty_target` if
+    /// there is none.
+    SeekEQ {
+        cursor: i32,
+        key_reg: usize,
+        empty_target: usize,
+    },
+    /// Position at the last row, jumping to `empty_target` if there is none.
+    RewindCursorLast {
+        cursor: i32,
+        empty_target: usize,
+    },
+    /// Step back a row and jump to `loop_target` unless the scan is done.
+    CursorPrev {
+        cursor: i32,
+        loop_target: usize,
+    },
     CursorAdvance {
         cursor: i32,
         loop_target: usize,
     },
+    /// Leave the cursor on a row of NULLs until it is rewound. Stepping from
+    /// it ends the scan.
+    NullRow {
+        cursor: i32,
+    },
     CloseCursor {
         cursor: i32,
     },
@@ -46,6 +106,10 @@ pub enum Instruction {
         value: bool,
         reg: usize,
     },
+    Blob {
+        value: Vec<u8>,
+        reg: usize,
+    },
     Null {
         reg: usize,
     },
@@ -55,6 +119,66 @@ pub enum Instruction {
         col_index: usize,
         reg: usize,
     },
+    /// Write a row per statistic of `Database::status`, made of its values
+    /// at `columns`, to the results or, if `sorter`, into the sorter.
+    StatusRows {
+        columns: Vec<usize>,
+        sorter: bool,
+    },
+    /// Replace the epoch milliseconds in `reg` with an ISO-8601 string.
+    FormatTimestamp {
+        reg: usize,
+    },
+    /// Lower the ASCII letters of the text in `reg`, so that comparing it
+    /// ignores their case. Other values are left alone.
+    FoldCase {
+        reg: usize,
+    },
+    /// Scalar string functions, applied to `reg` in place. NULL stays NULL.
+    Upper {
+        reg: usize,
+    },
+    Lower {
+        reg: usize,
+    },
+    /// Characters of a string, or bytes of a blob.
+    Length {
+        reg: usize,
+    },
+    /// Arithmetic on two registers. Two integers give an integer, any float
+    /// makes the result a float, and NULL on either side gives NULL.
+    Add {
+        left: usize,
+        right: usize,
+        dest: usize,
+    },
+    Subtract {
+        left: usize,
+        right: usize,
+        dest: usize,
+    },
+    Multiply {
+        left: usize,
+        right: usize,
+        dest: usize,
+    },
+    Divide {
+        left: usize,
+        right: usize,
+        dest: usize,
+    },
+    Remainder {
+        left: usize,
+        right: usize,
+        dest: usize,
+    },
+    /// `left || right`, with numbers and booleans turned into text. NULL if
+    /// either side is NULL.
+    Concat {
+        left: usize,
+        right: usize,
+        dest: usize,
+    },
     ReadRowId {
         cursor: i32,
         reg: usize,
@@ -63,42 +187,104 @@ pub enum Instruction {
         start: usize,
         count: usize,
     },
+    /// Write `values` as a result row, for a SELECT of nothing but literals.
+    ConstResultRow {
+        values: Vec<Value>,
+    },
+    /// Hold back the row in `start..start + count` until `SortResults`.
+    SorterInsert {
+        start: usize,
+        count: usize,
+    },
+    /// Sort the held rows by `keys` and write the first `columns` values of
+    /// each as a result row. With `distinct`, a row equal to the one before
+    /// it is dropped.
+    SortResults {
+        keys: Vec<SortKey>,
+        columns: usize,
+        distinct: bool,
+    },
+    /// Fold the values in `args..args + aggregates.len()`, one per aggregate,
+    /// into the group of rows keyed by the values in `key..key + key_count`.
+    AggStep {
+        key: usize,
+        key_count: usize,
+        args: usize,
+        aggregates: Vec<Aggregate>,
+    },
+    /// Finish every group into a row of its key values followed by its
+    /// aggregates, in key order. Without a key there is a single group, which
+    /// is returned even if no row reached it.
+    AggFinish {
+        key_count: usize,
+        aggregates: Vec<Aggregate>,
+    },
+    /// Load the next finished group into `dest..`, or jump to `done_target`
+    /// once there are no more.
+    AggNext {
+        dest: usize,
+        done_target: usize,
+    },
+    Copy {
+        src: usize,
+        dest: usize,
+    },
 
+    /// Compare two registers and jump if the comparison holds. A comparison
+    /// involving NULL is UNKNOWN, which jumps only when `null_jump` is set.
     Jeq {
         left: usize,
         right: usize,
         target: usize,
+        null_jump: bool,
     },
     Jne {
         left: usize,
         right: usize,
         target: usize,
+        null_jump: bool,
     },
     Jlt {
         left: usize,
         right: usize,
         target: usize,
+        null_jump: bool,
     },
     Jle {
         left: usize,
         right: usize,
         target: usize,
+        null_jump: bool,
     },
     Jgt {
         left: usize,
         right: usize,
         target: usize,
+        null_jump: bool,
     },
     Jge {
         left: usize,
         right: usize,
         target: usize,
+        null_jump: bool,
+    },
+    JumpIfNull {
+        reg: usize,
+        target: usize,
+    },
+    JumpIfNotNull {
+        reg: usize,
+        target: usize,
     },
 
     CreateRecord {
         start: usize,
         count: usize,
     },
+    NewRowId {
+        cursor: i32,
+        reg: usize,
+    },
     InsertRecord {
         cursor: i32,
         key_reg: usize,
@@ -106,16 +292,115 @@ pub enum Instruction {
     DeleteRow {
         cursor: i32,
     },
+    /// Replace the row under `cursor` with the record, keeping its rowid.
+    UpdateRow {
+        cursor: i32,
+    },
+    /// Remove every row of `table` at once, counting them as affected.
+    TruncateTable {
+        table: String,
+    },
+    /// Load the number of rows in `table` into `reg`, as of this session.
+    RowCount {
+        table: String,
+        reg: usize,
+    },
 
+    /// Register a new table. With `if_not_exists`, an existing table of the
+    /// same name is left alone instead of failing the statement.
     CreateTable {
         schema: TableSchema,
+        if_not_exists: bool,
+    },
+    CreateIndex {
+        index: IndexSchema,
     },
+    /// Drop `table` and its indexes. With `if_exists`, a missing table is
+    /// not an error.
+    DropTable {
+        table: String,
+        if_exists: bool,
+    },
+    /// Give `schema.table_name` the columns of `schema`, rewriting its rows.
+    /// `sources` holds the current position of each column, or None for a
+    /// new column, which starts out at its default.
+    AlterTable {
+        schema: TableSchema,
+        sources: Vec<Option<usize>>,
+    },
+
+    Begin,
+    Commit,
+    Rollback,
+}
+
+/// One ORDER BY term, naming a value of the rows held for sorting.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SortKey {
+    pub column: usize,
+    pub descending: bool,
+    pub nulls_first: bool,
+    pub collation: Collation,
+}
+
+/// A function folding the values of a group of rows into one. NULLs are
+/// left out, so only `COUNT(*)` counts them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregate {
+    /// `COUNT(*)`, the number of rows.
+    CountRows,
+    Count,
+    /// The sum of integers is an integer and fails on overflow; a float
+    /// anywhere makes it a float. NULL over no values.
+    Sum,
+    /// The mean as a float, NULL over no values.
+    Avg,
+    Min,
+    Max,
+}
+
+impl Instruction {
+    /// Cursor the instruction works on, other than opening or closing it.
+    fn cursor(&self) -> Option<i32> {
+        match self {
+            Instruction::RewindCursor { cursor, .. }
+            | Instruction::SeekGE { cursor, .. }
+            | Instruction::SeekEQ { cursor, .. }
+            | Instruction::IndexSeek { cursor, .. }
+            | Instruction::RewindCursorLast { cursor, .. }
+            | Instruction::CursorPrev { cursor, .. }
+            | Instruction::CursorAdvance { cursor, .. }
+            | Instruction::NullRow { cursor }
+            | Instruction::ReadColumn { cursor, .. }
+            | Instruction::ReadRowId { cursor, .. }
+            | Instruction::NewRowId { cursor, .. }
+            | Instruction::InsertRecord { cursor, .. }
+            | Instruction::DeleteRow { cursor }
+            | Instruction::UpdateRow { cursor } => Some(*cursor),
+            _ => None,
+        }
+    }
+}
+
+/// What a program's result message reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatementKind {
+    /// The number of rows returned.
+    Query,
+    /// The number of rows written, RETURNING or not.
+    Dml,
+    /// Nothing counted; instructions set their own message.
+    Other,
 }
 
 #[derive(Debug, Clone)]
 pub struct Program {
     pub instructions: Vec<Instruction>,
     pub result_columns: Vec<String>,
+    /// Type of every result column.
+    pub result_types: Vec<ValueType>,
+    pub kind: StatementKind,
+    next_register: usize,
 }
 
 impl Program {
@@ -123,6 +408,9 @@ impl Program {
         Self {
             instructions: Vec::new(),
             result_columns: Vec::new(),
+            result_types: Vec::new(),
+            kind: StatementKind::Other,
+            next_register: FIRST_ALLOCATED_REGISTER,
         }
     }
 
@@ -136,19 +424,150 @@ impl Program {
         match &mut self.instructions[addr] {
             Instruction::Init { target } => *target = new_target,
             Instruction::Goto { target } => *target = new_target,
-            Instruction::RewindCursor { empty_target, .. } => *empty_target = new_target,
-            Instruction::CursorAdvance { loop_target, .. } => *loop_target = new_target,
+            Instruction::RewindCursor { empty_target, .. }
+            | Instruction::RewindCursorLast { empty_target, .. }
+            | Instruction::SeekGE { empty_target, .. }
+            | Instruction::SeekEQ { empty_target, .. }
+            | Instruction::IndexSeek { empty_target, .. } => *empty_target = new_target,
+            Instruction::CursorAdvance { loop_target, .. }
+            | Instruction::CursorPrev { loop_target, .. } => *loop_target = new_target,
             Instruction::Jeq { target, .. } => *target = new_target,
             Instruction::Jne { target, .. } => *target = new_target,
             Instruction::Jlt { target, .. } => *target = new_target,
             Instruction::Jle { target, .. } => *target = new_target,
             Instruction::Jgt { target, .. } => *target = new_target,
             Instruction::Jge { target, .. } => *target = new_target,
+            Instruction::JumpIfNull { target, .. } => *target = new_target,
+            Instruction::JumpIfNotNull { target, .. } => *target = new_target,
+            Instruction::AggNext { done_target, .. } => *done_target = new_target,
             _ => panic!("patch_target called on non-jump instruction"),
         }
     }
 
+    /// Reserve `count` consecutive registers, returning the first one.
+    pub fn alloc_registers(&mut self, count: usize) -> usize {
+        let first = self.next_register;
+        self.next_register += count;
+        first
+    }
+
     pub fn current_addr(&self) -> usize {
         self.instructions.len()
     }
+
+    /// Check, in instruction order, that every cursor is opened before it is
+    /// used and closed exactly once. Compiled programs lay out their loops
+    /// between the open and the close, so a failure means a compiler bug.
+    pub fn validate(&self) -> LunarisResult<()> {
+        let invalid = |msg: String| Err(LunarisError::Vm(format!("invalid program: {msg}")));
+        let mut open = HashSet::new();
+        for (addr, inst) in self.instructions.iter().enumerate() {
+            match inst {
+                Instruction::OpenReadCursor { cursor, .. }
+                | Instruction::OpenReadWriteCursor { cursor, .. }
+                | Instruction::OpenIndexCursor { cursor, .. } => {
+                    if !open.insert(*cursor) {
+                        return invalid(format!("cursor {cursor} opened again at {addr}"));
+                    }
+                }
+                Instruction::CloseCursor { cursor } => {
+                    if !open.remove(cursor) {
+                        return invalid(format!("cursor {cursor} closed at {addr} but not open"));
+                    }
+                }
+                inst => {
+                    if let Some(cursor) = inst.cursor()
+                        && !open.contains(&cursor)
+                    {
+                        return invalid(format!("cursor {cursor} used at {addr} but not open"));
+                    }
+                }
+            }
+        }
+        match open.iter().min() {
+            Some(cursor) => invalid(format!("cursor {cursor} is never closed")),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Default for Program {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program(instructions: Vec<Instruction>) -> Program {
+        Program {
+            instructions,
+            ..Program::new()
+        }
+    }
+
+    fn open(cursor: i32) -> Instruction {
+        Instruction::OpenReadCursor {
+            cursor,
+            table: "t".into(),
+        }
+    }
+
+    #[test]
+    fn test_validate_cursors() {
+        let scan = program(vec![
+            open(0),
+            Instruction::RewindCursor {
+                cursor: 0,
+                empty_target: 4,
+            },
+            Instruction::ReadColumn {
+                cursor: 0,
+                col_index: 0,
+                reg: 1,
+            },
+            Instruction::CursorAdvance {
+                cursor: 0,
+                loop_target: 2,
+            },
+            Instruction::CloseCursor { cursor: 0 },
+            Instruction::Halt,
+        ]);
+        assert!(scan.validate().is_ok());
+        assert!(Program::new().validate().is_ok());
+
+        let invalid = [
+            // never opened
+            vec![
+                Instruction::RewindCursor {
+                    cursor: 1,
+                    empty_target: 0,
+                },
+                Instruction::Halt,
+            ],
+            // closed twice
+            vec![
+                open(0),
+                Instruction::CloseCursor { cursor: 0 },
+                Instruction::CloseCursor { cursor: 0 },
+            ],
+            // used after closing
+            vec![
+                open(0),
+                Instruction::CloseCursor { cursor: 0 },
+                Instruction::DeleteRow { cursor: 0 },
+            ],
+            // left open
+            vec![open(0), open(1), Instruction::CloseCursor { cursor: 0 }],
+        ];
+        for instructions in invalid {
+            let result = program(instructions.clone()).validate();
+            assert!(
+                matches!(result, Err(LunarisError::Vm(_))),
+                "{instructions:?}"
+            );
+        }
+    }
 }
@@ -1,19 +1,93 @@
 use ast::CharacterLength::IntegerLength;
 use ast::{BinaryOperator, UnaryOperator};
-use sqlparser::ast::{self, Expr, FromTable, SelectItem, SetExpr, Statement, Value as SqlValue};
+use sqlparser::ast::{
+    self, ColumnOption, Expr, FromTable, OrderByExpr, SelectItem, SetExpr, Statement,
+    Value as SqlValue,
+};
 
-use crate::catalog::Catalog;
-use crate::constants::CELL_AREA_SIZE;
+use crate::catalog::{Catalog, validate_name};
+use crate::constants::{CATALOG_TABLE, STATUS_TABLE};
+use crate::csv;
 use crate::error::{LunarisError, LunarisResult};
+use crate::storage::index::IndexSchema;
 use crate::storage::row::{ColumnDef, ColumnType, TableSchema};
-use crate::vm::bytecode::{Instruction, Program};
+use crate::vm::bytecode::{Aggregate, Instruction, Program, SortKey, StatementKind};
+use lunaris_common::protocol::ValueType;
+use lunaris_common::timestamp;
+use lunaris_common::value::{self, Collation, Value};
 
 pub fn compile(stmt: &Statement, catalog: &Catalog) -> LunarisResult<Program> {
+    let mut prog = compile_statement(stmt, catalog)?;
+    prog.kind = match stmt {
+        Statement::Query(_) => StatementKind::Query,
+        Statement::Insert(_)
+        | Statement::Delete(_)
+        | Statement::Update { .. }
+        | Statement::Copy { .. }
+        | Statement::Truncate { .. } => StatementKind::Dml,
+        _ => StatementKind::Other,
+    };
+    Ok(prog)
+}
+
+fn compile_statement(stmt: &Statement, catalog: &Catalog) -> LunarisResult<Program> {
     match stmt {
         Statement::CreateTable(ct) => compile_create_table(ct),
+        Statement::CreateIndex(ci) => compile_create_index(ci, catalog),
+        Statement::AlterTable {
+            name,
+            if_exists,
+            operations,
+            ..
+        } => compile_alter_table(name, *if_exists, operations, catalog),
+        Statement::Drop {
+            object_type: ast::ObjectType::Table,
+            if_exists,
+            names,
+            ..
+        } => {
+            let [name] = names.as_slice() else {
+                return Err(LunarisError::Compile(
+                    "DROP TABLE requires exactly one table".into(),
+                ));
+            };
+            Ok(compile_drop_table(name.to_string(), *if_exists))
+        }
         Statement::Insert(insert) => compile_insert(insert, catalog),
         Statement::Query(query) => compile_select(query, catalog),
         Statement::Delete(delete) => compile_delete(delete, catalog),
+        Statement::Update {
+            table,
+            assignments,
+            from: None,
+            selection,
+            returning: None,
+            or: None,
+        } => compile_update(table, assignments, selection.as_ref(), catalog),
+        Statement::Copy {
+            source,
+            to,
+            target,
+            options,
+            legacy_options,
+            ..
+        } if legacy_options.is_empty() => compile_copy(source, *to, target, options, catalog),
+        Statement::Truncate { table_names, .. } => {
+            let [target] = table_names.as_slice() else {
+                return Err(LunarisError::Compile(
+                    "TRUNCATE requires exactly one table".into(),
+                ));
+            };
+            compile_truncate(&target.name.to_string(), catalog)
+        }
+        Statement::StartTransaction { .. } => Ok(compile_transaction(Instruction::Begin)),
+        Statement::Commit { .. } => Ok(compile_transaction(Instruction::Commit)),
+        Statement::Rollback {
+            savepoint: None, ..
+        } => Ok(compile_transaction(Instruction::Rollback)),
+        Statement::Rollback {
+            savepoint: Some(_), ..
+        } => Err(LunarisError::Compile("savepoints are not supported".into())),
         _ => Err(LunarisError::Compile(format!(
             "unsupported statement: {stmt}"
         ))),
@@ -22,21 +96,292 @@ pub fn compile(stmt: &Statement, catalog: &Catalog) -> LunarisResult<Program> {
 
 fn compile_create_table(ct: &ast::CreateTable) -> LunarisResult<Program> {
     let table_name = ct.name.to_string();
+    validate_name("table", &table_name)?;
     let mut columns = Vec::new();
+    let mut primary_key = None;
+
+    for (idx, col_def) in ct.columns.iter().enumerate() {
+        let (column, is_primary) = parse_column_def(col_def)?;
+        if is_primary {
+            if primary_key.is_some() {
+                return Err(LunarisError::Compile(format!(
+                    "table '{table_name}' has more than one primary key"
+                )));
+            }
+            primary_key = Some(idx);
+        }
+        columns.push(column);
+    }
+
+    let mut key_columns = Vec::new();
+    for constraint in &ct.constraints {
+        let ast::TableConstraint::PrimaryKey { columns: names, .. } = constraint else {
+            continue;
+        };
+        if primary_key.is_some() || !key_columns.is_empty() {
+            return Err(LunarisError::Compile(format!(
+                "table '{table_name}' has more than one primary key"
+            )));
+        }
+        for name in names {
+            let idx = columns
+                .iter()
+                .position(|c: &ColumnDef| c.name.eq_ignore_ascii_case(&name.value))
+                .ok_or_else(|| LunarisError::ColumnNotFound(name.value.clone()))?;
+            if key_columns.contains(&idx) {
+                return Err(LunarisError::Compile(format!(
+                    "column '{}' appears twice in the primary key",
+                    name.value
+                )));
+            }
+            columns[idx].nullable = false;
+            key_columns.push(idx);
+        }
+        // a lone INTEGER column is the same as declaring it PRIMARY KEY
+        if let [idx] = key_columns[..]
+            && columns[idx].col_type == ColumnType::Integer
+        {
+            primary_key = Some(idx);
+            key_columns.clear();
+        }
+    }
+
+    let mut schema = TableSchema::new(table_name, columns);
+    schema.primary_key = primary_key;
+    schema.key_columns = key_columns;
+
+    let mut prog = Program::new();
+    prog.emit(Instruction::CreateTable {
+        schema,
+        if_not_exists: ct.if_not_exists,
+    });
+    prog.emit(Instruction::Halt);
+    Ok(prog)
+}
+
+/// Column declared by `col_def`, and whether it is the INTEGER PRIMARY KEY.
+fn parse_column_def(col_def: &ast::ColumnDef) -> LunarisResult<(ColumnDef, bool)> {
+    let name = col_def.name.value.clone();
+    let col_type = parse_column_type(&col_def.data_type)?;
+    let mut column = ColumnDef::new(name, col_type);
+    let mut is_primary = false;
+
+    for opt in &col_def.options {
+        match &opt.option {
+            ColumnOption::NotNull => column.nullable = false,
+            ColumnOption::Null => column.nullable = true,
+            ColumnOption::Unique {
+                is_primary: true, ..
+            } => {
+                if column.col_type != ColumnType::Integer {
+                    return Err(LunarisError::Compile(format!(
+                        "primary key column '{}' must be INTEGER",
+                        column.name
+                    )));
+                }
+                column.nullable = false;
+                is_primary = true;
+            }
+            ColumnOption::Unique {
+                is_primary: false, ..
+            } => column.unique = true,
+            ColumnOption::Default(expr) => {
+                let value = literal_value(expr)?;
+                column.default = Some(coerce_literal(value, &column)?);
+            }
+            ColumnOption::Collation(name) => {
+                if !matches!(column.col_type, ColumnType::Varchar(_) | ColumnType::Text) {
+                    return Err(LunarisError::Compile(format!(
+                        "COLLATE needs a text column, '{}' is {}",
+                        column.name, column.col_type
+                    )));
+                }
+                column.collation = parse_collation(name)?;
+            }
+            other => {
+                return Err(LunarisError::Compile(format!(
+                    "unsupported column option: {other}"
+                )));
+            }
+        }
+    }
+    Ok((column, is_primary))
+}
+
+fn parse_collation(name: &ast::ObjectName) -> LunarisResult<Collation> {
+    match name.to_string().to_ascii_uppercase().as_str() {
+        "BINARY" => Ok(Collation::Binary),
+        "NOCASE" => Ok(Collation::NoCase),
+        _ => Err(LunarisError::Compile(format!(
+            "unknown collation '{name}', expected BINARY or NOCASE"
+        ))),
+    }
+}
+
+/// `ALTER TABLE ... ADD [COLUMN] ...` and `DROP [COLUMN] ...`, applied in
+/// order. Added columns go last; the rows are rewritten when it runs.
+fn compile_alter_table(
+    name: &ast::ObjectName,
+    if_exists: bool,
+    operations: &[ast::AlterTableOperation],
+    catalog: &Catalog,
+) -> LunarisResult<Program> {
+    let table_name = name.to_string();
+    let mut prog = Program::new();
+    if if_exists && !catalog.table_exists(&table_name) {
+        prog.emit(Instruction::Halt);
+        return Ok(prog);
+    }
+    let schema = catalog.get_schema(&table_name)?;
+    let indexes = catalog.indexes_on(&table_name);
+    let mut columns = schema.columns.clone();
+    let mut sources: Vec<Option<usize>> = (0..columns.len()).map(Some).collect();
+
+    for op in operations {
+        match op {
+            ast::AlterTableOperation::AddColumn {
+                if_not_exists,
+                column_def,
+                column_position: None,
+                ..
+            } => {
+                let (column, is_primary) = parse_column_def(column_def)?;
+                if columns
+                    .iter()
+                    .any(|c| c.name.eq_ignore_ascii_case(&column.name))
+                {
+                    if *if_not_exists {
+                        continue;
+                    }
+                    return Err(LunarisError::Compile(format!(
+                        "column '{}' already exists in table '{table_name}'",
+                        column.name
+                    )));
+                }
+                if is_primary {
+                    return Err(LunarisError::Compile(
+                        "a primary key can't be added to an existing table".into(),
+                    ));
+                }
+                columns.push(column);
+                sources.push(None);
+            }
+            ast::AlterTableOperation::DropColumn {
+                column_name,
+                if_exists,
+                drop_behavior: None,
+            } => {
+                let Some(pos) = columns
+                    .iter()
+                    .position(|c| c.name.eq_ignore_ascii_case(&column_name.value))
+                else {
+                    if *if_exists {
+                        continue;
+                    }
+                    return Err(LunarisError::ColumnNotFound(column_name.value.clone()));
+                };
+                let name = &columns[pos].name;
+                if let Some(old) = sources[pos] {
+                    if schema.primary_key == Some(old) || schema.key_columns.contains(&old) {
+                        return Err(LunarisError::Compile(format!(
+                            "column '{name}' is part of the primary key and can't be dropped"
+                        )));
+                    }
+                    if let Some(index) = indexes.iter().find(|index| index.column == old) {
+                        return Err(LunarisError::Compile(format!(
+                            "column '{name}' is indexed by '{}' and can't be dropped",
+                            index.index_name
+                        )));
+                    }
+                }
+                if columns.len() == 1 {
+                    return Err(LunarisError::Compile(format!(
+                        "column '{name}' is the last column of '{table_name}' and can't be dropped"
+                    )));
+                }
+                columns.remove(pos);
+                sources.remove(pos);
+            }
+            other => {
+                return Err(LunarisError::Compile(format!(
+                    "unsupported ALTER TABLE operation: {other}"
+                )));
+            }
+        }
+    }
+
+    let new_position = |old: usize| sources.iter().position(|s| *s == Some(old));
+    let mut altered = TableSchema::new(table_name, columns);
+    altered.primary_key = schema.primary_key.and_then(new_position);
+    altered.key_columns = schema
+        .key_columns
+        .iter()
+        .filter_map(|&old| new_position(old))
+        .collect();
+    prog.emit(Instruction::AlterTable {
+        schema: altered,
+        sources,
+    });
+    prog.emit(Instruction::Halt);
+    Ok(prog)
+}
 
-    for col_def in &ct.columns {
-        let name = col_def.name.value.clone();
-        let col_type = parse_column_type(&col_def.data_type)?;
-        columns.push(ColumnDef { name, col_type });
+fn compile_drop_table(table: String, if_exists: bool) -> Program {
+    let mut prog = Program::new();
+    prog.emit(Instruction::DropTable { table, if_exists });
+    prog.emit(Instruction::Halt);
+    prog
+}
+
+fn compile_create_index(ci: &ast::CreateIndex, catalog: &Catalog) -> LunarisResult<Program> {
+    let Some(name) = &ci.name else {
+        return Err(LunarisError::Compile("CREATE INDEX requires a name".into()));
+    };
+    validate_name("index", &name.to_string())?;
+    if ci.unique {
+        return Err(LunarisError::Compile(
+            "unique indexes are not supported, declare the column UNIQUE instead".into(),
+        ));
     }
+    let table_name = ci.table_name.to_string();
+    let schema = catalog.get_schema(&table_name)?;
+    let column = match ci.columns.as_slice() {
+        [
+            OrderByExpr {
+                expr: Expr::Identifier(ident),
+                ..
+            },
+        ] => schema
+            .find_column(&ident.value)
+            .ok_or_else(|| LunarisError::ColumnNotFound(ident.value.clone()))?,
+        _ => {
+            return Err(LunarisError::Compile(
+                "an index must cover exactly one column".into(),
+            ));
+        }
+    };
 
-    let schema = TableSchema::new(table_name, columns);
     let mut prog = Program::new();
-    prog.emit(Instruction::CreateTable { schema });
+    if !(ci.if_not_exists && catalog.index_exists(&name.to_string())) {
+        prog.emit(Instruction::CreateIndex {
+            index: IndexSchema {
+                index_name: name.to_string(),
+                table_name,
+                column,
+            },
+        });
+    }
     prog.emit(Instruction::Halt);
     Ok(prog)
 }
 
+fn compile_transaction(instr: Instruction) -> Program {
+    let mut prog = Program::new();
+    prog.emit(instr);
+    prog.emit(Instruction::Halt);
+    prog
+}
+
 fn compile_insert(insert: &ast::Insert, catalog: &Catalog) -> LunarisResult<Program> {
     let table_name = insert.table.to_string();
     let schema = catalog.get_schema(&table_name)?;
@@ -50,208 +395,2043 @@ fn compile_insert(insert: &ast::Insert, catalog: &Catalog) -> LunarisResult<Prog
         _ => return Err(LunarisError::Compile("only VALUES(...) supported".into())),
     };
 
-    let mut prog = Program::new();
-    let init_addr = prog.emit(Instruction::Init { target: 0 });
-    prog.emit(Instruction::Halt);
-    let body = prog.current_addr();
-    prog.update_target(init_addr, body);
-
-    prog.emit(Instruction::OpenReadWriteCursor {
-        cursor: 0,
-        table: table_name.clone(),
-    });
+    let columns: Vec<&str> = insert.columns.iter().map(|c| c.value.as_str()).collect();
+    let positions = column_positions(&schema, &columns)?;
+    let value_count = positions.iter().flatten().count();
 
+    let mut prog = begin_insert(&table_name);
+    if let Some(returning) = &insert.returning {
+        prog.result_columns = vec![returning_column(returning, &schema)?];
+        prog.result_types = vec![ValueType::Integer];
+    }
     for row in rows {
-        if row.len() != schema.columns.len() {
+        if row.len() != value_count {
             return Err(LunarisError::ValueCountMismatch {
-                expected: schema.columns.len(),
+                expected: value_count,
                 got: row.len(),
             });
         }
 
-        let base_reg = 1;
-        for (i, expr) in row.iter().enumerate() {
-            let dest = base_reg + i;
-            emit_expr(&mut prog, expr, dest)?;
+        for (i, (col, pos)) in schema.columns.iter().zip(&positions).enumerate() {
+            let dest = INSERT_BASE_REG + i;
+            match pos {
+                Some(pos) => emit_column_value(&mut prog, &row[*pos], col, dest)?,
+                None => emit_missing_column(&mut prog, &schema, i, dest)?,
+            }
+        }
+        let key_reg = emit_insert_record(&mut prog, &schema);
+        if insert.returning.is_some() {
+            prog.emit(Instruction::WriteResultRow {
+                start: key_reg,
+                count: 1,
+            });
         }
-
-        prog.emit(Instruction::CreateRecord {
-            start: base_reg,
-            count: schema.columns.len(),
-        });
-
-        // Key = first column value (row_id)
-        prog.emit(Instruction::InsertRecord {
-            cursor: 0,
-            key_reg: base_reg,
-        });
     }
-
-    prog.emit(Instruction::CloseCursor { cursor: 0 });
-    prog.emit(Instruction::Halt);
-    Ok(prog)
+    Ok(end_insert(prog))
 }
 
-fn compile_select(query: &ast::Query, catalog: &Catalog) -> LunarisResult<Program> {
-    let select = match query.body.as_ref() {
-        SetExpr::Select(s) => s,
-        _ => return Err(LunarisError::Compile("only simple SELECT supported".into())),
+/// Name of the result column of `RETURNING`, which may only name the
+/// primary key, since that is the one value an INSERT can choose itself.
+fn returning_column(items: &[SelectItem], schema: &TableSchema) -> LunarisResult<String> {
+    let unsupported = || {
+        LunarisError::Compile(format!(
+            "RETURNING supports only the primary key of '{}'",
+            schema.table_name
+        ))
+    };
+    let (ident, alias) = match items {
+        [SelectItem::UnnamedExpr(Expr::Identifier(ident))] => (ident, None),
+        [
+            SelectItem::ExprWithAlias {
+                expr: Expr::Identifier(ident),
+                alias,
+            },
+        ] => (ident, Some(alias)),
+        _ => return Err(unsupported()),
     };
+    let idx = schema
+        .find_column(&ident.value)
+        .ok_or_else(|| LunarisError::ColumnNotFound(ident.value.clone()))?;
+    if schema.primary_key != Some(idx) {
+        return Err(unsupported());
+    }
+    Ok(alias.unwrap_or(ident).value.clone())
+}
 
-    if select.from.len() != 1 {
+/// `COPY <table> [(columns)] FROM '<file>' (FORMAT csv [, HEADER] [, DELIMITER 'c'])`
+/// reads the file while compiling and inserts its records the way a single
+/// multi-row INSERT would, so the whole file goes in one transaction.
+fn compile_copy(
+    source: &ast::CopySource,
+    to: bool,
+    target: &ast::CopyTarget,
+    options: &[ast::CopyOption],
+    catalog: &Catalog,
+) -> LunarisResult<Program> {
+    let ast::CopySource::Table {
+        table_name,
+        columns,
+    } = source
+    else {
+        return Err(LunarisError::Compile("COPY needs a table".into()));
+    };
+    let (false, ast::CopyTarget::File { filename }) = (to, target) else {
         return Err(LunarisError::Compile(
-            "exactly one table in FROM required".into(),
+            "only COPY ... FROM '<file>' is supported".into(),
         ));
+    };
+    let table_name = table_name.to_string();
+    let schema = catalog.get_schema(&table_name)?;
+
+    let (mut csv_format, mut header, mut delimiter) = (false, false, ',');
+    for option in options {
+        match option {
+            ast::CopyOption::Format(format) if format.value.eq_ignore_ascii_case("csv") => {
+                csv_format = true
+            }
+            ast::CopyOption::Header(on) => header = *on,
+            ast::CopyOption::Delimiter(c) => delimiter = *c,
+            _ => {
+                return Err(LunarisError::Compile(format!(
+                    "unsupported COPY option: {option}"
+                )));
+            }
+        }
+    }
+    if !csv_format {
+        return Err(LunarisError::Compile("COPY requires (FORMAT csv)".into()));
     }
 
-    let table_name = match &select.from[0].relation {
-        ast::TableFactor::Table { name, .. } => name.to_string(),
-        _ => return Err(LunarisError::Compile("only table names in FROM".into())),
+    let mut records = csv::parse(&std::fs::read_to_string(filename)?, delimiter)?;
+    // with a header, its names pick the columns unless the statement lists them
+    let names: Vec<String> = match header {
+        true if records.is_empty() => Vec::new(),
+        true => records.remove(0).fields.into_iter().flatten().collect(),
+        false => Vec::new(),
+    };
+    let columns: Vec<&str> = match columns.is_empty() {
+        true => names.iter().map(String::as_str).collect(),
+        false => columns.iter().map(|c| c.value.as_str()).collect(),
     };
+    let positions = column_positions(&schema, &columns)?;
+    let field_count = positions.iter().flatten().count();
 
-    let schema = catalog.get_schema(&table_name)?;
-    let mut prog = Program::new();
+    let mut prog = begin_insert(&table_name);
+    for record in &records {
+        if record.fields.len() != field_count {
+            return Err(csv::import_error(
+                record.line,
+                format!("expected {field_count} fields, got {}", record.fields.len()),
+            ));
+        }
+        for (i, (col, pos)) in schema.columns.iter().zip(&positions).enumerate() {
+            let dest = INSERT_BASE_REG + i;
+            match pos {
+                Some(pos) => {
+                    let value = csv::field_value(record.fields[*pos].as_deref(), col)
+                        .map_err(|message| csv::import_error(record.line, message))?;
+                    prog.emit(value_instruction(value, dest));
+                }
+                None => emit_missing_column(&mut prog, &schema, i, dest)?,
+            }
+        }
+        emit_insert_record(&mut prog, &schema);
+    }
+    Ok(end_insert(prog))
+}
 
-    // Resolve projected columns
-    let projected_columns = parse_column_projection(&select.projection, &schema)?;
-    prog.result_columns = projected_columns
-        .iter()
-        .map(|(name, _)| name.clone())
-        .collect();
+/// First register of the row an INSERT or COPY builds.
+const INSERT_BASE_REG: usize = 1;
+
+/// For every schema column, the position of its value in each inserted row,
+/// or None when `columns` omits it. No columns means all of them, in order.
+fn column_positions(schema: &TableSchema, columns: &[&str]) -> LunarisResult<Vec<Option<usize>>> {
+    if columns.is_empty() {
+        return Ok((0..schema.columns.len()).map(Some).collect());
+    }
+    let mut positions = vec![None; schema.columns.len()];
+    for (pos, name) in columns.iter().enumerate() {
+        let idx = schema
+            .find_column(name)
+            .ok_or_else(|| LunarisError::ColumnNotFound(name.to_string()))?;
+        if positions[idx].is_some() {
+            return Err(LunarisError::Compile(format!(
+                "column '{name}' specified more than once"
+            )));
+        }
+        positions[idx] = Some(pos);
+    }
+    Ok(positions)
+}
 
+fn begin_insert(table_name: &str) -> Program {
+    let mut prog = Program::new();
     let init_addr = prog.emit(Instruction::Init { target: 0 });
     prog.emit(Instruction::Halt);
     let body = prog.current_addr();
     prog.update_target(init_addr, body);
 
-    prog.emit(Instruction::OpenReadCursor {
+    prog.emit(Instruction::OpenReadWriteCursor {
         cursor: 0,
-        table: table_name.clone(),
+        table: table_name.to_string(),
     });
+    prog
+}
 
-    // Rewind — jump to close if empty
-    let rewind_addr = prog.emit(Instruction::RewindCursor {
-        cursor: 0,
-        empty_target: 0,
+/// Insert the row whose values are in the registers from `INSERT_BASE_REG`,
+/// returning the register holding its rowid.
+fn emit_insert_record(prog: &mut Program, schema: &TableSchema) -> usize {
+    prog.emit(Instruction::CreateRecord {
+        start: INSERT_BASE_REG,
+        count: schema.columns.len(),
     });
 
-    let loop_top = prog.current_addr();
-
-    // WHERE clause — emit negated condition that skips to Next
-    let next_placeholder = if let Some(where_expr) = &select.selection {
-        Some(emit_where_skip(&mut prog, where_expr, &schema)?)
-    } else {
-        None
+    // The rowid is the primary key value, or a freshly allocated one
+    // for tables without a primary key.
+    let key_reg = match schema.primary_key {
+        Some(pk) => INSERT_BASE_REG + pk,
+        None => {
+            let reg = INSERT_BASE_REG + schema.columns.len();
+            prog.emit(Instruction::NewRowId { cursor: 0, reg });
+            reg
+        }
     };
+    prog.emit(Instruction::InsertRecord { cursor: 0, key_reg });
+    key_reg
+}
 
-    // Emit columns into registers and produce a result row
-    let result_base = 32; // use high registers to avoid conflicts with WHERE
-    for (i, (_name, col_idx)) in projected_columns.iter().enumerate() {
-        prog.emit(Instruction::ReadColumn {
-            cursor: 0,
-            col_index: *col_idx,
-            reg: result_base + i,
-        });
-    }
-    prog.emit(Instruction::WriteResultRow {
-        start: result_base,
-        count: projected_columns.len(),
-    });
+fn end_insert(mut prog: Program) -> Program {
+    prog.emit(Instruction::CloseCursor { cursor: 0 });
+    prog.emit(Instruction::Halt);
+    prog
+}
 
-    let next_addr = prog.emit(Instruction::CursorAdvance {
-        cursor: 0,
-        loop_target: loop_top,
-    });
+fn compile_select(query: &ast::Query, catalog: &Catalog) -> LunarisResult<Program> {
+    let mut prog = Program::new();
+    let init_addr = prog.emit(Instruction::Init { target: 0 });
+    prog.emit(Instruction::Halt);
+    let body = prog.current_addr();
+    prog.update_target(init_addr, body);
 
-    // Patch the WHERE skip and Rewind to jump here (past the loop)
-    let after_loop = prog.current_addr();
-    if let Some(skip_addr) = next_placeholder {
-        prog.update_target(skip_addr, next_addr);
+    let (limit, offset) = limit_offset(query)?;
+    if limit.is_some() || offset > 0 {
+        prog.emit(Instruction::ResultLimit { limit, offset });
+    }
+    match query.body.as_ref() {
+        SetExpr::Select(select) => {
+            let order_by = query.order_by.as_ref();
+            emit_select(&mut prog, select, order_by, RowTarget::Result, catalog)?;
+        }
+        SetExpr::SetOperation { .. } => emit_union(&mut prog, query, catalog)?,
+        _ => return Err(LunarisError::Compile("only simple SELECT supported".into())),
     }
-    prog.update_target(rewind_addr, after_loop);
-
-    prog.emit(Instruction::CloseCursor { cursor: 0 });
     prog.emit(Instruction::Halt);
     Ok(prog)
 }
 
-fn compile_delete(delete: &ast::Delete, catalog: &Catalog) -> LunarisResult<Program> {
-    let tables = match &delete.from {
-        FromTable::WithFromKeyword(t) | FromTable::WithoutKeyword(t) => t,
+/// `LIMIT` and `OFFSET` of a query, each a constant integer expression such
+/// as `LIMIT 2 + 3`. No LIMIT means every row, no OFFSET skips none.
+fn limit_offset(query: &ast::Query) -> LunarisResult<(Option<u64>, u64)> {
+    if query.fetch.is_some() || !query.limit_by.is_empty() {
+        return Err(LunarisError::Compile(
+            "only LIMIT and OFFSET can restrict the rows".into(),
+        ));
+    }
+    let count = |what: &str, expr: &Expr| -> LunarisResult<u64> {
+        let n = constant_integer(expr).map_err(|e| match e {
+            LunarisError::Compile(msg) => {
+                LunarisError::Compile(format!("{what} must be an integer: {msg}"))
+            }
+            other => other,
+        })?;
+        u64::try_from(n)
+            .map_err(|_| LunarisError::Compile(format!("{what} must not be negative, got {n}")))
     };
-    if tables.len() != 1 {
+    let limit = query
+        .limit
+        .as_ref()
+        .map(|e| count("LIMIT", e))
+        .transpose()?;
+    let offset = match &query.offset {
+        Some(offset) => count("OFFSET", &offset.value)?,
+        None => 0,
+    };
+    Ok((limit, offset))
+}
+
+/// Evaluate an integer expression built from literals, `+`, `-`, `*`, `/`
+/// and `%`.
+fn constant_integer(expr: &Expr) -> LunarisResult<i64> {
+    let overflow = || LunarisError::Compile(format!("integer overflow in {expr}"));
+    match expr {
+        Expr::Nested(inner) => constant_integer(inner),
+        Expr::BinaryOp { left, op, right } => {
+            let (l, r) = (constant_integer(left)?, constant_integer(right)?);
+            let result = match op {
+                BinaryOperator::Plus => l.checked_add(r),
+                BinaryOperator::Minus => l.checked_sub(r),
+                BinaryOperator::Multiply => l.checked_mul(r),
+                BinaryOperator::Divide | BinaryOperator::Modulo if r == 0 => {
+                    return Err(LunarisError::Compile(format!("division by zero in {expr}")));
+                }
+                BinaryOperator::Divide => l.checked_div(r),
+                BinaryOperator::Modulo => l.checked_rem(r),
+                _ => {
+                    return Err(LunarisError::Compile(format!(
+                        "expected a constant, got: {expr}"
+                    )));
+                }
+            };
+            result.ok_or_else(overflow)
+        }
+        _ => match literal_value(expr)? {
+            Value::Integer(n) => Ok(n),
+            other => Err(LunarisError::Compile(format!("got {other:?}"))),
+        },
+    }
+}
+
+/// Where a SELECT sends its rows.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RowTarget {
+    /// Straight to the client.
+    Result,
+    /// Into the sorter, to be returned by a later `SortResults`.
+    Sorter,
+}
+
+fn emit_result_row(prog: &mut Program, target: RowTarget, start: usize, count: usize) {
+    match target {
+        RowTarget::Result => prog.emit(Instruction::WriteResultRow { start, count }),
+        RowTarget::Sorter => prog.emit(Instruction::SorterInsert { start, count }),
+    };
+}
+
+/// Emit a single SELECT, setting the program's result columns. Returns the
+/// type of every result column that is known from the schema.
+fn emit_select(
+    prog: &mut Program,
+    select: &ast::Select,
+    order_by: Option<&ast::OrderBy>,
+    target: RowTarget,
+    catalog: &Catalog,
+) -> LunarisResult<Vec<Option<ColumnType>>> {
+    if select.from.is_empty() {
+        return emit_constant_select(prog, select, target);
+    }
+    if select.from.len() != 1 {
         return Err(LunarisError::Compile(
-            "DELETE requires exactly one table".into(),
+            "exactly one table in FROM required".into(),
         ));
     }
-    let table_name = match &tables[0].relation {
-        ast::TableFactor::Table { name, .. } => name.to_string(),
-        _ => return Err(LunarisError::Compile("only table names in FROM".into())),
+
+    let tables = joined_tables(&select.from[0])?;
+    for virtual_table in [CATALOG_TABLE, STATUS_TABLE] {
+        if !tables
+            .iter()
+            .any(|t| t.name.eq_ignore_ascii_case(virtual_table))
+        {
+            continue;
+        }
+        if tables.len() > 1 {
+            return Err(LunarisError::Compile(format!(
+                "{virtual_table} cannot be joined"
+            )));
+        }
+        return match virtual_table {
+            CATALOG_TABLE => emit_catalog_select(prog, select, order_by, target, catalog),
+            _ => emit_status_select(prog, select, order_by, target),
+        };
+    }
+
+    let schemas = tables
+        .iter()
+        .map(|t| catalog.get_schema(&t.name))
+        .collect::<LunarisResult<Vec<_>>>()?;
+    let scope = Scope {
+        tables: tables
+            .iter()
+            .zip(&schemas)
+            .enumerate()
+            .map(|(i, (table, schema))| ScopeTable {
+                schema,
+                alias: table.alias,
+                cursor: i as i32,
+            })
+            .collect(),
     };
+    let group_by = group_by_exprs(select)?;
+    if tables.len() == 1
+        && select.selection.is_none()
+        && group_by.is_empty()
+        && let Some(call) = aggregate_call(&select.projection)
+    {
+        let table_name = tables[0].name.clone();
+        let pk = scope.primary_key();
+        match (call.function.as_str(), call.args) {
+            ("COUNT", [ast::FunctionArg::Unnamed(ast::FunctionArgExpr::Wildcard)]) => {
+                return emit_count(
+                    prog, select, order_by, target, table_name, call.name, &scope,
+                );
+            }
+            ("MIN" | "MAX", [ast::FunctionArg::Unnamed(ast::FunctionArgExpr::Expr(arg))]) if matches!(scope.resolve_expr(arg), Some(Ok(col)) if Some(col) == pk) =>
+            {
+                return emit_min_max(prog, select, order_by, target, call, &scope);
+            }
+            _ => {}
+        }
+    }
 
-    let schema = catalog.get_schema(&table_name)?;
-    let mut prog = Program::new();
+    // Resolve projected columns
+    let projected_columns = parse_column_projection(&select.projection, &scope)?;
+    let aggregation = aggregation(group_by, &projected_columns, &scope)?;
+    let (order, group_order) = match aggregation {
+        Some(_) => (
+            RowOrder::Scan { descending: false },
+            aggregate_order(order_by, &projected_columns, &scope)?,
+        ),
+        None => (row_order(order_by, &projected_columns, &scope)?, None),
+    };
+    let descending = matches!(order, RowOrder::Scan { descending: true });
+    set_result_columns(prog, &projected_columns, &scope);
+    if let Some(aggregation) = &aggregation {
+        prog.result_types = aggregation.result_types(&scope);
+    }
 
-    let init_addr = prog.emit(Instruction::Init { target: 0 });
-    prog.emit(Instruction::Halt);
-    let body = prog.current_addr();
-    prog.update_target(init_addr, body);
+    // Joins are nested loops, the first table's outermost. Rewind or seek
+    // each one, jumping past its loop if there is no row
+    let seek = match &select.selection {
+        Some(where_expr) if !descending => primary_key_seek(where_expr, &scope),
+        _ => None,
+    };
+    // tables with a PRIMARY KEY (...) are scanned in key order, which an
+    // index doesn't give
+    let index_seek = match &select.selection {
+        Some(where_expr) if seek.is_none() && !descending && schemas[0].key_columns.is_empty() => {
+            index_seek(where_expr, &scope, &catalog.indexes_on(&tables[0].name))
+        }
+        _ => None,
+    };
+    for (i, table) in tables.iter().enumerate() {
+        let cursor = i as i32;
+        let table = table.name.clone();
+        match &index_seek {
+            Some((index, _)) if i == 0 => prog.emit(Instruction::OpenIndexCursor {
+                cursor,
+                table,
+                index: index.clone(),
+            }),
+            _ => prog.emit(Instruction::OpenReadCursor { cursor, table }),
+        };
+    }
+    let mut loops = Vec::new();
+    for (i, table) in tables.iter().enumerate() {
+        let cursor = i as i32;
+        // NULL until a row of an outer-joined table matches
+        let matched_reg = table.outer.then(|| {
+            let reg = prog.alloc_registers(1);
+            prog.emit(Instruction::Null { reg });
+            reg
+        });
+        let rewind_addr = match (seek, &index_seek) {
+            (Some(seek), _) if i == 0 => emit_seek(prog, seek),
+            (_, Some((_, key))) if i == 0 => {
+                let key_reg = prog.alloc_registers(1);
+                prog.emit(value_instruction(key.clone(), key_reg));
+                prog.emit(Instruction::IndexSeek {
+                    cursor,
+                    key_reg,
+                    empty_target: 0,
+                })
+            }
+            _ if i == 0 && descending => prog.emit(Instruction::RewindCursorLast {
+                cursor,
+                empty_target: 0,
+            }),
+            _ => prog.emit(Instruction::RewindCursor {
+                cursor,
+                empty_target: 0,
+            }),
+        };
+        let top = prog.current_addr();
+        // ON can only see the tables joined so far
+        let joined = Scope {
+            tables: scope.tables[..=i].to_vec(),
+        };
+        let skips = match table.on {
+            Some(on) => emit_where_skip(prog, on, &joined)?,
+            None => Vec::new(),
+        };
+        let matched =
+            matched_reg.map(|reg| (reg, prog.emit(Instruction::Integer { value: 1, reg })));
+        loops.push(JoinLoop {
+            rewind_addr,
+            top,
+            skips,
+            matched,
+        });
+    }
 
-    prog.emit(Instruction::OpenReadWriteCursor {
-        cursor: 0,
-        table: table_name.clone(),
-    });
-    let rewind_addr = prog.emit(Instruction::RewindCursor {
+    // WHERE clause — emit negated condition that skips to Next
+    let skips = match &select.selection {
+        Some(where_expr) => emit_where_skip(prog, where_expr, &scope)?,
+        None => Vec::new(),
+    };
+    loops.last_mut().expect("a table").skips.extend(skips);
+
+    // Emit columns into registers and produce a result row, followed by any
+    // sort keys that aren't result columns
+    let extra: &[Projected] = match &order {
+        RowOrder::Sort { extra, .. } => extra,
+        RowOrder::Scan { .. } => &[],
+    };
+    match &aggregation {
+        Some(aggregation) => aggregation.emit_step(prog, &scope)?,
+        None => {
+            let values = projected_columns.iter().map(|(_, p)| p).chain(extra);
+            let count = projected_columns.len() + extra.len();
+            let result_base = prog.alloc_registers(count);
+            for (i, projected) in values.enumerate() {
+                emit_projected(prog, projected, result_base + i, &scope)?;
+            }
+            let row_target = match order {
+                RowOrder::Sort { .. } => RowTarget::Sorter,
+                RowOrder::Scan { .. } => target,
+            };
+            emit_result_row(prog, row_target, result_base, count);
+        }
+    }
+
+    for (i, join_loop) in loops.into_iter().enumerate().rev() {
+        let cursor = i as i32;
+        let eq_seek = i == 0 && matches!(seek, Some(Seek::Eq(_)));
+        let next_addr = if eq_seek {
+            // keys are unique, so there is nothing after the matching row
+            prog.emit(Instruction::Goto { target: 0 })
+        } else if i == 0 && descending {
+            prog.emit(Instruction::CursorPrev {
+                cursor,
+                loop_target: join_loop.top,
+            })
+        } else {
+            prog.emit(Instruction::CursorAdvance {
+                cursor,
+                loop_target: join_loop.top,
+            })
+        };
+
+        // Patch the skips and Rewind to jump here (past the loop)
+        let after_loop = prog.current_addr();
+        if eq_seek {
+            prog.update_target(next_addr, after_loop);
+        }
+        for skip_addr in join_loop.skips {
+            prog.update_target(skip_addr, next_addr);
+        }
+        prog.update_target(join_loop.rewind_addr, after_loop);
+
+        // An outer-joined table without a matching row goes through the
+        // loop once more as a row of NULLs. Its cursor is then done, so the
+        // loop ends right after.
+        if let Some((reg, body)) = join_loop.matched {
+            let done = prog.emit(Instruction::JumpIfNotNull { reg, target: 0 });
+            prog.emit(Instruction::NullRow { cursor });
+            prog.emit(Instruction::Goto { target: body });
+            let after_padding = prog.current_addr();
+            prog.update_target(done, after_padding);
+        }
+    }
+
+    for i in 0..tables.len() {
+        prog.emit(Instruction::CloseCursor { cursor: i as i32 });
+    }
+    if let Some(aggregation) = &aggregation {
+        aggregation.emit_groups(prog, target, group_order.is_some(), &scope);
+    }
+    if let RowOrder::Sort { keys, .. } = order {
+        prog.emit(Instruction::SortResults {
+            keys,
+            columns: projected_columns.len(),
+            distinct: false,
+        });
+    }
+    if let Some(keys) = group_order {
+        prog.emit(Instruction::SortResults {
+            keys,
+            columns: projected_columns.len(),
+            distinct: false,
+        });
+    }
+    Ok(projected_columns
+        .iter()
+        .map(|(_, projected)| match projected {
+            Projected::Column(col) => Some(scope.column(*col).col_type.clone()),
+            Projected::Expr(_) => None,
+        })
+        .collect())
+}
+
+/// `SELECT ... UNION [ALL] SELECT ...`, the rows of every SELECT one after
+/// the other. UNION drops duplicate rows by sorting them, so the SELECTs up
+/// to the last UNION feed the sorter and any after it return their rows
+/// directly. An ORDER BY sorts all of them.
+fn emit_union(prog: &mut Program, query: &ast::Query, catalog: &Catalog) -> LunarisResult<()> {
+    let mut parts = Vec::new();
+    union_parts(&query.body, &mut parts)?;
+    let order_by = query.order_by.as_ref();
+    let last_distinct = parts.iter().rposition(|(_, distinct)| *distinct);
+    let sorted = match (last_distinct, order_by) {
+        (Some(last), Some(_)) if last + 1 < parts.len() => {
+            return Err(LunarisError::Compile(
+                "ORDER BY over UNION ALL after UNION is not supported".into(),
+            ));
+        }
+        (_, Some(_)) => parts.len(),
+        (Some(last), None) => last + 1,
+        (None, None) => 0,
+    };
+
+    let mut first: Option<Vec<Option<ColumnType>>> = None;
+    let mut names = Vec::new();
+    let mut result_types: Vec<ValueType> = Vec::new();
+    for (i, (select, _)) in parts.iter().enumerate() {
+        let target = match i < sorted {
+            true => RowTarget::Sorter,
+            false => RowTarget::Result,
+        };
+        if i == sorted && sorted > 0 {
+            emit_union_sort(prog, order_by, last_distinct.is_some())?;
+        }
+        let types = emit_select(prog, select, None, target, catalog)?;
+        match &first {
+            None => {
+                names = prog.result_columns.clone();
+                result_types = prog.result_types.clone();
+                first = Some(types);
+            }
+            Some(first_types) => {
+                check_union_columns(first_types, &types)?;
+                // a later SELECT may tell what the first one can't
+                for (known, other) in result_types.iter_mut().zip(&prog.result_types) {
+                    if *known == ValueType::Unknown {
+                        *known = *other;
+                    }
+                }
+            }
+        }
+    }
+    prog.result_columns = names;
+    prog.result_types = result_types;
+    if sorted == parts.len() {
+        emit_union_sort(prog, order_by, last_distinct.is_some())?;
+    }
+    Ok(())
+}
+
+/// Flatten `a UNION b UNION ALL c`, which nests to the left, into its
+/// SELECTs, each with whether it is joined to the ones before by UNION
+/// rather than UNION ALL.
+fn union_parts<'a>(
+    body: &'a SetExpr,
+    parts: &mut Vec<(&'a ast::Select, bool)>,
+) -> LunarisResult<()> {
+    let unsupported = |what: String| Err(LunarisError::Compile(format!("{what} is not supported")));
+    match body {
+        SetExpr::Select(select) => parts.push((select, false)),
+        SetExpr::SetOperation {
+            op: ast::SetOperator::Union,
+            set_quantifier,
+            left,
+            right,
+        } => {
+            let distinct = match set_quantifier {
+                ast::SetQuantifier::All => false,
+                ast::SetQuantifier::Distinct | ast::SetQuantifier::None => true,
+                other => return unsupported(format!("UNION {other}")),
+            };
+            union_parts(left, parts)?;
+            let SetExpr::Select(select) = right.as_ref() else {
+                return unsupported(format!("UNION with {right}"));
+            };
+            parts.push((select, distinct));
+        }
+        SetExpr::SetOperation { op, .. } => return unsupported(op.to_string()),
+        other => return unsupported(format!("UNION with {other}")),
+    }
+    Ok(())
+}
+
+/// Sort the rows a UNION held back, by its ORDER BY and then, to bring
+/// duplicates together, by every column.
+fn emit_union_sort(
+    prog: &mut Program,
+    order_by: Option<&ast::OrderBy>,
+    distinct: bool,
+) -> LunarisResult<()> {
+    let columns = prog.result_columns.len();
+    let mut keys = Vec::new();
+    if let Some(order_by) = order_by {
+        let ast::OrderByKind::Expressions(exprs) = &order_by.kind else {
+            return Err(LunarisError::Compile(
+                "ORDER BY ALL is not supported".into(),
+            ));
+        };
+        for order in exprs {
+            let name = match &order.expr {
+                Expr::Identifier(ident) => prog
+                    .result_columns
+                    .iter()
+                    .position(|name| name.eq_ignore_ascii_case(&ident.value)),
+                _ => None,
+            };
+            let column = match (result_position(&order.expr, columns), name) {
+                (Some(position), _) => position?,
+                (None, Some(column)) => column,
+                (None, None) => {
+                    return Err(LunarisError::Compile(format!(
+                        "ORDER BY of a UNION must name a result column, got: {}",
+                        order.expr
+                    )));
+                }
+            };
+            let descending = order.options.asc == Some(false);
+            keys.push(SortKey {
+                column,
+                descending,
+                nulls_first: order.options.nulls_first.unwrap_or(!descending),
+                collation: Collation::Binary,
+            });
+        }
+    }
+    if distinct {
+        keys.extend((0..columns).map(|column| SortKey {
+            column,
+            descending: false,
+            nulls_first: true,
+            collation: Collation::Binary,
+        }));
+    }
+    prog.emit(Instruction::SortResults {
+        keys,
+        columns,
+        distinct,
+    });
+    Ok(())
+}
+
+/// Check that another SELECT of a UNION returns as many columns as the
+/// first, of compatible types where the schema tells them.
+fn check_union_columns(
+    first: &[Option<ColumnType>],
+    other: &[Option<ColumnType>],
+) -> LunarisResult<()> {
+    if first.len() != other.len() {
+        return Err(LunarisError::Compile(format!(
+            "each SELECT of a UNION must have the same number of columns, got {} and {}",
+            first.len(),
+            other.len()
+        )));
+    }
+    // timestamps are returned as text
+    let kind = |t: &ColumnType| match t {
+        ColumnType::Integer | ColumnType::Float => 0,
+        ColumnType::Varchar(_) | ColumnType::Text | ColumnType::Timestamp => 1,
+        ColumnType::Boolean => 2,
+        ColumnType::Blob(_) => 3,
+    };
+    for (i, pair) in first.iter().zip(other).enumerate() {
+        if let (Some(a), Some(b)) = pair
+            && kind(a) != kind(b)
+        {
+            return Err(LunarisError::Compile(format!(
+                "column {} of a UNION mixes {a} and {b}",
+                i + 1
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// A table of FROM and how it is joined to the tables before it.
+struct JoinedTable<'a> {
+    name: String,
+    alias: Option<&'a str>,
+    /// Set for LEFT JOIN, which keeps the rows before it even if no row of
+    /// this table matches.
+    outer: bool,
+    on: Option<&'a Expr>,
+}
+
+/// The tables of `FROM a [LEFT] JOIN b ON ...`, in order.
+fn joined_tables<'a>(from: &'a ast::TableWithJoins) -> LunarisResult<Vec<JoinedTable<'a>>> {
+    let table = |relation: &'a ast::TableFactor| match relation {
+        ast::TableFactor::Table { name, alias, .. } => Ok((
+            name.to_string(),
+            alias.as_ref().map(|a| a.name.value.as_str()),
+        )),
+        _ => Err(LunarisError::Compile("only table names in FROM".into())),
+    };
+    let (name, alias) = table(&from.relation)?;
+    let mut tables = vec![JoinedTable {
+        name,
+        alias,
+        outer: false,
+        on: None,
+    }];
+    for join in &from.joins {
+        let (outer, constraint) = match &join.join_operator {
+            ast::JoinOperator::Join(c) | ast::JoinOperator::Inner(c) => (false, Some(c)),
+            ast::JoinOperator::Left(c) | ast::JoinOperator::LeftOuter(c) => (true, Some(c)),
+            ast::JoinOperator::CrossJoin => (false, None),
+            other => {
+                return Err(LunarisError::Compile(format!(
+                    "unsupported join: {other:?}"
+                )));
+            }
+        };
+        let on = match constraint {
+            Some(ast::JoinConstraint::On(expr)) => Some(expr),
+            Some(ast::JoinConstraint::None) | None => None,
+            Some(_) => {
+                return Err(LunarisError::Compile(
+                    "only JOIN ... ON is supported".into(),
+                ));
+            }
+        };
+        let (name, alias) = table(&join.relation)?;
+        tables.push(JoinedTable {
+            name,
+            alias,
+            outer,
+            on,
+        });
+    }
+    Ok(tables)
+}
+
+/// The jumps of one table's loop in a join, patched once the loops nested
+/// inside it are emitted.
+struct JoinLoop {
+    rewind_addr: usize,
+    top: usize,
+    /// Conditions failing for a row, which move on to the next one.
+    skips: Vec<usize>,
+    /// For an outer join, the register recording a match and the
+    /// instruction setting it, where a row of NULLs resumes.
+    matched: Option<(usize, usize)>,
+}
+
+/// How the rows of a SELECT are put in ORDER BY order.
+enum RowOrder<'a> {
+    /// The scan yields them in order already: without ORDER BY, or ordered
+    /// by the primary key, where DESC walks the table backwards.
+    Scan { descending: bool },
+    /// The rows are held back and sorted by `keys`, which index the result
+    /// columns followed by `extra`, the keys that aren't result columns.
+    Sort {
+        keys: Vec<SortKey>,
+        extra: Vec<Projected<'a>>,
+    },
+}
+
+/// Work out the ORDER BY of a SELECT. A term can name a result column by its
+/// alias or position (`ORDER BY 2`), repeat a projected expression, or be
+/// any other expression over the table.
+fn row_order<'a>(
+    order_by: Option<&'a ast::OrderBy>,
+    projected: &[(String, Projected)],
+    scope: &Scope,
+) -> LunarisResult<RowOrder<'a>> {
+    let Some(order_by) = order_by else {
+        return Ok(RowOrder::Scan { descending: false });
+    };
+    let ast::OrderByKind::Expressions(exprs) = &order_by.kind else {
+        return Err(LunarisError::Compile(
+            "ORDER BY ALL is not supported".into(),
+        ));
+    };
+
+    let mut keys = Vec::new();
+    let mut extra = Vec::new();
+    for order in exprs {
+        let column = match result_column(&order.expr, projected)? {
+            Some(i) => i,
+            None => {
+                extra.push(match scope.resolve_expr(&order.expr) {
+                    Some(col) => Projected::Column(col?),
+                    None => Projected::Expr(&order.expr),
+                });
+                projected.len() + extra.len() - 1
+            }
+        };
+        let sorted_on = match projected.get(column) {
+            Some((_, p)) => p,
+            None => &extra[column - projected.len()],
+        };
+        let collation = match sorted_on {
+            Projected::Column(col) => scope.column(*col).collation,
+            Projected::Expr(_) => Collation::Binary,
+        };
+        let descending = order.options.asc == Some(false);
+        keys.push(SortKey {
+            column,
+            descending,
+            nulls_first: order.options.nulls_first.unwrap_or(!descending),
+            collation,
+        });
+    }
+
+    // rows come out of the table in key order, and `row_key` keeps that the
+    // order of the rowids, so the primary key needs no sorting
+    if let [key] = keys[..] {
+        let sorted_on = match projected.get(key.column) {
+            Some((_, p)) => p,
+            None => &extra[key.column - projected.len()],
+        };
+        if let Projected::Column(col) = sorted_on
+            && scope.primary_key() == Some(*col)
+        {
+            return Ok(RowOrder::Scan {
+                descending: key.descending,
+            });
+        }
+    }
+    Ok(RowOrder::Sort { keys, extra })
+}
+
+/// The result column an ORDER BY term refers to, if it refers to one.
+fn result_column(expr: &Expr, projected: &[(String, Projected)]) -> LunarisResult<Option<usize>> {
+    if let Some(position) = result_position(expr, projected.len()) {
+        return position.map(Some);
+    }
+    let found = projected.iter().position(|(name, p)| match (expr, p) {
+        // result names win over the table's columns
+        (Expr::Identifier(ident), _) => name.eq_ignore_ascii_case(&ident.value),
+        (_, Projected::Expr(e)) => e.to_string() == expr.to_string(),
+        _ => false,
+    });
+    Ok(found)
+}
+
+/// Where a scan can start instead of the first row.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Seek {
+    /// Only the row with this key can match.
+    Eq(i64),
+    /// No row with a key below this one can match.
+    Ge(i64),
+}
+
+/// Find a bound on the primary key among the AND-ed conditions of a WHERE
+/// clause. The full WHERE still runs on every row, so the bound only has to
+/// be safe, not exact.
+fn primary_key_seek(expr: &Expr, scope: &Scope) -> Option<Seek> {
+    let pk = scope.primary_key()?;
+    let is_pk = |e: &Expr| matches!(scope.resolve_expr(e), Some(Ok(col)) if col == pk);
+    let key = |e: &Expr| match literal_value(e) {
+        Ok(Value::Integer(k)) => Some(k),
+        _ => None,
+    };
+
+    match expr {
+        Expr::Nested(inner) => primary_key_seek(inner, scope),
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::And,
+            right,
+        } => match (
+            primary_key_seek(left, scope),
+            primary_key_seek(right, scope),
+        ) {
+            (Some(Seek::Eq(k)), _) | (_, Some(Seek::Eq(k))) => Some(Seek::Eq(k)),
+            (Some(Seek::Ge(a)), Some(Seek::Ge(b))) => Some(Seek::Ge(a.max(b))),
+            (bound, None) | (None, bound) => bound,
+        },
+        Expr::BinaryOp { left, op, right } => {
+            // normalize to `pk <op> key`
+            let (op, k) = if is_pk(left) {
+                (op.clone(), key(right)?)
+            } else if is_pk(right) {
+                let flipped = match op {
+                    BinaryOperator::Lt => BinaryOperator::Gt,
+                    BinaryOperator::LtEq => BinaryOperator::GtEq,
+                    BinaryOperator::Gt => BinaryOperator::Lt,
+                    BinaryOperator::GtEq => BinaryOperator::LtEq,
+                    other => other.clone(),
+                };
+                (flipped, key(left)?)
+            } else {
+                return None;
+            };
+            match op {
+                BinaryOperator::Eq => Some(Seek::Eq(k)),
+                BinaryOperator::Gt => k.checked_add(1).map(Seek::Ge),
+                BinaryOperator::GtEq => Some(Seek::Ge(k)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// An equality between a column of the first table that has an index and a
+/// constant, among the AND-ed conditions of `expr`. Returns the index and
+/// the constant as the column stores it.
+fn index_seek(expr: &Expr, scope: &Scope, indexes: &[IndexSchema]) -> Option<(String, Value)> {
+    match expr {
+        Expr::Nested(inner) => index_seek(inner, scope, indexes),
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::And,
+            right,
+        } => index_seek(left, scope, indexes).or_else(|| index_seek(right, scope, indexes)),
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::Eq,
+            right,
+        } => {
+            let (col, constant) = match (scope.resolve_expr(left), scope.resolve_expr(right)) {
+                (Some(Ok(col)), None) => (col, right),
+                (None, Some(Ok(col))) => (col, left),
+                _ => return None,
+            };
+            // the index holds text as stored, so NOCASE columns are scanned
+            let index = indexes.iter().find(|index| {
+                col.cursor == 0
+                    && index.column == col.index
+                    && scope.column(col).collation == Collation::Binary
+            })?;
+            // a constant of another type can't be looked up, the scan
+            // compares it instead
+            let value = literal_value(constant).ok()?;
+            let value = coerce_literal(value, scope.column(col)).ok()?;
+            Some((index.index_name.clone(), value))
+        }
+        _ => None,
+    }
+}
+
+/// Load the seek key and position cursor 0 on it. Returns the address of
+/// the seek, whose empty target the caller patches.
+fn emit_seek(prog: &mut Program, seek: Seek) -> usize {
+    let key_reg = prog.alloc_registers(1);
+    let (key, exact) = match seek {
+        Seek::Eq(k) => (k, true),
+        Seek::Ge(k) => (k, false),
+    };
+    prog.emit(Instruction::Integer {
+        value: key,
+        reg: key_reg,
+    });
+    if exact {
+        prog.emit(Instruction::SeekEQ {
+            cursor: 0,
+            key_reg,
+            empty_target: 0,
+        })
+    } else {
+        prog.emit(Instruction::SeekGE {
+            cursor: 0,
+            key_reg,
+            empty_target: 0,
+        })
+    }
+}
+
+/// A SELECT without FROM evaluates its expressions once, producing a single
+/// row unless the WHERE clause is not TRUE.
+fn emit_constant_select(
+    prog: &mut Program,
+    select: &ast::Select,
+    target: RowTarget,
+) -> LunarisResult<Vec<Option<ColumnType>>> {
+    if select
+        .projection
+        .iter()
+        .any(|item| matches!(item, SelectItem::Wildcard(_)))
+    {
+        return Err(LunarisError::Compile(
+            "SELECT * requires a FROM clause".into(),
+        ));
+    }
+
+    // no table, so any column reference fails to resolve
+    let scope = Scope::default();
+    let projected_columns = parse_column_projection(&select.projection, &scope)?;
+    set_result_columns(prog, &projected_columns, &scope);
+
+    // nothing to compute, so the row is known right away
+    if select.selection.is_none() && target == RowTarget::Result {
+        let literals: Option<Vec<Value>> = projected_columns
+            .iter()
+            .map(|(_, projected)| match projected {
+                Projected::Expr(expr) => literal_value(expr).ok(),
+                Projected::Column(_) => None,
+            })
+            .collect();
+        if let Some(values) = literals {
+            prog.emit(Instruction::ConstResultRow { values });
+            return Ok(vec![None; projected_columns.len()]);
+        }
+    }
+
+    let skips = match &select.selection {
+        Some(where_expr) => emit_where_skip(prog, where_expr, &scope)?,
+        None => Vec::new(),
+    };
+
+    let result_base = prog.alloc_registers(projected_columns.len());
+    for (i, (_name, projected)) in projected_columns.iter().enumerate() {
+        let Projected::Expr(expr) = projected else {
+            unreachable!("table columns don't resolve without a table");
+        };
+        emit_operand(prog, expr, result_base + i, &scope)?;
+    }
+    emit_result_row(prog, target, result_base, projected_columns.len());
+
+    let after_row = prog.current_addr();
+    for skip_addr in skips {
+        prog.update_target(skip_addr, after_row);
+    }
+    Ok(vec![None; projected_columns.len()])
+}
+
+/// A projection that is a single call of a function, such as `COUNT(*)`
+/// or `MAX(id)`.
+struct AggregateCall<'a> {
+    /// Function name in upper case.
+    function: String,
+    args: &'a [ast::FunctionArg],
+    /// Name of the result column.
+    name: String,
+}
+
+fn aggregate_call(projection: &[SelectItem]) -> Option<AggregateCall<'_>> {
+    let [item] = projection else {
+        return None;
+    };
+    let (expr, alias) = match item {
+        SelectItem::UnnamedExpr(expr) => (expr, None),
+        SelectItem::ExprWithAlias { expr, alias } => (expr, Some(alias.value.clone())),
+        _ => return None,
+    };
+    let Expr::Function(func) = expr else {
+        return None;
+    };
+    let ast::FunctionArguments::List(list) = &func.args else {
+        return None;
+    };
+    if list.duplicate_treatment.is_some() || !list.clauses.is_empty() {
+        return None;
+    }
+    Some(AggregateCall {
+        function: func.name.to_string().to_ascii_uppercase(),
+        args: &list.args,
+        name: alias.unwrap_or_else(|| expr.to_string()),
+    })
+}
+
+// The catalog keeps every table's row count, so an unfiltered COUNT(*)
+// never has to look at the rows.
+fn emit_count(
+    prog: &mut Program,
+    select: &ast::Select,
+    order_by: Option<&ast::OrderBy>,
+    target: RowTarget,
+    table: String,
+    column: String,
+    scope: &Scope,
+) -> LunarisResult<Vec<Option<ColumnType>>> {
+    // there is a single row, so ORDER BY only needs to make sense
+    let projected = parse_column_projection(&select.projection, scope)?;
+    if let RowOrder::Sort { extra, .. } = row_order(order_by, &projected, scope)?
+        && !extra.is_empty()
+    {
+        return Err(LunarisError::Compile(format!(
+            "ORDER BY of a COUNT(*) query can only use {column}"
+        )));
+    }
+    prog.result_columns = vec![column];
+    prog.result_types = vec![ValueType::Integer];
+    let reg = prog.alloc_registers(1);
+    prog.emit(Instruction::RowCount { table, reg });
+    emit_result_row(prog, target, reg, 1);
+    Ok(vec![Some(ColumnType::Integer)])
+}
+
+// Rows are stored in rowid order, so the smallest and largest primary key
+// are read from one end of the table.
+fn emit_min_max(
+    prog: &mut Program,
+    select: &ast::Select,
+    order_by: Option<&ast::OrderBy>,
+    target: RowTarget,
+    call: AggregateCall,
+    scope: &Scope,
+) -> LunarisResult<Vec<Option<ColumnType>>> {
+    let AggregateCall {
+        function,
+        name: column,
+        ..
+    } = call;
+    let max = function == "MAX";
+    let pk = scope.primary_key();
+    let projected = parse_column_projection(&select.projection, scope)?;
+    if let RowOrder::Sort { extra, .. } = row_order(order_by, &projected, scope)?
+        && !extra.is_empty()
+    {
+        return Err(LunarisError::Compile(format!(
+            "ORDER BY of a {function} query can only use {column}"
+        )));
+    }
+    prog.result_columns = vec![column];
+    prog.result_types = vec![ValueType::Integer];
+
+    let table = scope.tables[0].schema.table_name.clone();
+    prog.emit(Instruction::OpenReadCursor { cursor: 0, table });
+    let reg = prog.alloc_registers(1);
+    let rewind = match max {
+        true => prog.emit(Instruction::RewindCursorLast {
+            cursor: 0,
+            empty_target: 0,
+        }),
+        false => prog.emit(Instruction::RewindCursor {
+            cursor: 0,
+            empty_target: 0,
+        }),
+    };
+    prog.emit(Instruction::ReadColumn {
         cursor: 0,
-        empty_target: 0,
+        col_index: pk.expect("a primary key").index,
+        reg,
     });
+    let done = prog.emit(Instruction::Goto { target: 0 });
+    let empty = prog.emit(Instruction::Null { reg });
+    prog.update_target(rewind, empty);
+    let row = prog.current_addr();
+    prog.update_target(done, row);
+    emit_result_row(prog, target, reg, 1);
+    prog.emit(Instruction::CloseCursor { cursor: 0 });
+    Ok(vec![Some(ColumnType::Integer)])
+}
+
+/// The aggregates a SELECT computes over the groups of its rows.
+struct Aggregation<'a> {
+    /// What the rows are grouped by. Without any key, all rows form one
+    /// group.
+    keys: Vec<Projected<'a>>,
+    /// The aggregate calls of the projection, with their argument, which
+    /// `COUNT(*)` doesn't have.
+    calls: Vec<(Aggregate, Option<&'a Expr>)>,
+    /// Position of every result column in a finished group, which holds the
+    /// keys followed by the aggregates.
+    columns: Vec<usize>,
+}
+
+impl Aggregation<'_> {
+    fn aggregates(&self) -> Vec<Aggregate> {
+        self.calls.iter().map(|(aggregate, _)| *aggregate).collect()
+    }
+
+    fn result_types(&self, scope: &Scope) -> Vec<ValueType> {
+        self.columns
+            .iter()
+            .map(|&column| match column.checked_sub(self.keys.len()) {
+                None => projected_type(&self.keys[column], scope),
+                Some(call) => match self.calls[call] {
+                    (Aggregate::CountRows | Aggregate::Count, _) => ValueType::Integer,
+                    (Aggregate::Avg, _) => ValueType::Float,
+                    (Aggregate::Sum, arg) => match arg.map(|arg| expr_type(arg, scope)) {
+                        Some(t @ (ValueType::Integer | ValueType::Float)) => t,
+                        _ => ValueType::Unknown,
+                    },
+                    (Aggregate::Min | Aggregate::Max, arg) => {
+                        arg.map_or(ValueType::Unknown, |arg| expr_type(arg, scope))
+                    }
+                },
+            })
+            .collect()
+    }
+
+    /// Fold the row the cursors are on into its group.
+    fn emit_step(&self, prog: &mut Program, scope: &Scope) -> LunarisResult<()> {
+        let key = prog.alloc_registers(self.keys.len());
+        for (i, projected) in self.keys.iter().enumerate() {
+            emit_projected(prog, projected, key + i, scope)?;
+        }
+        let args = prog.alloc_registers(self.calls.len());
+        for (i, (_, arg)) in self.calls.iter().enumerate() {
+            if let Some(arg) = arg {
+                emit_operand(prog, arg, args + i, scope)?;
+            }
+        }
+        prog.emit(Instruction::AggStep {
+            key,
+            key_count: self.keys.len(),
+            args,
+            aggregates: self.aggregates(),
+        });
+        Ok(())
+    }
+
+    /// Write a result row per group, into the sorter if it is `sorted`.
+    fn emit_groups(&self, prog: &mut Program, target: RowTarget, sorted: bool, scope: &Scope) {
+        prog.emit(Instruction::AggFinish {
+            key_count: self.keys.len(),
+            aggregates: self.aggregates(),
+        });
+        let group = prog.alloc_registers(self.keys.len() + self.calls.len());
+        let next = prog.emit(Instruction::AggNext {
+            dest: group,
+            done_target: 0,
+        });
+        // the smallest or largest timestamp is shown as text, like the column
+        for (i, (aggregate, arg)) in self.calls.iter().enumerate() {
+            if matches!(aggregate, Aggregate::Min | Aggregate::Max)
+                && let Some(Some(Ok(col))) = arg.map(|arg| scope.resolve_expr(arg))
+                && scope.column(col).col_type == ColumnType::Timestamp
+            {
+                let reg = group + self.keys.len() + i;
+                prog.emit(Instruction::FormatTimestamp { reg });
+            }
+        }
+        let row = prog.alloc_registers(self.columns.len());
+        for (i, &column) in self.columns.iter().enumerate() {
+            prog.emit(Instruction::Copy {
+                src: group + column,
+                dest: row + i,
+            });
+        }
+        let row_target = match sorted {
+            true => RowTarget::Sorter,
+            false => target,
+        };
+        emit_result_row(prog, row_target, row, self.columns.len());
+        prog.emit(Instruction::Goto { target: next });
+        let done = prog.current_addr();
+        prog.update_target(next, done);
+    }
+}
+
+/// The GROUP BY expressions of a SELECT, none without GROUP BY.
+fn group_by_exprs(select: &ast::Select) -> LunarisResult<&[Expr]> {
+    if select.having.is_some() {
+        return Err(LunarisError::Compile("HAVING is not supported".into()));
+    }
+    match &select.group_by {
+        ast::GroupByExpr::Expressions(exprs, modifiers) if modifiers.is_empty() => Ok(exprs),
+        _ => Err(LunarisError::Compile(
+            "only GROUP BY <expressions> is supported".into(),
+        )),
+    }
+}
+
+/// How a SELECT aggregates its rows, if it has a GROUP BY or a result column
+/// calls an aggregate function. Every other result column must then be one
+/// of the GROUP BY expressions.
+fn aggregation<'a>(
+    group_by: &'a [Expr],
+    projected: &[(String, Projected<'a>)],
+    scope: &Scope,
+) -> LunarisResult<Option<Aggregation<'a>>> {
+    let calls = projected
+        .iter()
+        .map(|(_, p)| match p {
+            Projected::Expr(expr) => aggregate_function(expr),
+            Projected::Column(_) => Ok(None),
+        })
+        .collect::<LunarisResult<Vec<_>>>()?;
+    if group_by.is_empty() && calls.iter().all(Option::is_none) {
+        return Ok(None);
+    }
+
+    let keys = group_by
+        .iter()
+        .map(|expr| match scope.resolve_expr(expr) {
+            Some(col) => col.map(Projected::Column),
+            None => Ok(Projected::Expr(expr)),
+        })
+        .collect::<LunarisResult<Vec<_>>>()?;
+    let mut aggregation = Aggregation {
+        keys,
+        calls: Vec::new(),
+        columns: Vec::new(),
+    };
+    for ((name, p), call) in projected.iter().zip(calls) {
+        let column = match call {
+            Some(call) => {
+                aggregation.calls.push(call);
+                aggregation.keys.len() + aggregation.calls.len() - 1
+            }
+            None => {
+                let key = aggregation.keys.iter().position(|key| match (key, p) {
+                    (Projected::Column(a), Projected::Column(b)) => a == b,
+                    (Projected::Expr(a), Projected::Expr(b)) => a.to_string() == b.to_string(),
+                    _ => false,
+                });
+                key.ok_or_else(|| {
+                    LunarisError::Compile(format!("{name} must be aggregated or in GROUP BY"))
+                })?
+            }
+        };
+        aggregation.columns.push(column);
+    }
+    Ok(Some(aggregation))
+}
+
+/// The aggregate `expr` calls, with its argument, if it is a call of one.
+fn aggregate_function(expr: &Expr) -> LunarisResult<Option<(Aggregate, Option<&Expr>)>> {
+    let Expr::Function(func) = expr else {
+        return Ok(None);
+    };
+    let name = func.name.to_string().to_ascii_uppercase();
+    let aggregate = match name.as_str() {
+        "COUNT" => Aggregate::Count,
+        "SUM" => Aggregate::Sum,
+        "AVG" => Aggregate::Avg,
+        "MIN" => Aggregate::Min,
+        "MAX" => Aggregate::Max,
+        _ => return Ok(None),
+    };
+    if let ast::FunctionArguments::List(list) = &func.args
+        && aggregate == Aggregate::Count
+        && list.duplicate_treatment.is_none()
+        && list.clauses.is_empty()
+        && matches!(
+            list.args.as_slice(),
+            [ast::FunctionArg::Unnamed(ast::FunctionArgExpr::Wildcard)]
+        )
+    {
+        return Ok(Some((Aggregate::CountRows, None)));
+    }
+    match function_args(func)?.as_slice() {
+        [arg] => Ok(Some((aggregate, Some(*arg)))),
+        args => Err(LunarisError::Compile(format!(
+            "{name} takes 1 argument, got {}",
+            args.len()
+        ))),
+    }
+}
+
+/// ORDER BY of a SELECT that aggregates. Its rows are gone once they are
+/// grouped, so a term can only refer to a result column.
+fn aggregate_order(
+    order_by: Option<&ast::OrderBy>,
+    projected: &[(String, Projected)],
+    scope: &Scope,
+) -> LunarisResult<Option<Vec<SortKey>>> {
+    let Some(order_by) = order_by else {
+        return Ok(None);
+    };
+    let ast::OrderByKind::Expressions(exprs) = &order_by.kind else {
+        return Err(LunarisError::Compile(
+            "ORDER BY ALL is not supported".into(),
+        ));
+    };
+    let keys = exprs.iter().map(|order| {
+        let Some(column) = result_column(&order.expr, projected)? else {
+            return Err(LunarisError::Compile(format!(
+                "ORDER BY {} is not a result column of the aggregate query",
+                order.expr
+            )));
+        };
+        let collation = match &projected[column].1 {
+            Projected::Column(col) => scope.column(*col).collation,
+            Projected::Expr(_) => Collation::Binary,
+        };
+        let descending = order.options.asc == Some(false);
+        Ok(SortKey {
+            column,
+            descending,
+            nulls_first: order.options.nulls_first.unwrap_or(!descending),
+            collation,
+        })
+    });
+    keys.collect::<LunarisResult<_>>().map(Some)
+}
+
+/// `SELECT ... FROM lunaris_tables`, one row per table with its name and
+/// number of columns. The rows are taken from the catalog while compiling,
+/// so only plain column lists are supported.
+fn emit_catalog_select(
+    prog: &mut Program,
+    select: &ast::Select,
+    order_by: Option<&ast::OrderBy>,
+    target: RowTarget,
+    catalog: &Catalog,
+) -> LunarisResult<Vec<Option<ColumnType>>> {
+    let schema = TableSchema::new(
+        CATALOG_TABLE.to_string(),
+        vec![
+            ColumnDef::new("name".into(), ColumnType::Text),
+            ColumnDef::new("column_count".into(), ColumnType::Integer),
+        ],
+    );
+    let columns = virtual_table_columns(prog, select, order_by, &schema)?;
+
+    let result_base = prog.alloc_registers(columns.len());
+    for name in catalog.table_names() {
+        let column_count = catalog.get_schema(&name)?.columns.len();
+        let row = [Value::Text(name), Value::Integer(column_count as i64)];
+        for (i, idx) in columns.iter().enumerate() {
+            prog.emit(value_instruction(row[*idx].clone(), result_base + i));
+        }
+        emit_result_row(prog, target, result_base, columns.len());
+    }
+    Ok(columns
+        .iter()
+        .map(|&idx| Some(schema.columns[idx].col_type.clone()))
+        .collect())
+}
+
+/// `SELECT ... FROM lunaris_status`, one row per statistic of the server
+/// (see `Database::status`), gathered when the statement runs.
+fn emit_status_select(
+    prog: &mut Program,
+    select: &ast::Select,
+    order_by: Option<&ast::OrderBy>,
+    target: RowTarget,
+) -> LunarisResult<Vec<Option<ColumnType>>> {
+    let schema = TableSchema::new(
+        STATUS_TABLE.to_string(),
+        vec![
+            ColumnDef::new("stat".into(), ColumnType::Text),
+            ColumnDef::new("object".into(), ColumnType::Text),
+            ColumnDef::new("value".into(), ColumnType::Integer),
+        ],
+    );
+    let columns = virtual_table_columns(prog, select, order_by, &schema)?;
+    prog.emit(Instruction::StatusRows {
+        columns: columns.clone(),
+        sorter: matches!(target, RowTarget::Sorter),
+    });
+    Ok(columns
+        .iter()
+        .map(|&idx| Some(schema.columns[idx].col_type.clone()))
+        .collect())
+}
+
+/// Positions of the columns a SELECT of a virtual table projects, setting
+/// the program's result columns. Its rows aren't stored anywhere that a
+/// scan could filter or sort, so only plain column lists are supported.
+fn virtual_table_columns(
+    prog: &mut Program,
+    select: &ast::Select,
+    order_by: Option<&ast::OrderBy>,
+    schema: &TableSchema,
+) -> LunarisResult<Vec<usize>> {
+    let table = &schema.table_name;
+    if select.selection.is_some() || order_by.is_some() {
+        return Err(LunarisError::Compile(format!(
+            "{table} does not support WHERE or ORDER BY"
+        )));
+    }
+
+    let scope = Scope::single(schema, None);
+    let projected_columns = parse_column_projection(&select.projection, &scope)?;
+    let columns = projected_columns
+        .iter()
+        .map(|(_, projected)| match projected {
+            Projected::Column(col) => Ok(col.index),
+            Projected::Expr(expr) => Err(LunarisError::Compile(format!(
+                "{table} only supports column names, got: {expr}"
+            ))),
+        })
+        .collect::<LunarisResult<Vec<_>>>()?;
+
+    set_result_columns(prog, &projected_columns, &scope);
+    Ok(columns)
+}
+
+fn compile_delete(delete: &ast::Delete, catalog: &Catalog) -> LunarisResult<Program> {
+    let tables = match &delete.from {
+        FromTable::WithFromKeyword(t) | FromTable::WithoutKeyword(t) => t,
+    };
+    if tables.len() != 1 {
+        return Err(LunarisError::Compile(
+            "DELETE requires exactly one table".into(),
+        ));
+    }
+    let (table_name, alias) = match &tables[0].relation {
+        ast::TableFactor::Table { name, alias, .. } => (
+            name.to_string(),
+            alias.as_ref().map(|a| a.name.value.as_str()),
+        ),
+        _ => return Err(LunarisError::Compile("only table names in FROM".into())),
+    };
+
+    // without a filter every row goes, which doesn't need a scan unless
+    // the rows are returned
+    if delete.selection.is_none() && delete.returning.is_none() {
+        return compile_truncate(&table_name, catalog);
+    }
+
+    let schema = catalog.get_schema(&table_name)?;
+    let scope = Scope::single(&schema, alias);
+    let returning = match &delete.returning {
+        Some(items) => parse_column_projection(items, &scope)?,
+        None => Vec::new(),
+    };
+    let mut prog = Program::new();
+    set_result_columns(&mut prog, &returning, &scope);
+    let start = prog.alloc_registers(returning.len());
+    emit_write_loop(
+        &mut prog,
+        &table_name,
+        delete.selection.as_ref(),
+        &scope,
+        |prog| {
+            // the row is read before it is gone
+            if !returning.is_empty() {
+                for (i, (_, projected)) in returning.iter().enumerate() {
+                    emit_projected(prog, projected, start + i, &scope)?;
+                }
+                emit_result_row(prog, RowTarget::Result, start, returning.len());
+            }
+            prog.emit(Instruction::DeleteRow { cursor: 0 });
+            Ok(())
+        },
+    )?;
+    Ok(prog)
+}
+
+fn compile_update(
+    table: &ast::TableWithJoins,
+    assignments: &[ast::Assignment],
+    selection: Option<&Expr>,
+    catalog: &Catalog,
+) -> LunarisResult<Program> {
+    let (table_name, alias) = match &table.relation {
+        ast::TableFactor::Table { name, alias, .. } if table.joins.is_empty() => (
+            name.to_string(),
+            alias.as_ref().map(|a| a.name.value.as_str()),
+        ),
+        _ => {
+            return Err(LunarisError::Compile(
+                "UPDATE requires exactly one table".into(),
+            ));
+        }
+    };
+    let schema = catalog.get_schema(&table_name)?;
+    let scope = Scope::single(&schema, alias);
+
+    // the new value of every assigned column, by position
+    let mut values: Vec<Option<&Expr>> = vec![None; schema.columns.len()];
+    for assignment in assignments {
+        let ast::AssignmentTarget::ColumnName(name) = &assignment.target else {
+            return Err(LunarisError::Compile(format!(
+                "unsupported assignment: {assignment}"
+            )));
+        };
+        let column = name
+            .0
+            .last()
+            .map(|part| part.to_string())
+            .unwrap_or_default();
+        let idx = schema
+            .find_column(&column)
+            .ok_or_else(|| LunarisError::ColumnNotFound(column.clone()))?;
+        if schema.primary_key == Some(idx) {
+            return Err(LunarisError::Compile(format!(
+                "primary key column '{column}' can't be updated"
+            )));
+        }
+        if values[idx].replace(&assignment.value).is_some() {
+            return Err(LunarisError::Compile(format!(
+                "column '{column}' specified more than once"
+            )));
+        }
+    }
+
+    let mut prog = Program::new();
+    let start = prog.alloc_registers(schema.columns.len());
+    emit_write_loop(&mut prog, &table_name, selection, &scope, |prog| {
+        for (i, (col, value)) in schema.columns.iter().zip(&values).enumerate() {
+            let dest = start + i;
+            match value {
+                // constants are checked against the column up front, like
+                // the VALUES of an INSERT
+                Some(expr) if literal_value(expr).is_ok() => {
+                    emit_column_value(prog, expr, col, dest)?
+                }
+                Some(expr) => emit_operand(prog, expr, dest, &scope)?,
+                None => {
+                    prog.emit(Instruction::ReadColumn {
+                        cursor: 0,
+                        col_index: i,
+                        reg: dest,
+                    });
+                }
+            }
+        }
+        prog.emit(Instruction::CreateRecord {
+            start,
+            count: schema.columns.len(),
+        });
+        prog.emit(Instruction::UpdateRow { cursor: 0 });
+        Ok(())
+    })?;
+    Ok(prog)
+}
+
+/// Visit the rows of `table_name` that match `selection` through cursor 0,
+/// emitting `body` for each one. `body` leaves the cursor on the row, or
+/// like `DeleteRow` on the row after it.
+fn emit_write_loop(
+    prog: &mut Program,
+    table_name: &str,
+    selection: Option<&Expr>,
+    scope: &Scope,
+    body: impl FnOnce(&mut Program) -> LunarisResult<()>,
+) -> LunarisResult<()> {
+    let init_addr = prog.emit(Instruction::Init { target: 0 });
+    prog.emit(Instruction::Halt);
+    let start = prog.current_addr();
+    prog.update_target(init_addr, start);
+
+    prog.emit(Instruction::OpenReadWriteCursor {
+        cursor: 0,
+        table: table_name.to_string(),
+    });
+    // a bound on the key saves the scan up to it, like in SELECT
+    let seek = selection.and_then(|where_expr| primary_key_seek(where_expr, scope));
+    let rewind_addr = match seek {
+        Some(seek) => emit_seek(prog, seek),
+        None => prog.emit(Instruction::RewindCursor {
+            cursor: 0,
+            empty_target: 0,
+        }),
+    };
+
+    let loop_top = prog.current_addr();
+
+    // WHERE — skip non-matching rows
+    let skips = match selection {
+        Some(where_expr) => emit_where_skip(prog, where_expr, scope)?,
+        None => Vec::new(),
+    };
+
+    body(prog)?;
+
+    let next_addr = if let Some(Seek::Eq(_)) = seek {
+        prog.emit(Instruction::Goto { target: 0 })
+    } else {
+        prog.emit(Instruction::CursorAdvance {
+            cursor: 0,
+            loop_target: loop_top,
+        })
+    };
+
+    let after_loop = prog.current_addr();
+    if let Some(Seek::Eq(_)) = seek {
+        prog.update_target(next_addr, after_loop);
+    }
+    for skip_addr in skips {
+        prog.update_target(skip_addr, next_addr);
+    }
+    prog.update_target(rewind_addr, after_loop);
+
+    prog.emit(Instruction::CloseCursor { cursor: 0 });
+    prog.emit(Instruction::Halt);
+    Ok(())
+}
+
+fn compile_truncate(table_name: &str, catalog: &Catalog) -> LunarisResult<Program> {
+    catalog.get_schema(table_name)?;
+    let mut prog = Program::new();
+    let init_addr = prog.emit(Instruction::Init { target: 0 });
+    prog.emit(Instruction::Halt);
+    let body = prog.current_addr();
+    prog.update_target(init_addr, body);
+
+    prog.emit(Instruction::TruncateTable {
+        table: table_name.to_string(),
+    });
+    prog.emit(Instruction::Halt);
+    Ok(prog)
+}
+
+/// The tables whose columns the expressions of a statement can refer to,
+/// each read through its own cursor.
+#[derive(Default)]
+struct Scope<'a> {
+    tables: Vec<ScopeTable<'a>>,
+}
+
+#[derive(Clone)]
+struct ScopeTable<'a> {
+    schema: &'a TableSchema,
+    /// Name given to the table in FROM, if any. Either it or the table name
+    /// can qualify a column.
+    alias: Option<&'a str>,
+    cursor: i32,
+}
+
+impl ScopeTable<'_> {
+    fn is_named(&self, name: &str) -> bool {
+        self.schema.table_name.eq_ignore_ascii_case(name)
+            || self.alias.is_some_and(|a| a.eq_ignore_ascii_case(name))
+    }
+}
+
+/// A column of one of the tables in a scope.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ColumnRef {
+    cursor: i32,
+    index: usize,
+}
+
+impl<'a> Scope<'a> {
+    /// A single table, read through cursor 0.
+    fn single(schema: &'a TableSchema, alias: Option<&'a str>) -> Self {
+        Self {
+            tables: vec![ScopeTable {
+                schema,
+                alias,
+                cursor: 0,
+            }],
+        }
+    }
+
+    /// Resolve `expr` if it is a column reference, plain or qualified with
+    /// its table.
+    fn resolve_expr(&self, expr: &Expr) -> Option<LunarisResult<ColumnRef>> {
+        match expr {
+            Expr::Identifier(ident) => Some(self.resolve(&ident.value)),
+            Expr::CompoundIdentifier(parts) => Some(match parts.as_slice() {
+                [table, column] => self.resolve_in(&table.value, &column.value),
+                _ => Err(LunarisError::Compile(format!(
+                    "unsupported column reference: {expr}"
+                ))),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Find the column `name` of the table called `table`.
+    fn resolve_in(&self, table: &str, name: &str) -> LunarisResult<ColumnRef> {
+        let Some(table) = self.tables.iter().find(|t| t.is_named(table)) else {
+            return Err(LunarisError::Compile(format!(
+                "table '{table}' is not in FROM"
+            )));
+        };
+        let index = table.schema.find_column(name).ok_or_else(|| {
+            LunarisError::ColumnNotFound(format!("{}.{name}", table.schema.table_name))
+        })?;
+        Ok(ColumnRef {
+            cursor: table.cursor,
+            index,
+        })
+    }
+
+    /// The primary key of the first table, the one scanned by cursor 0.
+    fn primary_key(&self) -> Option<ColumnRef> {
+        let table = self.tables.first()?;
+        Some(ColumnRef {
+            cursor: table.cursor,
+            index: table.schema.primary_key?,
+        })
+    }
+
+    /// Find the column `name`. It must belong to exactly one of the tables.
+    fn resolve(&self, name: &str) -> LunarisResult<ColumnRef> {
+        let mut found = self.tables.iter().filter_map(|table| {
+            let index = table.schema.find_column(name)?;
+            Some(ColumnRef {
+                cursor: table.cursor,
+                index,
+            })
+        });
+        match (found.next(), found.next()) {
+            (Some(col), None) => Ok(col),
+            (Some(_), Some(_)) => Err(LunarisError::AmbiguousColumn(name.to_string())),
+            (None, _) => Err(LunarisError::ColumnNotFound(name.to_string())),
+        }
+    }
+
+    fn column(&self, col: ColumnRef) -> &'a ColumnDef {
+        let table = self
+            .tables
+            .iter()
+            .find(|table| table.cursor == col.cursor)
+            .expect("column of a table in scope");
+        &table.schema.columns[col.index]
+    }
+}
 
-    let loop_top = prog.current_addr();
+/// A single result column of a SELECT.
+enum Projected<'a> {
+    /// A table column, copied as stored.
+    Column(ColumnRef),
+    /// Any other expression, evaluated for every row.
+    Expr(&'a Expr),
+}
 
-    // WHERE — skip non-matching rows
-    let next_placeholder = if let Some(where_expr) = &delete.selection {
-        Some(emit_where_skip(&mut prog, where_expr, &schema)?)
-    } else {
-        None
-    };
+/// Name the program's result columns after `projected` and give them the
+/// types the projections are known to have.
+fn set_result_columns(prog: &mut Program, projected: &[(String, Projected)], scope: &Scope) {
+    prog.result_columns = projected.iter().map(|(name, _)| name.clone()).collect();
+    prog.result_types = projected
+        .iter()
+        .map(|(_, projected)| projected_type(projected, scope))
+        .collect();
+}
 
-    prog.emit(Instruction::DeleteRow { cursor: 0 });
+fn projected_type(projected: &Projected, scope: &Scope) -> ValueType {
+    match projected {
+        Projected::Column(col) => scope.column(*col).col_type.value_type(),
+        Projected::Expr(expr) => expr_type(expr, scope),
+    }
+}
 
-    let next_addr = prog.emit(Instruction::CursorAdvance {
-        cursor: 0,
-        loop_target: loop_top,
-    });
+/// Type of the values `expr` evaluates to, following the rules of the
+/// instructions computing it. `Unknown` if they depend on the row, as for
+/// NULL or arithmetic on text.
+fn expr_type(expr: &Expr, scope: &Scope) -> ValueType {
+    if let Some(col) = scope.resolve_expr(expr) {
+        return match col {
+            Ok(col) => scope.column(col).col_type.value_type(),
+            Err(_) => ValueType::Unknown,
+        };
+    }
+    match expr {
+        Expr::Nested(inner) => expr_type(inner, scope),
+        Expr::Value(_) => match literal_value(expr) {
+            Ok(Value::Integer(_)) => ValueType::Integer,
+            Ok(Value::Float(_)) => ValueType::Float,
+            Ok(Value::Boolean(_)) => ValueType::Boolean,
+            Ok(Value::Text(_)) => ValueType::Text,
+            Ok(Value::Blob(_)) => ValueType::Blob,
+            Ok(Value::Null) | Err(_) => ValueType::Unknown,
+        },
+        Expr::UnaryOp {
+            op: UnaryOperator::Minus,
+            expr: operand,
+        } => arithmetic_type(ValueType::Integer, expr_type(operand, scope)),
+        Expr::Function(func) => match func.name.to_string().to_uppercase().as_str() {
+            "UPPER" | "LOWER" => ValueType::Text,
+            "LENGTH" => ValueType::Integer,
+            "COALESCE" => function_args(func)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|arg| expr_type(arg, scope))
+                .find(|t| *t != ValueType::Unknown)
+                .unwrap_or(ValueType::Unknown),
+            _ => ValueType::Unknown,
+        },
+        Expr::BinaryOp { left, op, right } => match op {
+            BinaryOperator::StringConcat => ValueType::Text,
+            BinaryOperator::Plus
+            | BinaryOperator::Minus
+            | BinaryOperator::Multiply
+            | BinaryOperator::Divide
+            | BinaryOperator::Modulo => {
+                arithmetic_type(expr_type(left, scope), expr_type(right, scope))
+            }
+            _ => ValueType::Unknown,
+        },
+        _ => ValueType::Unknown,
+    }
+}
 
-    let after_loop = prog.current_addr();
-    if let Some(skip_addr) = next_placeholder {
-        prog.update_target(skip_addr, next_addr);
+// Two integers give an integer and a float on either side a float.
+// Timestamps are computed with as the milliseconds they hold.
+fn arithmetic_type(left: ValueType, right: ValueType) -> ValueType {
+    let number = |t| match t {
+        ValueType::Timestamp => ValueType::Integer,
+        other => other,
+    };
+    match (number(left), number(right)) {
+        (ValueType::Integer, ValueType::Integer) => ValueType::Integer,
+        (ValueType::Integer | ValueType::Float, ValueType::Integer | ValueType::Float) => {
+            ValueType::Float
+        }
+        _ => ValueType::Unknown,
     }
-    prog.update_target(rewind_addr, after_loop);
+}
 
-    prog.emit(Instruction::CloseCursor { cursor: 0 });
-    prog.emit(Instruction::Halt);
-    Ok(prog)
+/// The result column of `ORDER BY <n>`, counting from 1, if `expr` is a
+/// number.
+fn result_position(expr: &Expr, columns: usize) -> Option<LunarisResult<usize>> {
+    let Expr::Value(val) = expr else {
+        return None;
+    };
+    let SqlValue::Number(n, _) = &val.value else {
+        return None;
+    };
+    Some(match n.parse::<usize>() {
+        Ok(position) if (1..=columns).contains(&position) => Ok(position - 1),
+        _ => Err(LunarisError::Compile(format!(
+            "ORDER BY position {n} is not between 1 and {columns}"
+        ))),
+    })
 }
 
-/// Resolve `SELECT <column1>, <column2>, ...` into (name, column_index) pairs.
-fn parse_column_projection(
-    projection: &[SelectItem],
-    schema: &TableSchema,
-) -> LunarisResult<Vec<(String, usize)>> {
+/// Load a projected value into `reg`. Timestamps are shown as text.
+fn emit_projected(
+    prog: &mut Program,
+    projected: &Projected,
+    reg: usize,
+    scope: &Scope,
+) -> LunarisResult<()> {
+    match projected {
+        Projected::Column(col) => {
+            prog.emit(Instruction::ReadColumn {
+                cursor: col.cursor,
+                col_index: col.index,
+                reg,
+            });
+            if scope.column(*col).col_type == ColumnType::Timestamp {
+                prog.emit(Instruction::FormatTimestamp { reg });
+            }
+            Ok(())
+        }
+        Projected::Expr(expr) => emit_operand(prog, expr, reg, scope),
+    }
+}
+
+/// Resolve `SELECT <expr1>, <expr2>, ...` into (name, projected) pairs. Plain
+/// columns keep their names, other expressions are named after their text
+/// unless given an alias.
+fn parse_column_projection<'a>(
+    projection: &'a [SelectItem],
+    scope: &Scope,
+) -> LunarisResult<Vec<(String, Projected<'a>)>> {
     let mut result = Vec::new();
     for item in projection {
         match item {
             SelectItem::Wildcard(_) => {
-                for (i, col) in schema.columns.iter().enumerate() {
-                    result.push((col.name.clone(), i));
+                for table in &scope.tables {
+                    for (index, col) in table.schema.columns.iter().enumerate() {
+                        let col_ref = ColumnRef {
+                            cursor: table.cursor,
+                            index,
+                        };
+                        result.push((col.name.clone(), Projected::Column(col_ref)));
+                    }
                 }
             }
-            SelectItem::UnnamedExpr(Expr::Identifier(ident)) => {
-                let name = &ident.value;
-                let idx = schema
-                    .find_column(name)
-                    .ok_or_else(|| LunarisError::ColumnNotFound(name.clone()))?;
-                result.push((name.clone(), idx));
+            // qualified columns are named after the column alone
+            SelectItem::UnnamedExpr(expr @ (Expr::Identifier(_) | Expr::CompoundIdentifier(_))) => {
+                let name = match expr {
+                    Expr::CompoundIdentifier(parts) => parts.last().map(|p| p.value.clone()),
+                    _ => None,
+                };
+                let col = scope.resolve_expr(expr).expect("a column reference")?;
+                result.push((
+                    name.unwrap_or_else(|| expr.to_string()),
+                    Projected::Column(col),
+                ));
+            }
+            SelectItem::UnnamedExpr(expr) => {
+                result.push((expr.to_string(), Projected::Expr(expr)));
+            }
+            SelectItem::ExprWithAlias { expr, alias } => {
+                let projected = match scope.resolve_expr(expr) {
+                    Some(col) => Projected::Column(col?),
+                    None => Projected::Expr(expr),
+                };
+                result.push((alias.value.clone(), projected));
             }
             other => {
                 return Err(LunarisError::Compile(format!(
@@ -273,104 +2453,153 @@ fn parse_column_type(dt: &ast::DataType) -> LunarisResult<ColumnType> {
         }
         ast::DataType::Boolean => Ok(ColumnType::Boolean),
         ast::DataType::Varchar(len_opt) => {
+            // like in PostgreSQL, VARCHAR without a length is unbounded
             let Some(IntegerLength { length, .. }) = len_opt.as_ref() else {
-                return Err(LunarisError::Compile("VARCHAR requires a length".into()));
+                return Ok(ColumnType::Text);
             };
 
-            if *length >= CELL_AREA_SIZE as u64 {
-                return Err(LunarisError::Compile(
-                    "VARCHAR too big - record must fit in a single page".into(),
-                ));
+            if *length > u16::MAX as u64 {
+                return Err(LunarisError::Compile(format!(
+                    "VARCHAR length must be at most {}",
+                    u16::MAX
+                )));
             }
 
             Ok(ColumnType::Varchar(*length as u16))
         }
-        ast::DataType::Text => Ok(ColumnType::Varchar(255)),
+        ast::DataType::Text => Ok(ColumnType::Text),
+        ast::DataType::Blob(len) | ast::DataType::Binary(len) => {
+            let length = len.unwrap_or(255);
+            if length > u16::MAX as u64 {
+                return Err(LunarisError::Compile(format!(
+                    "BLOB length must be at most {}",
+                    u16::MAX
+                )));
+            }
+            Ok(ColumnType::Blob(length as u16))
+        }
+        ast::DataType::Bytea => Ok(ColumnType::Blob(255)),
+        ast::DataType::Timestamp(..) | ast::DataType::Datetime(_) | ast::DataType::Date => {
+            Ok(ColumnType::Timestamp)
+        }
         _ => Err(LunarisError::Compile(format!("unsupported type: {dt}"))),
     }
 }
 
-fn emit_where_skip(prog: &mut Program, expr: &Expr, schema: &TableSchema) -> LunarisResult<usize> {
-    match expr {
-        Expr::BinaryOp { left, op, right } => match op {
-            BinaryOperator::And => {
-                let skip1 = emit_where_skip(prog, left, schema)?;
-                let skip2 = emit_where_skip(prog, right, schema)?;
-                let goto_addr = prog.emit(Instruction::Goto { target: 0 });
-                prog.update_target(skip1, goto_addr);
-                prog.update_target(skip2, goto_addr);
-                Ok(goto_addr)
-            }
-            BinaryOperator::Or => {
-                let true_check = emit_where_pass(prog, left, schema)?;
-                let skip2 = emit_where_skip(prog, right, schema)?;
-                let body_start = prog.current_addr();
-                prog.update_target(true_check, body_start);
-                Ok(skip2)
-            }
-            _ => emit_inversed_conditional(prog, left, op, right, schema),
-        },
-        _ => Err(LunarisError::Compile(format!(
-            "unsupported WHERE expression: {expr}"
-        ))),
-    }
+/// Emit the jumps that skip a row failing the WHERE clause, i.e. one for
+/// which it is FALSE or UNKNOWN. Returns the jumps to patch with the address
+/// of the next row.
+fn emit_where_skip(prog: &mut Program, expr: &Expr, scope: &Scope) -> LunarisResult<Vec<usize>> {
+    emit_condition_jump(prog, expr, false, true, scope)
 }
 
-fn emit_where_pass(prog: &mut Program, expr: &Expr, schema: &TableSchema) -> LunarisResult<usize> {
+/// Emit code that jumps if `expr` evaluates to `jump_on`, or to UNKNOWN when
+/// `on_unknown` is set, and falls through otherwise. Returns the addresses of
+/// the emitted jumps, whose targets the caller patches.
+fn emit_condition_jump(
+    prog: &mut Program,
+    expr: &Expr,
+    jump_on: bool,
+    on_unknown: bool,
+    scope: &Scope,
+) -> LunarisResult<Vec<usize>> {
     match expr {
-        Expr::BinaryOp { left, op, right }
-            if !matches!(op, BinaryOperator::And | BinaryOperator::Or) =>
-        {
-            emit_comparison_jump(prog, left, op, right, schema)
+        Expr::Nested(inner) => emit_condition_jump(prog, inner, jump_on, on_unknown, scope),
+        // NOT swaps TRUE and FALSE and keeps UNKNOWN
+        Expr::UnaryOp {
+            op: UnaryOperator::Not,
+            expr,
+        } => emit_condition_jump(prog, expr, !jump_on, on_unknown, scope),
+        Expr::IsNull(operand) | Expr::IsNotNull(operand) => {
+            let reg = 1;
+            emit_operand(prog, operand, reg, scope)?;
+            let addr = if matches!(expr, Expr::IsNull(_)) == jump_on {
+                prog.emit(Instruction::JumpIfNull { reg, target: 0 })
+            } else {
+                prog.emit(Instruction::JumpIfNotNull { reg, target: 0 })
+            };
+            Ok(vec![addr])
         }
+        // never UNKNOWN: NULL IS TRUE and NULL IS FALSE are both FALSE
+        Expr::IsTrue(operand)
+        | Expr::IsNotTrue(operand)
+        | Expr::IsFalse(operand)
+        | Expr::IsNotFalse(operand) => {
+            let (reg, bool_reg) = (1, 2);
+            let value = matches!(expr, Expr::IsTrue(_) | Expr::IsNotTrue(_));
+            let negated = matches!(expr, Expr::IsNotTrue(_) | Expr::IsNotFalse(_));
+            emit_operand(prog, operand, reg, scope)?;
+            prog.emit(Instruction::Bool {
+                value,
+                reg: bool_reg,
+            });
+            let addr = if jump_on != negated {
+                prog.emit(Instruction::Jeq {
+                    left: reg,
+                    right: bool_reg,
+                    target: 0,
+                    null_jump: false,
+                })
+            } else {
+                prog.emit(Instruction::Jne {
+                    left: reg,
+                    right: bool_reg,
+                    target: 0,
+                    null_jump: true,
+                })
+            };
+            Ok(vec![addr])
+        }
+        Expr::BinaryOp { left, op, right } => match op {
+            // AND is TRUE only if both sides are, OR is FALSE only if both
+            // sides are. In those cases the first side must be checked
+            // without jumping away, e.g. `a AND b` jumps on TRUE by skipping
+            // the check of `b` when `a` is not TRUE.
+            BinaryOperator::And | BinaryOperator::Or => {
+                let is_and = matches!(op, BinaryOperator::And);
+                if jump_on != is_and {
+                    let mut jumps = emit_condition_jump(prog, left, jump_on, on_unknown, scope)?;
+                    jumps.extend(emit_condition_jump(
+                        prog, right, jump_on, on_unknown, scope,
+                    )?);
+                    return Ok(jumps);
+                }
+                let skips = emit_condition_jump(prog, left, !jump_on, !on_unknown, scope)?;
+                let jumps = emit_condition_jump(prog, right, jump_on, on_unknown, scope)?;
+                let after = prog.current_addr();
+                for addr in skips {
+                    prog.update_target(addr, after);
+                }
+                Ok(jumps)
+            }
+            _ => {
+                let op = if jump_on {
+                    op.clone()
+                } else {
+                    negate_comparison(op)?
+                };
+                let addr = emit_comparison_jump(prog, left, &op, right, on_unknown, scope)?;
+                Ok(vec![addr])
+            }
+        },
         _ => Err(LunarisError::Compile(format!(
-            "unsupported OR sub-expression: {expr}"
+            "unsupported WHERE expression: {expr}"
         ))),
     }
 }
 
-fn emit_inversed_conditional(
-    prog: &mut Program,
-    left: &Expr,
-    op: &BinaryOperator,
-    right: &Expr,
-    schema: &TableSchema,
-) -> LunarisResult<usize> {
-    let (col_reg, lit_reg) = emit_comparison_operands(prog, left, right, schema)?;
-
-    match op {
-        BinaryOperator::Eq => Ok(prog.emit(Instruction::Jne {
-            left: col_reg,
-            right: lit_reg,
-            target: 0,
-        })),
-        BinaryOperator::NotEq => Ok(prog.emit(Instruction::Jeq {
-            left: col_reg,
-            right: lit_reg,
-            target: 0,
-        })),
-        BinaryOperator::Gt => Ok(prog.emit(Instruction::Jle {
-            left: col_reg,
-            right: lit_reg,
-            target: 0,
-        })),
-        BinaryOperator::GtEq => Ok(prog.emit(Instruction::Jlt {
-            left: col_reg,
-            right: lit_reg,
-            target: 0,
-        })),
-        BinaryOperator::Lt => Ok(prog.emit(Instruction::Jge {
-            left: col_reg,
-            right: lit_reg,
-            target: 0,
-        })),
-        BinaryOperator::LtEq => Ok(prog.emit(Instruction::Jgt {
-            left: col_reg,
-            right: lit_reg,
-            target: 0,
-        })),
-        _ => Err(LunarisError::Compile(format!("unsupported operator: {op}"))),
-    }
+/// The comparison that holds exactly when `op` doesn't, for operands that
+/// aren't NULL.
+fn negate_comparison(op: &BinaryOperator) -> LunarisResult<BinaryOperator> {
+    Ok(match op {
+        BinaryOperator::Eq => BinaryOperator::NotEq,
+        BinaryOperator::NotEq => BinaryOperator::Eq,
+        BinaryOperator::Gt => BinaryOperator::LtEq,
+        BinaryOperator::GtEq => BinaryOperator::Lt,
+        BinaryOperator::Lt => BinaryOperator::GtEq,
+        BinaryOperator::LtEq => BinaryOperator::Gt,
+        _ => return Err(LunarisError::Compile(format!("unsupported operator: {op}"))),
+    })
 }
 
 fn emit_comparison_jump(
@@ -378,40 +2607,47 @@ fn emit_comparison_jump(
     left: &Expr,
     op: &BinaryOperator,
     right: &Expr,
-    schema: &TableSchema,
+    null_jump: bool,
+    scope: &Scope,
 ) -> LunarisResult<usize> {
-    let (col_reg, lit_reg) = emit_comparison_operands(prog, left, right, schema)?;
+    let (col_reg, lit_reg) = emit_comparison_operands(prog, left, right, scope)?;
 
     let addr = match op {
         BinaryOperator::Eq => prog.emit(Instruction::Jeq {
             left: col_reg,
             right: lit_reg,
             target: 0,
+            null_jump,
         }),
         BinaryOperator::NotEq => prog.emit(Instruction::Jne {
             left: col_reg,
             right: lit_reg,
             target: 0,
+            null_jump,
         }),
         BinaryOperator::Gt => prog.emit(Instruction::Jgt {
             left: col_reg,
             right: lit_reg,
             target: 0,
+            null_jump,
         }),
         BinaryOperator::GtEq => prog.emit(Instruction::Jge {
             left: col_reg,
             right: lit_reg,
             target: 0,
+            null_jump,
         }),
         BinaryOperator::Lt => prog.emit(Instruction::Jlt {
             left: col_reg,
             right: lit_reg,
             target: 0,
+            null_jump,
         }),
         BinaryOperator::LtEq => prog.emit(Instruction::Jle {
             left: col_reg,
             right: lit_reg,
             target: 0,
+            null_jump,
         }),
         _ => return Err(LunarisError::Compile(format!("unsupported operator: {op}"))),
     };
@@ -423,55 +2659,153 @@ fn emit_comparison_operands(
     prog: &mut Program,
     left: &Expr,
     right: &Expr,
-    schema: &TableSchema,
+    scope: &Scope,
 ) -> LunarisResult<(usize, usize)> {
     // Registers 1-16 are used for comparison operands
     let col_reg = 1;
     let lit_reg = 2;
 
-    emit_operand(prog, left, col_reg, schema)?;
-    emit_operand(prog, right, lit_reg, schema)?;
+    let collation = comparison_collation(left, right, scope)?;
+    let (left, right) = (uncollated(left), uncollated(right));
+    emit_compared_operand(prog, left, right, col_reg, scope)?;
+    emit_compared_operand(prog, right, left, lit_reg, scope)?;
+    if collation == Collation::NoCase {
+        prog.emit(Instruction::FoldCase { reg: col_reg });
+        prog.emit(Instruction::FoldCase { reg: lit_reg });
+    }
     Ok((col_reg, lit_reg))
 }
 
-fn emit_operand(
+/// Collation of a comparison: an explicit `COLLATE` on either side, else
+/// NOCASE if either side is a NOCASE column.
+fn comparison_collation(left: &Expr, right: &Expr, scope: &Scope) -> LunarisResult<Collation> {
+    for side in [left, right] {
+        if let Expr::Collate { collation, .. } = side {
+            return parse_collation(collation);
+        }
+    }
+    let nocase = [left, right].into_iter().any(|side| {
+        matches!(scope.resolve_expr(side), Some(Ok(col))
+            if scope.column(col).collation == Collation::NoCase)
+    });
+    Ok(match nocase {
+        true => Collation::NoCase,
+        false => Collation::Binary,
+    })
+}
+
+fn uncollated(expr: &Expr) -> &Expr {
+    match expr {
+        Expr::Collate { expr, .. } => uncollated(expr),
+        other => other,
+    }
+}
+
+/// Load one side of a comparison. A constant compared with a TIMESTAMP
+/// column is converted to the column's representation first, so that
+/// `created_at > '2024-01-01T00:00:00Z'` compares epoch milliseconds.
+fn emit_compared_operand(
     prog: &mut Program,
     expr: &Expr,
+    other: &Expr,
     dest: usize,
-    schema: &TableSchema,
+    scope: &Scope,
 ) -> LunarisResult<()> {
+    let timestamp_col = scope
+        .resolve_expr(other)
+        .and_then(Result::ok)
+        .map(|col| scope.column(col))
+        .filter(|col| col.col_type == ColumnType::Timestamp);
+    match timestamp_col {
+        Some(col) if scope.resolve_expr(expr).is_none() => {
+            let value = coerce_literal(literal_value(expr)?, col)?;
+            prog.emit(value_instruction(value, dest));
+            Ok(())
+        }
+        _ => emit_operand(prog, expr, dest, scope),
+    }
+}
+
+fn emit_operand(prog: &mut Program, expr: &Expr, dest: usize, scope: &Scope) -> LunarisResult<()> {
     match expr {
-        Expr::Identifier(ident) => {
-            let col_idx = schema
-                .find_column(&ident.value)
-                .ok_or_else(|| LunarisError::ColumnNotFound(ident.value.clone()))?;
+        Expr::Identifier(_) | Expr::CompoundIdentifier(_) => {
+            let col = scope.resolve_expr(expr).expect("a column reference")?;
             prog.emit(Instruction::ReadColumn {
-                cursor: 0,
-                col_index: col_idx,
+                cursor: col.cursor,
+                col_index: col.index,
                 reg: dest,
             });
         }
-        Expr::Value(val) => {
-            emit_literal(prog, &val.value, dest)?;
+        Expr::Value(_) => {
+            prog.emit(value_instruction(literal_value(expr)?, dest));
         }
-        Expr::UnaryOp {
-            op: UnaryOperator::Minus,
-            expr,
-        } => {
-            let Expr::Value(val) = expr.as_ref() else {
-                return Err(LunarisError::Compile(format!(
-                    "unsupported expression: -{expr}"
-                )));
-            };
-
-            let SqlValue::Number(n, _) = &val.value else {
-                return Err(LunarisError::Compile(format!(
-                    "unsupported expression: -{expr}"
-                )));
+        Expr::Nested(inner) => emit_operand(prog, inner, dest, scope)?,
+        Expr::Function(func) => emit_function(prog, func, dest, scope)?,
+        Expr::BinaryOp { left, op, right } => {
+            let right_reg = prog.alloc_registers(1);
+            let instr = match op {
+                BinaryOperator::StringConcat => Instruction::Concat {
+                    left: dest,
+                    right: right_reg,
+                    dest,
+                },
+                BinaryOperator::Plus => Instruction::Add {
+                    left: dest,
+                    right: right_reg,
+                    dest,
+                },
+                BinaryOperator::Minus => Instruction::Subtract {
+                    left: dest,
+                    right: right_reg,
+                    dest,
+                },
+                BinaryOperator::Multiply => Instruction::Multiply {
+                    left: dest,
+                    right: right_reg,
+                    dest,
+                },
+                BinaryOperator::Divide => Instruction::Divide {
+                    left: dest,
+                    right: right_reg,
+                    dest,
+                },
+                BinaryOperator::Modulo => Instruction::Remainder {
+                    left: dest,
+                    right: right_reg,
+                    dest,
+                },
+                _ => {
+                    return Err(LunarisError::Compile(format!(
+                        "unsupported expression: {expr}"
+                    )));
+                }
             };
-
-            prog.emit(parse_number(n, dest)?);
+            emit_operand(prog, left, dest, scope)?;
+            emit_operand(prog, right, right_reg, scope)?;
+            prog.emit(instr);
         }
+        Expr::UnaryOp {
+            op: UnaryOperator::Minus,
+            expr: operand,
+        } => match literal_value(expr) {
+            Ok(value) => {
+                prog.emit(value_instruction(value, dest));
+            }
+            // -x is computed as 0 - x
+            Err(_) => {
+                let operand_reg = prog.alloc_registers(1);
+                prog.emit(Instruction::Integer {
+                    value: 0,
+                    reg: dest,
+                });
+                emit_operand(prog, operand, operand_reg, scope)?;
+                prog.emit(Instruction::Subtract {
+                    left: dest,
+                    right: operand_reg,
+                    dest,
+                });
+            }
+        },
         _ => {
             return Err(LunarisError::Compile(format!(
                 "unsupported expression: {expr}"
@@ -481,74 +2815,363 @@ fn emit_operand(
     Ok(())
 }
 
-fn parse_number(n: &str, dest: usize) -> LunarisResult<Instruction> {
-    if let Ok(i) = n.parse::<i64>() {
-        Ok(Instruction::Integer {
-            value: i,
-            reg: dest,
-        })
-    } else if let Ok(f) = n.parse::<f64>() {
-        Ok(Instruction::Float {
-            value: f,
+/// Evaluate a scalar function call into `dest`.
+fn emit_function(
+    prog: &mut Program,
+    func: &ast::Function,
+    dest: usize,
+    scope: &Scope,
+) -> LunarisResult<()> {
+    let name = func.name.to_string().to_uppercase();
+    let args = function_args(func)?;
+    if name == "COALESCE" {
+        return emit_coalesce(prog, &args, dest, scope);
+    }
+
+    let unary = match name.as_str() {
+        "UPPER" => Instruction::Upper { reg: dest },
+        "LOWER" => Instruction::Lower { reg: dest },
+        "LENGTH" => Instruction::Length { reg: dest },
+        _ => {
+            return Err(LunarisError::Compile(format!(
+                "unsupported function: {name}"
+            )));
+        }
+    };
+    let [arg] = args.as_slice() else {
+        return Err(LunarisError::Compile(format!(
+            "{name} takes 1 argument, got {}",
+            args.len()
+        )));
+    };
+    emit_operand(prog, arg, dest, scope)?;
+    prog.emit(unary);
+    Ok(())
+}
+
+/// Evaluate the arguments into `dest` one after another, stopping at the
+/// first that isn't NULL.
+fn emit_coalesce(
+    prog: &mut Program,
+    args: &[&Expr],
+    dest: usize,
+    scope: &Scope,
+) -> LunarisResult<()> {
+    let Some((last, rest)) = args.split_last() else {
+        return Err(LunarisError::Compile(
+            "COALESCE needs at least one argument".into(),
+        ));
+    };
+
+    let mut jumps = Vec::new();
+    for arg in rest {
+        emit_operand(prog, arg, dest, scope)?;
+        jumps.push(prog.emit(Instruction::JumpIfNotNull {
             reg: dest,
-        })
-    } else {
-        Err(LunarisError::Compile(format!("invalid number: {n}")))
+            target: 0,
+        }));
+    }
+    emit_operand(prog, last, dest, scope)?;
+
+    let end = prog.current_addr();
+    for addr in jumps {
+        prog.update_target(addr, end);
     }
+    Ok(())
 }
 
-fn emit_literal(prog: &mut Program, val: &SqlValue, dest: usize) -> LunarisResult<()> {
-    match val {
-        SqlValue::Number(n, _) => {
-            prog.emit(parse_number(n, dest)?);
-        }
+/// The plain positional arguments of a function call.
+fn function_args(func: &ast::Function) -> LunarisResult<Vec<&Expr>> {
+    let unsupported = || LunarisError::Compile(format!("unsupported function call: {func}"));
+    let ast::FunctionArguments::List(list) = &func.args else {
+        return Err(unsupported());
+    };
+    if list.duplicate_treatment.is_some() || !list.clauses.is_empty() {
+        return Err(unsupported());
+    }
+    list.args
+        .iter()
+        .map(|arg| match arg {
+            ast::FunctionArg::Unnamed(ast::FunctionArgExpr::Expr(expr)) => Ok(expr),
+            _ => Err(unsupported()),
+        })
+        .collect()
+}
 
-        SqlValue::SingleQuotedString(s) | SqlValue::DoubleQuotedString(s) => {
-            prog.emit(Instruction::String {
-                value: s.clone(),
-                reg: dest,
-            });
-        }
+/// Fill in column `idx` of a row the statement leaves it out of. An omitted
+/// INTEGER PRIMARY KEY gets the next free rowid, as in tables without one.
+fn emit_missing_column(
+    prog: &mut Program,
+    schema: &TableSchema,
+    idx: usize,
+    dest: usize,
+) -> LunarisResult<()> {
+    if schema.primary_key == Some(idx) {
+        prog.emit(Instruction::NewRowId {
+            cursor: 0,
+            reg: dest,
+        });
+        return Ok(());
+    }
+    emit_column_default(prog, &schema.columns[idx], dest)
+}
 
-        SqlValue::Boolean(b) => {
-            prog.emit(Instruction::Bool {
-                value: *b,
-                reg: dest,
-            });
+/// Fill in a column omitted from an INSERT: its DEFAULT, else NULL if allowed.
+fn emit_column_default(prog: &mut Program, col: &ColumnDef, dest: usize) -> LunarisResult<()> {
+    match &col.default {
+        Some(value) => {
+            prog.emit(value_instruction(value.clone(), dest));
         }
-
-        SqlValue::Null => {
+        None if col.nullable => {
             prog.emit(Instruction::Null { reg: dest });
         }
-        _ => return Err(LunarisError::Compile(format!("unsupported literal: {val}"))),
+        None => return Err(LunarisError::NullConstraint(col.name.clone())),
     }
     Ok(())
 }
 
-fn emit_expr(prog: &mut Program, expr: &Expr, dest: usize) -> LunarisResult<()> {
+fn value_instruction(value: Value, dest: usize) -> Instruction {
+    match value {
+        Value::Null => Instruction::Null { reg: dest },
+        Value::Integer(value) => Instruction::Integer { value, reg: dest },
+        Value::Float(value) => Instruction::Float { value, reg: dest },
+        Value::Boolean(value) => Instruction::Bool { value, reg: dest },
+        Value::Text(value) => Instruction::String { value, reg: dest },
+        Value::Blob(value) => Instruction::Blob { value, reg: dest },
+    }
+}
+
+/// Evaluate a constant expression such as a DEFAULT clause.
+fn literal_value(expr: &Expr) -> LunarisResult<Value> {
     match expr {
-        Expr::Value(val) => emit_literal(prog, &val.value, dest),
+        Expr::Value(val) => match &val.value {
+            SqlValue::Number(n, _) => number_value(n, false),
+            SqlValue::SingleQuotedString(s) | SqlValue::DoubleQuotedString(s) => {
+                Ok(Value::Text(s.clone()))
+            }
+            SqlValue::Boolean(b) => Ok(Value::Boolean(*b)),
+            SqlValue::HexStringLiteral(hex) => Ok(Value::Blob(parse_hex(hex)?)),
+            SqlValue::Null => Ok(Value::Null),
+            other => Err(LunarisError::Compile(format!(
+                "unsupported literal: {other}"
+            ))),
+        },
         Expr::UnaryOp {
             op: UnaryOperator::Minus,
             expr,
-        } => {
-            let Expr::Value(val) = expr.as_ref() else {
-                return Err(LunarisError::Compile(format!(
+        } => match expr.as_ref() {
+            // the sign belongs to the number, so -9223372036854775808 fits
+            Expr::Value(val) if let SqlValue::Number(n, _) = &val.value => number_value(n, true),
+            _ => match literal_value(expr)? {
+                Value::Integer(i) => i
+                    .checked_neg()
+                    .map(Value::Integer)
+                    .ok_or_else(|| LunarisError::Compile(format!("integer overflow in -{expr}"))),
+                Value::Float(f) => Ok(Value::Float(-f)),
+                _ => Err(LunarisError::Compile(format!(
                     "unsupported expression: -{expr}"
-                )));
-            };
+                ))),
+            },
+        },
+        Expr::Nested(inner) => literal_value(inner),
+        _ => Err(LunarisError::Compile(format!(
+            "expected a constant, got: {expr}"
+        ))),
+    }
+}
 
-            let SqlValue::Number(n, _) = &val.value else {
-                return Err(LunarisError::Compile(format!(
-                    "unsupported expression: -{expr}"
-                )));
-            };
+/// Decode the digits of a hex literal such as `X'deadbeef'`.
+fn parse_hex(hex: &str) -> LunarisResult<Vec<u8>> {
+    value::from_hex(hex)
+        .ok_or_else(|| LunarisError::Compile(format!("invalid hex literal: X'{hex}'")))
+}
 
-            prog.emit(parse_number(n, dest)?);
-            Ok(())
+/// The value of a numeric literal, negated if it follows a minus sign.
+/// Literals that fit in an i64 are integers; anything else, including
+/// fractions, exponents such as `1.5e3` and integers too large for an i64,
+/// is a float.
+fn number_value(n: &str, negative: bool) -> LunarisResult<Value> {
+    let text = match negative {
+        true => format!("-{n}"),
+        false => n.to_string(),
+    };
+    if let Ok(i) = text.parse::<i64>() {
+        Ok(Value::Integer(i))
+    } else if let Ok(f) = text.parse::<f64>() {
+        Ok(Value::Float(f))
+    } else {
+        Err(LunarisError::Compile(format!("invalid number: {n}")))
+    }
+}
+
+/// Check a constant against the column it is destined for, promoting
+/// integers stored into FLOAT columns.
+fn coerce_literal(value: Value, col: &ColumnDef) -> LunarisResult<Value> {
+    match (&col.col_type, value) {
+        (_, Value::Null) => Ok(Value::Null),
+        (ColumnType::Integer, v @ Value::Integer(_)) => Ok(v),
+        (ColumnType::Float, v @ Value::Float(_)) => Ok(v),
+        (ColumnType::Float, Value::Integer(i)) => Ok(Value::Float(i as f64)),
+        (ColumnType::Boolean, v @ Value::Boolean(_)) => Ok(v),
+        (ColumnType::Varchar(_) | ColumnType::Text, v @ Value::Text(_)) => Ok(v),
+        (ColumnType::Blob(_), v @ Value::Blob(_)) => Ok(v),
+        (ColumnType::Timestamp, v @ Value::Integer(_)) => Ok(v),
+        (ColumnType::Timestamp, Value::Text(s)) => timestamp::parse_timestamp(&s)
+            .map(Value::Integer)
+            .ok_or_else(|| LunarisError::TypeMismatch {
+                expected: format!("ISO-8601 timestamp for column '{}'", col.name),
+                got: format!("'{s}'"),
+            }),
+        (col_type, v) => Err(LunarisError::TypeMismatch {
+            expected: format!("{col_type} for column '{}'", col.name),
+            got: format!("{v:?}"),
+        }),
+    }
+}
+
+/// Emit a VALUES entry destined for `col`, checking its type and
+/// nullability against the column so a mismatch is reported before anything
+/// is written. TIMESTAMP columns accept ISO-8601 strings, which are converted
+/// to epoch milliseconds here.
+fn emit_column_value(
+    prog: &mut Program,
+    expr: &Expr,
+    col: &ColumnDef,
+    dest: usize,
+) -> LunarisResult<()> {
+    let value = coerce_literal(literal_value(expr)?, col)?;
+    if value == Value::Null && !col.nullable {
+        return Err(LunarisError::NullConstraint(col.name.clone()));
+    }
+    prog.emit(value_instruction(value, dest));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::parser;
+
+    fn schema(name: &str, columns: &[&str]) -> TableSchema {
+        let columns = columns
+            .iter()
+            .map(|c| ColumnDef::new(c.to_string(), ColumnType::Integer))
+            .collect();
+        TableSchema::new(name.to_string(), columns)
+    }
+
+    #[test]
+    fn test_column_resolution() {
+        let users = schema("users", &["id", "name"]);
+        let orders = schema("orders", &["id", "user_id"]);
+        let scope = Scope {
+            tables: vec![
+                ScopeTable {
+                    schema: &users,
+                    alias: None,
+                    cursor: 0,
+                },
+                ScopeTable {
+                    schema: &orders,
+                    alias: Some("o"),
+                    cursor: 1,
+                },
+            ],
+        };
+
+        assert_eq!(
+            scope.resolve("user_id").unwrap(),
+            ColumnRef {
+                cursor: 1,
+                index: 1
+            }
+        );
+        assert_eq!(scope.column(scope.resolve("name").unwrap()).name, "name");
+        assert!(matches!(
+            scope.resolve("id"),
+            Err(LunarisError::AmbiguousColumn(name)) if name == "id"
+        ));
+        assert!(matches!(
+            scope.resolve("total"),
+            Err(LunarisError::ColumnNotFound(_))
+        ));
+        // a single table is never ambiguous
+        let single = Scope::single(&orders, None);
+        assert_eq!(single.resolve("id").unwrap().index, 0);
+
+        let column = |sql: &str| {
+            let mut parser = sqlparser::parser::Parser::new(&sqlparser::dialect::GenericDialect {})
+                .try_with_sql(sql)
+                .unwrap();
+            parser.parse_expr().unwrap()
+        };
+        let resolve = |sql: &str| scope.resolve_expr(&column(sql)).unwrap();
+        assert_eq!(
+            resolve("users.id").unwrap(),
+            ColumnRef {
+                cursor: 0,
+                index: 0
+            }
+        );
+        assert_eq!(
+            resolve("o.id").unwrap(),
+            ColumnRef {
+                cursor: 1,
+                index: 0
+            }
+        );
+        assert_eq!(
+            resolve("ORDERS.ID").unwrap(),
+            ColumnRef {
+                cursor: 1,
+                index: 0
+            }
+        );
+        assert!(matches!(resolve("items.id"), Err(LunarisError::Compile(_))));
+        assert!(matches!(
+            resolve("users.total"),
+            Err(LunarisError::ColumnNotFound(_))
+        ));
+        assert!(scope.resolve_expr(&column("1 + id")).is_none());
+    }
+
+    #[test]
+    fn test_constant_select() {
+        let dir = std::env::temp_dir()
+            .join("lunaris_test")
+            .join("compile_constant_select");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let catalog = Catalog::open(&dir).unwrap();
+        let program = |sql: &str| compile(&parser::parse_sql(sql).unwrap(), &catalog).unwrap();
+
+        let prog = program("SELECT 1, 'x', -2.5, NULL");
+        assert!(prog.instructions.iter().any(|inst| matches!(
+            inst,
+            Instruction::ConstResultRow { values }
+                if *values == [
+                    Value::Integer(1),
+                    Value::Text("x".into()),
+                    Value::Float(-2.5),
+                    Value::Null,
+                ]
+        )));
+        // something to compute takes the usual way, still without a cursor
+        for sql in ["SELECT 1, 'x'", "SELECT 1 + 1", "SELECT 1 WHERE 1 = 0"] {
+            let prog = program(sql);
+            assert!(
+                !prog.instructions.iter().any(|inst| matches!(
+                    inst,
+                    Instruction::OpenReadCursor { .. } | Instruction::OpenReadWriteCursor { .. }
+                )),
+                "{sql}"
+            );
         }
-        _ => Err(LunarisError::Compile(format!(
-            "unsupported expression: {expr}"
-        ))),
+        assert!(
+            !program("SELECT 1 + 1")
+                .instructions
+                .iter()
+                .any(|inst| matches!(inst, Instruction::ConstResultRow { .. }))
+        );
     }
 }
@@ -1,4 +1,5 @@
 pub mod bytecode;
 pub mod compiler;
 pub mod parser;
+#[allow(clippy::module_inception)]
 pub mod vm;
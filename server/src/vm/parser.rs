@@ -1,20 +1,232 @@
 use sqlparser::ast::Statement;
 use sqlparser::dialect::GenericDialect;
 use sqlparser::parser::Parser;
+use sqlparser::tokenizer::{Token, Tokenizer};
 
 use crate::error::{LunarisError, LunarisResult};
+use lunaris_common::protocol::SourceLocation;
 
 pub fn parse_sql(sql: &str) -> LunarisResult<Statement> {
     let dialect = GenericDialect {};
-    let mut stmts =
-        Parser::parse_sql(&dialect, sql).map_err(|e| LunarisError::Parse(e.to_string()))?;
+    let mut stmts = Parser::parse_sql(&dialect, sql).map_err(|e| parse_error(&e.to_string()))?;
 
     if stmts.is_empty() {
-        return Err(LunarisError::Parse("empty statement".into()));
+        return Err(LunarisError::parse("empty statement"));
     }
     if stmts.len() > 1 {
-        return Err(LunarisError::Parse("only one statement at a time".into()));
+        return Err(LunarisError::parse("only one statement at a time"));
     }
 
     Ok(stmts.remove(0))
 }
+
+/// Split a script into its statements on the semicolons the tokenizer sees,
+/// so semicolons in string literals and comments are left alone. Fragments
+/// holding nothing but whitespace and comments are dropped.
+pub fn split_statements(sql: &str) -> LunarisResult<Vec<String>> {
+    let dialect = GenericDialect {};
+    // keep literals as written so the tokens print back to the original text
+    let tokens = Tokenizer::new(&dialect, sql)
+        .with_unescape(false)
+        .tokenize()
+        .map_err(|e| parse_error(&e.to_string()))?;
+
+    let mut statements = Vec::new();
+    let mut current = String::new();
+    let mut code = false;
+    for token in tokens {
+        match token {
+            Token::SemiColon => {
+                push_statement(&mut statements, &mut current, code);
+                code = false;
+            }
+            token => {
+                code |= !matches!(token, Token::Whitespace(_));
+                current.push_str(&token.to_string());
+            }
+        }
+    }
+    push_statement(&mut statements, &mut current, code);
+    Ok(statements)
+}
+
+/// Whether `sql` holds nothing but whitespace, comments and semicolons.
+/// Text that doesn't tokenize is left to `parse_sql` to report.
+pub fn is_empty_statement(sql: &str) -> bool {
+    let dialect = GenericDialect {};
+    Tokenizer::new(&dialect, sql)
+        .tokenize()
+        .is_ok_and(|tokens| {
+            tokens
+                .iter()
+                .all(|token| matches!(token, Token::Whitespace(_) | Token::SemiColon))
+        })
+}
+
+/// Statements of our own, which sqlparser doesn't know.
+#[derive(Debug, PartialEq)]
+pub enum AdminStatement {
+    /// `BACKUP TO '<path>'`
+    Backup(String),
+    /// `DUMP <table>`
+    Dump(String),
+}
+
+/// Recognise an `AdminStatement`. Anything else is left to `parse_sql`.
+pub fn parse_admin(sql: &str) -> LunarisResult<Option<AdminStatement>> {
+    let dialect = GenericDialect {};
+    let tokens = Tokenizer::new(&dialect, sql)
+        .tokenize()
+        .map_err(|e| parse_error(&e.to_string()))?;
+    let mut tokens = tokens
+        .into_iter()
+        .filter(|token| !matches!(token, Token::Whitespace(_)));
+
+    let Some(Token::Word(first)) = tokens.next() else {
+        return Ok(None);
+    };
+    let (stmt, usage) = match first.value.to_ascii_uppercase().as_str() {
+        "BACKUP" => {
+            let stmt = match (tokens.next(), tokens.next()) {
+                (Some(Token::Word(to)), Some(Token::SingleQuotedString(path)))
+                    if to.value.eq_ignore_ascii_case("TO") =>
+                {
+                    Some(AdminStatement::Backup(path))
+                }
+                _ => None,
+            };
+            (stmt, "BACKUP TO '<path>'")
+        }
+        "DUMP" => {
+            let stmt = match tokens.next() {
+                Some(Token::Word(table)) => Some(AdminStatement::Dump(table.value)),
+                _ => None,
+            };
+            (stmt, "DUMP <table>")
+        }
+        _ => return Ok(None),
+    };
+    let mut rest = tokens.filter(|token| *token != Token::SemiColon);
+    match stmt {
+        Some(stmt) if rest.next().is_none() => Ok(Some(stmt)),
+        _ => Err(LunarisError::parse(format!("expected {usage}"))),
+    }
+}
+
+/// sqlparser only reports the position of an error as part of its message,
+/// as a trailing ` at Line: <n>, Column: <n>`. Move it into the location.
+fn parse_error(text: &str) -> LunarisError {
+    let text = text.strip_prefix("sql parser error: ").unwrap_or(text);
+    let location = text
+        .rsplit_once(" at Line: ")
+        .and_then(|(message, position)| {
+            let (line, column) = position.split_once(", Column: ")?;
+            let location = SourceLocation {
+                line: line.parse().ok()?,
+                column: column.parse().ok()?,
+            };
+            Some((message, location))
+        });
+    match location {
+        Some((message, location)) => LunarisError::Parse {
+            message: message.to_string(),
+            location: Some(location),
+        },
+        None => LunarisError::parse(text),
+    }
+}
+
+fn push_statement(statements: &mut Vec<String>, current: &mut String, code: bool) {
+    if code {
+        statements.push(current.trim().to_string());
+    }
+    current.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_statements() {
+        let script = "CREATE TABLE t (s VARCHAR(8));\n\
+                      INSERT INTO t VALUES ('a;b'), ('it''s;');\n  \n;  \t\n";
+        assert_eq!(
+            split_statements(script).unwrap(),
+            vec![
+                "CREATE TABLE t (s VARCHAR(8))",
+                "INSERT INTO t VALUES ('a;b'), ('it''s;')",
+            ]
+        );
+
+        // the last statement doesn't need a semicolon
+        assert_eq!(
+            split_statements("SELECT 1; -- done; really\nSELECT 2").unwrap(),
+            vec!["SELECT 1", "-- done; really\nSELECT 2"]
+        );
+        assert!(split_statements("  \n ").unwrap().is_empty());
+        assert_eq!(
+            split_statements("SELECT /* a; b */ 1; -- c;\n/* d; */;").unwrap(),
+            vec!["SELECT /* a; b */ 1"]
+        );
+        assert!(split_statements("SELECT 'unterminated").is_err());
+    }
+
+    #[test]
+    fn test_empty_statement() {
+        for sql in ["", "  \n\t", ";", "-- nothing here", "/* a */ ; -- b\n"] {
+            assert!(is_empty_statement(sql), "{sql:?}");
+        }
+        for sql in ["SELECT 1", "-- a\nSELECT 1", "'unterminated"] {
+            assert!(!is_empty_statement(sql), "{sql:?}");
+        }
+    }
+
+    #[test]
+    fn test_admin_statements() {
+        assert_eq!(
+            parse_admin("backup to '/tmp/it''s';").unwrap(),
+            Some(AdminStatement::Backup("/tmp/it's".into()))
+        );
+        assert_eq!(
+            parse_admin("DUMP users").unwrap(),
+            Some(AdminStatement::Dump("users".into()))
+        );
+        assert_eq!(parse_admin("SELECT 1").unwrap(), None);
+        for sql in [
+            "BACKUP",
+            "BACKUP TO dir",
+            "BACKUP TO 'a' 'b'",
+            "DUMP",
+            "DUMP a b",
+        ] {
+            assert!(parse_admin(sql).is_err(), "{sql}");
+        }
+    }
+
+    #[test]
+    fn test_parse_error_location() {
+        let location = |sql: &str| match parse_sql(sql) {
+            Err(LunarisError::Parse { location, .. }) => location,
+            other => panic!("expected a parse error, got {other:?}"),
+        };
+        assert_eq!(
+            location("SELECT id\nFROM t WHERE id = = 1"),
+            Some(SourceLocation {
+                line: 2,
+                column: 19
+            })
+        );
+        assert_eq!(
+            location("INSERT INTO t VALUES ('abc"),
+            Some(SourceLocation {
+                line: 1,
+                column: 23
+            })
+        );
+        assert_eq!(location("SELECT 1; SELECT 2"), None);
+
+        let err = parse_sql("SELECT (1 + 2 FROM t").unwrap_err();
+        assert!(err.to_string().ends_with(" at line 1, column 15"), "{err}");
+    }
+}
@@ -1,36 +1,96 @@
-use crate::constants::VM_STARTING_REGISTERS;
-use crate::database::Database;
+use crate::constants::{TIMEOUT_CHECK_INTERVAL, VM_STARTING_REGISTERS};
+use crate::database::{Database, Session};
 use crate::error::{LunarisError, LunarisResult};
+use crate::storage::btree;
 use crate::storage::cursor::Cursor;
-use crate::vm::bytecode::{Instruction, Program};
+use crate::vm::bytecode::{Aggregate, Instruction, Program, SortKey, StatementKind};
+use lunaris_common::protocol::ValueType;
+use lunaris_common::timestamp;
 use lunaris_common::value;
-use lunaris_common::value::Value;
+use lunaris_common::value::{OrderedValue, Value};
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Instant;
 
 struct RuntimeCursor {
     table_name: String,
     cursor: Cursor,
+    /// Set by `DeleteRow`, which leaves the cursor on the row after the
+    /// deleted one, so the next `CursorAdvance` must not move it again.
+    advanced: bool,
+    /// Keys of the rows still to visit when the scan isn't in rowid order: a table
+    /// with a `PRIMARY KEY (...)` constraint is visited in key order, and an
+    /// index cursor visits the rows its last `IndexSeek` found.
+    rowids: Option<std::vec::IntoIter<u64>>,
+    /// Index an `OpenIndexCursor` cursor looks rows up in.
+    index: Option<String>,
+    /// Set by `NullRow`: every column reads as NULL and the scan is over.
+    null_row: bool,
 }
 
+impl RuntimeCursor {
+    /// Move to the next of the listed rowids. `None` if the cursor scans in
+    /// rowid order.
+    fn next_listed_row(
+        &mut self,
+        db: &Database,
+        session: &mut Session,
+    ) -> LunarisResult<Option<bool>> {
+        let Some(order) = &mut self.rowids else {
+            return Ok(None);
+        };
+        let Some(rowid) = order.next() else {
+            return Ok(Some(false));
+        };
+        let cursor = &mut self.cursor;
+        let found = db.with_table_ref(session, &self.table_name, |tree| {
+            Ok(cursor.seek(tree, rowid)? && cursor.row_id(tree)? == rowid)
+        })?;
+        // the index and the table disagree; another row must not stand in
+        if !found {
+            return Err(LunarisError::Storage(format!(
+                "row {} listed by an index is missing from table {}",
+                btree::key_row_id(rowid),
+                self.table_name
+            )));
+        }
+        Ok(Some(true))
+    }
+}
+
+/// Receives result rows one at a time while a program runs.
+pub type RowSink<'a> = Box<dyn FnMut(Vec<Value>) -> LunarisResult<()> + 'a>;
+
 /// Lunaris virtual machine - the core component, executing query logic.
-pub struct Lvm {
+pub struct Lvm<'a> {
     pc: usize,
     halted: bool,
     registers: Vec<Value>,
 
-    // Built for future compatibility - currently all cursor indexes are
-    // hardcoded to 0.
     cursors: HashMap<i32, RuntimeCursor>,
 
     result_rows: Vec<Vec<Value>>,
+    /// Rows held back by `SorterInsert`.
+    sorter: Vec<Vec<Value>>,
+    /// Groups of rows folded by `AggStep`, by their key.
+    groups: BTreeMap<Vec<OrderedValue>, Vec<Accumulator>>,
+    /// Rows of the groups `AggNext` hasn't loaded yet.
+    finished_groups: std::vec::IntoIter<Vec<Value>>,
+    /// Set in streaming mode, where rows bypass `result_rows`.
+    row_sink: Option<RowSink<'a>>,
+    rows_returned: u64,
+    /// Result rows still to skip, and how many may be written after them,
+    /// as set by `ResultLimit`.
+    rows_to_skip: u64,
+    row_limit: Option<u64>,
     record_buffer: Vec<Value>,
     rows_affected: u64,
+    rows_examined: u64,
 
     message: String,
 }
 
-impl Lvm {
+impl<'a> Lvm<'a> {
     pub fn new() -> Self {
         Self {
             pc: 0,
@@ -38,46 +98,92 @@ impl Lvm {
             registers: vec![Value::Null; VM_STARTING_REGISTERS],
             cursors: HashMap::new(),
             result_rows: Vec::new(),
+            sorter: Vec::new(),
+            groups: BTreeMap::new(),
+            finished_groups: Vec::new().into_iter(),
+            row_sink: None,
+            rows_returned: 0,
+            rows_to_skip: 0,
+            row_limit: None,
             record_buffer: Vec::new(),
             rows_affected: 0,
+            rows_examined: 0,
             message: String::new(),
         }
     }
 
-    pub fn execute(mut self, db: &Database, program: &Program) -> LunarisResult<ExecutionResult> {
+    /// A VM that hands every result row to `sink` as soon as it is produced
+    /// instead of collecting them into `ExecutionResult::rows`.
+    pub fn streaming(sink: RowSink<'a>) -> Self {
+        Self {
+            row_sink: Some(sink),
+            ..Self::new()
+        }
+    }
+
+    pub fn execute(
+        mut self,
+        db: &Database,
+        session: &mut Session,
+        program: &Program,
+    ) -> LunarisResult<ExecutionResult> {
+        program.validate()?;
+        let started = Instant::now();
+        let mut steps: u64 = 0;
         loop {
             if self.pc >= program.instructions.len() || self.halted {
                 break;
             }
+            // the VM can't be interrupted from outside, so it keeps an eye on
+            // the clock itself
+            steps += 1;
+            if steps.is_multiple_of(TIMEOUT_CHECK_INTERVAL) {
+                let timeout = db.statement_timeout();
+                if started.elapsed() > timeout {
+                    return Err(LunarisError::Timeout(timeout.as_millis() as u64));
+                }
+            }
 
             let instr = &program.instructions[self.pc];
             self.pc += 1;
-            self.execute_instr(instr, db)?;
+            self.execute_instr(instr, db, session)?;
         }
 
         if self.message.is_empty() {
-            if !self.result_rows.is_empty() {
-                self.message = format!("{} row(s) returned", self.result_rows.len());
-            } else if self.rows_affected > 0 {
-                self.message = format!("{} row(s) affected", self.rows_affected);
-            } else {
-                self.message = "OK".into();
-            }
+            self.message = match program.kind {
+                StatementKind::Query => format!("{} row(s) returned", self.rows_returned),
+                StatementKind::Dml => format!("{} row(s) affected", self.rows_affected),
+                StatementKind::Other => "OK".into(),
+            };
         }
 
         Ok(ExecutionResult {
             columns: program.result_columns.clone(),
+            column_types: program.result_types.clone(),
             rows: self.result_rows,
             rows_affected: self.rows_affected,
+            rows_examined: self.rows_examined,
+            elapsed_micros: 0,
             message: self.message,
+            kind: program.kind,
         })
     }
 
-    fn execute_instr(&mut self, instr: &Instruction, db: &Database) -> LunarisResult<()> {
+    fn execute_instr(
+        &mut self,
+        instr: &Instruction,
+        db: &Database,
+        session: &mut Session,
+    ) -> LunarisResult<()> {
         match instr {
             Instruction::Init { target } => self.pc = *target,
             Instruction::Goto { target } => self.pc = *target,
             Instruction::Halt => self.halted = true,
+            Instruction::ResultLimit { limit, offset } => {
+                self.rows_to_skip = *offset;
+                self.row_limit = *limit;
+                self.halted = *limit == Some(0);
+            }
 
             Instruction::OpenReadCursor { cursor, table } => {
                 self.open_cursor(*cursor, table, db)?
@@ -87,28 +193,153 @@ impl Lvm {
                 self.open_cursor(*cursor, table, db)?
             }
 
+            Instruction::OpenIndexCursor {
+                cursor,
+                table,
+                index,
+            } => {
+                self.open_cursor(*cursor, table, db)?;
+                let oc = self.get_cursor_mut(cursor)?;
+                oc.index = Some(index.clone());
+                oc.rowids = Some(Vec::new().into_iter());
+            }
+
+            Instruction::IndexSeek {
+                cursor,
+                key_reg,
+                empty_target,
+            } => {
+                let key = self.registers[*key_reg].clone();
+                let oc = self.get_cursor_mut(cursor)?;
+                oc.null_row = false;
+                let Some(index) = &oc.index else {
+                    return Err(LunarisError::Vm(format!(
+                        "cursor {cursor} does not read through an index"
+                    )));
+                };
+                // rowid order, like a scan of the table
+                let mut rowids = db.index_lookup(session, index, &key)?;
+                rowids.sort_unstable();
+                let keys: Vec<u64> = rowids.into_iter().map(btree::row_key).collect();
+                oc.rowids = Some(keys.into_iter());
+                if oc.next_listed_row(db, session)? == Some(true) {
+                    self.rows_examined += 1;
+                } else {
+                    self.pc = *empty_target;
+                }
+            }
+
             Instruction::RewindCursor {
                 cursor,
                 empty_target,
             } => {
                 let oc = self.get_cursor_mut(cursor)?;
-                let has_data = db.with_table_mut(&oc.table_name, |tree| oc.cursor.rewind(tree))?;
-                if !has_data {
+                oc.null_row = false;
+                if oc.index.is_some() {
+                    return Err(LunarisError::Vm(format!(
+                        "cursor {cursor} reads through an index and can't be rewound"
+                    )));
+                }
+                if oc.rowids.is_some() {
+                    oc.rowids = Some(db.key_order(session, &oc.table_name)?.into_iter());
+                }
+                let has_data = match oc.next_listed_row(db, session)? {
+                    Some(has_data) => has_data,
+                    None => {
+                        db.with_table_ref(session, &oc.table_name, |tree| oc.cursor.rewind(tree))?
+                    }
+                };
+                if has_data {
+                    self.rows_examined += 1;
+                } else {
                     self.pc = *empty_target;
                 }
             }
 
+            Instruction::SeekGE {
+                cursor,
+                key_reg,
+                empty_target,
+            }
+            | Instruction::SeekEQ {
+                cursor,
+                key_reg,
+                empty_target,
+            } => {
+                let key = match &self.registers[*key_reg] {
+                    Value::Integer(id) => btree::row_key(*id),
+                    other => {
+                        return Err(LunarisError::Vm(format!(
+                            "key register is not integer: {other:?}"
+                        )));
+                    }
+                };
+                let exact = matches!(instr, Instruction::SeekEQ { .. });
+                let oc = self.get_cursor_mut(cursor)?;
+                oc.null_row = false;
+                let found = db.with_table_ref(session, &oc.table_name, |tree| {
+                    let found = oc.cursor.seek(tree, key)?;
+                    Ok(found && (!exact || oc.cursor.row_id(tree)? == key))
+                })?;
+                if found {
+                    self.rows_examined += 1;
+                } else {
+                    self.pc = *empty_target;
+                }
+            }
+
+            Instruction::RewindCursorLast {
+                cursor,
+                empty_target,
+            } => {
+                let oc = self.get_cursor_mut(cursor)?;
+                oc.null_row = false;
+                let has_data =
+                    db.with_table_ref(session, &oc.table_name, |tree| oc.cursor.rewind_last(tree))?;
+                if has_data {
+                    self.rows_examined += 1;
+                } else {
+                    self.pc = *empty_target;
+                }
+            }
+
+            Instruction::CursorPrev {
+                cursor,
+                loop_target,
+            } => {
+                let open_cur = self.get_cursor_mut(cursor)?;
+                let has_more = !open_cur.null_row
+                    && db.with_table_ref(session, &open_cur.table_name, |tree| {
+                        open_cur.cursor.prev(tree)
+                    })?;
+                if has_more {
+                    self.rows_examined += 1;
+                    self.pc = *loop_target;
+                }
+            }
+
             Instruction::CursorAdvance {
                 cursor,
                 loop_target,
             } => {
                 let open_cur = self.get_cursor_mut(cursor)?;
-                let has_more =
-                    db.with_table_mut(&open_cur.table_name, |tree| open_cur.cursor.next(tree))?;
+                let advanced = std::mem::take(&mut open_cur.advanced);
+                let has_more = match open_cur.next_listed_row(db, session)? {
+                    _ if open_cur.null_row => false,
+                    Some(has_more) => has_more,
+                    None if advanced => !open_cur.cursor.is_done(),
+                    None => db.with_table_ref(session, &open_cur.table_name, |tree| {
+                        open_cur.cursor.next(tree)
+                    })?,
+                };
                 if has_more {
+                    self.rows_examined += 1;
                     self.pc = *loop_target;
                 }
             }
+            Instruction::NullRow { cursor } => {
+                self.get_cursor_mut(cursor)?.null_row = true;
+            }
             Instruction::CloseCursor { cursor } => {
                 self.cursors.remove(cursor);
             }
@@ -129,6 +360,10 @@ impl Lvm {
                 ensure_reg(&mut self.registers, *dest);
                 self.registers[*dest] = Value::Boolean(*value);
             }
+            Instruction::Blob { value, reg: dest } => {
+                ensure_reg(&mut self.registers, *dest);
+                self.registers[*dest] = Value::Blob(value.clone());
+            }
             Instruction::Null { reg: dest } => {
                 ensure_reg(&mut self.registers, *dest);
                 self.registers[*dest] = Value::Null;
@@ -140,87 +375,227 @@ impl Lvm {
             } => {
                 ensure_reg(&mut self.registers, *dest);
                 let open_cur = self.get_cursor_mut(cursor)?;
-                let val = db.with_table_mut(&open_cur.table_name, |tree| {
-                    open_cur.cursor.column(tree, *col_index)
-                })?;
+                let val = match open_cur.null_row {
+                    true => Value::Null,
+                    false => db.with_table_ref(session, &open_cur.table_name, |tree| {
+                        open_cur.cursor.column(tree, *col_index)
+                    })?,
+                };
                 self.registers[*dest] = val;
             }
+            Instruction::FormatTimestamp { reg } => {
+                if let Value::Integer(millis) = self.registers[*reg] {
+                    self.registers[*reg] = Value::Text(timestamp::format_timestamp(millis));
+                }
+            }
+            Instruction::Upper { reg } => {
+                self.registers[*reg] = map_text(&self.registers[*reg], "UPPER", |s| {
+                    Value::Text(s.to_uppercase())
+                })?;
+            }
+            Instruction::FoldCase { reg } => {
+                if let Value::Text(s) = &mut self.registers[*reg] {
+                    s.make_ascii_lowercase();
+                }
+            }
+            Instruction::Lower { reg } => {
+                self.registers[*reg] = map_text(&self.registers[*reg], "LOWER", |s| {
+                    Value::Text(s.to_lowercase())
+                })?;
+            }
+            Instruction::Length { reg } => {
+                self.registers[*reg] = match &self.registers[*reg] {
+                    Value::Blob(b) => Value::Integer(b.len() as i64),
+                    other => {
+                        map_text(
+                            other,
+                            "LENGTH",
+                            |s| Value::Integer(s.chars().count() as i64),
+                        )?
+                    }
+                };
+            }
+            Instruction::Add { left, right, dest } => {
+                self.arithmetic(*left, *right, *dest, ArithOp::Add)?
+            }
+            Instruction::Subtract { left, right, dest } => {
+                self.arithmetic(*left, *right, *dest, ArithOp::Subtract)?
+            }
+            Instruction::Multiply { left, right, dest } => {
+                self.arithmetic(*left, *right, *dest, ArithOp::Multiply)?
+            }
+            Instruction::Divide { left, right, dest } => {
+                self.arithmetic(*left, *right, *dest, ArithOp::Divide)?
+            }
+            Instruction::Remainder { left, right, dest } => {
+                self.arithmetic(*left, *right, *dest, ArithOp::Remainder)?
+            }
+            Instruction::Concat { left, right, dest } => {
+                let value = match (&self.registers[*left], &self.registers[*right]) {
+                    (Value::Null, _) | (_, Value::Null) => Value::Null,
+                    (l, r) => Value::Text(concat_text(l)? + &concat_text(r)?),
+                };
+                ensure_reg(&mut self.registers, *dest);
+                self.registers[*dest] = value;
+            }
             Instruction::ReadRowId { cursor, reg: dest } => {
                 ensure_reg(&mut self.registers, *dest);
                 let oc = self.get_cursor_mut(cursor)?;
-                let id = db.with_table_mut(&oc.table_name, |tree| oc.cursor.row_id(tree))?;
-                self.registers[*dest] = Value::Integer(id as i64);
+                self.registers[*dest] = match oc.null_row {
+                    true => Value::Null,
+                    false => {
+                        let key = db.with_table_ref(session, &oc.table_name, |tree| {
+                            oc.cursor.row_id(tree)
+                        })?;
+                        Value::Integer(btree::key_row_id(key))
+                    }
+                };
             }
             Instruction::WriteResultRow { start, count } => {
                 let row: Vec<Value> = self.registers[*start..*start + *count].to_vec();
-                self.result_rows.push(row);
+                self.write_result_row(row, db)?;
+            }
+            Instruction::ConstResultRow { values } => {
+                self.write_result_row(values.clone(), db)?;
+            }
+            Instruction::SorterInsert { start, count } => {
+                // every held row is returned later, so the limit applies here
+                check_result_size(self.sorter.len() as u64, db)?;
+                self.sorter
+                    .push(self.registers[*start..*start + *count].to_vec());
+            }
+            Instruction::AggStep {
+                key,
+                key_count,
+                args,
+                aggregates,
+            } => {
+                let key: Vec<OrderedValue> = self.registers[*key..*key + *key_count]
+                    .iter()
+                    .map(|value| OrderedValue(value.clone()))
+                    .collect();
+                if !self.groups.contains_key(&key) {
+                    // every group becomes a result row
+                    check_result_size(self.groups.len() as u64, db)?;
+                }
+                let group = self
+                    .groups
+                    .entry(key)
+                    .or_insert_with(|| vec![Accumulator::new(); aggregates.len()]);
+                for (i, (acc, aggregate)) in group.iter_mut().zip(aggregates).enumerate() {
+                    acc.step(*aggregate, &self.registers[*args + i])?;
+                }
+            }
+            Instruction::AggFinish {
+                key_count,
+                aggregates,
+            } => {
+                let mut groups = std::mem::take(&mut self.groups);
+                if *key_count == 0 && groups.is_empty() {
+                    groups.insert(Vec::new(), vec![Accumulator::new(); aggregates.len()]);
+                }
+                let rows: Vec<Vec<Value>> = groups
+                    .into_iter()
+                    .map(|(key, accs)| {
+                        let finished = accs.into_iter().zip(aggregates);
+                        key.into_iter()
+                            .map(|value| value.0)
+                            .chain(finished.map(|(acc, aggregate)| acc.finish(*aggregate)))
+                            .collect()
+                    })
+                    .collect();
+                self.finished_groups = rows.into_iter();
+            }
+            Instruction::AggNext { dest, done_target } => match self.finished_groups.next() {
+                Some(row) => {
+                    ensure_reg(&mut self.registers, *dest + row.len());
+                    for (i, value) in row.into_iter().enumerate() {
+                        self.registers[*dest + i] = value;
+                    }
+                }
+                None => self.pc = *done_target,
+            },
+            Instruction::Copy { src, dest } => {
+                ensure_reg(&mut self.registers, *dest);
+                self.registers[*dest] = self.registers[*src].clone();
+            }
+            Instruction::SortResults {
+                keys,
+                columns,
+                distinct,
+            } => {
+                let mut rows = std::mem::take(&mut self.sorter);
+                rows.sort_by(|a, b| sort_order(a, b, keys));
+                if *distinct {
+                    rows.dedup_by(|a, b| {
+                        (a[..*columns].iter().zip(&b[..*columns]))
+                            .all(|(a, b)| value::total_compare(a, b) == Ordering::Equal)
+                    });
+                }
+                for mut row in rows {
+                    if self.halted {
+                        break;
+                    }
+                    row.truncate(*columns);
+                    self.write_result_row(row, db)?;
+                }
             }
 
+            // Comparisons follow SQL's three-valued logic: comparing anything
+            // with NULL gives UNKNOWN rather than TRUE or FALSE. The compiler
+            // combines the jumps according to these truth tables:
+            //
+            //   NOT |          AND | T F U        OR  | T F U
+            //   T F |          T   | T F U        T   | T T T
+            //   F T |          F   | F F F        F   | T F U
+            //   U U |          U   | U F U        U   | T U U
+            //
+            // and a WHERE clause keeps a row only if it is TRUE, so a row for
+            // which it is FALSE or UNKNOWN is skipped. IS [NOT] NULL is never
+            // UNKNOWN.
             Instruction::Jeq {
                 left,
                 right,
                 target,
-            } => {
-                if value::compare(&self.registers[*left], &self.registers[*right])
-                    == Some(Ordering::Equal)
-                {
-                    self.pc = *target;
-                }
-            }
+                null_jump,
+            } => self.compare_jump(*left, *right, *target, *null_jump, |o| o.is_eq())?,
             Instruction::Jne {
                 left,
                 right,
                 target,
-            } => {
-                if value::compare(&self.registers[*left], &self.registers[*right])
-                    != Some(Ordering::Equal)
-                {
-                    self.pc = *target;
-                }
-            }
+                null_jump,
+            } => self.compare_jump(*left, *right, *target, *null_jump, |o| o.is_ne())?,
             Instruction::Jlt {
                 left,
                 right,
                 target,
-            } => {
-                if value::compare(&self.registers[*left], &self.registers[*right])
-                    == Some(Ordering::Less)
-                {
-                    self.pc = *target;
-                }
-            }
+                null_jump,
+            } => self.compare_jump(*left, *right, *target, *null_jump, |o| o.is_lt())?,
             Instruction::Jle {
                 left,
                 right,
                 target,
-            } => {
-                if matches!(
-                    value::compare(&self.registers[*left], &self.registers[*right]),
-                    Some(Ordering::Less | Ordering::Equal)
-                ) {
-                    self.pc = *target;
-                }
-            }
+                null_jump,
+            } => self.compare_jump(*left, *right, *target, *null_jump, |o| o.is_le())?,
             Instruction::Jgt {
                 left,
                 right,
                 target,
-            } => {
-                if value::compare(&self.registers[*left], &self.registers[*right])
-                    == Some(Ordering::Greater)
-                {
-                    self.pc = *target;
-                }
-            }
+                null_jump,
+            } => self.compare_jump(*left, *right, *target, *null_jump, |o| o.is_gt())?,
             Instruction::Jge {
                 left,
                 right,
                 target,
-            } => {
-                if matches!(
-                    value::compare(&self.registers[*left], &self.registers[*right]),
-                    Some(Ordering::Greater | Ordering::Equal)
-                ) {
+                null_jump,
+            } => self.compare_jump(*left, *right, *target, *null_jump, |o| o.is_ge())?,
+            Instruction::JumpIfNull { reg, target } => {
+                if self.registers[*reg] == Value::Null {
+                    self.pc = *target;
+                }
+            }
+            Instruction::JumpIfNotNull { reg, target } => {
+                if self.registers[*reg] != Value::Null {
                     self.pc = *target;
                 }
             }
@@ -229,13 +604,17 @@ impl Lvm {
                 self.record_buffer = self.registers[*start..*start + *count].to_vec();
             }
 
+            Instruction::NewRowId { cursor, reg: dest } => {
+                ensure_reg(&mut self.registers, *dest);
+                let oc = self.get_cursor_mut(cursor)?;
+                let id =
+                    db.with_table_for_write(session, &oc.table_name, |tree| tree.next_row_id())?;
+                self.registers[*dest] = Value::Integer(id);
+            }
+
             Instruction::InsertRecord { cursor, key_reg } => {
-                let oc = self
-                    .cursors
-                    .get_mut(cursor)
-                    .ok_or_else(|| LunarisError::Vm(format!("cursor {cursor} not open")))?;
-                let key = match &self.registers[*key_reg] {
-                    Value::Integer(k) => *k as u64,
+                let rowid = match &self.registers[*key_reg] {
+                    Value::Integer(id) => *id,
                     other => {
                         return Err(LunarisError::Vm(format!(
                             "key register is not integer: {other:?}"
@@ -243,52 +622,461 @@ impl Lvm {
                     }
                 };
                 let values = self.record_buffer.clone();
-                db.insert_row(&oc.table_name, key, &values)?;
+                let oc = self.get_cursor_mut(cursor)?;
+                db.insert_row(session, &oc.table_name, rowid, &values)?;
                 self.rows_affected += 1;
             }
 
             Instruction::DeleteRow { cursor } => {
                 let oc = self.get_cursor_mut(cursor)?;
-                db.with_table_mut(&oc.table_name, |tree| {
-                    oc.cursor.delete_current(tree)?;
-                    tree.flush()
-                })?;
+                db.delete_row(session, &oc.table_name, &mut oc.cursor)?;
+                oc.advanced = true;
                 self.rows_affected += 1;
             }
+            Instruction::UpdateRow { cursor } => {
+                let values = self.record_buffer.clone();
+                let oc = self.get_cursor_mut(cursor)?;
+                db.update_row(session, &oc.table_name, &mut oc.cursor, &values)?;
+                self.rows_affected += 1;
+            }
+            Instruction::TruncateTable { table } => {
+                self.rows_affected += db.truncate_table(session, table)?;
+            }
+            Instruction::StatusRows { columns, sorter } => {
+                for stat in db.status() {
+                    if self.halted {
+                        break;
+                    }
+                    let row = columns.iter().map(|&col| stat[col].clone()).collect();
+                    match sorter {
+                        true => {
+                            check_result_size(self.sorter.len() as u64, db)?;
+                            self.sorter.push(row);
+                        }
+                        false => self.write_result_row(row, db)?,
+                    }
+                }
+            }
+            Instruction::RowCount { table, reg } => {
+                let count = db.row_count(session, table)?;
+                ensure_reg(&mut self.registers, *reg);
+                self.registers[*reg] = Value::Integer(count as i64);
+            }
 
-            Instruction::CreateTable { schema } => {
-                db.create_table(schema)?;
-                self.message = format!("Table '{}' created", schema.table_name);
+            Instruction::CreateTable {
+                schema,
+                if_not_exists,
+            } => {
+                if session.in_transaction() {
+                    return Err(LunarisError::Transaction(
+                        "CREATE TABLE cannot run inside a transaction".into(),
+                    ));
+                }
+                let name = &schema.table_name;
+                self.message = match db.create_table(schema) {
+                    Ok(()) => format!("Table '{name}' created"),
+                    Err(LunarisError::TableAlreadyExists(_)) if *if_not_exists => {
+                        format!("Table '{name}' already exists, nothing done")
+                    }
+                    Err(e) => return Err(e),
+                };
+            }
+            Instruction::CreateIndex { index } => {
+                if session.in_transaction() {
+                    return Err(LunarisError::Transaction(
+                        "CREATE INDEX cannot run inside a transaction".into(),
+                    ));
+                }
+                db.create_index(session, index)?;
+                self.message = format!("Index '{}' created", index.index_name);
             }
+            Instruction::DropTable { table, if_exists } => {
+                if session.in_transaction() {
+                    return Err(LunarisError::Transaction(
+                        "DROP TABLE cannot run inside a transaction".into(),
+                    ));
+                }
+                self.message = match db.drop_table(session, table) {
+                    Ok(()) => format!("Table '{table}' dropped"),
+                    Err(LunarisError::TableNotFound(_)) if *if_exists => {
+                        format!("Table '{table}' does not exist, nothing done")
+                    }
+                    Err(e) => return Err(e),
+                };
+            }
+            Instruction::AlterTable { schema, sources } => {
+                if session.in_transaction() {
+                    return Err(LunarisError::Transaction(
+                        "ALTER TABLE cannot run inside a transaction".into(),
+                    ));
+                }
+                db.alter_table(session, schema, sources)?;
+                self.message = format!("Table '{}' altered", schema.table_name);
+            }
+
+            Instruction::Begin => {
+                db.begin(session)?;
+                self.message = "Transaction started".into();
+            }
+            Instruction::Commit => {
+                if !session.in_transaction() {
+                    return Err(LunarisError::Transaction("no transaction is active".into()));
+                }
+                db.commit(session)?;
+                self.message = "Transaction committed".into();
+            }
+            Instruction::Rollback => {
+                if !session.in_transaction() {
+                    return Err(LunarisError::Transaction("no transaction is active".into()));
+                }
+                db.rollback(session)?;
+                self.message = "Transaction rolled back".into();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Jump to `target` if `holds` accepts the ordering of the two registers,
+    /// or if the comparison is UNKNOWN, with a NULL or NaN on either side,
+    /// and `null_jump` is set. Values of types that can't be compared at
+    /// all, such as text and a number, fail the statement.
+    fn compare_jump(
+        &mut self,
+        left: usize,
+        right: usize,
+        target: usize,
+        null_jump: bool,
+        holds: impl Fn(Ordering) -> bool,
+    ) -> LunarisResult<()> {
+        let (l, r) = (&self.registers[left], &self.registers[right]);
+        let jump = match value::compare(l, r) {
+            Some(ordering) => holds(ordering),
+            None if comparable(l, r) => null_jump,
+            None => {
+                return Err(LunarisError::TypeMismatch {
+                    expected: format!("a value comparable with {}", type_name(l)),
+                    got: type_name(r).into(),
+                });
+            }
+        };
+        if jump {
+            self.pc = target;
         }
+        Ok(())
+    }
 
+    fn arithmetic(
+        &mut self,
+        left: usize,
+        right: usize,
+        dest: usize,
+        op: ArithOp,
+    ) -> LunarisResult<()> {
+        let value = op.apply(&self.registers[left], &self.registers[right])?;
+        ensure_reg(&mut self.registers, dest);
+        self.registers[dest] = value;
         Ok(())
     }
 
+    fn write_result_row(&mut self, row: Vec<Value>, db: &Database) -> LunarisResult<()> {
+        if self.rows_to_skip > 0 {
+            self.rows_to_skip -= 1;
+            return Ok(());
+        }
+        check_result_size(self.rows_returned, db)?;
+        self.rows_returned += 1;
+        // nothing after the last row that fits the limit is needed
+        if self.row_limit == Some(self.rows_returned) {
+            self.halted = true;
+        }
+        match &mut self.row_sink {
+            Some(sink) => sink(row),
+            None => {
+                self.result_rows.push(row);
+                Ok(())
+            }
+        }
+    }
+
     fn open_cursor(&mut self, cursor: i32, table_name: &str, db: &Database) -> LunarisResult<()> {
+        let schema = db.get_schema(table_name)?;
         self.cursors.insert(
             cursor,
             RuntimeCursor {
                 table_name: table_name.to_owned(),
-                cursor: Cursor::new(db.get_schema(table_name)?),
+                rowids: (!schema.key_columns.is_empty()).then(|| Vec::new().into_iter()),
+                index: None,
+                cursor: Cursor::new(schema),
+                advanced: false,
+                null_row: false,
             },
         );
         Ok(())
     }
 
     fn get_cursor_mut(&mut self, cursor: &i32) -> LunarisResult<&mut RuntimeCursor> {
-        Ok(self
-            .cursors
+        self.cursors
             .get_mut(cursor)
-            .ok_or_else(|| LunarisError::Vm(format!("cursor {cursor} not open")))?)
+            .ok_or_else(|| LunarisError::Vm(format!("cursor {cursor} not open")))
+    }
+}
+
+impl Default for Lvm<'_> {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
+#[derive(Debug)]
 pub struct ExecutionResult {
     pub columns: Vec<String>,
+    /// Type of every result column, as far as the statement tells.
+    pub column_types: Vec<ValueType>,
     pub rows: Vec<Vec<Value>>,
     pub rows_affected: u64,
+    /// Rows the cursors were positioned on, i.e. how much of the tables a
+    /// statement had to look at.
+    pub rows_examined: u64,
+    /// Time spent parsing, compiling and running the statement.
+    pub elapsed_micros: u64,
     pub message: String,
+    pub kind: StatementKind,
+}
+
+/// Apply a string function to a text value, passing NULL through.
+fn map_text(value: &Value, name: &str, f: impl Fn(&str) -> Value) -> LunarisResult<Value> {
+    match value {
+        Value::Null => Ok(Value::Null),
+        Value::Text(s) => Ok(f(s)),
+        other => Err(LunarisError::Vm(format!(
+            "{name} expects text, got {other:?}"
+        ))),
+    }
+}
+
+#[derive(Clone, Copy)]
+enum ArithOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+}
+
+impl ArithOp {
+    fn symbol(self) -> &'static str {
+        match self {
+            ArithOp::Add => "+",
+            ArithOp::Subtract => "-",
+            ArithOp::Multiply => "*",
+            ArithOp::Divide => "/",
+            ArithOp::Remainder => "%",
+        }
+    }
+
+    fn apply(self, l: &Value, r: &Value) -> LunarisResult<Value> {
+        Ok(match (l, r) {
+            (Value::Null, _) | (_, Value::Null) => Value::Null,
+            (Value::Integer(l), Value::Integer(r)) => Value::Integer(self.integers(*l, *r)?),
+            (l, r) => match (as_float(l), as_float(r)) {
+                (Some(l), Some(r)) => Value::Float(self.floats(l, r)?),
+                _ => {
+                    return Err(LunarisError::Vm(format!(
+                        "cannot apply {} to {l:?} and {r:?}",
+                        self.symbol()
+                    )));
+                }
+            },
+        })
+    }
+
+    /// Integer arithmetic, failing on overflow and division by zero.
+    /// Division truncates toward zero and the remainder takes the sign of
+    /// the dividend, so `-7 / 2 = -3`, `-7 % 3 = -1` and `7 % -3 = 1`.
+    fn integers(self, l: i64, r: i64) -> LunarisResult<i64> {
+        let result = match self {
+            ArithOp::Add => l.checked_add(r),
+            ArithOp::Subtract => l.checked_sub(r),
+            ArithOp::Multiply => l.checked_mul(r),
+            ArithOp::Divide | ArithOp::Remainder if r == 0 => {
+                return Err(LunarisError::Vm("division by zero".into()));
+            }
+            ArithOp::Divide => l.checked_div(r),
+            ArithOp::Remainder => l.checked_rem(r),
+        };
+        result.ok_or_else(|| {
+            LunarisError::Vm(format!("integer overflow in {l} {} {r}", self.symbol()))
+        })
+    }
+
+    /// Floating point arithmetic. Dividing by zero fails like it does for
+    /// integers rather than giving an infinity or NaN; the remainder again
+    /// takes the sign of the dividend.
+    fn floats(self, l: f64, r: f64) -> LunarisResult<f64> {
+        Ok(match self {
+            ArithOp::Add => l + r,
+            ArithOp::Subtract => l - r,
+            ArithOp::Multiply => l * r,
+            ArithOp::Divide | ArithOp::Remainder if r == 0.0 => {
+                return Err(LunarisError::Vm("division by zero".into()));
+            }
+            ArithOp::Divide => l / r,
+            ArithOp::Remainder => l % r,
+        })
+    }
+}
+
+/// Running state of an aggregate over the rows of one group: the values
+/// counted, and the sum or extreme value seen so far.
+#[derive(Debug, Clone)]
+struct Accumulator {
+    count: i64,
+    value: Value,
+}
+
+impl Accumulator {
+    fn new() -> Self {
+        Self {
+            count: 0,
+            value: Value::Null,
+        }
+    }
+
+    fn step(&mut self, aggregate: Aggregate, arg: &Value) -> LunarisResult<()> {
+        if aggregate == Aggregate::CountRows {
+            self.count += 1;
+            return Ok(());
+        }
+        if matches!(arg, Value::Null) {
+            return Ok(());
+        }
+        self.count += 1;
+        match aggregate {
+            Aggregate::CountRows | Aggregate::Count => {}
+            Aggregate::Sum | Aggregate::Avg => {
+                if as_float(arg).is_none() {
+                    return Err(LunarisError::Vm(format!(
+                        "cannot add up {}",
+                        type_name(arg)
+                    )));
+                }
+                self.value = match (&self.value, aggregate) {
+                    (Value::Null, _) => arg.clone(),
+                    // the mean is taken over floats, which can't overflow
+                    (sum, Aggregate::Avg) => {
+                        ArithOp::Add.apply(&Value::Float(as_float(sum).unwrap_or(0.0)), arg)?
+                    }
+                    (sum, _) => ArithOp::Add.apply(sum, arg)?,
+                };
+            }
+            Aggregate::Min | Aggregate::Max => {
+                let wanted = match aggregate {
+                    Aggregate::Min => Ordering::Less,
+                    _ => Ordering::Greater,
+                };
+                let replace = match value::compare(arg, &self.value) {
+                    _ if matches!(self.value, Value::Null) => true,
+                    Some(ordering) => ordering == wanted,
+                    None if comparable(arg, &self.value) => false,
+                    None => {
+                        return Err(LunarisError::TypeMismatch {
+                            expected: format!("a value comparable with {}", type_name(&self.value)),
+                            got: type_name(arg).into(),
+                        });
+                    }
+                };
+                if replace {
+                    self.value = arg.clone();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self, aggregate: Aggregate) -> Value {
+        match aggregate {
+            Aggregate::CountRows | Aggregate::Count => Value::Integer(self.count),
+            Aggregate::Sum | Aggregate::Min | Aggregate::Max => self.value,
+            Aggregate::Avg => match as_float(&self.value) {
+                Some(sum) => Value::Float(sum / self.count as f64),
+                None => Value::Null,
+            },
+        }
+    }
+}
+
+/// Whether `compare` can order values of the types of `a` and `b`, when
+/// neither is NULL.
+fn comparable(a: &Value, b: &Value) -> bool {
+    matches!(
+        (a, b),
+        (Value::Null, _)
+            | (_, Value::Null)
+            | (
+                Value::Integer(_) | Value::Float(_),
+                Value::Integer(_) | Value::Float(_)
+            )
+    ) || std::mem::discriminant(a) == std::mem::discriminant(b)
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "NULL",
+        Value::Integer(_) => "INTEGER",
+        Value::Float(_) => "FLOAT",
+        Value::Boolean(_) => "BOOLEAN",
+        Value::Text(_) => "TEXT",
+        Value::Blob(_) => "BLOB",
+    }
+}
+
+fn as_float(value: &Value) -> Option<f64> {
+    match value {
+        Value::Integer(i) => Some(*i as f64),
+        Value::Float(f) => Some(*f),
+        _ => None,
+    }
+}
+
+fn concat_text(value: &Value) -> LunarisResult<String> {
+    match value {
+        Value::Integer(_) | Value::Float(_) | Value::Boolean(_) | Value::Text(_) => {
+            Ok(value.to_string())
+        }
+        other => Err(LunarisError::Vm(format!("cannot concatenate {other:?}"))),
+    }
+}
+
+fn check_result_size(rows: u64, db: &Database) -> LunarisResult<()> {
+    if rows >= db.max_result_rows() {
+        return Err(LunarisError::Vm(format!(
+            "result set too large: more than {} rows",
+            db.max_result_rows()
+        )));
+    }
+    Ok(())
+}
+
+/// Order of two held rows under the ORDER BY `keys`, the first key that
+/// tells them apart deciding.
+fn sort_order(a: &[Value], b: &[Value], keys: &[SortKey]) -> Ordering {
+    for key in keys {
+        let (a, b) = (&a[key.column], &b[key.column]);
+        let order = match (a, b) {
+            (Value::Null, Value::Null) => Ordering::Equal,
+            (Value::Null, _) if key.nulls_first => Ordering::Less,
+            (Value::Null, _) => Ordering::Greater,
+            (_, Value::Null) if key.nulls_first => Ordering::Greater,
+            (_, Value::Null) => Ordering::Less,
+            _ if key.descending => value::total_compare_collated(b, a, key.collation),
+            _ => value::total_compare_collated(a, b, key.collation),
+        };
+        if order != Ordering::Equal {
+            return order;
+        }
+    }
+    Ordering::Equal
 }
 
 fn ensure_reg(regs: &mut Vec<Value>, index: usize) {
@@ -0,0 +1,40 @@
+//! Uses the engine through the crate root only, as an embedding program
+//! would.
+
+use lunaris_server::{Database, LunarisError, Value};
+
+#[test]
+fn test_embedded_database() {
+    let dir = std::env::temp_dir().join("lunaris_test").join("embedding");
+    let _ = std::fs::remove_dir_all(&dir);
+
+    {
+        let db = Database::open(&dir).unwrap();
+        let mut session = db.new_session();
+        for sql in [
+            "CREATE TABLE notes (id INTEGER PRIMARY KEY, body TEXT)",
+            "INSERT INTO notes VALUES (1, 'first'), (2, 'second')",
+        ] {
+            db.execute_sql(&mut session, sql).unwrap();
+        }
+        let result = db
+            .execute_sql(&mut session, "DELETE FROM notes WHERE id = 2")
+            .unwrap();
+        assert_eq!(result.rows_affected, 1);
+        assert!(matches!(
+            db.execute_sql(&mut session, "SELECT * FROM missing"),
+            Err(LunarisError::TableNotFound(_))
+        ));
+    }
+
+    // the data outlives the handle
+    let db = Database::open(&dir).unwrap();
+    let result = db
+        .execute_sql(&mut db.new_session(), "SELECT id, body FROM notes")
+        .unwrap();
+    assert_eq!(result.columns, vec!["id", "body"]);
+    assert_eq!(
+        result.rows,
+        vec![vec![Value::Integer(1), Value::Text("first".into())]]
+    );
+}